@@ -0,0 +1,63 @@
+//! Compares the throughput of the fallback policies on a dequeue-heavy workload which keeps the
+//! queue close to empty, where most dequeues find their chosen sub-queue empty and fall back
+//!
+//! Run with `cargo bench --bench fallback`.
+
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use relaxation_analysis::{DChoiceQueueBuilder, FallbackPolicy};
+
+const SUBQUEUES: usize = 1024;
+const OPERATIONS: usize = 1_000_000;
+const RUNS: usize = 5;
+
+/// The fraction of the operations which are enqueues
+const ENQUEUE_SHARE: f64 = 0.48;
+
+fn main() {
+    let mut rng = rand::thread_rng();
+    let operations: Vec<bool> = (0..OPERATIONS)
+        .map(|_| rng.gen_bool(ENQUEUE_SHARE))
+        .collect();
+
+    for (name, policy) in [
+        ("first-non-empty", FallbackPolicy::FirstNonEmpty),
+        ("hinted", FallbackPolicy::Hinted),
+    ] {
+        report(name, || {
+            let mut queue = DChoiceQueueBuilder::new(SUBQUEUES)
+                .fallback_policy(policy)
+                .build();
+            let mut dequeued = 0;
+            for (item, is_enqueue) in operations.iter().enumerate() {
+                if *is_enqueue {
+                    queue.enqueue(item);
+                } else {
+                    dequeued += usize::from(queue.dequeue().is_some());
+                }
+            }
+            (dequeued, queue.cost_report().scanned)
+        });
+    }
+}
+
+/// Prints the best operation throughput over the runs, and the sub-queues scanned per operation
+fn report(name: &str, mut run: impl FnMut() -> (usize, usize)) {
+    let mut scanned = 0;
+    let best = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let (dequeued, run_scanned) = run();
+            assert!(dequeued > 0);
+            scanned = run_scanned;
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or(Duration::MAX);
+    let ops_per_sec = OPERATIONS as f64 / best.as_secs_f64();
+    let scanned_per_op = scanned as f64 / OPERATIONS as f64;
+    println!(
+        "{name:>16}: {best:>10.2?} per run, {ops_per_sec:.3e} ops/s, {scanned_per_op:.1} scanned per op"
+    );
+}
@@ -0,0 +1,58 @@
+//! Compares the simulation throughput of plain usize items against 64 byte payloads
+//!
+//! Run with `cargo bench --bench payload`.
+
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use relaxation_analysis::{analyze_simple, analyze_simple_with, DChoiceQueueBuilder};
+
+const SUBQUEUES: usize = 16;
+const PREFILL: usize = 10_000;
+const OPERATIONS: usize = 1_000_000;
+const RUNS: usize = 5;
+
+/// A 64 byte payload, identified by its sequence number
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Payload {
+    id: usize,
+    data: [u8; 56],
+}
+
+fn main() {
+    let mut rng = rand::thread_rng();
+    let operations: Vec<bool> = (0..OPERATIONS).map(|_| rng.gen()).collect();
+
+    report("usize", || {
+        let mut queue = DChoiceQueueBuilder::new(SUBQUEUES).build();
+        analyze_simple(&mut queue, PREFILL, &operations).expect("The queue is consistent")
+    });
+    report("usize (payload path)", || {
+        let mut queue = DChoiceQueueBuilder::new(SUBQUEUES).build();
+        analyze_simple_with(&mut queue, PREFILL, &operations, |id| id)
+            .expect("The queue is consistent")
+    });
+    report("64 byte payload", || {
+        let mut queue = DChoiceQueueBuilder::new(SUBQUEUES).build();
+        analyze_simple_with(&mut queue, PREFILL, &operations, |id| Payload {
+            id,
+            data: [id as u8; 56],
+        })
+        .expect("The queue is consistent")
+    });
+}
+
+/// Prints the best operation throughput over the runs
+fn report(name: &str, mut run: impl FnMut() -> Vec<usize>) {
+    let best = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let rank_errors = run();
+            assert!(!rank_errors.is_empty());
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or(Duration::MAX);
+    let ops_per_sec = (PREFILL + OPERATIONS) as f64 / best.as_secs_f64();
+    println!("{name:>22}: {best:>10.2?} per run, {ops_per_sec:.3e} ops/s");
+}
@@ -0,0 +1,51 @@
+//! Compares the queue operation throughput of power-of-two sub-queue counts, which sample indexes
+//! by masking, against the neighbouring counts which sample them by range. Only the queue is timed,
+//! not the rank error analysis
+//!
+//! Run with `cargo bench --bench subqueues`.
+
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use relaxation_analysis::DChoiceQueueBuilder;
+
+const D: usize = 8;
+const PREFILL: usize = 10_000;
+const OPERATIONS: usize = 1_000_000;
+const RUNS: usize = 5;
+
+fn main() {
+    let mut rng = rand::thread_rng();
+    let operations: Vec<bool> = (0..OPERATIONS).map(|_| rng.gen()).collect();
+
+    for subqueues in [1000, 1024, 4000, 4096] {
+        report(subqueues, || {
+            let mut queue = DChoiceQueueBuilder::new(subqueues).d(D).build();
+            (0..PREFILL).for_each(|item| queue.enqueue(item));
+            let mut dequeued = 0;
+            for (item, is_enqueue) in operations.iter().enumerate() {
+                if *is_enqueue {
+                    queue.enqueue(PREFILL + item);
+                } else {
+                    dequeued += usize::from(queue.dequeue().is_some());
+                }
+            }
+            dequeued
+        });
+    }
+}
+
+/// Prints the best operation throughput over the runs
+fn report(subqueues: usize, mut run: impl FnMut() -> usize) {
+    let best = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let dequeued = run();
+            assert!(dequeued > 0);
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or(Duration::MAX);
+    let ops_per_sec = (PREFILL + OPERATIONS) as f64 / best.as_secs_f64();
+    println!("{subqueues:>6} sub-queues: {best:>10.2?} per run, {ops_per_sec:.3e} ops/s");
+}
@@ -1,10 +1,54 @@
 import argparse
 import subprocess
+import gzip
 import json
 import seaborn as sns
 import matplotlib.pyplot as plt
 
 
+def open_output(path):
+    # Outputs can be gzipped, which is shown by the suffix
+    if str(path).endswith('.gz'):
+        return gzip.open(path, 'rt')
+    return open(path, 'r')
+
+
+def plot_heatmap(name, heatmap, ax):
+    # Rows are operation buckets, so transpose to get rank errors on the y-axis
+    counts = list(map(list, zip(*heatmap["counts"])))
+    sns.heatmap(counts, ax=ax, cmap="viridis",
+                xticklabels=heatmap["op_edges"][:-1],
+                yticklabels=heatmap["error_edges"])
+    ax.invert_yaxis()
+    ax.set_title(name)
+    ax.set_xlabel('Operation index')
+    ax.set_ylabel('Rank error (lower bucket edge)')
+
+
+def plot_band(name, band, ax):
+    # The sorted values are evenly spaced quantiles, so plot the CDF of the mean with the spread
+    # of each quantile over the runs around it
+    n = len(band["mean"])
+    quantiles = [i / max(n - 1, 1) for i in range(n)]
+    lower = [mean - std for mean, std in zip(band["mean"], band["std"])]
+    upper = [mean + std for mean, std in zip(band["mean"], band["std"])]
+    ax.fill_betweenx(quantiles, band["min"], band["max"], alpha=0.2, label='Min-max')
+    ax.fill_betweenx(quantiles, lower, upper, alpha=0.4, label='Mean ± std')
+    ax.plot(band["mean"], quantiles, label='Mean')
+    ax.set_title(f'CDF: {name}')
+    ax.set_xlabel('Values')
+    ax.set_ylabel('Quantile')
+    ax.legend()
+
+
+def plot_per_thread(name, summaries, ax):
+    threads = list(range(len(summaries)))
+    ax.bar(threads, [summary["mean_rank_error"] for summary in summaries])
+    ax.set_title(name)
+    ax.set_xlabel('Thread')
+    ax.set_ylabel('Mean rank error')
+
+
 def main():
     # Setting up command line arguments
     parser = argparse.ArgumentParser(
@@ -18,6 +62,8 @@ def main():
     parser.add_argument('-r', '--runs', help='How many runs to average over')
     parser.add_argument('--old_json', type=str,
                         help='Path to old JSON file containing to plot')
+    parser.add_argument('--heatmap', action='store_true',
+                        help='Also output and plot a heatmap of rank errors over time')
 
     args = parser.parse_args()
 
@@ -32,6 +78,8 @@ def main():
 
         if args.runs:
             cmd.extend(["-r", args.runs])
+        if args.heatmap:
+            cmd.append("--heatmap")
         # if args.distribution_samples:
         #     cmd.extend(["-s", args.distribution_samples])
 
@@ -52,8 +100,11 @@ def main():
         file_path = args.old_json
 
     # Load JSON data from the output file
-    with open(file_path, 'r') as file:
+    with open_output(file_path) as file:
         data = json.load(file)
+        # Results are wrapped together with their metadata, but older files are not
+        if isinstance(data, dict):
+            data = data["results"]
 
     # # Plotting the distributions one by one
     # for distribution in data:
@@ -76,6 +127,18 @@ def main():
     # Plotting the distributions
     for i, distribution in enumerate(data):
         name, values = distribution
+        if isinstance(values, (int, float)):
+            print(f'{name}: {values}')
+            continue
+        if isinstance(values, dict) and "mean" in values:
+            plot_band(name, values, axs[i])
+            continue
+        if isinstance(values, dict):
+            plot_heatmap(name, values, axs[i])
+            continue
+        if values and isinstance(values[0], dict):
+            plot_per_thread(name, values, axs[i])
+            continue
         sns.kdeplot(values, fill=False, ax=axs[i])
         axs[i].set_title(f'PDF: {name}')
         axs[i].set_xlabel('Values')
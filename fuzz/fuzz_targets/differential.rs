@@ -0,0 +1,161 @@
+#![no_main]
+
+use std::collections::{HashSet, VecDeque};
+
+use libfuzzer_sys::fuzz_target;
+use relaxation_analysis::{
+    partitions_nonempty, DChoiceQueue, DChoiceQueueBuilder, Heuristic, InstrumentedFifo, TraceEvent,
+};
+
+/// A plain model of the sub-queues, updated from the choice trace of the queue
+struct Reference {
+    subqueues: Vec<VecDeque<usize>>,
+    heads: Vec<usize>,
+    tails: Vec<usize>,
+    help_buffer: Option<usize>,
+
+    /// All items enqueued but not yet dequeued
+    live: HashSet<usize>,
+}
+
+impl Reference {
+    fn new(nbr_subqueues: usize) -> Self {
+        Self {
+            subqueues: vec![VecDeque::new(); nbr_subqueues],
+            heads: vec![0; nbr_subqueues],
+            tails: vec![0; nbr_subqueues],
+            help_buffer: None,
+            live: HashSet::new(),
+        }
+    }
+
+    fn pop(&mut self, subqueue: usize) -> usize {
+        self.heads[subqueue] += 1;
+        self.subqueues[subqueue]
+            .pop_front()
+            .expect("The trace dequeued from an empty sub-queue")
+    }
+
+    /// Replays the events of one operation, returning the item it should have dequeued
+    fn replay(&mut self, events: &[TraceEvent], enqueued: Option<usize>) -> Option<usize> {
+        let mut dequeued = None;
+        for event in events {
+            match *event {
+                TraceEvent::Enqueue { subqueue } => {
+                    let item = enqueued.expect("Only enqueues enqueue");
+                    self.tails[subqueue] += 1;
+                    self.subqueues[subqueue].push_back(item);
+                    self.live.insert(item);
+                }
+                TraceEvent::Dequeue { subqueue } => {
+                    dequeued = subqueue.map(|subqueue| self.pop(subqueue));
+                }
+                TraceEvent::Help { subqueue } => {
+                    assert!(self.help_buffer.is_none(), "Helped into a full buffer");
+                    self.help_buffer = Some(self.pop(subqueue));
+                }
+                TraceEvent::Buffered => {
+                    dequeued = Some(self.help_buffer.take().expect("The help buffer is empty"));
+                }
+            }
+        }
+        if let Some(item) = dequeued {
+            assert!(self.live.remove(&item), "Dequeued item {item} is not in the queue");
+        }
+        dequeued
+    }
+
+    fn check(&self, queue: &DChoiceQueue<usize>) {
+        assert_eq!(queue.subqueue_dequeue_counts(), self.heads);
+        assert_eq!(queue.subqueue_enqueue_counts(), self.tails);
+        assert_eq!(queue.len(), self.live.len());
+    }
+
+    /// The items of each sub-queue from the front, with the buffered item back at the front of
+    /// the sub-queue it was helped from
+    fn items(&self, helped_from: Option<usize>) -> Vec<Vec<usize>> {
+        let mut items: Vec<Vec<usize>> = self
+            .subqueues
+            .iter()
+            .map(|subqueue| subqueue.iter().copied().collect())
+            .collect();
+        if let (Some(item), Some(subqueue)) = (self.help_buffer, helped_from) {
+            items[subqueue].insert(0, item);
+        }
+        items
+    }
+}
+
+/// Builds a queue from the configuration bytes, returning it with the operation bytes
+fn build(data: &[u8]) -> Option<(DChoiceQueue<usize>, &[u8])> {
+    let [subqueues, d, heuristic, flags, skew_cap, helping, seed, ops @ ..] = data else {
+        return None;
+    };
+    let subqueues = 1 + *subqueues as usize % 8;
+    let d = 1 + *d as usize % subqueues;
+    let flag = |bit: u8| flags & (1 << bit) != 0;
+    let queue = DChoiceQueueBuilder::new(subqueues)
+        .d(d)
+        .heuristic(match heuristic % 4 {
+            0 => Heuristic::Operation,
+            1 => Heuristic::Length,
+            2 => Heuristic::Softmax { temperature: 1.0 },
+            _ => Heuristic::OldestFront,
+        })
+        .uniques(flag(0))
+        .partition(flag(1) && partitions_nonempty(subqueues, d))
+        .left(flag(2))
+        .round_robin(flag(3))
+        .memory(flag(4))
+        .zipf(flag(5).then_some(1.0))
+        .empty_lin(!flag(6))
+        .recency_penalty(if flag(7) { 1.0 } else { 0.0 })
+        .skew_cap((*skew_cap > 0).then_some(*skew_cap as usize))
+        .helping_threshold((*helping > 0).then(|| (*helping as usize - 1) % 8))
+        .seed(Some(*seed as u64))
+        .trace_choices(true)
+        .build_sequenced();
+    Some((queue, ops))
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((mut queue, ops)) = build(data) else {
+        return;
+    };
+    let mut reference = Reference::new(queue.nbr_subqueues());
+    let mut next_item = 0;
+    let mut helped_from = None;
+
+    for op in ops {
+        let events_before = queue.choice_trace().expect("Built with a trace").len();
+        if op % 2 == 0 {
+            queue.enqueue(next_item);
+            let events = &queue.choice_trace().unwrap()[events_before..];
+            reference.replay(events, Some(next_item));
+            next_item += 1;
+        } else {
+            let item = queue.dequeue();
+            let events = &queue.choice_trace().unwrap()[events_before..];
+            assert_eq!(item, reference.replay(events, None));
+            for event in events {
+                if let TraceEvent::Help { subqueue } = event {
+                    helped_from = Some(*subqueue);
+                }
+            }
+        }
+        reference.check(&queue);
+    }
+
+    // Every item left in the queue comes out of it exactly once, either all of them grouped by
+    // their sub-queues or drained in relaxed order
+    if ops.len() % 2 == 0 {
+        assert_eq!(queue.into_items(), reference.items(helped_from));
+    } else {
+        let mut drained = queue.drain();
+        drained.sort_unstable();
+        let mut live: Vec<usize> = reference.live.into_iter().collect();
+        live.sort_unstable();
+        assert_eq!(drained, live);
+        assert!(queue.into_items().iter().all(Vec::is_empty));
+    }
+});
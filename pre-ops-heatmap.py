@@ -1,5 +1,6 @@
 import argparse
 import subprocess
+import gzip
 import json
 import re
 import matplotlib.pyplot as plt
@@ -9,6 +10,13 @@ import numpy as np
 from matplotlib.colors import LogNorm
 
 
+def open_output(path):
+    # Outputs can be gzipped, which is shown by the suffix
+    if str(path).endswith('.gz'):
+        return gzip.open(path, 'rt')
+    return open(path, 'r')
+
+
 def parse_arguments():
     parser = argparse.ArgumentParser(
         description="Run Rust tests and plot a single heatmap.")
@@ -60,8 +68,11 @@ def extract_file_path(output):
 
 
 def read_data(filepath):
-    with open(filepath, 'r') as file:
+    with open_output(filepath) as file:
         data = json.load(file)
+        # Results are wrapped together with their metadata, but older files are not
+        if isinstance(data, dict):
+            data = data["results"]
     return data
 
 
@@ -69,7 +80,7 @@ def parse_and_transform_data(data):
     x_vals = []
     y_vals = []
     z_vals = []
-    for pre_ops, value in data:
+    for pre_ops, value, *_ in data:
         prefill, ops = eval(pre_ops)
         x_vals.append(prefill)
         y_vals.append(ops)
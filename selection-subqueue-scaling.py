@@ -1,5 +1,6 @@
 import argparse
 import subprocess
+import gzip
 import json
 import re
 import matplotlib.pyplot as plt
@@ -9,6 +10,13 @@ import numpy as np
 from pathlib import Path
 
 
+def open_output(path):
+    # Outputs can be gzipped, which is shown by the suffix
+    if str(path).endswith('.gz'):
+        return gzip.open(path, 'rt')
+    return open(path, 'r')
+
+
 def parse_arguments():
     parser = argparse.ArgumentParser(
         description="Run Rust tests and plot heatmap.")
@@ -63,10 +71,13 @@ def extract_file_path(output):
 
 
 def read_and_parse_data(filepath):
-    with open(filepath, 'r') as file:
+    with open_output(filepath) as file:
         data = json.load(file)
+        # Results are wrapped together with their metadata, but older files are not
+        if isinstance(data, dict):
+            data = data["results"]
         subqueue_errors = sorted(
-            [(int(p_ops_str[1:].split(',')[0]), avg) for [p_ops_str, avg] in data], key=lambda pair: pair[0])
+            [(int(p_ops_str[1:].split(',')[0]), avg) for [p_ops_str, avg, *_] in data], key=lambda pair: pair[0])
     return subqueue_errors
 
 
@@ -108,7 +119,7 @@ def main():
         # Read data from the old folder with previous json
         data_points = {}
         for file in Path(args.old_json).iterdir():
-            if file.suffix == ".json":
+            if file.name.endswith((".json", ".json.gz")):
                 data_points[file.stem.split("-")[0]] = read_and_parse_data(file)
     else:
         if not (args.operations and args.subqueues and args.prefill):
@@ -0,0 +1,162 @@
+use crate::relaxed_fifo::{InstrumentedFifo, RelaxedFifo};
+
+/// Wraps a queue to cap how many operations each sub-queue serves per epoch
+///
+/// Each epoch is a run of epoch operations, in which each sub-queue may be chosen by at most
+/// ceil(epoch / sub-queues) of them. A sub-queue at its budget is vetoed from the choices until
+/// the epoch ends, which bounds the throughput skew between the sub-queues by construction. The
+/// budgets leave room for every operation of an epoch, so some sub-queue is always allowed.
+pub struct BudgetedQueue<Q> {
+    queue: Q,
+    epoch: usize,
+    budget: usize,
+
+    /// The operations of the current epoch each sub-queue was chosen by
+    served: Vec<usize>,
+
+    /// The operations done in the current epoch
+    epoch_ops: usize,
+
+    /// The most operations any sub-queue was chosen by in one epoch
+    max_served: usize,
+}
+
+impl<Q> BudgetedQueue<Q> {
+    /// Wraps the queue with epochs of the given number of operations, which must be positive
+    pub fn new<T>(queue: Q, epoch: usize) -> Self
+    where
+        Q: InstrumentedFifo<T>,
+    {
+        assert!(epoch > 0, "An epoch must hold at least one operation");
+        let subqueues = queue.nbr_subqueues();
+        Self {
+            queue,
+            epoch,
+            budget: epoch.div_ceil(subqueues),
+            served: vec![0; subqueues],
+            epoch_ops: 0,
+            max_served: 0,
+        }
+    }
+
+    /// The operations each sub-queue may be chosen by per epoch
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// The most operations any sub-queue was chosen by in one epoch, at most the budget
+    pub fn max_served(&self) -> usize {
+        self.max_served
+    }
+
+    pub fn inner(&self) -> &Q {
+        &self.queue
+    }
+
+    pub fn into_inner(self) -> Q {
+        self.queue
+    }
+
+    /// Charges the choice of the last operation to its sub-queue, and steps the epoch
+    fn record<T>(&mut self)
+    where
+        Q: InstrumentedFifo<T>,
+    {
+        if let Some(subqueue) = self.queue.take_choice() {
+            self.served[subqueue] += 1;
+            self.max_served = self.max_served.max(self.served[subqueue]);
+            if self.served[subqueue] == self.budget {
+                self.queue.set_vetoed(subqueue, true);
+            }
+        }
+        self.epoch_ops += 1;
+        if self.epoch_ops == self.epoch {
+            self.reset_epoch::<T>();
+        }
+    }
+
+    fn reset_epoch<T>(&mut self)
+    where
+        Q: InstrumentedFifo<T>,
+    {
+        for (subqueue, served) in self.served.iter_mut().enumerate() {
+            if *served >= self.budget {
+                self.queue.set_vetoed(subqueue, false);
+            }
+            *served = 0;
+        }
+        self.epoch_ops = 0;
+    }
+}
+
+impl<T, Q: InstrumentedFifo<T>> RelaxedFifo<T> for BudgetedQueue<Q> {
+    fn enqueue(&mut self, item: T) {
+        self.queue.enqueue(item);
+        self.record::<T>();
+    }
+
+    fn dequeue(&mut self) -> Option<T> {
+        let item = self.queue.dequeue();
+        self.record::<T>();
+        item
+    }
+
+    /// Starts the first epoch with the measured operations
+    fn prefill_done(&mut self) {
+        self.queue.prefill_done();
+        self.reset_epoch::<T>();
+        self.max_served = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DChoiceQueueBuilder;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn subqueues_stay_within_their_budgets() {
+        let prefill = 50;
+        for seed in 0..10 {
+            for (subqueues, epoch, d) in [(2, 1, 2), (4, 5, 2), (7, 16, 3), (8, 33, 1)] {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let queue = DChoiceQueueBuilder::new(subqueues)
+                    .d(d)
+                    .log_choices(Some(usize::MAX))
+                    .seed(Some(seed))
+                    .build();
+                let mut budgeted = BudgetedQueue::new(queue, epoch);
+                for item in 0..prefill {
+                    budgeted.enqueue(item);
+                }
+                budgeted.prefill_done();
+                for item in prefill..prefill + 1000 {
+                    if rng.gen_bool(0.5) {
+                        budgeted.enqueue(item);
+                    } else {
+                        budgeted.dequeue();
+                    }
+                }
+
+                let budget = budgeted.budget();
+                assert_eq!(budget, epoch.div_ceil(subqueues));
+                assert!(budgeted.max_served() <= budget);
+                // Recount the choices of each epoch from the log of the wrapped queue. Fallbacks
+                // of dequeues from empty sub-queues are not choices, so they are not budgeted
+                let log = budgeted.inner().choice_log().unwrap();
+                assert_eq!(log.len(), prefill + 1000);
+                for (ind, epoch_choices) in log[prefill..].chunks(epoch).enumerate() {
+                    let mut served = vec![0; subqueues];
+                    for record in epoch_choices {
+                        served[record.chosen.unwrap()] += 1;
+                    }
+                    assert!(
+                        served.iter().all(|count| *count <= budget),
+                        "Epoch {ind} served {served:?} with budget {budget}, seed {seed}"
+                    );
+                }
+            }
+        }
+    }
+}
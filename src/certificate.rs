@@ -0,0 +1,142 @@
+use serde::Serialize;
+
+use crate::ErrorTag;
+
+/// The most operations of a run with a certificate, as it is written as json and checked in
+/// quadratic time
+pub const MAX_CERTIFICATE_OPERATIONS: usize = 100_000;
+
+/// The realized order of a run, from which any relaxation metric can be recomputed independently
+///
+/// The items are their sequence numbers, so the prefill items are 0..prefill and the enqueue
+/// order is their linearization order.
+#[derive(Clone, Debug, Serialize)]
+pub struct Certificate {
+    pub prefill: usize,
+
+    /// The operations after the prefill, true for enqueue
+    pub operations: Vec<bool>,
+
+    /// The enqueued items in linearization order, including the prefill
+    pub enqueue_order: Vec<usize>,
+
+    /// The dequeued items in order, where None marks an empty return
+    pub dequeue_order: Vec<Option<usize>>,
+}
+
+impl Certificate {
+    /// Records the run from the error tags of its dequeues, as from analyze_extra
+    pub fn new(prefill: usize, operations: &[bool], error_tags: &[ErrorTag]) -> Self {
+        let enqueues = operations.iter().filter(|is_enqueue| **is_enqueue).count();
+        Self {
+            prefill,
+            operations: operations.to_vec(),
+            enqueue_order: (0..prefill + enqueues).collect(),
+            dequeue_order: error_tags.iter().map(|tag| tag.enq_nbr()).collect(),
+        }
+    }
+
+    /// Recomputes the rank errors from the orders alone, as the number of older items in the
+    /// queue at each dequeue, or all items in the queue for empty returns
+    ///
+    /// Scans all earlier enqueues for each dequeue, as a simple check of the incremental oracle.
+    pub fn rank_errors(&self) -> Vec<usize> {
+        let mut live = vec![false; self.enqueue_order.len()];
+        let mut enqueues = self.enqueue_order.iter();
+        let mut enqueued = vec![];
+        for item in enqueues.by_ref().take(self.prefill) {
+            live[*item] = true;
+            enqueued.push(*item);
+        }
+        let mut dequeues = self.dequeue_order.iter();
+        let mut rank_errors = vec![];
+        for is_enqueue in self.operations.iter() {
+            if *is_enqueue {
+                let item = *enqueues.next().expect("Each enqueue has an item");
+                live[item] = true;
+                enqueued.push(item);
+                continue;
+            }
+            let dequeued = dequeues.next().expect("Each dequeue has an outcome");
+            let mut rank_error = 0;
+            for other in enqueued.iter() {
+                if Some(*other) == *dequeued {
+                    break;
+                }
+                if live[*other] {
+                    rank_error += 1;
+                }
+            }
+            if let Some(item) = dequeued {
+                live[*item] = false;
+            }
+            rank_errors.push(rank_error);
+        }
+        rank_errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analyze_extra, gen_balanced_ops, DChoiceQueueBuilder};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn run(seed: u64) -> (Certificate, Vec<usize>) {
+        let operations = gen_balanced_ops(2000, &mut StdRng::seed_from_u64(seed));
+        let mut queue = DChoiceQueueBuilder::new(8).seed(Some(seed)).build();
+        let error_tags = analyze_extra(&mut queue, 30, &operations, None).unwrap();
+        let rank_errors = error_tags.iter().map(ErrorTag::rank_error).collect();
+        (Certificate::new(30, &operations, &error_tags), rank_errors)
+    }
+
+    #[test]
+    fn valid_certificates_verify() {
+        for seed in 0..5 {
+            let (certificate, rank_errors) = run(seed);
+            assert_eq!(certificate.rank_errors(), rank_errors, "Seed {seed}");
+        }
+    }
+
+    #[test]
+    fn tampered_certificates_are_rejected() {
+        let (certificate, rank_errors) = run(1);
+
+        // Two dequeued items swapped
+        let (first, second) = (0..certificate.dequeue_order.len())
+            .flat_map(|i| (i + 1..certificate.dequeue_order.len()).map(move |j| (i, j)))
+            .find(|&(i, j)| {
+                let order = &certificate.dequeue_order;
+                order[i].is_some() && order[j].is_some() && rank_errors[i] != rank_errors[j]
+            })
+            .unwrap();
+        let mut swapped = certificate.clone();
+        swapped.dequeue_order.swap(first, second);
+        assert_ne!(swapped.rank_errors(), rank_errors);
+
+        // An item turned into an empty return
+        let mut emptied = certificate.clone();
+        let dequeue = (0..rank_errors.len())
+            .find(|&i| emptied.dequeue_order[i].is_some() && rank_errors[i] > 0)
+            .unwrap();
+        emptied.dequeue_order[dequeue] = None;
+        assert_ne!(emptied.rank_errors(), rank_errors);
+
+        // The enqueues reordered
+        let mut reordered = certificate;
+        reordered.enqueue_order.swap(0, 1);
+        assert_ne!(reordered.rank_errors(), rank_errors);
+    }
+
+    #[test]
+    fn rank_errors_count_the_older_live_items() {
+        // Prefill 0 and 1, then enqueue 2, dequeue 1, dequeue 2, dequeue 0 and an empty return
+        let certificate = Certificate {
+            prefill: 2,
+            operations: vec![true, false, false, false, false],
+            enqueue_order: vec![0, 1, 2],
+            dequeue_order: vec![Some(1), Some(2), Some(0), None],
+        };
+        assert_eq!(certificate.rank_errors(), [1, 1, 0, 0]);
+    }
+}
@@ -0,0 +1,7018 @@
+use std::{
+    collections::{BTreeMap, BinaryHeap, HashSet},
+    ffi::OsString,
+    fs::{create_dir_all, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    analyze_batched, analyze_bounded, analyze_choice_impact, analyze_concurrent,
+    analyze_distributions, analyze_exhaustive, analyze_extra, analyze_from_steady_state,
+    analyze_item_fates, analyze_logged, analyze_minmax_gap, analyze_online, analyze_per_thread,
+    analyze_phases, analyze_prefill_flush, analyze_recovery, analyze_simple,
+    analyze_simple_checked, analyze_skewed_prefill, analyze_streaming, analyze_with_barriers,
+    analyze_with_hook, compare_sweep_outputs, dequeue_relative_errors, derive_seed,
+    describe_operations, diagnose_point, estimate_run_memory, evaluate_heuristic, find_preset,
+    fit_power_law, format_choice_trace, load_results, median, merge_sweep_outputs,
+    normalize_rank_errors, pareto_front, registered_queue, registered_queue_names, summarize_runs,
+    windowed_mean_errors, AdaptiveD, AnalysisError, Blackout, BudgetedQueue, CandidateFilter,
+    Certificate, ConcurrentConfig, CostReport, CounterGaps, DChoiceQueue, DChoiceQueueBuilder,
+    DiagnosticThresholds, DistributionOptions, EnqueueFailures, ErrorSink, ErrorTag,
+    ExhaustiveConfig, HeuristicEvalConfig, InstrumentedFifo, KFifo, LatencyModel, LogHistogram,
+    LogRecord, OpKind, OpOutcome, OpSource, OpsDescription, PhaseSummary, PointRuns, PointWarning,
+    PrefillFlush, Preset, QueueFactory, RankErrorHeatmap, RelaxedFifo, ResultFile, RunLogReader,
+    RunSummary, SeedStream, ServiceWeights, SteadyState, SubqueueAges, SweepRow, ThreadSummary,
+    VirtualTime, MAX_ANALYSIS_ITEMS, MAX_CERTIFICATE_OPERATIONS, PRESETS, SCHEMA_VERSION,
+    SEED_SCHEME,
+};
+use chrono::Local;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use rand::{
+    distributions::WeightedIndex, prelude::Distribution, rngs::StdRng, seq::SliceRandom,
+    thread_rng, Rng, SeedableRng,
+};
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Parser, Debug)]
+#[command(version, about, args_override_self = true)]
+struct Cli {
+    #[command(subcommand)]
+    test: Test,
+
+    /// Run a named configuration, expanded into its subcommand and arguments, which the arguments
+    /// after it override. The expansion is written to the metadata of the output
+    #[arg(long)]
+    preset: Option<String>,
+
+    /// List the presets with their expansions and exit
+    #[arg(long)]
+    list_presets: bool,
+
+    /// Cap the operations, prefill, runs and sweep points to tiny values, keeping the structure of
+    /// the test, so that it finishes quickly. Marked as "smoke" in the metadata of the output
+    #[arg(long, global = true)]
+    smoke: bool,
+
+    #[command(flatten)]
+    output: OutputOptions,
+
+    /// Seeds all randomness of the test, which is otherwise seeded at random. The seed is written
+    /// to the metadata of the output, so that the test can be replayed
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+}
+
+/// Where and how the output files are written
+#[derive(Args, Clone, Debug)]
+struct OutputOptions {
+    /// Gzip the output files, which is also done if the output name ends in .gz
+    #[arg(long, global = true)]
+    compress: bool,
+
+    /// The directory to write the output files to, which is created if missing. Defaults to
+    /// results, except that replay writes next to the file it replays
+    #[arg(long, global = true)]
+    output_dir: Option<PathBuf>,
+
+    /// Leave the datetime out of the output file names, so that a test always writes to the same
+    /// path, overwriting earlier outputs
+    #[arg(long, global = true)]
+    no_timestamp: bool,
+}
+
+#[derive(Subcommand, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Test {
+    /// Runs a single test
+    Single {
+        /// The queue configuration to use
+        #[command(flatten)]
+        queue: QueueArg,
+
+        /// The number of operations to run
+        #[arg(short, long = "ops", required_unless_present_any = ["enqueues", "phases"])]
+        operations: Option<usize>,
+
+        /// Instead run exactly this many enqueues, randomly shuffled with the dequeues
+        #[arg(long, requires = "dequeues", conflicts_with_all = ["operations", "ops_pattern", "threads"])]
+        enqueues: Option<usize>,
+
+        /// The number of dequeues to run together with the enqueues
+        #[arg(long, requires = "enqueues")]
+        dequeues: Option<usize>,
+
+        /// Instead run three phases given as ramp:steady:drain, of only enqueues, then operations
+        /// from the distribution, then only dequeues, and report the rank errors of each phase
+        #[arg(long, value_parser = parse_phases, conflicts_with_all = ["operations", "enqueues", "threads", "counter_gaps"])]
+        phases: Option<Phases>,
+
+        /// The number of initial items in the queue before starting the experiment
+        #[arg(short = 'i', long)]
+        prefill: usize,
+
+        /// How to generate the operations
+        #[command(flatten)]
+        workload: OpsConfig,
+
+        /// How to readout the rank error from a single simulation
+        #[arg(value_enum, long = "readout", default_value_t = ErrorReadout::Average)]
+        error_readout: ErrorReadout,
+
+        /// Writes all rank errors to this file, as csv, json or bin depending on the extension
+        #[arg(long)]
+        dump_errors: Option<PathBuf>,
+
+        /// Dump the full records of each dequeue, instead of only the rank errors
+        #[arg(long, requires = "dump_errors")]
+        extra: bool,
+
+        /// Print the items left in each sub-queue after the run, and write them to the FinalState
+        /// json output
+        #[arg(long)]
+        final_state: bool,
+
+        /// Print the most items each sub-queue held at once, and the largest of them
+        #[arg(long)]
+        memory_stats: bool,
+
+        /// Print the spread of the sub-queue operation counters every this many operations, as json
+        #[arg(long, conflicts_with_all = ["extra", "threads"])]
+        counter_gaps: Option<usize>,
+
+        /// Rebalance the sub-queues in enqueue order every this many operations, as a global
+        /// synchronization barrier, and print the items moved per sync and the mean rank error of
+        /// each window of operations, as json
+        #[arg(long, conflicts_with_all = ["extra", "threads", "counter_gaps", "split_fallback", "trace_stdout", "choice_impact", "certificate", "phases"])]
+        sync_every: Option<usize>,
+
+        /// The number of operations in each window of the mean rank errors printed by --sync-every
+        #[arg(long, default_value_t = 100, requires = "sync_every")]
+        sync_window: usize,
+
+        /// Follow each item with this probability, and print where it was enqueued, its dequeue
+        /// and how long it waited, or where it was left if never dequeued, as json
+        #[arg(long, conflicts_with_all = ["counter_gaps", "sync_every", "external_queue", "full_log", "with_without_fallback"])]
+        sample_items: Option<f64>,
+
+        /// Also run the queue without the empty dequeue fallback, with the same seeds, and print the
+        /// rank errors of both runs and how many dequeues returned a different item, as json
+        #[arg(long, conflicts_with_all = ["extra", "threads", "phases", "counter_gaps", "sync_every", "split_fallback", "trace_stdout", "choice_impact", "certificate", "external_queue", "full_log", "dump_errors", "final_state"])]
+        with_without_fallback: bool,
+
+        /// Also run the operations as generated before --deq-priority moved their dequeues, with
+        /// the same seeds, and print the rank errors of both runs and how many dequeues moved, as
+        /// json
+        #[arg(long, requires = "deq_priority", conflicts_with_all = ["extra", "threads", "phases", "counter_gaps", "sync_every", "split_fallback", "trace_stdout", "choice_impact", "certificate", "external_queue", "full_log", "dump_errors", "final_state", "with_without_fallback", "sample_items"])]
+        with_without_priority: bool,
+
+        /// Writes a binary record of every operation to this file, optionally gzipped if it ends
+        /// in .gz, with a json sidecar describing it at "{path}.json", see LogRecord and log-stats
+        #[arg(long, conflicts_with_all = ["extra", "threads", "counter_gaps", "sync_every", "split_fallback", "trace_stdout", "choice_impact", "certificate", "phases", "external_queue"])]
+        full_log: Option<PathBuf>,
+
+        /// Which definition of rank error to read out and dump
+        #[arg(value_enum, long, default_value_t = ErrorDefinition::EnqueueRelative)]
+        error_def: ErrorDefinition,
+
+        /// Also print the rank errors of dequeues that fell back to the round-robin scan separately
+        #[arg(long, conflicts_with = "counter_gaps")]
+        split_fallback: bool,
+
+        /// Print a line for each of the first n operations, with the sampled sub-queues and their
+        /// scores, the chosen one and the rank error of dequeues
+        #[arg(long, conflicts_with = "counter_gaps")]
+        trace_stdout: Option<usize>,
+
+        /// Print how often sampling more than the first sub-queue changed the choices, and how
+        /// much it improved the rank errors of the changed dequeues, as a table per operation kind
+        #[arg(long, conflicts_with = "counter_gaps")]
+        choice_impact: bool,
+
+        /// Write the enqueue order and the dequeue order with its empty returns to this file, as
+        /// json, so that the rank errors can be recomputed independently. Only for runs of at most
+        /// MAX_CERTIFICATE_OPERATIONS operations
+        #[arg(long, conflicts_with = "counter_gaps")]
+        certificate: Option<PathBuf>,
+
+        /// Recompute the rank errors from the certificate by a simple quadratic scan, and exit with
+        /// an error if they differ from those of the run
+        #[arg(long, requires = "certificate")]
+        check_certificate: bool,
+
+        /// Seed the queue with exactly this instead of deriving its seed, such as a queue seed from
+        /// the run seeds of a sweep, to reproduce that run. Also seeds the operations, unless
+        /// given --exact-ops-seed
+        #[arg(long)]
+        exact_seed: Option<u64>,
+
+        /// Seed the operations with exactly this, such as the operations seed of a sweep run
+        #[arg(long, requires = "exact_seed")]
+        exact_ops_seed: Option<u64>,
+
+        /// Run a queue registered with register_queue by a binary wrapping this one, given as
+        /// external:<name>, instead of the d-RA queue. Only supports the default readouts and
+        /// error definition
+        #[arg(long = "queue", value_parser = parse_external_queue, conflicts_with_all = ["extra", "final_state", "counter_gaps", "split_fallback", "trace_stdout", "choice_impact", "certificate", "threads", "max_items", "enqueue_batch", "dequeue_batch", "budget_epoch", "phases", "memory_stats", "sync_every"])]
+        external_queue: Option<String>,
+
+        /// Approximate the run by splitting the operations into this many chunks and simulating
+        /// them in parallel, where each chunk after the first starts from the balanced steady state
+        /// of the strict queue size and dequeues at its start, and discards the rank errors of its
+        /// warmup. Only supports the rank error readouts and the default error definition
+        #[arg(long, conflicts_with_all = ["extra", "final_state", "memory_stats", "counter_gaps", "sync_every", "sample_items", "with_without_fallback", "with_without_priority", "full_log", "split_fallback", "trace_stdout", "choice_impact", "certificate", "phases", "external_queue", "threads", "max_items", "enqueue_batch", "dequeue_batch", "budget_epoch", "dump_errors"])]
+        chunked: Option<usize>,
+
+        /// The operations before each chunk boundary which the chunk starts at to warm up, and
+        /// whose rank errors it discards. Defaults to twice the strict queue size at the boundary
+        /// plus 100 per sub-queue
+        #[arg(long, requires = "chunked")]
+        chunk_warmup: Option<usize>,
+    },
+
+    /// Performsrmany tests for a queue, for combinations of operations and prefill
+    OpsAndPrefill {
+        /// The queue configuration to use
+        #[command(flatten)]
+        queue: QueueArg,
+
+        /// Instead run the full sweep for each of these queue kinds, where each kind sets the
+        /// heuristic, with the same seeds and operations. The kind prefixes the keys, and is
+        /// added as a queue column
+        #[arg(long, value_enum, value_delimiter = ' ', num_args = 1.., action = ArgAction::Set, conflicts_with = "fit")]
+        kinds: Vec<QueueKind>,
+
+        /// Run a queue registered with register_queue by a binary wrapping this one, given as
+        /// external:<name>, instead of the d-RA queue, which then only sets the sub-queues. Only
+        /// supports the rank error readouts, without a histogram
+        #[arg(long = "queue", value_parser = parse_external_queue, conflicts_with_all = ["kinds", "histogram"])]
+        external_queue: Option<String>,
+
+        /// The number of operations
+        #[arg(short, long = "ops", value_delimiter = ' ', num_args = 1.., action = ArgAction::Set, required_unless_present_any = ["enqueues", "ops_per_prefill"])]
+        operations: Vec<usize>,
+
+        /// Instead run round(x * prefill) operations for each prefill, but at least 2
+        #[arg(long, conflicts_with_all = ["operations", "enqueues"])]
+        ops_per_prefill: Option<f64>,
+
+        /// Instead run exactly these numbers of enqueues, paired up with the dequeues
+        #[arg(long, value_delimiter = ' ', num_args = 1.., action = ArgAction::Set, requires = "dequeues", conflicts_with_all = ["operations", "ops_pattern", "threads"])]
+        enqueues: Vec<usize>,
+
+        /// The numbers of dequeues, paired up with the enqueues
+        #[arg(long, value_delimiter = ' ', num_args = 1.., action = ArgAction::Set, requires = "enqueues")]
+        dequeues: Vec<usize>,
+
+        /// The number of initial items in the queue before starting the experiment
+        #[arg(short = 'i', long, value_delimiter = ' ', num_args = 1.., action = ArgAction::Set)]
+        prefill: Vec<usize>,
+
+        /// How to generate the operations
+        #[command(flatten)]
+        workload: OpsConfig,
+
+        /// The name of the output json file, ends up at "{output_dir}/{output_name}-{datetime}.json"
+        #[arg(long, default_value_t = format!("OpsAndPrefill"))]
+        output_name: String,
+
+        /// The number of runs to average over for each data point
+        #[arg(short, long, default_value_t = 1, value_parser = parse_runs)]
+        runs: usize,
+
+        /// How to readout the rank error from a single simulation
+        #[arg(value_enum, long = "readout", default_value_t = ErrorReadout::Average)]
+        error_readout: ErrorReadout,
+
+        /// Stream the rank errors into a log-bucketed histogram with this many significant bits,
+        /// instead of storing them. The readout is then within a relative error of 2^(1 - bits),
+        /// and the histogram merged over the runs is written with each json point
+        #[arg(long)]
+        histogram: Option<u32>,
+
+        /// How to combine the readouts of the runs into the value of each data point
+        #[arg(value_enum, long, default_value_t = Aggregate::MeanOfReadouts)]
+        aggregate: Aggregate,
+
+        /// The file format of the results
+        #[arg(value_enum, long, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
+
+        /// The threshold and window of the recovery readout
+        #[command(flatten)]
+        recovery: RecoveryConfig,
+
+        /// When to warn that a point is likely noise
+        #[command(flatten)]
+        diagnostics: DiagnosticsConfig,
+
+        /// Limits on the concurrently executing runs
+        #[command(flatten)]
+        limits: RunLimits,
+
+        /// Also fit error = c * x^a over this sweep dimension, for each combination of the others
+        #[arg(value_enum, long)]
+        fit: Option<FitDimension>,
+
+        /// Also describe the generated operations of each point in the metadata, as describe-ops
+        #[arg(long)]
+        describe_ops: bool,
+    },
+
+    /// Tests all combinations of sub-queues and prefill
+    SubqueuesAndPrefill {
+        /// The queue configuration to use
+        #[command(flatten)]
+        queue: QueueConfig,
+
+        /// Instead run the full sweep for each of these queue kinds, where each kind sets the
+        /// heuristic, with the same seeds and operations. The kind prefixes the keys, and is
+        /// added as a queue column
+        #[arg(long, value_enum, value_delimiter = ' ', num_args = 1.., action = ArgAction::Set, conflicts_with = "fit")]
+        kinds: Vec<QueueKind>,
+
+        /// Run a queue registered with register_queue by a binary wrapping this one, given as
+        /// external:<name>, instead of the d-RA queue, which then only sets the sub-queues. Only
+        /// supports the rank error readouts, without a histogram
+        #[arg(long = "queue", value_parser = parse_external_queue, conflicts_with_all = ["kinds", "histogram"])]
+        external_queue: Option<String>,
+
+        /// The number of operations to run
+        #[arg(short, long = "ops", required_unless_present_any = ["enqueues", "ops_per_prefill"])]
+        operations: Option<usize>,
+
+        /// Instead run round(x * prefill) operations for each prefill, but at least 2
+        #[arg(long, conflicts_with_all = ["operations", "enqueues"])]
+        ops_per_prefill: Option<f64>,
+
+        /// Instead run exactly this many enqueues, randomly shuffled with the dequeues
+        #[arg(long, requires = "dequeues", conflicts_with_all = ["operations", "ops_pattern", "threads"])]
+        enqueues: Option<usize>,
+
+        /// The number of dequeues to run together with the enqueues
+        #[arg(long, requires = "enqueues")]
+        dequeues: Option<usize>,
+
+        /// All subqueue configurations to test
+        #[arg(short, value_delimiter = ' ', num_args = 1.., action = ArgAction::Set)]
+        subqueues: Vec<usize>,
+
+        /// The number of initial items in the queue before starting the experiment
+        #[arg(short = 'i', long, value_delimiter = ' ', num_args = 1.., action = ArgAction::Set)]
+        prefill: Vec<usize>,
+
+        /// How to generate the operations
+        #[command(flatten)]
+        workload: OpsConfig,
+
+        /// The name of the output json file, ends up at "{output_dir}/{output_name}-{datetime}.json"
+        #[arg(long, default_value_t = format!("SubqueuesAndPrefill"))]
+        output_name: String,
+
+        /// The number of runs to average over for each data point
+        #[arg(short, long, default_value_t = 1, value_parser = parse_runs)]
+        runs: usize,
+
+        /// How to readout the rank error from a single simulation
+        #[arg(value_enum, long = "readout", default_value_t = ErrorReadout::Average)]
+        error_readout: ErrorReadout,
+
+        /// Stream the rank errors into a log-bucketed histogram with this many significant bits,
+        /// instead of storing them. The readout is then within a relative error of 2^(1 - bits),
+        /// and the histogram merged over the runs is written with each json point
+        #[arg(long)]
+        histogram: Option<u32>,
+
+        /// How to combine the readouts of the runs into the value of each data point
+        #[arg(value_enum, long, default_value_t = Aggregate::MeanOfReadouts)]
+        aggregate: Aggregate,
+
+        /// The file format of the results
+        #[arg(value_enum, long, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
+
+        /// The threshold and window of the recovery readout
+        #[command(flatten)]
+        recovery: RecoveryConfig,
+
+        /// When to warn that a point is likely noise
+        #[command(flatten)]
+        diagnostics: DiagnosticsConfig,
+
+        /// Limits on the concurrently executing runs
+        #[command(flatten)]
+        limits: RunLimits,
+
+        /// Also fit error = c * x^a over this sweep dimension, for each combination of the others
+        #[arg(value_enum, long)]
+        fit: Option<FitDimension>,
+
+        /// Also describe the generated operations of each point in the metadata, as describe-ops
+        #[arg(long)]
+        describe_ops: bool,
+    },
+
+    Distributions {
+        /// The queue configuration to use
+        #[command(flatten)]
+        queue: QueueArg,
+
+        /// The number of operations to run
+        #[arg(short, long = "ops", required_unless_present_any = ["enqueues", "phases"])]
+        operations: Option<usize>,
+
+        /// Instead run exactly this many enqueues, randomly shuffled with the dequeues
+        #[arg(long, requires = "dequeues", conflicts_with_all = ["operations", "ops_pattern", "threads"])]
+        enqueues: Option<usize>,
+
+        /// The number of dequeues to run together with the enqueues
+        #[arg(long, requires = "enqueues")]
+        dequeues: Option<usize>,
+
+        /// Instead run three phases given as ramp:steady:drain, of only enqueues, then operations
+        /// from the distribution, then only dequeues, and report the rank errors of each phase
+        #[arg(long, value_parser = parse_phases, conflicts_with_all = ["operations", "enqueues", "threads"])]
+        phases: Option<Phases>,
+
+        /// The number of initial items in the queue before starting the experiment
+        #[arg(short = 'i', long)]
+        prefill: usize,
+
+        /// How to generate the operations
+        #[command(flatten)]
+        workload: OpsConfig,
+
+        /// The name of the output json file, ends up at "{output_dir}/{output_name}-{datetime}.json"
+        #[arg(long, default_value_t = format!("Distributions"))]
+        output_name: String,
+
+        /// The number of runs to average over for each data point
+        #[arg(short, long, default_value_t = 1, value_parser = parse_runs)]
+        runs: usize,
+
+        /// Also output a heatmap of rank errors over operation time
+        #[arg(long)]
+        heatmap: bool,
+
+        /// The number of operation-time buckets in the heatmap
+        #[arg(long, default_value_t = 100, value_parser = parse_heatmap_op_buckets)]
+        heatmap_op_buckets: usize,
+
+        /// The number of logarithmic rank error buckets in the heatmap, at most 65 on 64-bit
+        /// targets
+        #[arg(long, default_value_t = 24, value_parser = parse_heatmap_error_buckets)]
+        heatmap_error_buckets: usize,
+
+        /// Also output the rank errors of prefill items and of enqueued items separately
+        #[arg(long)]
+        tag_prefill: bool,
+
+        /// Also output the rank errors of dequeues that fell back to the round-robin scan
+        /// separately, with the fraction of such dequeues in each run
+        #[arg(long)]
+        split_fallback: bool,
+
+        /// Also output the mean and p99 age of the items each sub-queue served, and the correlation
+        /// of the ages with the load rank of the serving sub-queues
+        #[arg(long)]
+        age_by_subqueue: bool,
+
+        /// Also output the most items each sub-queue held at once per run, and the largest of them
+        #[arg(long)]
+        memory_stats: bool,
+
+        /// Also split the rank errors into placement errors, which even the best choice among the
+        /// sampled sub-queue fronts would have, and the selection errors of the actual choices.
+        /// Peeks at every sub-queue for each dequeue, so best used with few sub-queues and
+        /// operations
+        #[arg(long)]
+        decompose: bool,
+
+        /// Also output how often sampling more than the first sub-queue changed the choices, and
+        /// how much it improved the rank errors of the changed dequeues, per run
+        #[arg(long)]
+        choice_impact: bool,
+
+        /// Also output the sorted score spreads of the candidates of each dequeue, and the mean
+        /// rank error of each decile of the spreads, per run
+        #[arg(long)]
+        conditional_spread: bool,
+
+        /// Do not run the random extra operations which decorrelate the final sub-queue loads
+        /// between the runs, so a seeded run only does the requested operations
+        #[arg(long)]
+        no_decorrelate: bool,
+
+        /// Also output the steady-state rank errors, without the transient burn-in dequeues and
+        /// thinned to reduce their autocorrelation, with a summary per run including the
+        /// effective sample size
+        #[arg(long)]
+        stationary: bool,
+
+        /// The number of burn-in dequeues to discard, instead of detecting it by comparing the
+        /// early and late mean rank errors
+        #[arg(long, requires = "stationary")]
+        burn_in: Option<usize>,
+
+        /// Only keep every k-th steady-state rank error
+        #[arg(long, default_value_t = 10, requires = "stationary")]
+        thinning: usize,
+
+        /// Scale the rank errors of each run before averaging them, so that configurations of
+        /// different sizes can be compared. The other distributions are left as they are
+        #[arg(long, value_enum, default_value_t = Normalization::None)]
+        normalize: Normalization,
+
+        /// Resample the sorted distributions of each run to this many evenly spaced quantiles
+        /// before banding them over the runs, to shrink the output and line up runs of different
+        /// lengths. Each distribution is written as its mean, min, max and std over the runs
+        #[arg(long)]
+        pdf_samples: Option<usize>,
+    },
+
+    /// Compares several queue kinds on the same operation sequences, as a table
+    Matrix {
+        /// The queue configuration shared by all kinds, where each kind sets the heuristic
+        #[command(flatten)]
+        queue: QueueArg,
+
+        /// The queue kinds to compare
+        #[arg(long, value_enum, value_delimiter = ' ', num_args = 1.., action = ArgAction::Set, default_values_t = [QueueKind::Operation, QueueKind::Length, QueueKind::Strict])]
+        kinds: Vec<QueueKind>,
+
+        /// Also compare k-FIFO queues with segments of each of these sizes, which do not use the
+        /// queue configuration
+        #[arg(long, value_delimiter = ' ', num_args = 1.., action = ArgAction::Set)]
+        k_fifo: Vec<usize>,
+
+        /// The number of operations to run
+        #[arg(short, long = "ops", required_unless_present = "enqueues")]
+        operations: Option<usize>,
+
+        /// Instead run exactly this many enqueues, randomly shuffled with the dequeues
+        #[arg(long, requires = "dequeues", conflicts_with_all = ["operations", "ops_pattern", "threads"])]
+        enqueues: Option<usize>,
+
+        /// The number of dequeues to run together with the enqueues
+        #[arg(long, requires = "enqueues")]
+        dequeues: Option<usize>,
+
+        /// The number of initial items in the queue before starting the experiment
+        #[arg(short = 'i', long)]
+        prefill: usize,
+
+        /// How to generate the operations
+        #[command(flatten)]
+        workload: OpsConfig,
+
+        /// The name of the output json file, ends up at "{output_dir}/{output_name}-{datetime}.json"
+        #[arg(long, default_value_t = format!("Matrix"))]
+        output_name: String,
+
+        /// The number of runs to pool for each queue kind
+        #[arg(short, long, default_value_t = 1, value_parser = parse_runs)]
+        runs: usize,
+    },
+
+    /// Runs the operations on concurrent threads of different speeds, in a discrete-event
+    /// simulation where each thread chooses its sub-queue at the start of an operation
+    Concurrent {
+        /// The queue configuration to use
+        #[command(flatten)]
+        queue: QueueArg,
+
+        /// The number of operations to run
+        #[arg(short, long = "ops")]
+        operations: usize,
+
+        /// The number of initial items in the queue before starting the experiment
+        #[arg(short = 'i', long)]
+        prefill: usize,
+
+        /// How to generate the operations, which the threads take in order
+        #[command(flatten)]
+        workload: OpsConfig,
+
+        /// The number of threads running the operations
+        #[arg(long = "threads", default_value_t = 4)]
+        thread_count: usize,
+
+        /// How many times longer the operations of the slowest thread take than of the fastest,
+        /// with the thread speeds spread geometrically between them
+        #[arg(long, default_value_t = 1.0)]
+        speed_spread: f64,
+
+        /// How to readout the rank error from a single simulation
+        #[arg(value_enum, long = "readout", default_value_t = ErrorReadout::Average)]
+        error_readout: ErrorReadout,
+
+        /// The number of runs to average over
+        #[arg(short, long, default_value_t = 1, value_parser = parse_runs)]
+        runs: usize,
+    },
+
+    /// Finds the worst rank error over all possible executions of a tiny configuration
+    Exhaustive {
+        /// The number of sub-queues to use
+        #[arg(short, long)]
+        subqueues: usize,
+
+        /// The number of subqueues to sample for each operation (d).
+        #[arg(short = 'd', long, default_value_t = 2)]
+        sample_nbr: usize,
+
+        /// What sampling heuristic to use
+        #[arg(value_enum, long, default_value_t = Heuristic::Operation)]
+        heuristic: Heuristic,
+
+        /// Do not sample the same sub-queue twice in one operation
+        #[arg(long)]
+        uniques: bool,
+
+        /// The number of initial items, enqueued by the heuristic before the operations
+        #[arg(short = 'i', long, default_value_t = 0)]
+        prefill: usize,
+
+        /// The enqueues (E) and dequeues (D) to run
+        #[arg(long)]
+        ops_pattern: String,
+
+        /// Give up after exploring this many distinct queue states
+        #[arg(long, default_value_t = 1_000_000)]
+        max_states: usize,
+    },
+
+    /// Evaluates the choices of a heuristic on synthetic sub-queue counters, against the optimal
+    /// sampled sub-queue, without running a queue
+    EvalHeuristic {
+        /// The number of sub-queues to use
+        #[arg(short, long, default_value_t = 64)]
+        subqueues: usize,
+
+        /// The number of subqueues to sample for each operation (d).
+        #[arg(short = 'd', long, default_value_t = 2)]
+        sample_nbr: usize,
+
+        /// What sampling heuristic to use, which must choose by the counters
+        #[arg(value_enum, long, default_value_t = Heuristic::Operation)]
+        heuristic: Heuristic,
+
+        /// The temperature of the softmax heuristic
+        #[arg(long, default_value_t = 1.0)]
+        temperature: f64,
+
+        /// How the counters of the sub-queues lag behind the furthest one
+        #[arg(value_enum, long, default_value_t = GapDistribution::Uniform)]
+        gaps: GapDistribution,
+
+        /// The largest uniform gap, the scale of the heavy-tailed gaps, or the lag of the straggler
+        #[arg(long, default_value_t = 16)]
+        gap: usize,
+
+        /// The tail index of the heavy-tailed gaps, where lower is heavier
+        #[arg(long, default_value_t = 1.5)]
+        alpha: f64,
+
+        /// How many items the furthest tail is ahead of the furthest head
+        #[arg(long, default_value_t = 100)]
+        length: usize,
+
+        /// The number of synthetic counter states to draw
+        #[arg(long, default_value_t = 1000)]
+        states: usize,
+
+        /// The number of candidate sets to sample in each state, per operation kind
+        #[arg(long, default_value_t = 100)]
+        samples: usize,
+
+        /// The name of the output json file, ends up at "{output_dir}/{output_name}-{datetime}.json"
+        #[arg(long, default_value_t = format!("EvalHeuristic"))]
+        output_name: String,
+    },
+
+    /// Generates an operation sequence, or loads it from a run log, and describes its realized
+    /// statistics without running a queue
+    DescribeOps {
+        /// The number of operations to generate
+        #[arg(short, long = "ops", required_unless_present_any = ["enqueues", "phases", "from_log"])]
+        operations: Option<usize>,
+
+        /// Instead generate exactly this many enqueues, randomly shuffled with the dequeues
+        #[arg(long, requires = "dequeues", conflicts_with_all = ["operations", "ops_pattern", "threads"])]
+        enqueues: Option<usize>,
+
+        /// The number of dequeues to generate together with the enqueues
+        #[arg(long, requires = "enqueues")]
+        dequeues: Option<usize>,
+
+        /// Instead generate three phases given as ramp:steady:drain, of only enqueues, then
+        /// operations from the distribution, then only dequeues
+        #[arg(long, value_parser = parse_phases, conflicts_with_all = ["operations", "enqueues", "threads"])]
+        phases: Option<Phases>,
+
+        /// The number of initial items in the queue before the operations
+        #[arg(short = 'i', long, required_unless_present = "from_log")]
+        prefill: Option<usize>,
+
+        /// How to generate the operations
+        #[command(flatten)]
+        workload: OpsConfig,
+
+        /// Instead load the operations and prefill of a run log, which can be gzipped
+        #[arg(long, conflicts_with_all = ["operations", "enqueues", "phases", "prefill"])]
+        from_log: Option<PathBuf>,
+
+        /// The name of the output json file, ends up at "{output_dir}/{output_name}-{datetime}.json"
+        #[arg(long, default_value_t = format!("DescribeOps"))]
+        output_name: String,
+    },
+
+    /// Finds the Pareto front of mean rank error against cost per operation over sweep results
+    Pareto {
+        /// The json sweep result files, which can be gzipped, with the cost of each point
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// The name of the output json file, ends up at "{output_dir}/{output_name}-{datetime}.json"
+        #[arg(long, default_value_t = format!("Pareto"))]
+        output_name: String,
+    },
+
+    /// Merges json sweep results of the same configuration, such as from several machines
+    Merge {
+        /// The json sweep result files, which can be gzipped
+        #[arg(required = true, num_args = 2..)]
+        files: Vec<PathBuf>,
+
+        /// The name of the output json file, ends up at "{output_dir}/{output_name}-{datetime}.json"
+        #[arg(long, default_value_t = format!("Merged"))]
+        output_name: String,
+    },
+
+    /// Reruns the sweep of a json output with its seed, checking that each point reproduces
+    Replay {
+        /// The json sweep output to replay, which can be gzipped
+        file: PathBuf,
+
+        /// The largest relative difference allowed between a replayed number and the original
+        #[arg(long, default_value_t = 0.0)]
+        tolerance: f64,
+    },
+
+    /// Recomputes the readouts from a run log written by single --full-log, to check that it
+    /// round-trips
+    LogStats {
+        /// The run log, which can be gzipped, with its json sidecar at "{file}.json"
+        file: PathBuf,
+
+        /// How to readout the rank error from the logged dequeues
+        #[arg(value_enum, long = "readout", default_value_t = ErrorReadout::Average)]
+        error_readout: ErrorReadout,
+    },
+
+    /// So far just does a single run, would like more
+    MinMaxGaps {
+        /// The number of operations to run
+        #[arg(short, long = "ops")]
+        operations: usize,
+
+        /// All numbers of bins to use
+        #[arg(short, long)]
+        bins: Vec<usize>,
+
+        /// The number of subqueues to sample for each operation (d).
+        #[arg(short = 'd', long, default_value_t = 2)]
+        sample_nbr: usize,
+
+        /// The name of the output json file, ends up at "{output_dir}/{output_name}-{datetime}.json"
+        #[arg(long, default_value_t = format!("MinMaxGaps"))]
+        output_name: String,
+        // /// The number of runs to average over for each data point
+        // #[arg(short, long, default_value_t = 1)]
+        // runs: usize,
+    },
+}
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+struct QueueArg {
+    /// The number of sub-queues to use
+    #[arg(short, long)]
+    subqueues: usize,
+
+    /// Further config about how the queues works
+    #[command(flatten)]
+    config: QueueConfig,
+}
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+struct RecoveryConfig {
+    /// The fraction of the initial windowed mean rank error the recovery readout waits for
+    #[arg(long, default_value_t = 0.1)]
+    recovery_threshold: f32,
+
+    /// The number of operations in each window of the recovery readout
+    #[arg(long, default_value_t = 1000)]
+    recovery_window: usize,
+}
+
+impl RecoveryConfig {
+    /// Exits the program if the recovery readout is used with an invalid threshold or window
+    fn assert_valid(&self, readout: ErrorReadout) {
+        if readout != ErrorReadout::Recovery {
+            return;
+        }
+        if !(self.recovery_threshold > 0.0 && self.recovery_threshold < 1.0) {
+            eprintln!("The recovery threshold must be between 0 and 1. Exiting program.");
+            process::exit(1);
+        }
+        if self.recovery_window == 0 {
+            eprintln!("The recovery window must be positive. Exiting program.");
+            process::exit(1);
+        }
+    }
+}
+
+/// The thresholds of the warnings about statistically meaningless sweep points
+#[derive(Args, Debug, Serialize, Deserialize)]
+struct DiagnosticsConfig {
+    /// Warn about points whose run values have a coefficient of variation (std / |mean|) above
+    /// this
+    #[arg(long, default_value_t = 0.1)]
+    max_cv: f64,
+
+    /// Warn about tail readouts read from fewer than this many rank errors, such as the worst
+    /// one percent of 5000 dequeues
+    #[arg(long, default_value_t = 50)]
+    min_tail_samples: usize,
+
+    /// Warn about points where more than this fraction of the dequeues returned empty
+    #[arg(long, default_value_t = 0.05)]
+    max_empty_fraction: f64,
+}
+
+impl DiagnosticsConfig {
+    fn thresholds(&self) -> DiagnosticThresholds {
+        DiagnosticThresholds {
+            max_cv: self.max_cv,
+            min_tail_samples: self.min_tail_samples,
+            max_empty_fraction: self.max_empty_fraction,
+        }
+    }
+}
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+struct RunLimits {
+    /// The maximum number of runs to execute at once, independent of the rayon thread count
+    #[arg(long)]
+    max_concurrent_runs: Option<usize>,
+
+    /// Warn if the estimated peak memory of the concurrent runs exceeds this many MiB
+    #[arg(long)]
+    memory_budget: Option<usize>,
+}
+
+impl RunLimits {
+    /// Prints the estimated peak memory, warning if it is over budget, and returns the run limiter
+    ///
+    /// Each configuration is a (prefill, operations, sub-queues) triple, which is run runs times.
+    /// Pooling the runs without histograms keeps the rank errors of every run, which are counted
+    /// as if all points were in progress at once.
+    fn setup(
+        &self,
+        configs: &[(usize, usize, usize)],
+        runs: usize,
+        aggregate: Aggregate,
+        histogram: Option<u32>,
+    ) -> RunLimiter {
+        if self.max_concurrent_runs == Some(0) {
+            eprintln!("Must allow at least one concurrent run. Exiting program.");
+            process::exit(1);
+        }
+        let concurrent = self
+            .max_concurrent_runs
+            .unwrap_or(rayon::current_num_threads())
+            .min(configs.len() * runs);
+        let per_run = configs
+            .iter()
+            .map(|&(pre, ops, p)| estimate_run_memory(pre, ops, p))
+            .max()
+            .unwrap_or(0);
+        let kept = if aggregate == Aggregate::PooledReadout && histogram.is_none() {
+            configs
+                .iter()
+                .map(|&(_, ops, _)| ops * runs * size_of::<usize>())
+                .sum()
+        } else {
+            0
+        };
+        let peak_mib = (per_run * concurrent + kept).div_ceil(1 << 20);
+        if kept > 0 {
+            println!(
+                "Estimated peak memory: {peak_mib} MiB ({} MiB per run, {concurrent} concurrent runs, {} MiB of kept rank errors)",
+                per_run.div_ceil(1 << 20),
+                kept.div_ceil(1 << 20)
+            );
+        } else {
+            println!(
+                "Estimated peak memory: {peak_mib} MiB ({} MiB per run, {concurrent} concurrent runs)",
+                per_run.div_ceil(1 << 20)
+            );
+        }
+        if let Some(budget) = self.memory_budget.filter(|budget| peak_mib > *budget) {
+            eprintln!(
+                "Warning: the estimated peak memory exceeds the budget of {budget} MiB, consider lowering --max-concurrent-runs"
+            );
+        }
+        RunLimiter::new(self.max_concurrent_runs)
+    }
+}
+
+/// A counting semaphore around the body of each run
+struct RunLimiter {
+    /// The free permits, if limited
+    permits: Option<(Mutex<usize>, Condvar)>,
+}
+
+impl RunLimiter {
+    fn new(max_concurrent_runs: Option<usize>) -> Self {
+        Self {
+            permits: max_concurrent_runs.map(|max| (Mutex::new(max), Condvar::new())),
+        }
+    }
+
+    /// Runs f once a permit is free
+    fn run<R>(&self, f: impl FnOnce() -> R) -> R {
+        let Some((free, released)) = &self.permits else {
+            return f();
+        };
+        {
+            let mut free = released
+                .wait_while(free.lock().unwrap(), |free| *free == 0)
+                .unwrap();
+            *free -= 1;
+        }
+        let result = f();
+        *free.lock().unwrap() += 1;
+        released.notify_one();
+        result
+    }
+}
+
+#[derive(Args, Clone, Debug, Serialize, Deserialize)]
+struct QueueConfig {
+    /// The number of subqueues to sample for each operation (d).
+    #[arg(short = 'd', long, default_value_t = 2)]
+    sample_nbr: usize,
+
+    /// The number of subqueues to sample for each enqueue, overriding d. The sweeps take several,
+    /// and run every pair of them with the dequeue d as a further dimension
+    #[arg(long, value_delimiter = ' ', num_args = 1.., action = ArgAction::Set)]
+    enq_d: Vec<usize>,
+
+    /// The number of subqueues to sample for each dequeue, overriding d. The sweeps take several
+    #[arg(long, value_delimiter = ' ', num_args = 1.., action = ArgAction::Set)]
+    deq_d: Vec<usize>,
+
+    /// What sampling heuristic to use
+    #[arg(value_enum, long, default_value_t = Heuristic::Operation)]
+    heuristic: Heuristic,
+
+    /// The temperature of the softmax heuristic, where lower is closer to the operation heuristic
+    #[arg(long, default_value_t = 1.0)]
+    temperature: f64,
+
+    /// The weight of each enqueue in the front age estimates of the estimated-oldest heuristic,
+    /// where higher follows the latest enqueues more closely
+    #[arg(long, default_value_t = 0.1)]
+    estimate_weight: f64,
+
+    /// The operations of a kind between the publishes of the global counter of the coarse-counter
+    /// heuristic, where 1 is the operation heuristic
+    #[arg(long, default_value_t = 1)]
+    counter_granularity: usize,
+
+    /// Penalty weight for choosing a sub-queue again soon after it was last chosen
+    #[arg(long, default_value_t = 0.0)]
+    recency_penalty: f64,
+
+    /// Maximum spread of the sub-queue operation counters, redirecting to the least used sub-queue
+    #[arg(long)]
+    skew_cap: Option<usize>,
+
+    /// Also dequeue from a sampled sub-queue lagging more than this, buffering the item
+    #[arg(long)]
+    helping_threshold: Option<usize>,
+
+    /// Cap the operations choosing each sub-queue per epoch of this many operations at
+    /// ceil(epoch / sub-queues), vetoing the sub-queues at their budget from the choices until
+    /// the epoch ends. Only for single and the prefill sweeps, without batches
+    #[arg(long = "budget")]
+    budget_epoch: Option<usize>,
+
+    /// Keep a sub-queue from being chosen during a window of operations after the prefill, given
+    /// as sub-queue:start:end for the operations start..end. Several can be given
+    #[arg(long = "blackout", value_parser = parse_blackout, value_delimiter = ' ', num_args = 1.., action = ArgAction::Set)]
+    blackouts: Vec<Blackout>,
+
+    /// Serve the sub-queues at different speeds, rejecting a sampled sub-queue with probability
+    /// 1 - weight / the largest weight. Given as uniform, one-slow:x for sub-queue 0 at weight x
+    /// and the rest at 1, linear for sub-queue i at weight (i + 1) / sub-queues, or a comma
+    /// separated list of one weight per sub-queue
+    #[arg(long, value_parser = parse_subqueue_weights)]
+    subqueue_weights: Option<SubqueueWeights>,
+
+    /// How an empty dequeue chooses the sub-queue to fall back to
+    #[arg(value_enum, long, default_value_t = FallbackPolicy::FirstNonEmpty)]
+    fallback_policy: FallbackPolicy,
+
+    /// Stop a dequeue batch short when its sub-queue runs out, instead of taking the rest from
+    /// the following sub-queues, see --dequeue-batch
+    #[arg(long)]
+    batch_stop_short: bool,
+
+    /// Adapts d between min and max (given as min:max) to the counter skew, overriding the ds
+    #[arg(long, value_parser = parse_d_range)]
+    adaptive_d: Option<(usize, usize)>,
+
+    /// Samples one more sub-queue while the average counter skew is above this
+    #[arg(long, default_value_t = 2.0, requires = "adaptive_d")]
+    adaptive_upper: f64,
+
+    /// Samples one fewer sub-queue while the average counter skew is below this
+    #[arg(long, default_value_t = 1.0, requires = "adaptive_d")]
+    adaptive_lower: f64,
+
+    /// The weight of the newest choice in the average counter skew of the adaptive d
+    #[arg(long, default_value_t = 0.01, requires = "adaptive_d")]
+    adaptive_weight: f64,
+
+    /// Charges each operation virtual nanoseconds as base:sample:scan:retry, for a base cost, a
+    /// cost per sampled sub-queue, per sub-queue scanned or item helped, and per fallback or skew
+    /// cap retry. Reports the virtual time and operations per virtual second
+    #[arg(long, value_parser = parse_latency_model)]
+    latency_model: Option<LatencyModel>,
+
+    /// What index sampling method to use
+    #[arg(value_enum, long, default_value_t = Sampling::Naive)]
+    sampling: Sampling,
+
+    /// The exponent of the Zipf sampling, where 0 is uniform and higher makes few sub-queues hot
+    #[arg(long, default_value_t = 1.0)]
+    zipf_s: f64,
+
+    /// What selection strategy to use for selecting sub-queue
+    #[arg(value_enum, long, default_value_t = QueueSelection::Random)]
+    selection: QueueSelection,
+
+    /// Randomly permute which sub-queue each sampling index maps to, by a seed derived from the
+    /// queue seed, so that the structured sampling and fallback scans meet a new layout each run
+    #[arg(long)]
+    permute_subqueues: bool,
+}
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+struct OpsConfig {
+    /// How to generate the operations
+    #[arg(value_enum, long = "ops-distr", default_value_t = OperationDistribution::RandomBalanced)]
+    distribution: OperationDistribution,
+
+    /// Instead use this fixed pattern of enqueues (E) and dequeues (D), repeated to fill the operations
+    #[arg(long, conflicts_with = "distribution")]
+    ops_pattern: Option<String>,
+
+    /// The number of operations in one period of the alternating distribution
+    #[arg(long, default_value_t = 2)]
+    pattern_period: usize,
+
+    /// The number of enqueues in each period of the alternating distribution, which come before
+    /// the dequeues of the period
+    #[arg(long, default_value_t = 1)]
+    pattern_enqueues: usize,
+
+    /// Start each period of the alternating distribution with its dequeues instead
+    #[arg(long)]
+    start_with_dequeue: bool,
+
+    /// The number of operations in one period of the periodic distribution
+    #[arg(long, default_value_t = 1000)]
+    period: usize,
+
+    /// How far the periodic distribution's target size swings above and below the prefill
+    #[arg(long, default_value_t = 100)]
+    amplitude: usize,
+
+    /// The shape of the periodic distribution's target size
+    #[arg(value_enum, long, default_value_t = Wave::Sine)]
+    wave: Wave,
+
+    /// Random noise of up to this many items added to the periodic distribution's target size
+    #[arg(long, default_value_t = 0)]
+    jitter: usize,
+
+    /// The smallest queue size the reflecting distribution allows a dequeue to lead to
+    #[arg(long, default_value_t = 0)]
+    floor: usize,
+
+    /// How many enqueues follow each successful dequeue of the frontier distribution
+    #[arg(value_enum, long, default_value_t = Branching::Constant)]
+    branching: Branching,
+
+    /// The number of enqueues of the constant branching, and the most of the other branchings
+    #[arg(long, default_value_t = 2)]
+    max_branching: usize,
+
+    /// The exponent of the power-law branching, where k enqueues have weight (k + 1)^-exponent
+    #[arg(long, default_value_t = 2.0)]
+    branching_exponent: f64,
+
+    /// The queue size the occupancy distribution keeps the queue below
+    #[arg(long, default_value_t = 100)]
+    target_size: usize,
+
+    /// How far below the target size the occupancy distribution starts to dequeue
+    #[arg(long, default_value_t = 10)]
+    control_window: usize,
+
+    /// Attribute the operations to this many logical threads, each with its own operation stream
+    #[arg(long = "threads-model")]
+    threads: Option<usize>,
+
+    /// Interleave the thread streams in round-robin quanta of this length, or uniformly at random if 0
+    #[arg(long, default_value_t = 0, requires = "threads")]
+    quantum: usize,
+
+    /// The fraction of enqueues in each thread's stream, one per thread. Otherwise each stream
+    /// follows the operation distribution
+    #[arg(long, value_delimiter = ' ', num_args = 1.., action = ArgAction::Set, requires = "threads")]
+    thread_enq_fractions: Vec<f64>,
+
+    /// Fail the enqueues while the queue holds this many items, so neither the queue nor the
+    /// strict oracle receives them, and report the failures. Only for single and the prefill
+    /// sweeps, without the closed-loop distributions
+    #[arg(long)]
+    max_items: Option<usize>,
+
+    /// Each dequeue takes a batch of up to this many items from its chosen sub-queue, each with
+    /// its own rank error. Only for single and the prefill sweeps, without the closed-loop
+    /// distributions or an item capacity
+    #[arg(long, conflicts_with = "max_items")]
+    dequeue_batch: Option<usize>,
+
+    /// Each enqueue inserts a batch of this many sequential items into its chosen sub-queue, as
+    /// producers publishing a local buffer do. Only for single and the prefill sweeps, without
+    /// the closed-loop distributions or an item capacity
+    #[arg(long, conflicts_with = "max_items")]
+    enqueue_batch: Option<usize>,
+
+    /// Move each dequeue up to this many enqueues earlier, as long as it still finds an item, as
+    /// when dequeues are prioritized over queued-up enqueues. Not for the closed-loop
+    /// distributions
+    #[arg(long, conflicts_with = "threads")]
+    deq_priority: Option<usize>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Heuristic {
+    /// Length-based heuristic, as in the original d-RA load balancer.
+    Length,
+
+    /// Operation-based heuristic, which is our new and improved heuristic.
+    Operation,
+
+    /// Random choice weighted by the softmax of the operation-based counters.
+    Softmax,
+
+    /// Dequeues from the sub-queue with the oldest front item, which requires peeking.
+    OldestFront,
+
+    /// Dequeues from the sub-queue with the oldest front item as estimated from its enqueues.
+    EstimatedOldest,
+
+    /// Operation-based heuristic against a shared global counter published every
+    /// --counter-granularity operations.
+    CoarseCounter,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum GapDistribution {
+    /// Gaps drawn uniformly up to the gap.
+    Uniform,
+
+    /// Pareto distributed gaps, scaled by the gap.
+    HeavyTailed,
+
+    /// One sub-queue lagging by the gap, and no other gaps.
+    Straggler,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Normalization {
+    /// Leaves the rank errors as they are.
+    None,
+
+    /// Divides the rank errors by the number of sub-queues minus one.
+    PerPartial,
+
+    /// Divides the rank errors by the prefill.
+    PerPrefill,
+
+    /// Divides each rank error by the number of items in the queue at its dequeue.
+    PerSize,
+}
+
+impl From<Normalization> for crate::Normalization {
+    fn from(normalization: Normalization) -> Self {
+        match normalization {
+            Normalization::None => crate::Normalization::None,
+            Normalization::PerPartial => crate::Normalization::PerPartial,
+            Normalization::PerPrefill => crate::Normalization::PerPrefill,
+            Normalization::PerSize => crate::Normalization::PerSize,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum FallbackPolicy {
+    /// Takes the first non-empty sub-queue after the chosen one, as the original round robin.
+    FirstNonEmpty,
+
+    /// Scans all sub-queues and takes the longest one.
+    LongestQueue,
+
+    /// Scans all sub-queues and takes the non-empty one with the lowest head counter.
+    OldestHead,
+
+    /// Takes the first non-empty sub-queue from the one the last fallback took its item from.
+    Hinted,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum OutputFormat {
+    /// Json with the metadata, as "[key, value]" records
+    Json,
+
+    /// A Parquet table with one typed row per run of each data point (needs the arrow feature)
+    Parquet,
+}
+
+impl OutputFormat {
+    /// Exits if the format is not compiled in, before running the whole sweep
+    fn assert_available(self) {
+        if self == OutputFormat::Parquet && !cfg!(feature = "arrow") {
+            eprintln!("Parquet output needs the arrow feature. Exiting program.");
+            process::exit(1);
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ErrorDefinition {
+    /// The number of older items still in the queue, which the dequeued item overtook
+    EnqueueRelative,
+
+    /// The number of younger items dequeued before the dequeued item, which overtook it.
+    /// Empty returns are skipped
+    DequeueRelative,
+
+    /// Reads out both definitions side by side, but dumps the enqueue-relative errors
+    Both,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum FitDimension {
+    /// The number of sub-queues
+    Subqueues,
+
+    /// The prefill
+    Prefill,
+
+    /// The total number of operations
+    Ops,
+}
+
+impl FitDimension {
+    /// Exits if the sweep does not vary this dimension, or cannot output the fits
+    fn assert_in_sweep(fit: Option<Self>, dimensions: &[Self], format: OutputFormat) {
+        let Some(fit) = fit else {
+            return;
+        };
+        if !dimensions.contains(&fit) {
+            eprintln!("Can only fit over the dimensions of the sweep. Exiting program.");
+            process::exit(1);
+        }
+        if format != OutputFormat::Json {
+            eprintln!("The fits are only written to json output. Exiting program.");
+            process::exit(1);
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum QueueKind {
+    /// The d-RA queue with the operation-based heuristic
+    Operation,
+
+    /// The d-RA queue with the length-based heuristic
+    Length,
+
+    /// The d-RA queue with the softmax heuristic
+    Softmax,
+
+    /// The d-RA queue dequeuing from the oldest front item
+    OldestFront,
+
+    /// The d-RA queue dequeuing from the oldest estimated front item
+    EstimatedOldest,
+
+    /// The d-RA queue with d = 1, taking its sub-queues round-robin per operation kind
+    RoundRobin,
+
+    /// A strict FIFO queue, as a baseline
+    Strict,
+}
+
+impl QueueKind {
+    /// The name used on the command line
+    fn name(&self) -> String {
+        self.to_possible_value()
+            .expect("No queue kinds are skipped")
+            .get_name()
+            .to_string()
+    }
+}
+
+/// The queue of a sweep point, as the compared kind and the swept enqueue and dequeue d
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+struct SweepQueue {
+    /// The kind, if the sweep compares several
+    kind: Option<QueueKind>,
+
+    /// The enqueue and dequeue d, if the sweep runs several
+    ds: Option<(usize, usize)>,
+}
+
+impl SweepQueue {
+    /// Every kind with every pair of d the sweep runs, where no kinds just runs the configured
+    /// queue
+    fn all(kinds: &[QueueKind], config: &QueueConfig) -> Vec<SweepQueue> {
+        let kinds: Vec<Option<QueueKind>> = match kinds.is_empty() {
+            true => vec![None],
+            false => kinds.iter().cloned().map(Some).collect(),
+        };
+        let ds: Vec<Option<(usize, usize)>> = match config.sweep_ds() {
+            Some(ds) => ds.into_iter().map(Some).collect(),
+            None => vec![None],
+        };
+        kinds
+            .into_iter()
+            .flat_map(|kind| ds.iter().map(move |ds| SweepQueue { kind, ds: *ds }))
+            .collect()
+    }
+
+    /// The key of a sweep point, prefixed by the kind and d if the sweep runs several
+    fn key(&self, dimensions: String) -> String {
+        let kind = self.kind.map(|kind| format!("{}, ", kind.name()));
+        let ds = self.ds.map(|(enq_d, deq_d)| format!("{enq_d}, {deq_d}, "));
+        format!(
+            "({}{}{dimensions})",
+            kind.unwrap_or_default(),
+            ds.unwrap_or_default()
+        )
+    }
+
+    /// Adds the kind and d of a sweep record as its columns, if the sweep runs several
+    fn with_columns(&self, mut record: serde_json::Value) -> serde_json::Value {
+        if let Some(kind) = self.kind {
+            record[2]["queue"] = serde_json::json!(kind.name());
+        }
+        if let Some((enq_d, deq_d)) = self.ds {
+            record[2]["enqueue_d"] = serde_json::json!(enq_d);
+            record[2]["dequeue_d"] = serde_json::json!(deq_d);
+        }
+        record
+    }
+
+    /// The sub-queues and d of the enqueues and dequeues of the queue in a sweep
+    fn shape(&self, subqueues: usize, config: &QueueConfig) -> [usize; 3] {
+        let ds = self.ds.unwrap_or(config.ds());
+        match self.kind {
+            Some(QueueKind::Strict) => [1, 1, 1],
+            Some(QueueKind::RoundRobin) => [subqueues, 1, 1],
+            _ => [subqueues, ds.0, ds.1],
+        }
+    }
+
+    /// The configuration with the d of the queue, if the sweep runs several
+    fn config(&self, config: &QueueConfig) -> QueueConfig {
+        match self.ds {
+            Some((enq_d, deq_d)) => QueueConfig {
+                enq_d: vec![enq_d],
+                deq_d: vec![deq_d],
+                ..config.clone()
+            },
+            None => config.clone(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Sampling {
+    /// Just samples d values at random
+    Naive,
+
+    /// Does not sample the same index twice
+    Uniques,
+
+    /// Samples from a Zipf distribution over randomly permuted indexes, see --zipf-s
+    Zipf,
+
+    /// As zipf, but does not sample the same index twice
+    ZipfUniques,
+
+    /// Samples one index from a round-robin cursor per operation kind, the rest at random
+    RoundRobinPlusRandom,
+
+    /// Samples the index chosen by the previous operation of the same kind, the rest at random
+    Memory,
+
+    /// Samples d / 2 pairs of adjacent indexes, choosing a pair by the summed heuristic and then
+    /// an index within it, which needs an even number of sub-queues
+    Paired,
+
+    /// Takes the d indexes whose choice is the worst instead of random ones, to bound the
+    /// heuristic from above, which needs d <= 2 and at most 64 sub-queues
+    Adversary,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum QueueSelection {
+    /// Uniformly random
+    Random,
+
+    /// Prefer queues to the left
+    Left,
+
+    /// Partition queues into d groups, sampling randomly per group, random tiebreak
+    RandomPart,
+
+    /// Partition queues into d groups, sampling randomly per group, and prefer left
+    LeftPart,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum OperationDistribution {
+    /// Randomly generate at equal probability
+    RandomBalanced,
+
+    /// Repeats periods of enqueues followed by dequeues, see --pattern-period. By default
+    /// alternates single enqueues and dequeues
+    Alternating,
+
+    /// Makes the queue size follow a periodic target around the prefill
+    Periodic,
+
+    /// Randomly balanced, but never lets the queue become empty (requires a prefill)
+    NeverEmpty,
+
+    /// Random coin flips, but forces an enqueue whenever a dequeue would drop the size below the floor
+    Reflecting,
+
+    /// BFS-like processing, where each successful dequeue is followed by its enqueues, see
+    /// --branching. Stops early if the queue empties. Only for single and the prefill sweeps
+    Frontier,
+
+    /// Enqueues with probability clamp(target size - size, 0, window) / window, keeping the size
+    /// just below the target, see --target-size. Only for single and the prefill sweeps
+    Occupancy,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Branching {
+    /// Always the max branching
+    Constant,
+
+    /// Uniformly random between 0 and the max branching
+    Uniform,
+
+    /// Random between 0 and the max branching, heavily favoring few enqueues
+    PowerLaw,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Wave {
+    /// Smoothly grows and shrinks
+    Sine,
+
+    /// Grows linearly, then drops at the end of each period
+    Sawtooth,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ErrorReadout {
+    /// Reports the average rank error from each simulation
+    Average,
+
+    /// Reports the data point from each experiment that is at the 99-percentile quantile
+    WorstOnePercent,
+
+    /// Reports after how many dequeues the last prefill item left the queue. If some never
+    /// left, instead reports minus the number of remaining prefill items
+    PrefillFlush,
+
+    /// Places all prefill items in one sub-queue, and reports after how many operations the
+    /// windowed mean rank error dropped to the recovery threshold of its initial value. If it
+    /// never did, instead reports minus the number of operations. See --recovery-threshold
+    Recovery,
+
+    /// Reports the most items any sub-queue held at once, bounding the buffer each sub-queue
+    /// needs, instead of a rank error
+    MaxHighWaterMark,
+}
+
+/// How the runs of a sweep data point are combined into its value
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Aggregate {
+    /// The mean of the readouts of the runs
+    MeanOfReadouts,
+
+    /// The readout of the rank errors of all runs pooled together. Keeps the rank errors of every
+    /// run until the point is done, unless they are streamed into histograms, which are merged
+    PooledReadout,
+
+    /// The median of the readouts of the runs
+    MedianOfReadouts,
+}
+
+impl ErrorReadout {
+    /// The name used on the command line
+    fn name(&self) -> String {
+        self.to_possible_value()
+            .expect("No readouts are skipped")
+            .get_name()
+            .to_string()
+    }
+
+    /// Runs the analysis of one simulation and reads out its error
+    ///
+    /// If given a histogram, the rank errors are streamed into it instead of being stored, and
+    /// read out from it. The workload can also bound the items or batch the dequeues, and the
+    /// queue can be budgeted, see analyze_into.
+    #[allow(clippy::too_many_arguments)]
+    fn analyze(
+        &self,
+        queue: &mut DChoiceQueue<usize>,
+        prefill: usize,
+        operations: &[bool],
+        histogram: Option<&mut LogHistogram>,
+        workload: &OpsConfig,
+        recovery: &RecoveryConfig,
+        budget: Option<usize>,
+    ) -> RunReadout {
+        let value = match self {
+            ErrorReadout::PrefillFlush => {
+                let error_tags = or_exit(analyze_extra(queue, prefill, operations, None));
+                Some(Self::flush_readout(&analyze_prefill_flush(
+                    &error_tags,
+                    prefill,
+                )))
+            }
+            ErrorReadout::Recovery => {
+                let op_errors = or_exit(analyze_skewed_prefill(queue, prefill, operations));
+                let recovered = analyze_recovery(
+                    &op_errors,
+                    recovery.recovery_window,
+                    recovery.recovery_threshold,
+                );
+                Some(match recovered.recovery_ops {
+                    Some(ops) => ops as f32,
+                    None => -(operations.len() as f32),
+                })
+            }
+            ErrorReadout::MaxHighWaterMark => {
+                analyze_into(queue, prefill, operations, workload, budget, &mut vec![]);
+                Some(Self::high_water_readout(queue))
+            }
+            _ => None,
+        };
+        if let Some(value) = value {
+            return RunReadout {
+                value,
+                rank_errors: None,
+                enqueue_failures: None,
+            };
+        }
+        match histogram {
+            Some(histogram) => {
+                let enqueue_failures =
+                    analyze_into(queue, prefill, operations, workload, budget, histogram);
+                RunReadout {
+                    value: self.histogram_readout(histogram),
+                    rank_errors: None,
+                    enqueue_failures,
+                }
+            }
+            None => {
+                let mut rank_errors = vec![];
+                let enqueue_failures = analyze_into(
+                    queue,
+                    prefill,
+                    operations,
+                    workload,
+                    budget,
+                    &mut rank_errors,
+                );
+                RunReadout {
+                    value: self.readout(&rank_errors),
+                    rank_errors: Some(rank_errors),
+                    enqueue_failures,
+                }
+            }
+        }
+    }
+
+    /// As analyze, but generates the operations online from the source
+    fn analyze_online(
+        &self,
+        queue: &mut DChoiceQueue<usize>,
+        prefill: usize,
+        source: &mut dyn OpSource,
+        histogram: Option<&mut LogHistogram>,
+        budget: Option<usize>,
+    ) -> RunReadout {
+        let rank_errors = match budget {
+            Some(epoch) => {
+                or_exit(analyze_online(
+                    &mut BudgetedQueue::new(queue, epoch),
+                    prefill,
+                    source,
+                ))
+                .0
+            }
+            None => or_exit(analyze_online(queue, prefill, source)).0,
+        };
+        match histogram {
+            Some(histogram) => {
+                rank_errors
+                    .into_iter()
+                    .for_each(|rank_error| histogram.record(rank_error));
+                RunReadout {
+                    value: self.histogram_readout(histogram),
+                    rank_errors: None,
+                    enqueue_failures: None,
+                }
+            }
+            None => RunReadout {
+                value: self.readout(&rank_errors),
+                rank_errors: Some(rank_errors),
+                enqueue_failures: None,
+            },
+        }
+    }
+
+    /// As readout, but approximated from the histogram
+    fn histogram_readout(&self, histogram: &LogHistogram) -> f32 {
+        match self {
+            ErrorReadout::Average => histogram.mean() as f32,
+            ErrorReadout::WorstOnePercent => histogram.value_at_quantile(0.99) as f32,
+            ErrorReadout::PrefillFlush
+            | ErrorReadout::Recovery
+            | ErrorReadout::MaxHighWaterMark => unreachable!("Read out from the whole run"),
+        }
+    }
+
+    /// Reads out the error of a simulation from its error tags, and the queue it ran on
+    fn tags_readout(
+        &self,
+        error_tags: &[ErrorTag],
+        prefill: usize,
+        queue: &DChoiceQueue<usize>,
+    ) -> f32 {
+        match self {
+            ErrorReadout::PrefillFlush => {
+                Self::flush_readout(&analyze_prefill_flush(error_tags, prefill))
+            }
+            ErrorReadout::MaxHighWaterMark => Self::high_water_readout(queue),
+            _ => self.readout(
+                &error_tags
+                    .iter()
+                    .map(|tag| tag.rank_error())
+                    .collect::<Vec<usize>>(),
+            ),
+        }
+    }
+
+    fn flush_readout(flush: &PrefillFlush) -> f32 {
+        match flush.flush_dequeue {
+            Some(dequeue) => dequeue as f32,
+            None => -(flush.remaining as f32),
+        }
+    }
+
+    fn high_water_readout(queue: &DChoiceQueue<usize>) -> f32 {
+        *queue
+            .subqueue_high_water_marks()
+            .iter()
+            .max()
+            .expect("The queue has sub-queues") as f32
+    }
+
+    fn readout(&self, nbrs: &[usize]) -> f32 {
+        let len = nbrs.len();
+        match self {
+            ErrorReadout::Average => nbrs.iter().sum::<usize>() as f32 / len as f32,
+            ErrorReadout::WorstOnePercent => {
+                let track_nbr = len / 100;
+                let mut heap = BinaryHeap::with_capacity(track_nbr);
+                for &error in nbrs {
+                    if heap.len() < track_nbr {
+                        heap.push(-(error as i64));
+                    } else if -heap.peek().unwrap() < error as i64 {
+                        heap.pop();
+                        heap.push(-(error as i64));
+                    }
+                }
+                -heap.pop().unwrap() as f32
+            }
+            ErrorReadout::PrefillFlush
+            | ErrorReadout::Recovery
+            | ErrorReadout::MaxHighWaterMark => unreachable!("Read out from the whole run"),
+        }
+    }
+
+    /// The fraction of the rank errors a tail readout reads its value from, or None if the
+    /// readout is not a tail
+    fn tail_fraction(&self) -> Option<f64> {
+        match self {
+            ErrorReadout::WorstOnePercent => Some(0.01),
+            _ => None,
+        }
+    }
+
+    /// If the readout is read from the rank errors alone, rather than from the whole run
+    fn reads_rank_errors(&self) -> bool {
+        matches!(self, ErrorReadout::Average | ErrorReadout::WorstOnePercent)
+    }
+}
+
+impl QueueArg {
+    fn init(&self, seed: u64) -> DChoiceQueue<usize> {
+        self.config.init(self.subqueues, seed)
+    }
+
+    fn builder(&self, seed: u64) -> DChoiceQueueBuilder {
+        self.config.builder(self.subqueues, seed)
+    }
+
+    /// Initializes a queue of the given kind, otherwise using this configuration
+    fn init_kind(&self, kind: QueueKind, seed: u64) -> DChoiceQueue<usize> {
+        self.config.init_kind(self.subqueues, kind, seed)
+    }
+
+    /// This configuration with the heuristic of the kind, or None for the strict queue
+    fn kind_config(&self, kind: QueueKind) -> Option<QueueConfig> {
+        self.config.kind_config(kind)
+    }
+}
+
+/// A queue compared by the matrix
+#[derive(Clone, Copy, Debug)]
+enum MatrixQueue {
+    Kind(QueueKind),
+
+    /// The k-FIFO queue with segments of this size
+    KFifo(usize),
+}
+
+impl MatrixQueue {
+    fn name(&self) -> String {
+        match self {
+            MatrixQueue::Kind(kind) => kind.name(),
+            MatrixQueue::KFifo(k) => format!("k-fifo-{k}"),
+        }
+    }
+}
+
+/// One row of the queue comparison matrix
+#[derive(Debug, Serialize)]
+struct MatrixRow {
+    /// The queue kind, or k-fifo-{k} for the k-FIFO queues
+    kind: String,
+    mean_rank_error: f32,
+    p99_rank_error: usize,
+    max_rank_error: usize,
+    /// The average number of empty returns per run
+    empty_returns: f32,
+}
+
+impl QueueConfig {
+    /// Initializes a queue of the given kind, otherwise using this configuration
+    fn init_kind(&self, subqueues: usize, kind: QueueKind, seed: u64) -> DChoiceQueue<usize> {
+        match self.kind_config(kind) {
+            Some(config) => config.init(subqueues, seed),
+            None => DChoiceQueueBuilder::new(1)
+                .d(1)
+                .seed(Some(seed))
+                .build_sequenced(),
+        }
+    }
+
+    /// Initializes the queue of a sweep point, otherwise using this configuration
+    fn init_sweep(&self, subqueues: usize, queue: SweepQueue, seed: u64) -> DChoiceQueue<usize> {
+        let config = queue.config(self);
+        match queue.kind {
+            Some(kind) => config.init_kind(subqueues, kind, seed),
+            None => config.init(subqueues, seed),
+        }
+    }
+
+    /// This configuration with the heuristic of the kind, or None for the strict queue
+    fn kind_config(&self, kind: QueueKind) -> Option<QueueConfig> {
+        let heuristic = match kind {
+            QueueKind::Operation => Heuristic::Operation,
+            QueueKind::Length => Heuristic::Length,
+            QueueKind::Softmax => Heuristic::Softmax,
+            QueueKind::OldestFront => Heuristic::OldestFront,
+            QueueKind::EstimatedOldest => Heuristic::EstimatedOldest,
+            QueueKind::RoundRobin => {
+                return Some(QueueConfig {
+                    sample_nbr: 1,
+                    enq_d: vec![],
+                    deq_d: vec![],
+                    sampling: Sampling::RoundRobinPlusRandom,
+                    ..self.clone()
+                })
+            }
+            QueueKind::Strict => return None,
+        };
+        Some(QueueConfig {
+            heuristic,
+            ..self.clone()
+        })
+    }
+
+    /// Exits the program if any of the queues of a sweep is invalid with these sub-queues
+    fn assert_sweep_valid(&self, queues: &[SweepQueue], subqueues: usize) {
+        for queue in queues {
+            let config = queue.config(self);
+            match queue.kind {
+                Some(kind) => {
+                    if let Some(config) = config.kind_config(kind) {
+                        config.assert_valid(subqueues);
+                    }
+                }
+                None => config.assert_valid(subqueues),
+            }
+        }
+    }
+
+    /// The blackouts and service weights as one candidate filter, if there are any, where the
+    /// rejections of the service weights are seeded from the queue seed
+    ///
+    /// Exits the program if the service weights are invalid.
+    fn candidate_filter(&self, subqueues: usize, seed: u64) -> Option<Box<dyn CandidateFilter>> {
+        let service = self.subqueue_weights.as_ref().map(|weights| {
+            ServiceWeights::new(
+                &weights.weights(subqueues),
+                derive_seed(seed, SeedStream::Service, &[]),
+            )
+            .unwrap_or_else(|err| {
+                eprintln!("{err}. Exiting program.");
+                process::exit(1);
+            })
+        });
+        match (self.blackouts.is_empty(), service) {
+            (true, None) => None,
+            (false, None) => Some(Box::new(self.blackouts.clone())),
+            (true, Some(service)) => Some(Box::new(service)),
+            (false, Some(service)) => Some(Box::new((self.blackouts.clone(), service))),
+        }
+    }
+
+    /// Exits the program if the sub-queues are budgeted with a workload or readout which does not
+    /// support it, or where unsupported is set as the subcommand does not
+    fn assert_budget_supported(
+        &self,
+        workload: &OpsConfig,
+        readout: ErrorReadout,
+        unsupported: bool,
+    ) {
+        if self.budget_epoch.is_none() {
+            return;
+        }
+        if unsupported {
+            eprintln!(
+                "The budget is only supported by single and the prefill sweeps. Exiting program."
+            );
+            process::exit(1);
+        }
+        if workload.is_batched() {
+            eprintln!("The budget does not support the batches. Exiting program.");
+            process::exit(1);
+        }
+        if !readout.reads_rank_errors() {
+            eprintln!(
+                "The budget does not support the {} readout. Exiting program.",
+                readout.name()
+            );
+            process::exit(1);
+        }
+    }
+
+    /// The number of sub-queues to sample for enqueues and dequeues
+    /// The d of the enqueues and dequeues, where a sweep over them has set single ones per run
+    fn ds(&self) -> (usize, usize) {
+        (
+            self.enq_d.first().copied().unwrap_or(self.sample_nbr),
+            self.deq_d.first().copied().unwrap_or(self.sample_nbr),
+        )
+    }
+
+    /// The pairs of enqueue and dequeue d a sweep runs, or None if it does not sweep over them
+    fn sweep_ds(&self) -> Option<Vec<(usize, usize)>> {
+        if self.enq_d.len() <= 1 && self.deq_d.len() <= 1 {
+            return None;
+        }
+        let ds = |list: &Vec<usize>| match list.is_empty() {
+            true => vec![self.sample_nbr],
+            false => list.clone(),
+        };
+        let deq_ds = ds(&self.deq_d);
+        Some(
+            ds(&self.enq_d)
+                .into_iter()
+                .flat_map(|enq_d| deq_ds.iter().map(move |deq_d| (enq_d, *deq_d)))
+                .collect(),
+        )
+    }
+
+    fn init(&self, subqueues: usize, seed: u64) -> DChoiceQueue<usize> {
+        self.builder(subqueues, seed).build_sequenced()
+    }
+
+    /// Validates the configuration, exiting the program if it is invalid, and sets up its builder
+    fn builder(&self, subqueues: usize, seed: u64) -> DChoiceQueueBuilder {
+        let heuristic = match self.heuristic {
+            Heuristic::Length => crate::Heuristic::Length,
+            Heuristic::Operation => crate::Heuristic::Operation,
+            Heuristic::Softmax => {
+                if self.temperature <= 0.0 {
+                    eprintln!("The softmax temperature must be positive. Exiting program.");
+                    process::exit(1);
+                }
+                crate::Heuristic::Softmax {
+                    temperature: self.temperature,
+                }
+            }
+            Heuristic::OldestFront => crate::Heuristic::OldestFront,
+            Heuristic::EstimatedOldest => {
+                if !(self.estimate_weight > 0.0 && self.estimate_weight <= 1.0) {
+                    eprintln!("The estimate weight must be in (0, 1]. Exiting program.");
+                    process::exit(1);
+                }
+                crate::Heuristic::EstimatedOldest {
+                    weight: self.estimate_weight,
+                }
+            }
+            Heuristic::CoarseCounter => {
+                if self.counter_granularity == 0 {
+                    eprintln!("The counter granularity must be at least 1. Exiting program.");
+                    process::exit(1);
+                }
+                crate::Heuristic::CoarseCounter {
+                    granularity: self.counter_granularity,
+                }
+            }
+        };
+        if self.enq_d.len() > 1 || self.deq_d.len() > 1 {
+            eprintln!("Only the sweeps take several --enq-d or --deq-d. Exiting program.");
+            process::exit(1);
+        }
+        let (enqueue_d, dequeue_d) = self.ds();
+        let uniques = matches!(self.sampling, Sampling::Uniques | Sampling::ZipfUniques);
+        let zipf = matches!(self.sampling, Sampling::Zipf | Sampling::ZipfUniques);
+        if zipf && self.zipf_s < 0.0 {
+            eprintln!("The Zipf exponent can not be negative. Exiting program.");
+            process::exit(1);
+        }
+        let partition = matches!(
+            self.selection,
+            QueueSelection::RandomPart | QueueSelection::LeftPart
+        );
+        let round_robin = self.sampling == Sampling::RoundRobinPlusRandom;
+        let memory = self.sampling == Sampling::Memory;
+        let paired = self.sampling == Sampling::Paired;
+        let adversary = self.sampling == Sampling::Adversary;
+        if (zipf || round_robin || memory || paired || adversary) && partition {
+            eprintln!(
+                "Only naive and unique sampling can be combined with partitioning. Exiting program."
+            );
+            process::exit(1);
+        }
+        if let Some(blackout) = self
+            .blackouts
+            .iter()
+            .find(|blackout| blackout.subqueue >= subqueues)
+        {
+            eprintln!(
+                "Can not black out sub-queue {} of {subqueues}. Exiting program.",
+                blackout.subqueue
+            );
+            process::exit(1);
+        }
+        if self.budget_epoch == Some(0) {
+            eprintln!("The budget epoch must hold at least one operation. Exiting program.");
+            process::exit(1);
+        }
+        if self.skew_cap == Some(0) {
+            eprintln!("The skew cap must be at least 1. Exiting program.");
+            process::exit(1);
+        }
+        if self.recency_penalty < 0.0 {
+            eprintln!("The recency penalty can not be negative. Exiting program.");
+            process::exit(1);
+        }
+        let adaptive_d = self.adaptive_d.map(|(min, max)| {
+            if !(self.adaptive_weight > 0.0 && self.adaptive_weight <= 1.0) {
+                eprintln!("The adaptive weight must be in (0, 1]. Exiting program.");
+                process::exit(1);
+            }
+            AdaptiveD {
+                min,
+                max,
+                upper: self.adaptive_upper,
+                lower: self.adaptive_lower,
+                weight: self.adaptive_weight,
+            }
+        });
+
+        let builder = DChoiceQueueBuilder::new(subqueues)
+            .enqueue_d(enqueue_d)
+            .dequeue_d(dequeue_d)
+            .uniques(uniques)
+            .zipf(zipf.then_some(self.zipf_s))
+            .round_robin(round_robin)
+            .memory(memory)
+            .paired(paired)
+            .adversary(adversary)
+            .heuristic(heuristic)
+            .empty_lin(true)
+            .fallback_policy(match self.fallback_policy {
+                FallbackPolicy::FirstNonEmpty => crate::FallbackPolicy::FirstNonEmpty,
+                FallbackPolicy::LongestQueue => crate::FallbackPolicy::LongestQueue,
+                FallbackPolicy::OldestHead => crate::FallbackPolicy::OldestHead,
+                FallbackPolicy::Hinted => crate::FallbackPolicy::Hinted,
+            })
+            .recency_penalty(self.recency_penalty)
+            .skew_cap(self.skew_cap)
+            .adaptive_d(adaptive_d)
+            .helping_threshold(self.helping_threshold)
+            .batch_stop_short(self.batch_stop_short)
+            .latency_model(self.latency_model)
+            .candidate_filter(self.candidate_filter(subqueues, seed))
+            .partition(partition)
+            .left(
+                self.selection == QueueSelection::Left
+                    || self.selection == QueueSelection::LeftPart,
+            )
+            .seed(Some(seed))
+            .permute_subqueues(
+                self.permute_subqueues
+                    .then(|| derive_seed(seed, SeedStream::Layout, &[])),
+            );
+        if let Err(err) = builder.validate() {
+            eprintln!("{err}. Exiting program.");
+            process::exit(1);
+        }
+        builder
+    }
+
+    /// Exits the program if the configuration is invalid with this many sub-queues, so sweeps
+    /// can check all their points before running any of them
+    fn assert_valid(&self, subqueues: usize) {
+        self.builder(subqueues, 0);
+    }
+}
+
+/// Runs the command line interface on the arguments, starting with the binary name as from
+/// std::env::args_os, and returns the path of the output if it was a json sweep
+///
+/// A binary wrapping the crate can register its own queues with register_queue before calling
+/// this, to run them with `--queue external:<name>`. Exits the process on invalid arguments or
+/// failed tests, as the binary of the crate does.
+pub fn run_cli<I, T>(args: I) -> Option<PathBuf>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString>,
+{
+    let args = args.into_iter().map(Into::into).collect();
+    let cli = Cli::parse_from(expand_preset(args));
+    let preset = cli.preset.as_deref().and_then(find_preset);
+    let seed = cli.seed.unwrap_or_else(|| thread_rng().gen());
+    let test = if cli.smoke { smoke(cli.test) } else { cli.test };
+    run_test(test, seed, &cli.output, preset, cli.smoke)
+}
+
+/// The most operations of any count in a smoke run, including each phase of a phased run
+const SMOKE_OPERATIONS: usize = 200;
+/// The most prefill of a smoke run
+const SMOKE_PREFILL: usize = 50;
+/// The most runs of each point in a smoke run
+const SMOKE_RUNS: usize = 2;
+/// The most points of each sweep dimension in a smoke run
+const SMOKE_SWEEP_POINTS: usize = 2;
+/// The most explored states of an exhaustive smoke run
+const SMOKE_STATES: usize = 10_000;
+
+/// Caps the sizes of the test for a smoke run, by the names of its fields in the serialized
+/// configuration, so that every subcommand is covered in one place
+///
+/// Sweep lists keep their first points, scaled down together so that they stay distinct and in
+/// order. Enqueue and dequeue lists are paired up, so they are only truncated and scaled.
+fn smoke(test: Test) -> Test {
+    let mut config = serde_json::to_value(&test).expect("The test can be serialized");
+    cap_smoke(&mut config);
+    serde_json::from_value(config).expect("Capping keeps the test deserializable")
+}
+
+fn cap_smoke(value: &mut serde_json::Value) {
+    let serde_json::Value::Object(fields) = value else {
+        return;
+    };
+    for (key, field) in fields.iter_mut() {
+        match key.as_str() {
+            "operations" | "ramp" | "steady" | "drain" | "bins" => {
+                cap_smoke_field(field, SMOKE_OPERATIONS, true)
+            }
+            "enqueues" | "dequeues" => cap_smoke_field(field, SMOKE_OPERATIONS, false),
+            "prefill" => cap_smoke_field(field, SMOKE_PREFILL, true),
+            "runs" => cap_smoke_field(field, SMOKE_RUNS, true),
+            "max_states" => cap_smoke_field(field, SMOKE_STATES, true),
+            // Only the swept sub-queues, as the count of a single queue does not add operations
+            "subqueues" if field.is_array() => cap_smoke_field(field, usize::MAX, true),
+            _ => cap_smoke(field),
+        }
+    }
+}
+
+/// Caps a count, or truncates and scales down a list of counts, leaving nulls as they are
+fn cap_smoke_field(field: &mut serde_json::Value, cap: usize, dedup: bool) {
+    if let Some(count) = field.as_u64() {
+        *field = serde_json::json!(count.min(cap as u64));
+    } else if let Some(points) = field.as_array() {
+        let mut points: Vec<u64> = points
+            .iter()
+            .take(SMOKE_SWEEP_POINTS)
+            .filter_map(|point| point.as_u64())
+            .collect();
+        let top = points.iter().copied().max().unwrap_or(0);
+        if top > cap as u64 {
+            for point in points.iter_mut() {
+                *point = (*point as u128 * cap as u128 / top as u128) as u64;
+            }
+        }
+        if dedup {
+            let mut seen = HashSet::new();
+            points.retain(|point| seen.insert(*point));
+        }
+        *field = serde_json::json!(points);
+    } else {
+        cap_smoke(field);
+    }
+}
+
+/// Inserts the subcommand and arguments of the preset after `--preset <name>`, skipping the
+/// subcommand if it is also given right after, so that the arguments after it override the preset.
+/// Lists the presets and exits for `--list-presets`, as there is no subcommand to parse then
+fn expand_preset(args: Vec<OsString>) -> Vec<OsString> {
+    if args.iter().any(|arg| arg == "--list-presets") {
+        for preset in PRESETS {
+            println!("{}: {}", preset.name, preset.description);
+            println!("    {}", preset.args.join(" "));
+        }
+        process::exit(0);
+    }
+    let Some(flag) = args
+        .iter()
+        .position(|arg| arg == "--preset" || arg.to_string_lossy().starts_with("--preset="))
+    else {
+        return args;
+    };
+    let (name, after) = match args[flag].to_string_lossy().strip_prefix("--preset=") {
+        Some(name) => (name.to_string(), flag + 1),
+        None => match args.get(flag + 1) {
+            Some(name) => (name.to_string_lossy().into_owned(), flag + 2),
+            // Left for clap to report the missing value
+            None => return args,
+        },
+    };
+    let preset = find_preset(&name).unwrap_or_else(|| {
+        eprintln!(
+            "There is no preset named {name}, the presets are: [{}]. Exiting program.",
+            PRESETS
+                .iter()
+                .map(|preset| preset.name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        process::exit(1);
+    });
+    let rest = match args.get(after) {
+        Some(arg) if arg == preset.args[0] => after + 1,
+        _ => after,
+    };
+    args[..after]
+        .iter()
+        .cloned()
+        .chain(preset.args.iter().map(OsString::from))
+        .chain(args[rest..].iter().cloned())
+        .collect()
+}
+
+/// Runs the test with all its randomness derived from the seed, see SeedStream
+///
+/// Returns the path of the output if it was a json sweep.
+fn run_test(
+    test: Test,
+    seed: u64,
+    output: &OutputOptions,
+    preset: Option<&Preset>,
+    smoke: bool,
+) -> Option<PathBuf> {
+    let mut metadata = serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "seed": seed,
+        "seed_scheme": SEED_SCHEME,
+        "config": test,
+    });
+    if let Some(preset) = preset {
+        // The expansion as well as the name, as the preset may change after the run
+        metadata["preset"] = serde_json::json!(preset);
+    }
+    if smoke {
+        metadata["smoke"] = serde_json::json!(true);
+    }
+    let mut sweep_output = None;
+
+    // For the progress-based one, the average error seems to scale with the number of subqueues
+    // But the length-based one also scales with prefill and nbr_operations
+
+    match test {
+        Test::Single {
+            queue,
+            operations,
+            enqueues,
+            dequeues,
+            phases,
+            prefill,
+            workload,
+            error_readout,
+            dump_errors,
+            extra,
+            final_state,
+            memory_stats,
+            counter_gaps,
+            sync_every,
+            sync_window,
+            sample_items,
+            with_without_fallback,
+            with_without_priority,
+            full_log,
+            error_def,
+            split_fallback,
+            trace_stdout,
+            choice_impact,
+            certificate,
+            check_certificate,
+            exact_seed,
+            exact_ops_seed,
+            external_queue,
+            chunked,
+            chunk_warmup,
+        } => {
+            assert_not_recovery(error_readout);
+            let dump_format = dump_errors.as_ref().map(|path| {
+                DumpFormat::from_path(path).unwrap_or_else(|| {
+                    eprintln!(
+                        "The error dump must end in .csv, .json or .bin, optionally with .gz. Exiting program."
+                    );
+                    process::exit(1);
+                })
+            });
+            let count = OpsCount::new(operations, enqueues, dequeues, phases);
+            assert_has_dequeues([count]);
+            assert_item_capacity([(prefill, count)]);
+            let queue_seed =
+                exact_seed.unwrap_or_else(|| derive_seed(seed, SeedStream::Queue, &[0, 0]));
+            let ops_rng = |stream, indexes: &[u64]| match exact_ops_seed.or(exact_seed) {
+                Some(exact) => StdRng::seed_from_u64(exact),
+                None => stream_rng(seed, stream, indexes),
+            };
+            let mut online = workload.online(
+                count,
+                prefill,
+                ops_rng(SeedStream::OnlineOperations, &[0, 0]),
+            );
+            let (operations, threads) = match online {
+                Some(_) => (vec![], None),
+                None => workload.gen(count, prefill, &mut ops_rng(SeedStream::Operations, &[0])),
+            };
+            if let Some(name) = external_queue {
+                if error_def != ErrorDefinition::EnqueueRelative {
+                    eprintln!("The external queues only support the default error definition. Exiting program.");
+                    process::exit(1);
+                }
+                let factory = external_factory(&name, error_readout, &workload, None);
+                let mut external = factory(queue.subqueues, queue_seed);
+                let started = Instant::now();
+                let (rank_errors, oracle) = or_exit(analyze_simple_checked(
+                    external.as_mut(),
+                    prefill,
+                    &operations,
+                ));
+                let elapsed = started.elapsed();
+                if let (Some(path), Some(format)) = (dump_errors, dump_format) {
+                    if let Err(err) = dump_rank_errors(&path, format, &rank_errors, None) {
+                        eprintln!("Could not write the rank errors: {err}. Exiting program.");
+                        process::exit(1);
+                    }
+                    println!("Writing rank errors to: {}", path.to_string_lossy());
+                }
+                println!("{}", error_readout.readout(&rank_errors));
+                println!(
+                    "Oracle: {} enqueued, {} dequeued, {} left",
+                    oracle.enqueued, oracle.dead, oracle.live
+                );
+                println!(
+                    "Simulated operations per second: {:.0}",
+                    ops_per_second(prefill + operations.len(), elapsed)
+                );
+                return None;
+            }
+            if let Some(chunks) = chunked {
+                if online.is_some()
+                    || !error_readout.reads_rank_errors()
+                    || error_def != ErrorDefinition::EnqueueRelative
+                {
+                    eprintln!("The chunked runs only support the rank error readouts and the default error definition, without the closed-loop distributions. Exiting program.");
+                    process::exit(1);
+                }
+                if chunks == 0 || chunks > operations.len() {
+                    eprintln!("Must split the run into between 1 and the number of operations chunks. Exiting program.");
+                    process::exit(1);
+                }
+                queue.config.assert_valid(queue.subqueues);
+                let started = Instant::now();
+                let (rank_errors, warmup_ops) = chunked_rank_errors(
+                    &queue,
+                    prefill,
+                    &operations,
+                    chunks,
+                    chunk_warmup,
+                    queue_seed,
+                );
+                let elapsed = started.elapsed();
+                println!("{}", error_readout.readout(&rank_errors));
+                println!(
+                    "Approximated from {chunks} chunks started from steady states, with {warmup_ops} warmup operations"
+                );
+                println!(
+                    "Simulated operations per second: {:.0}",
+                    ops_per_second(prefill + operations.len() + warmup_ops, elapsed)
+                );
+                return None;
+            }
+            let budget_epoch = queue.config.budget_epoch;
+            queue
+                .config
+                .assert_budget_supported(&workload, error_readout, false);
+            let service_weights = queue
+                .config
+                .subqueue_weights
+                .as_ref()
+                .map(|weights| weights.weights(queue.subqueues));
+            // Shares the seed, so both queues sample the same indexes until their states diverge
+            let without_fallback = with_without_fallback
+                .then(|| queue.builder(queue_seed).empty_lin(false).build_sequenced());
+            let unprioritized_queue =
+                with_without_priority.then(|| queue.builder(queue_seed).build_sequenced());
+            let mut queue = queue
+                .builder(queue_seed)
+                .log_choices(
+                    choice_impact
+                        .then_some(prefill + operations.len())
+                        .or(trace_stdout.map(|ops| prefill + ops)),
+                )
+                .build_sequenced();
+
+            let flush = error_readout == ErrorReadout::PrefillFlush;
+            let dequeue_relative = error_def != ErrorDefinition::EnqueueRelative;
+            if (flush || dequeue_relative) && counter_gaps.is_some() {
+                eprintln!("The counter gaps can only be recorded with the default readout and error definition. Exiting program.");
+                process::exit(1);
+            }
+            if (flush || dequeue_relative) && sync_every.is_some() {
+                eprintln!("The sync barriers only support the default readouts and error definition. Exiting program.");
+                process::exit(1);
+            }
+            if (flush || dequeue_relative) && with_without_fallback {
+                eprintln!("The fallback comparison only supports the default readouts and error definition. Exiting program.");
+                process::exit(1);
+            }
+            if (flush || dequeue_relative) && with_without_priority {
+                eprintln!("The priority comparison only supports the default readouts and error definition. Exiting program.");
+                process::exit(1);
+            }
+            if (flush || dequeue_relative) && full_log.is_some() {
+                eprintln!("The run log only supports the default readouts and error definition. Exiting program.");
+                process::exit(1);
+            }
+            if sample_items.is_some_and(|probability| !(0.0..=1.0).contains(&probability)) {
+                eprintln!("The item sampling probability must be in [0, 1]. Exiting program.");
+                process::exit(1);
+            }
+            if certificate.is_some() && operations.len() > MAX_CERTIFICATE_OPERATIONS {
+                eprintln!("Certificates are only written for runs of at most {MAX_CERTIFICATE_OPERATIONS} operations. Exiting program.");
+                process::exit(1);
+            }
+            if sync_every == Some(0) || sync_window == 0 {
+                eprintln!("The syncs and their windows must be at least one operation apart. Exiting program.");
+                process::exit(1);
+            }
+            let trace = trace_stdout.is_some();
+            if online.is_some()
+                && (extra
+                    || flush
+                    || dequeue_relative
+                    || split_fallback
+                    || trace
+                    || choice_impact
+                    || certificate.is_some()
+                    || counter_gaps.is_some()
+                    || sync_every.is_some()
+                    || full_log.is_some()
+                    || with_without_fallback
+                    || sample_items.is_some())
+            {
+                eprintln!("The closed-loop distributions only support the default readouts and error definition. Exiting program.");
+                process::exit(1);
+            }
+            workload.assert_bounded_supported(error_readout, false);
+            workload.assert_batch_supported(error_readout, false);
+            if (workload.max_items.is_some() || workload.is_batched() || budget_epoch.is_some())
+                && (extra
+                    || phases.is_some()
+                    || dequeue_relative
+                    || split_fallback
+                    || trace
+                    || choice_impact
+                    || certificate.is_some()
+                    || threads.is_some()
+                    || counter_gaps.is_some()
+                    || sync_every.is_some()
+                    || full_log.is_some()
+                    || with_without_fallback
+                    || with_without_priority
+                    || sample_items.is_some())
+            {
+                eprintln!("The item capacity, batches and budget only support the default readouts and error definition. Exiting program.");
+                process::exit(1);
+            }
+            if let Some(mut without_fallback) = without_fallback {
+                let with_tags = or_exit(analyze_extra(&mut queue, prefill, &operations, None));
+                let without_tags = or_exit(analyze_extra(
+                    &mut without_fallback,
+                    prefill,
+                    &operations,
+                    None,
+                ));
+                let comparison = fallback_comparison(&with_tags, &without_tags, &operations);
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&comparison)
+                        .expect("Failed to serialize the comparison")
+                );
+                return None;
+            }
+            if let Some(mut unprioritized_queue) = unprioritized_queue {
+                let unprioritized = workload
+                    .gen_unprioritized(count, prefill, &mut ops_rng(SeedStream::Operations, &[0]))
+                    .0;
+                let prioritized_tags =
+                    or_exit(analyze_extra(&mut queue, prefill, &operations, None));
+                let unprioritized_tags = or_exit(analyze_extra(
+                    &mut unprioritized_queue,
+                    prefill,
+                    &unprioritized,
+                    None,
+                ));
+                let comparison = priority_comparison(
+                    (&prioritized_tags, &operations),
+                    (&unprioritized_tags, &unprioritized),
+                );
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&comparison)
+                        .expect("Failed to serialize the comparison")
+                );
+                return None;
+            }
+            let mut enqueue_failures = None;
+            let started = Instant::now();
+            let mut item_fates = None;
+            let error_tags = (extra
+                || phases.is_some()
+                || flush
+                || dequeue_relative
+                || split_fallback
+                || trace
+                || choice_impact
+                || certificate.is_some()
+                || threads.is_some()
+                || sample_items.is_some())
+            .then(|| match sample_items {
+                Some(probability) => {
+                    let (error_tags, fates) = or_exit(analyze_item_fates(
+                        &mut queue,
+                        prefill,
+                        &operations,
+                        threads.as_deref(),
+                        probability,
+                        &mut stream_rng(seed, SeedStream::ItemSampling, &[0, 0]),
+                    ));
+                    item_fates = Some(fates);
+                    error_tags
+                }
+                None => or_exit(analyze_extra(
+                    &mut queue,
+                    prefill,
+                    &operations,
+                    threads.as_deref(),
+                )),
+            });
+            let mut gap_series = vec![];
+            let mut sync_moves = vec![];
+            let mut rank_errors: Vec<usize> = match (&error_tags, counter_gaps) {
+                (Some(error_tags), _) => error_tags.iter().map(|tag| tag.rank_error()).collect(),
+                (None, _) if online.is_some() => {
+                    let source = online.as_deref_mut().expect("Checked by the guard");
+                    let (rank_errors, operations) = or_exit(match budget_epoch {
+                        Some(epoch) => analyze_online(
+                            &mut BudgetedQueue::new(&mut queue, epoch),
+                            prefill,
+                            source,
+                        ),
+                        None => analyze_online(&mut queue, prefill, source),
+                    });
+                    if operations.len() < count.total() {
+                        println!(
+                            "The queue emptied after {} of {} operations",
+                            operations.len(),
+                            count.total()
+                        );
+                    }
+                    rank_errors
+                }
+                (None, None) if full_log.is_some() => {
+                    let path = full_log.as_deref().expect("Checked by the guard");
+                    let compress = path.extension().is_some_and(|extension| extension == "gz");
+                    let logged = create_output(path, compress).and_then(|mut log| {
+                        let rank_errors =
+                            analyze_logged(&mut queue, prefill, &operations, &mut log)?;
+                        log.finish()?;
+                        Ok(rank_errors)
+                    });
+                    let sidecar = serde_json::json!({
+                        "schema": LogRecord::schema(),
+                        "prefill": prefill,
+                        "operations": operations.len(),
+                        "records": prefill + operations.len(),
+                        "metadata": metadata,
+                    });
+                    let mut sidecar_path = path.as_os_str().to_owned();
+                    sidecar_path.push(".json");
+                    let written = logged.and_then(|rank_errors| {
+                        let file = BufWriter::new(File::create(&sidecar_path)?);
+                        serde_json::to_writer_pretty(file, &sidecar)?;
+                        Ok(rank_errors)
+                    });
+                    match written {
+                        Ok(rank_errors) => {
+                            println!("Writing run log to: {}", path.to_string_lossy());
+                            rank_errors
+                        }
+                        Err(err) => {
+                            eprintln!("Could not write the run log: {err}. Exiting program.");
+                            process::exit(1);
+                        }
+                    }
+                }
+                (None, None) if sync_every.is_some() => {
+                    let every = sync_every.expect("Checked by the guard");
+                    let (rank_errors, moves) = or_exit(analyze_with_barriers(
+                        &mut queue,
+                        prefill,
+                        &operations,
+                        every,
+                    ));
+                    sync_moves = moves;
+                    rank_errors
+                }
+                (None, Some(every)) => or_exit(analyze_with_hook(
+                    &mut queue,
+                    prefill,
+                    &operations,
+                    every,
+                    |op_nbr, queue| {
+                        let (enqueue_gap, dequeue_gap) = queue.counter_gaps();
+                        gap_series.push(serde_json::json!({
+                            "operations": op_nbr,
+                            "enqueue_gap": enqueue_gap,
+                            "dequeue_gap": dequeue_gap,
+                        }));
+                    },
+                )),
+                (None, None) => {
+                    let mut rank_errors = vec![];
+                    enqueue_failures = analyze_into(
+                        &mut queue,
+                        prefill,
+                        &operations,
+                        &workload,
+                        budget_epoch,
+                        &mut rank_errors,
+                    );
+                    rank_errors
+                }
+            };
+            let elapsed = started.elapsed();
+            if let (Some(records), Some(error_tags), Some(ops)) =
+                (queue.choice_log(), &error_tags, trace_stdout)
+            {
+                for line in format_choice_trace(records, prefill, error_tags)
+                    .into_iter()
+                    .take(ops)
+                {
+                    println!("{line}");
+                }
+            }
+            if let (Some(path), Some(error_tags)) = (&certificate, &error_tags) {
+                let certificate = Certificate::new(prefill, &operations, error_tags);
+                if check_certificate {
+                    let recomputed = certificate.rank_errors();
+                    let mismatch = error_tags
+                        .iter()
+                        .zip(recomputed.iter())
+                        .position(|(tag, rank_error)| tag.rank_error() != *rank_error);
+                    if let Some(dequeue) = mismatch {
+                        eprintln!(
+                            "The certificate gives dequeue {dequeue} rank error {}, but the run gave {}. Exiting program.",
+                            recomputed[dequeue],
+                            error_tags[dequeue].rank_error()
+                        );
+                        process::exit(1);
+                    }
+                    println!("Certificate check: {} rank errors agree", recomputed.len());
+                }
+                let compress = path.extension().is_some_and(|extension| extension == "gz");
+                let written = create_output(path, compress).and_then(|mut file| {
+                    serde_json::to_writer(&mut file, &certificate)?;
+                    file.finish()
+                });
+                if let Err(err) = written {
+                    eprintln!("Could not write the certificate: {err}. Exiting program.");
+                    process::exit(1);
+                }
+                println!("Writing certificate to: {}", path.to_string_lossy());
+            }
+            let mut dequeue_relative_errors = error_tags
+                .as_deref()
+                .filter(|_| dequeue_relative)
+                .map(dequeue_relative_errors);
+            if error_def == ErrorDefinition::DequeueRelative {
+                rank_errors = dequeue_relative_errors.take().expect("Analyzed with tags");
+            }
+
+            if let (Some(path), Some(format)) = (dump_errors, dump_format) {
+                let tags = error_tags.as_deref().filter(|_| extra);
+                if let Err(err) = dump_rank_errors(&path, format, &rank_errors, tags) {
+                    eprintln!("Could not write the rank errors: {err}. Exiting program.");
+                    process::exit(1);
+                }
+                println!("Writing rank errors to: {}", path.to_string_lossy());
+            }
+
+            match &error_tags {
+                Some(error_tags) if flush => {
+                    let flush = analyze_prefill_flush(error_tags, prefill);
+                    println!("{}", ErrorReadout::flush_readout(&flush));
+                    println!(
+                        "Prefill items: {} remaining, mean rank error {}, max rank error {}",
+                        flush.remaining, flush.mean_rank_error, flush.max_rank_error
+                    );
+                }
+                _ if error_def == ErrorDefinition::Both => {
+                    let dequeue_relative_errors =
+                        dequeue_relative_errors.expect("Analyzed with tags");
+                    println!("Enqueue-relative: {}", error_readout.readout(&rank_errors));
+                    println!(
+                        "Dequeue-relative: {}",
+                        error_readout.readout(&dequeue_relative_errors)
+                    );
+                }
+                _ if error_readout == ErrorReadout::MaxHighWaterMark => {
+                    println!("{}", ErrorReadout::high_water_readout(&queue))
+                }
+                _ => println!("{}", error_readout.readout(&rank_errors)),
+            }
+            if let Some(failures) = &enqueue_failures {
+                println!(
+                    "Failed enqueues: {} ({})",
+                    failures.count,
+                    serde_json::json!(failures.sizes)
+                );
+            }
+            if let (Some(error_tags), Some(_)) = (&error_tags, &threads) {
+                for (thread, summary) in analyze_per_thread(error_tags).iter().enumerate() {
+                    println!(
+                        "Thread {thread}: {} dequeues, mean rank error {}, p99 rank error {}, max rank error {}",
+                        summary.dequeues,
+                        summary.mean_rank_error,
+                        summary.p99_rank_error,
+                        summary.max_rank_error
+                    );
+                }
+            }
+            if let (Some(error_tags), Some(phases)) = (&error_tags, phases) {
+                let summaries = analyze_phases(error_tags, &operations, &phases.lengths());
+                for (name, summary) in Phases::NAMES.iter().zip(summaries) {
+                    println!(
+                        "{name}: {} operations, {} dequeues ({} empty), mean rank error {}, p99 rank error {}, max rank error {}",
+                        summary.operations,
+                        summary.dequeues,
+                        summary.empty_returns,
+                        summary.mean_rank_error,
+                        summary.p99_rank_error,
+                        summary.max_rank_error
+                    );
+                }
+            }
+            if let Some(error_tags) = error_tags.as_deref().filter(|_| split_fallback) {
+                let (fallbacks, directs): (Vec<&ErrorTag>, Vec<&ErrorTag>) =
+                    error_tags.iter().partition(|tag| tag.fell_back());
+                let mean = |tags: &[&ErrorTag]| {
+                    tags.iter().map(|tag| tag.rank_error()).sum::<usize>() as f32
+                        / tags.len().max(1) as f32
+                };
+                println!(
+                    "Fallback dequeues: {} of {}, mean rank error {} (direct {})",
+                    fallbacks.len(),
+                    error_tags.len(),
+                    mean(&fallbacks),
+                    mean(&directs)
+                );
+            }
+            if let (Some(records), Some(error_tags)) =
+                (queue.choice_log().filter(|_| choice_impact), &error_tags)
+            {
+                let impact = analyze_choice_impact(records, prefill, error_tags);
+                println!(
+                    "Changed choices: {} of the operations, mean rank error improvement {} on the changed dequeues",
+                    impact.changed_fraction, impact.mean_improvement
+                );
+                println!(
+                    "{:<10}{:>12}{:>10}{:>10}{:>10}{:>14}",
+                    "Kind", "Operations", "Changed", "Fraction", "Compared", "Improvement"
+                );
+                for (name, row) in [("enqueue", &impact.enqueues), ("dequeue", &impact.dequeues)] {
+                    println!(
+                        "{:<10}{:>12}{:>10}{:>10.4}{:>10}{:>14.3}",
+                        name,
+                        row.operations,
+                        row.changed,
+                        row.changed_fraction,
+                        row.compared,
+                        row.mean_improvement
+                    );
+                }
+            }
+            if queue.skew_cap_triggers() > 0 {
+                println!("Skew cap triggered {} times", queue.skew_cap_triggers());
+            }
+            if let Some(average_d) = queue.average_d() {
+                println!("Average d: {average_d}");
+            }
+            let cost = queue.cost_report();
+            println!(
+                "Sub-queue touches per operation: {} ({})",
+                cost.touches_per_operation(),
+                serde_json::to_string(&cost).expect("Failed to serialize the cost report")
+            );
+            if let Some(weights) = service_weights {
+                println!(
+                    "Service rates: {}",
+                    serde_json::to_string(&service_rates(&weights, &queue))
+                        .expect("Failed to serialize the service rates")
+                );
+            }
+            if let Some(time) = queue.virtual_time() {
+                println!(
+                    "Virtual time: {} ns (enqueues {} ns, dequeues {} ns), {} operations per virtual second",
+                    time.total(),
+                    time.enqueue_time,
+                    time.dequeue_time,
+                    time.throughput()
+                );
+            }
+            println!(
+                "Simulated operations per second: {:.0}",
+                ops_per_second(prefill + cost.operations, elapsed)
+            );
+            if counter_gaps.is_some() {
+                println!(
+                    "{}",
+                    serde_json::to_string(&gap_series).expect("Failed to serialize the gaps")
+                );
+            }
+            if sync_every.is_some() {
+                println!(
+                    "Syncs: {}, items moved per sync: {}",
+                    sync_moves.len(),
+                    sync_moves.iter().sum::<usize>() as f32 / sync_moves.len().max(1) as f32
+                );
+                let mut errors = rank_errors.iter();
+                let op_errors: Vec<Option<usize>> = operations
+                    .iter()
+                    .map(|is_enqueue| {
+                        (!is_enqueue).then(|| *errors.next().expect("One error per dequeue"))
+                    })
+                    .collect();
+                println!(
+                    "{}",
+                    serde_json::json!(windowed_mean_errors(&op_errors, sync_window))
+                );
+            }
+            if memory_stats {
+                let marks = queue.subqueue_high_water_marks();
+                println!(
+                    "Sub-queue high-water marks: max {} ({})",
+                    marks.iter().max().expect("The queue has sub-queues"),
+                    serde_json::json!(marks)
+                );
+            }
+            if final_state {
+                let report = queue.final_state_report();
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report).expect("Failed to serialize the report")
+                );
+                write_output("FinalState", output, &metadata, &report);
+            }
+            if let Some(fates) = &item_fates {
+                println!(
+                    "{}",
+                    serde_json::to_string(fates).expect("Failed to serialize the item fates")
+                );
+            }
+            if queue.helps() > 0 {
+                println!("Helped lagging sub-queues {} times", queue.helps());
+            }
+            if queue.peeks() > 0 {
+                println!(
+                    "Peeked {} times, {} per dequeue",
+                    queue.peeks(),
+                    queue.peeks() as f32
+                        / queue.subqueue_dequeue_counts().iter().sum::<usize>() as f32
+                );
+            }
+            if final_state {
+                // Last, as draining changes the counters of the queue
+                let remaining = queue.len();
+                let drained = queue.drain();
+                let distinct: HashSet<&usize> = drained.iter().collect();
+                if drained.len() != remaining || distinct.len() != remaining {
+                    eprintln!(
+                        "Drained {} items ({} distinct) from a queue holding {remaining}. Exiting program.",
+                        drained.len(),
+                        distinct.len()
+                    );
+                    process::exit(1);
+                }
+                println!("Drained the {remaining} remaining items");
+            }
+        }
+        Test::OpsAndPrefill {
+            queue,
+            kinds,
+            external_queue,
+            operations,
+            ops_per_prefill,
+            enqueues,
+            dequeues,
+            prefill,
+            workload,
+            output_name,
+            runs,
+            error_readout,
+            histogram,
+            aggregate,
+            format,
+            recovery,
+            diagnostics,
+            limits,
+            fit,
+            describe_ops,
+        } => {
+            recovery.assert_valid(error_readout);
+            workload.assert_describable(describe_ops);
+            assert_histogram_supported(histogram, error_readout);
+            assert_aggregate_supported(aggregate, error_readout);
+            format.assert_available();
+            FitDimension::assert_in_sweep(fit, &[FitDimension::Prefill, FitDimension::Ops], format);
+            assert_uniques(&prefill);
+            let points: Vec<(usize, OpsCount)> = match ops_per_prefill {
+                Some(ratio) => prefill
+                    .iter()
+                    .map(|pre| (*pre, OpsCount::per_prefill(ratio, *pre)))
+                    .collect(),
+                None => {
+                    let counts = OpsCount::new_list(operations, enqueues, dequeues);
+                    assert_uniques(&counts);
+                    assert_has_dequeues(counts.iter().cloned());
+                    counts
+                        .iter()
+                        .flat_map(|ops| prefill.iter().map(|pre| (*pre, *ops)))
+                        .collect()
+                }
+            };
+            assert_item_capacity(points.iter().cloned());
+            workload.assert_online_supported(error_readout, points.iter().cloned());
+            workload.assert_bounded_supported(error_readout, false);
+            workload.assert_batch_supported(error_readout, false);
+            queue
+                .config
+                .assert_budget_supported(&workload, error_readout, false);
+            let variants = SweepQueue::all(&kinds, &queue.config);
+            queue.config.assert_sweep_valid(&variants, queue.subqueues);
+            let budget_epoch = queue.config.budget_epoch;
+            let external = external_queue
+                .map(|name| external_factory(&name, error_readout, &workload, budget_epoch));
+            if external.is_some() && variants.len() > 1 {
+                eprintln!(
+                    "The external queues do not take several --enq-d or --deq-d. Exiting program."
+                );
+                process::exit(1);
+            }
+            let configs: Vec<(usize, usize, usize)> = points
+                .iter()
+                .map(|(pre, ops)| (*pre, ops.total(), queue.subqueues))
+                .collect();
+            let limiter = limits.setup(&configs, runs * variants.len(), aggregate, histogram);
+
+            let (results, descriptions): (Vec<Vec<_>>, Vec<_>) = points
+                .par_iter()
+                .enumerate()
+                .map(|(point, &(pre, ops))| {
+                    let point = point as u64;
+                    // Some distributions depend on the prefill, so generate them for each point
+                    let ops_vec = workload.gen_fixed(
+                        ops,
+                        pre,
+                        &mut stream_rng(seed, SeedStream::Operations, &[point]),
+                    );
+                    // The kinds of a point run together, so that early results cover all kinds
+                    let variant_results: Vec<_> = variants
+                        .par_iter()
+                        .map(|&variant| {
+                            let values: Vec<RunResult> = (0..runs as u64)
+                                .into_par_iter()
+                                .map(|run| {
+                                    limiter.run(|| {
+                                        let seeds = RunSeeds {
+                                            queue: derive_seed(
+                                                seed,
+                                                SeedStream::Queue,
+                                                &[point, run],
+                                            ),
+                                            operations: match ops_vec {
+                                                Some(_) => derive_seed(
+                                                    seed,
+                                                    SeedStream::Operations,
+                                                    &[point],
+                                                ),
+                                                None => derive_seed(
+                                                    seed,
+                                                    SeedStream::OnlineOperations,
+                                                    &[point, run],
+                                                ),
+                                            },
+                                            layout: None,
+                                        };
+                                        if let Some(factory) = external {
+                                            return external_run(
+                                                factory(queue.subqueues, seeds.queue),
+                                                seeds,
+                                                pre,
+                                                ops_vec.as_deref().expect("Generated up front"),
+                                                error_readout,
+                                                aggregate,
+                                            );
+                                        }
+                                        let mut queue = queue.config.init_sweep(
+                                            queue.subqueues,
+                                            variant,
+                                            seeds.queue,
+                                        );
+                                        let mut run_histogram = histogram.map(LogHistogram::new);
+                                        let started = Instant::now();
+                                        let readout = match &ops_vec {
+                                            Some(ops_vec) => error_readout.analyze(
+                                                &mut queue,
+                                                pre,
+                                                ops_vec,
+                                                run_histogram.as_mut(),
+                                                &workload,
+                                                &recovery,
+                                                budget_epoch,
+                                            ),
+                                            None => error_readout.analyze_online(
+                                                &mut queue,
+                                                pre,
+                                                &mut *workload
+                                                    .online(
+                                                        ops,
+                                                        pre,
+                                                        StdRng::seed_from_u64(seeds.operations),
+                                                    )
+                                                    .expect("Online workload"),
+                                                run_histogram.as_mut(),
+                                                budget_epoch,
+                                            ),
+                                        };
+                                        RunResult::new(
+                                            readout,
+                                            &queue,
+                                            run_histogram,
+                                            aggregate,
+                                            seeds,
+                                            pre,
+                                            started.elapsed(),
+                                        )
+                                    })
+                                })
+                                .collect();
+                            (
+                                (variant, pre, ops),
+                                PointResult::combine(values, aggregate, error_readout),
+                            )
+                        })
+                        .collect();
+                    let description = ops_vec
+                        .filter(|_| describe_ops)
+                        .map(|ops_vec| describe_operations(pre, &ops_vec));
+                    (variant_results, description)
+                })
+                .unzip();
+            let mut results: Vec<_> = results.into_iter().flatten().collect();
+            // Rayon keeps the order already, but sort on the keys to not rely on it
+            results.sort_by_key(|((variant, pre, ops), _)| (*pre, ops.sort_key(), *variant));
+            if describe_ops {
+                metadata["ops_descriptions"] = ops_descriptions_json(
+                    points
+                        .iter()
+                        .cloned()
+                        .zip(descriptions.into_iter().flatten()),
+                );
+            }
+            let fits = fit.map(|dimension| {
+                let points = results
+                    .iter()
+                    .map(|((_, pre, ops), point)| match dimension {
+                        FitDimension::Ops => (*pre, ops.total(), point.value),
+                        _ => (ops.total(), *pre, point.value),
+                    });
+                let fixed = if dimension == FitDimension::Ops {
+                    "prefill"
+                } else {
+                    "operations"
+                };
+                fit_groups(fixed, points)
+            });
+
+            let warnings: Vec<PointWarning> = results
+                .iter()
+                .flat_map(|((variant, pre, ops), point)| {
+                    let key = variant.key(format!("{pre}, {}", ops.total()));
+                    point.diagnose(&key, error_readout, aggregate, &diagnostics)
+                })
+                .collect();
+            report_warnings(&warnings);
+
+            if format == OutputFormat::Parquet {
+                metadata["warnings"] = serde_json::json!(warnings);
+                let rows: Vec<SweepRow> = results
+                    .into_iter()
+                    .flat_map(|((variant, prefill, ops), point)| {
+                        let [subqueues, enqueue_d, dequeue_d] =
+                            variant.shape(queue.subqueues, &queue.config);
+                        point.sweep_rows(SweepRow {
+                            subqueues,
+                            enqueue_d,
+                            dequeue_d,
+                            prefill,
+                            operations: ops.total(),
+                            runs,
+                            readout: error_readout.name(),
+                            queue: variant.kind.map(|kind| kind.name()),
+                            ..SweepRow::default()
+                        })
+                    })
+                    .collect();
+                write_parquet_output(&output_name, output, &metadata, &rows);
+                return None;
+            }
+
+            // Inefficient way to get it to print nicely
+            let string_keyed_results: Vec<serde_json::Value> = results
+                .into_iter()
+                .map(|((variant, pre, ops), point)| {
+                    let key = variant.key(format!("{pre}, {}", ops.total()));
+                    variant.with_columns(point.with_extras(ops.record(key, point.value)))
+                })
+                .collect();
+            sweep_output = Some(write_sweep_output(
+                &output_name,
+                output,
+                &metadata,
+                &string_keyed_results,
+                fits,
+                &warnings,
+            ));
+        }
+        Test::SubqueuesAndPrefill {
+            queue,
+            kinds,
+            external_queue,
+            operations,
+            ops_per_prefill,
+            enqueues,
+            dequeues,
+            subqueues,
+            prefill,
+            workload,
+            output_name,
+            runs,
+            error_readout,
+            histogram,
+            aggregate,
+            format,
+            recovery,
+            diagnostics,
+            limits,
+            fit,
+            describe_ops,
+        } => {
+            recovery.assert_valid(error_readout);
+            workload.assert_describable(describe_ops);
+            assert_histogram_supported(histogram, error_readout);
+            assert_aggregate_supported(aggregate, error_readout);
+            format.assert_available();
+            FitDimension::assert_in_sweep(
+                fit,
+                &[FitDimension::Subqueues, FitDimension::Prefill],
+                format,
+            );
+            assert_uniques(&prefill);
+            assert_uniques(&subqueues);
+            let counts: Vec<OpsCount> = match ops_per_prefill {
+                Some(ratio) => prefill
+                    .iter()
+                    .map(|pre| OpsCount::per_prefill(ratio, *pre))
+                    .collect(),
+                None => vec![OpsCount::new(operations, enqueues, dequeues, None); prefill.len()],
+            };
+            assert_has_dequeues(counts.iter().cloned());
+            assert_item_capacity(prefill.iter().cloned().zip(counts.iter().cloned()));
+            workload.assert_online_supported(
+                error_readout,
+                prefill.iter().cloned().zip(counts.iter().cloned()),
+            );
+            workload.assert_bounded_supported(error_readout, false);
+            workload.assert_batch_supported(error_readout, false);
+            queue.assert_budget_supported(&workload, error_readout, false);
+            let variants = SweepQueue::all(&kinds, &queue);
+            for p in &subqueues {
+                queue.assert_sweep_valid(&variants, *p);
+            }
+            let budget_epoch = queue.budget_epoch;
+            let external = external_queue
+                .map(|name| external_factory(&name, error_readout, &workload, budget_epoch));
+            if external.is_some() && variants.len() > 1 {
+                eprintln!(
+                    "The external queues do not take several --enq-d or --deq-d. Exiting program."
+                );
+                process::exit(1);
+            }
+            let configs: Vec<(usize, usize, usize)> = subqueues
+                .iter()
+                .flat_map(|p| {
+                    prefill
+                        .iter()
+                        .zip(counts.iter())
+                        .map(|(pre, count)| (*pre, count.total(), *p))
+                })
+                .collect();
+            let limiter = limits.setup(&configs, runs * variants.len(), aggregate, histogram);
+
+            // Some distributions depend on the prefill, so generate them for each prefill value
+            let ops_vecs: Vec<Option<Vec<bool>>> = prefill
+                .iter()
+                .zip(counts.iter())
+                .enumerate()
+                .map(|(ind, (pre, count))| {
+                    let mut rng = stream_rng(seed, SeedStream::Operations, &[ind as u64]);
+                    workload.gen_fixed(*count, *pre, &mut rng)
+                })
+                .collect();
+            if describe_ops {
+                metadata["ops_descriptions"] = ops_descriptions_json(
+                    prefill
+                        .iter()
+                        .zip(counts.iter())
+                        .zip(ops_vecs.iter().flatten())
+                        .map(|((pre, count), ops_vec)| {
+                            ((*pre, *count), describe_operations(*pre, ops_vec))
+                        }),
+                );
+            }
+
+            let mut results: Vec<(_, PointResult)> = subqueues
+                .par_iter()
+                .enumerate()
+                .flat_map(|(p_ind, p)| {
+                    // Borrowed by the moving closures below, which have to own the indexes
+                    let (queue, variants, workload, recovery, limiter) =
+                        (&queue, &variants, &workload, &recovery, &limiter);
+                    prefill
+                        .par_iter()
+                        .zip(counts.par_iter())
+                        .zip(ops_vecs.par_iter())
+                        .enumerate()
+                        // The kinds of a point run together, so that early results cover all kinds
+                        .flat_map(move |(pre_ind, ((pre, count), ops_vec))| {
+                            variants
+                                .par_iter()
+                                .map(move |&variant| (pre_ind, pre, count, ops_vec, variant))
+                        })
+                        .map(move |(pre_ind, pre, count, ops_vec, variant)| {
+                            let key = (*p, *pre, variant);
+                            let indexes = [p_ind as u64, pre_ind as u64];
+                            let values: Vec<RunResult> = (0..runs as u64)
+                                .into_par_iter()
+                                .map(|run| {
+                                    let indexes = [indexes[0], indexes[1], run];
+                                    limiter.run(|| {
+                                        let seeds = RunSeeds {
+                                            queue: derive_seed(seed, SeedStream::Queue, &indexes),
+                                            operations: match ops_vec {
+                                                Some(_) => derive_seed(
+                                                    seed,
+                                                    SeedStream::Operations,
+                                                    &[indexes[1]],
+                                                ),
+                                                None => derive_seed(
+                                                    seed,
+                                                    SeedStream::OnlineOperations,
+                                                    &indexes,
+                                                ),
+                                            },
+                                            layout: None,
+                                        };
+                                        if let Some(factory) = external {
+                                            return external_run(
+                                                factory(*p, seeds.queue),
+                                                seeds,
+                                                *pre,
+                                                ops_vec.as_deref().expect("Generated up front"),
+                                                error_readout,
+                                                aggregate,
+                                            );
+                                        }
+                                        let mut queue = queue.init_sweep(*p, variant, seeds.queue);
+                                        let mut run_histogram = histogram.map(LogHistogram::new);
+                                        let started = Instant::now();
+                                        let readout = match ops_vec {
+                                            Some(ops_vec) => error_readout.analyze(
+                                                &mut queue,
+                                                *pre,
+                                                ops_vec,
+                                                run_histogram.as_mut(),
+                                                workload,
+                                                recovery,
+                                                budget_epoch,
+                                            ),
+                                            None => error_readout.analyze_online(
+                                                &mut queue,
+                                                *pre,
+                                                &mut *workload
+                                                    .online(
+                                                        *count,
+                                                        *pre,
+                                                        StdRng::seed_from_u64(seeds.operations),
+                                                    )
+                                                    .expect("Online workload"),
+                                                run_histogram.as_mut(),
+                                                budget_epoch,
+                                            ),
+                                        };
+                                        RunResult::new(
+                                            readout,
+                                            &queue,
+                                            run_histogram,
+                                            aggregate,
+                                            seeds,
+                                            *pre,
+                                            started.elapsed(),
+                                        )
+                                    })
+                                })
+                                .collect();
+                            (key, PointResult::combine(values, aggregate, error_readout))
+                        })
+                })
+                .collect();
+            results.sort_by_key(|(key, _)| *key);
+            let fits = fit.map(|dimension| {
+                let points = results.iter().map(|((p, pre, _), point)| match dimension {
+                    FitDimension::Subqueues => (*pre, *p, point.value),
+                    _ => (*p, *pre, point.value),
+                });
+                let fixed = if dimension == FitDimension::Subqueues {
+                    "prefill"
+                } else {
+                    "subqueues"
+                };
+                fit_groups(fixed, points)
+            });
+
+            let warnings: Vec<PointWarning> = results
+                .iter()
+                .flat_map(|((p, pre, variant), point)| {
+                    let key = variant.key(format!("{p}, {pre}"));
+                    point.diagnose(&key, error_readout, aggregate, &diagnostics)
+                })
+                .collect();
+            report_warnings(&warnings);
+
+            if format == OutputFormat::Parquet {
+                metadata["warnings"] = serde_json::json!(warnings);
+                let rows: Vec<SweepRow> = results
+                    .into_iter()
+                    .flat_map(|((p, pre, variant), point)| {
+                        let [subqueues, enqueue_d, dequeue_d] = variant.shape(p, &queue);
+                        point.sweep_rows(SweepRow {
+                            subqueues,
+                            enqueue_d,
+                            dequeue_d,
+                            prefill: pre,
+                            operations: counts
+                                [prefill.iter().position(|other| *other == pre).unwrap()]
+                            .total(),
+                            runs,
+                            readout: error_readout.name(),
+                            queue: variant.kind.map(|kind| kind.name()),
+                            ..SweepRow::default()
+                        })
+                    })
+                    .collect();
+                write_parquet_output(&output_name, output, &metadata, &rows);
+                return None;
+            }
+
+            // Inefficient way to get it to print nicely
+            let string_keyed_results: Vec<serde_json::Value> = results
+                .into_iter()
+                .map(|((p, pre, variant), point)| {
+                    let key = variant.key(format!("{p}, {pre}"));
+                    let count = counts[prefill.iter().position(|other| *other == pre).unwrap()];
+                    let record = match ops_per_prefill {
+                        Some(_) => {
+                            serde_json::json!([key, point.value, { "operations": count.total() }])
+                        }
+                        None => count.record(key, point.value),
+                    };
+                    variant.with_columns(point.with_extras(record))
+                })
+                .collect();
+            sweep_output = Some(write_sweep_output(
+                &output_name,
+                output,
+                &metadata,
+                &string_keyed_results,
+                fits,
+                &warnings,
+            ));
+        }
+        Test::Distributions {
+            queue,
+            operations,
+            enqueues,
+            dequeues,
+            phases,
+            prefill,
+            output_name,
+            runs,
+            workload,
+            heatmap,
+            heatmap_op_buckets,
+            heatmap_error_buckets,
+            tag_prefill,
+            split_fallback,
+            age_by_subqueue,
+            memory_stats,
+            decompose,
+            choice_impact,
+            conditional_spread,
+            no_decorrelate,
+            stationary,
+            burn_in,
+            thinning,
+            normalize,
+            pdf_samples,
+        } => {
+            if thinning == 0 {
+                eprintln!(
+                    "The thinning must keep every k-th rank error for some k > 0. Exiting program."
+                );
+                process::exit(1);
+            }
+            if pdf_samples == Some(0) {
+                eprintln!(
+                    "The distributions must be resampled to at least one point. Exiting program."
+                );
+                process::exit(1);
+            }
+            let count = OpsCount::new(operations, enqueues, dequeues, phases);
+            assert_item_capacity([(prefill, count)]);
+            workload.assert_bounded_supported(ErrorReadout::Average, true);
+            workload.assert_batch_supported(ErrorReadout::Average, true);
+            queue
+                .config
+                .assert_budget_supported(&workload, ErrorReadout::Average, true);
+            let (ops_vec, threads) = workload.gen(
+                count,
+                prefill,
+                &mut stream_rng(seed, SeedStream::Operations, &[0]),
+            );
+            let options = DistributionOptions {
+                heatmap: heatmap.then_some((heatmap_op_buckets, heatmap_error_buckets)),
+                tag_prefill,
+                split_fallback,
+                age_by_subqueue,
+                decorrelate: !no_decorrelate,
+                stationary: stationary.then_some((burn_in, thinning)),
+                phases: phases.map(|phases| phases.lengths()),
+                decompose,
+                choice_impact,
+                conditional_spread,
+                track_sizes: normalize == Normalization::PerSize,
+            };
+
+            // Collect each distribution of all the runs, to band each data point over them
+            let mut rank_errors = vec![];
+            let mut enq_deq_diffs = vec![];
+            let mut subqueue_deq_diffs = vec![];
+            let mut subqueue_enq_diffs = vec![];
+            let mut subqueue_deq_counts = vec![];
+            let mut subqueue_enq_counts = vec![];
+            let mut prefill_rank_errors = vec![];
+            let mut operation_rank_errors = vec![];
+            let mut direct_rank_errors = vec![];
+            let mut fallback_rank_errors = vec![];
+            let mut fallback_fractions = vec![];
+            let mut rank_error_heatmap: Option<RankErrorHeatmap> = None;
+            let mut per_thread: Option<Vec<ThreadSummary>> = None;
+            let mut subqueue_ages: Option<Vec<SubqueueAges>> = None;
+            let mut age_load_correlations = vec![];
+            let mut stationary_rank_errors = vec![];
+            let mut stationary_summaries = vec![];
+            let mut per_phase: Option<Vec<PhaseSummary>> = None;
+            let mut placement_rank_errors = vec![];
+            let mut selection_rank_errors = vec![];
+            let mut choice_impacts = vec![];
+            let mut candidate_spreads = vec![];
+            let mut spread_deciles = vec![];
+
+            let results: Vec<_> = (0..runs as u64)
+                .into_par_iter()
+                .map(|run| {
+                    let mut queue = queue
+                        .builder(derive_seed(seed, SeedStream::Queue, &[0, run]))
+                        .log_choices(if decompose {
+                            // Including the operations added to decorrelate the runs
+                            Some(usize::MAX)
+                        } else {
+                            (choice_impact || conditional_spread).then_some(prefill + ops_vec.len())
+                        })
+                        .build_sequenced();
+                    let analysis = or_exit(analyze_distributions(
+                        &mut queue,
+                        prefill,
+                        &ops_vec,
+                        threads.as_deref(),
+                        &options,
+                        &mut stream_rng(seed, SeedStream::Decorrelation, &[0, run]),
+                    ));
+                    let dequeues = queue.subqueue_dequeue_counts().iter().sum::<usize>();
+                    let peeks_per_dequeue = queue.peeks() as f32 / dequeues.max(1) as f32;
+                    (
+                        analysis,
+                        (queue.skew_cap_triggers(), peeks_per_dequeue),
+                        queue.subqueue_high_water_marks(),
+                    )
+                })
+                .collect();
+
+            let skew_cap_triggers = results
+                .iter()
+                .map(|(_, (triggers, _), _)| *triggers as f32)
+                .sum::<f32>()
+                / runs as f32;
+            let peeks_per_dequeue =
+                results.iter().map(|(_, (_, peeks), _)| peeks).sum::<f32>() / runs as f32;
+            let high_water_marks: Vec<Vec<usize>> =
+                results.iter().map(|(_, _, marks)| marks.clone()).collect();
+            results.into_iter().for_each(|(mut new, ..)| {
+                normalize_rank_errors(&mut new, normalize.into(), queue.subqueues, prefill)
+                    .unwrap_or_else(|reason| {
+                        eprintln!("{reason}. Exiting program.");
+                        process::exit(1);
+                    });
+                // Sum up all values in each x point
+                rank_errors.push(new.rank_errors);
+                enq_deq_diffs.push(new.enq_deq_diffs);
+                subqueue_deq_diffs.push(new.subqueue_deq_diffs);
+                subqueue_enq_diffs.push(new.subqueue_enq_diffs);
+                subqueue_enq_counts.push(new.subqueue_enq_counts);
+                subqueue_deq_counts.push(new.subqueue_deq_counts);
+                if let Some((prefill_errors, operation_errors)) = new.prefill_split {
+                    prefill_rank_errors.push(prefill_errors);
+                    operation_rank_errors.push(operation_errors);
+                }
+                if let Some((direct_errors, fallback_errors)) = new.fallback_split {
+                    direct_rank_errors.push(direct_errors);
+                    fallback_rank_errors.push(fallback_errors);
+                }
+                fallback_fractions.extend(new.fallback_fraction);
+                if let Some(new_heatmap) = new.heatmap {
+                    match rank_error_heatmap.as_mut() {
+                        Some(heatmap) => heatmap.add(&new_heatmap),
+                        None => rank_error_heatmap = Some(new_heatmap),
+                    }
+                }
+                if let Some(new_per_thread) = new.per_thread {
+                    add_thread_summaries(
+                        per_thread.get_or_insert_with(Vec::new),
+                        new_per_thread,
+                        runs,
+                    );
+                }
+                if let Some(ages) = new.age_by_subqueue {
+                    add_subqueue_ages(
+                        subqueue_ages.get_or_insert_with(Vec::new),
+                        ages.subqueues,
+                        runs,
+                    );
+                    age_load_correlations.push(ages.load_correlation);
+                }
+                if let Some((errors, summary)) = new.stationary {
+                    stationary_rank_errors.push(errors);
+                    stationary_summaries.push(summary);
+                }
+                if let Some((placement_errors, selection_errors)) = new.decomposition {
+                    placement_rank_errors.push(placement_errors);
+                    selection_rank_errors.push(selection_errors);
+                }
+                choice_impacts.extend(new.choice_impact);
+                if let Some(spread) = new.conditional_spread {
+                    candidate_spreads.push(spread.spreads);
+                    spread_deciles.push(spread.deciles);
+                }
+                if let Some(new_per_phase) = new.per_phase {
+                    add_phase_summaries(
+                        per_phase.get_or_insert_with(Vec::new),
+                        new_per_phase,
+                        runs,
+                    );
+                }
+            });
+
+            // The spread of each quantile point over the runs
+            let rank_errors = DistributionBand::new(rank_errors, pdf_samples);
+            let enq_deq_diffs = DistributionBand::new(enq_deq_diffs, pdf_samples);
+            let subqueue_deq_diffs = DistributionBand::new(subqueue_deq_diffs, pdf_samples);
+            let subqueue_enq_diffs = DistributionBand::new(subqueue_enq_diffs, pdf_samples);
+            let subqueue_deq_counts = DistributionBand::new(subqueue_deq_counts, pdf_samples);
+            let subqueue_enq_counts = DistributionBand::new(subqueue_enq_counts, pdf_samples);
+            let prefill_rank_errors = DistributionBand::new(prefill_rank_errors, pdf_samples);
+            let operation_rank_errors = DistributionBand::new(operation_rank_errors, pdf_samples);
+            let direct_rank_errors = DistributionBand::new(direct_rank_errors, pdf_samples);
+            let fallback_rank_errors = DistributionBand::new(fallback_rank_errors, pdf_samples);
+            let stationary_rank_errors = DistributionBand::new(stationary_rank_errors, pdf_samples);
+            let placement_rank_errors = DistributionBand::new(placement_rank_errors, pdf_samples);
+            let selection_rank_errors = DistributionBand::new(selection_rank_errors, pdf_samples);
+            let candidate_spreads = DistributionBand::new(candidate_spreads, pdf_samples);
+            if let Some(heatmap) = rank_error_heatmap.as_mut() {
+                heatmap.scale(runs as f32);
+            }
+
+            let mut string_keyed_results = vec![
+                ("Rank Errors", serde_json::json!(rank_errors)),
+                ("Enq-Deq id difference", serde_json::json!(enq_deq_diffs)),
+                ("Deq load offset", serde_json::json!(subqueue_deq_diffs)),
+                ("Enq load offset", serde_json::json!(subqueue_enq_diffs)),
+                (
+                    "Enqueue sub-queue counts",
+                    serde_json::json!(subqueue_enq_counts),
+                ),
+                (
+                    "Dequeue sub-queue counts",
+                    serde_json::json!(subqueue_deq_counts),
+                ),
+            ];
+            if queue.config.skew_cap.is_some() {
+                string_keyed_results
+                    .push(("Skew cap triggers", serde_json::json!(skew_cap_triggers)));
+            }
+            if queue.config.heuristic == Heuristic::OldestFront {
+                string_keyed_results
+                    .push(("Peeks per dequeue", serde_json::json!(peeks_per_dequeue)));
+            }
+            if let Some(heatmap) = rank_error_heatmap {
+                string_keyed_results.push(("Rank error heatmap", serde_json::json!(heatmap)));
+            }
+            if tag_prefill {
+                string_keyed_results.push((
+                    "Prefill rank errors",
+                    serde_json::json!(prefill_rank_errors),
+                ));
+                string_keyed_results.push((
+                    "Operation rank errors",
+                    serde_json::json!(operation_rank_errors),
+                ));
+            }
+            if split_fallback {
+                string_keyed_results
+                    .push(("Direct rank errors", serde_json::json!(direct_rank_errors)));
+                string_keyed_results.push((
+                    "Fallback rank errors",
+                    serde_json::json!(fallback_rank_errors),
+                ));
+                string_keyed_results.push((
+                    "Fallback fraction per run",
+                    serde_json::json!(fallback_fractions),
+                ));
+            }
+            if let Some(mut per_thread) = per_thread {
+                per_thread
+                    .iter_mut()
+                    .for_each(|summary| summary.dequeues /= runs);
+                string_keyed_results
+                    .push(("Per-thread rank errors", serde_json::json!(per_thread)));
+            }
+            if let Some(mut subqueue_ages) = subqueue_ages {
+                subqueue_ages
+                    .iter_mut()
+                    .for_each(|ages| ages.dequeues /= runs);
+                string_keyed_results.push(("Age by sub-queue", serde_json::json!(subqueue_ages)));
+                string_keyed_results.push((
+                    "Age-load correlation per run",
+                    serde_json::json!(age_load_correlations),
+                ));
+            }
+            if stationary {
+                string_keyed_results.push((
+                    "Stationary rank errors",
+                    serde_json::json!(stationary_rank_errors),
+                ));
+                string_keyed_results.push((
+                    "Stationary summary per run",
+                    serde_json::json!(stationary_summaries),
+                ));
+            }
+            if decompose {
+                string_keyed_results.push((
+                    "Placement rank errors",
+                    serde_json::json!(placement_rank_errors),
+                ));
+                string_keyed_results.push((
+                    "Selection rank errors",
+                    serde_json::json!(selection_rank_errors),
+                ));
+            }
+            if choice_impact {
+                string_keyed_results
+                    .push(("Choice impact per run", serde_json::json!(choice_impacts)));
+            }
+            if conditional_spread {
+                string_keyed_results
+                    .push(("Candidate spreads", serde_json::json!(candidate_spreads)));
+                string_keyed_results.push((
+                    "Rank errors by spread decile per run",
+                    serde_json::json!(spread_deciles),
+                ));
+            }
+            if memory_stats {
+                string_keyed_results.push((
+                    "Max high-water mark per run",
+                    serde_json::json!(high_water_marks
+                        .iter()
+                        .map(|marks| marks.iter().max().expect("The queue has sub-queues"))
+                        .collect::<Vec<_>>()),
+                ));
+                string_keyed_results.push((
+                    "Sub-queue high-water marks per run",
+                    serde_json::json!(high_water_marks),
+                ));
+            }
+            if let Some(per_phase) = per_phase {
+                for (name, mut summary) in Phases::NAMES.into_iter().zip(per_phase) {
+                    summary.dequeues /= runs;
+                    summary.empty_returns /= runs;
+                    string_keyed_results.push((name, serde_json::json!(summary)));
+                }
+            }
+
+            write_output(&output_name, output, &metadata, &string_keyed_results);
+        }
+        Test::Matrix {
+            queue,
+            kinds,
+            k_fifo,
+            operations,
+            enqueues,
+            dequeues,
+            prefill,
+            workload,
+            output_name,
+            runs,
+        } => {
+            let count = OpsCount::new(operations, enqueues, dequeues, None);
+            assert_has_dequeues([count]);
+            assert_item_capacity([(prefill, count)]);
+            workload.assert_bounded_supported(ErrorReadout::Average, true);
+            workload.assert_batch_supported(ErrorReadout::Average, true);
+            queue
+                .config
+                .assert_budget_supported(&workload, ErrorReadout::Average, true);
+            for kind in &kinds {
+                if let Some(config) = queue.kind_config(*kind) {
+                    config.assert_valid(queue.subqueues);
+                }
+            }
+            if k_fifo.contains(&0) {
+                eprintln!("The k-FIFO segments need at least one slot. Exiting program.");
+                process::exit(1);
+            }
+            let queues: Vec<MatrixQueue> = kinds
+                .iter()
+                .map(|kind| MatrixQueue::Kind(*kind))
+                .chain(k_fifo.iter().map(|k| MatrixQueue::KFifo(*k)))
+                .collect();
+            // Generate the workloads once, so that all kinds run on the same operation sequences
+            let workloads: Vec<(Vec<bool>, Option<Vec<usize>>)> = (0..runs as u64)
+                .map(|run| {
+                    let mut rng = stream_rng(seed, SeedStream::Operations, &[run]);
+                    workload.gen(count, prefill, &mut rng)
+                })
+                .collect();
+
+            let rows: Vec<MatrixRow> = queues
+                .iter()
+                .map(|matrix_queue| {
+                    let (rank_errors, empty_returns): (Vec<Vec<usize>>, Vec<usize>) = workloads
+                        .par_iter()
+                        .enumerate()
+                        .map(|(run, (ops, threads))| {
+                            // Every queue of a run gets the same seed
+                            let queue_seed = derive_seed(seed, SeedStream::Queue, &[run as u64]);
+                            match matrix_queue {
+                                MatrixQueue::Kind(kind) => {
+                                    let mut queue = queue.init_kind(*kind, queue_seed);
+                                    let tags = or_exit(analyze_extra(
+                                        &mut queue,
+                                        prefill,
+                                        ops,
+                                        threads.as_deref(),
+                                    ));
+                                    let empty_returns = tags
+                                        .iter()
+                                        .filter(|tag| matches!(tag, ErrorTag::EmptyDequeue { .. }))
+                                        .count();
+                                    (
+                                        tags.iter().map(|tag| tag.rank_error()).collect(),
+                                        empty_returns,
+                                    )
+                                }
+                                MatrixQueue::KFifo(k) => {
+                                    let mut queue = KFifo::new(*k, queue_seed);
+                                    let (rank_errors, oracle) =
+                                        or_exit(analyze_simple_checked(&mut queue, prefill, ops));
+                                    let dequeues = ops.iter().filter(|op| !**op).count();
+                                    (rank_errors, dequeues - oracle.dead)
+                                }
+                            }
+                        })
+                        .unzip();
+
+                    let mut rank_errors: Vec<usize> = rank_errors.into_iter().flatten().collect();
+                    rank_errors.sort_unstable();
+                    let p99_ind = (rank_errors.len() * 99).div_ceil(100).saturating_sub(1);
+                    MatrixRow {
+                        kind: matrix_queue.name(),
+                        mean_rank_error: rank_errors.iter().sum::<usize>() as f32
+                            / rank_errors.len().max(1) as f32,
+                        p99_rank_error: rank_errors.get(p99_ind).copied().unwrap_or(0),
+                        max_rank_error: rank_errors.last().copied().unwrap_or(0),
+                        empty_returns: empty_returns.iter().sum::<usize>() as f32 / runs as f32,
+                    }
+                })
+                .collect();
+
+            println!(
+                "{:<14}{:>12}{:>10}{:>10}{:>10}",
+                "Queue", "Mean", "p99", "Max", "Empty"
+            );
+            for row in rows.iter() {
+                println!(
+                    "{:<14}{:>12.3}{:>10}{:>10}{:>10.1}",
+                    row.kind,
+                    row.mean_rank_error,
+                    row.p99_rank_error,
+                    row.max_rank_error,
+                    row.empty_returns
+                );
+            }
+            write_output(&output_name, output, &metadata, &rows);
+        }
+        Test::Concurrent {
+            queue,
+            operations,
+            prefill,
+            workload,
+            thread_count,
+            speed_spread,
+            error_readout,
+            runs,
+        } => {
+            assert_not_recovery(error_readout);
+            if thread_count == 0 {
+                eprintln!("Needs at least one thread. Exiting program.");
+                process::exit(1);
+            }
+            if speed_spread.is_nan() || speed_spread < 1.0 {
+                eprintln!("The speed spread must be at least 1. Exiting program.");
+                process::exit(1);
+            }
+            workload.assert_bounded_supported(error_readout, true);
+            workload.assert_batch_supported(error_readout, true);
+            queue
+                .config
+                .assert_budget_supported(&workload, error_readout, true);
+            if workload.threads.is_some() || workload.is_online() {
+                eprintln!("The concurrent threads take the operations from one fixed sequence, so they can not be attributed to threads or generated online. Exiting program.");
+                process::exit(1);
+            }
+            let count = OpsCount::Total(operations);
+            assert_has_dequeues([count]);
+            assert_item_capacity([(prefill, count)]);
+            let mean_durations: Vec<f64> = (0..thread_count)
+                .map(|thread| speed_spread.powf(thread as f64 / (thread_count - 1).max(1) as f64))
+                .collect();
+
+            let results: Vec<(f32, usize, usize, Vec<ThreadSummary>)> = (0..runs as u64)
+                .into_par_iter()
+                .map(|run| {
+                    let ops = workload
+                        .gen(
+                            count,
+                            prefill,
+                            &mut stream_rng(seed, SeedStream::Operations, &[run]),
+                        )
+                        .0;
+                    let mut queue = queue.init(derive_seed(seed, SeedStream::Queue, &[run]));
+                    let config = ConcurrentConfig {
+                        mean_durations: mean_durations.clone(),
+                        seed: Some(derive_seed(seed, SeedStream::Durations, &[run])),
+                    };
+                    let error_tags =
+                        or_exit(analyze_concurrent(&mut queue, prefill, &ops, &config));
+                    let fallbacks = error_tags.iter().filter(|tag| tag.fell_back()).count();
+                    (
+                        error_readout.tags_readout(&error_tags, prefill, &queue),
+                        fallbacks,
+                        error_tags.len(),
+                        analyze_per_thread(&error_tags),
+                    )
+                })
+                .collect();
+            let values: Vec<f32> = results.iter().map(|(value, ..)| *value).collect();
+            println!("{}", sequential_mean(&values));
+            println!(
+                "Fallback dequeues: {} of {}",
+                results
+                    .iter()
+                    .map(|(_, fallbacks, ..)| fallbacks)
+                    .sum::<usize>(),
+                results
+                    .iter()
+                    .map(|(_, _, dequeues, _)| dequeues)
+                    .sum::<usize>()
+            );
+            let mut per_thread = vec![];
+            for (.., summaries) in results {
+                add_thread_summaries(&mut per_thread, summaries, runs);
+            }
+            for (thread, summary) in per_thread.iter().enumerate() {
+                println!(
+                    "Thread {thread} (mean duration {}): {} dequeues per run, mean rank error {}, p99 rank error {}, max rank error {}",
+                    mean_durations[thread],
+                    summary.dequeues / runs,
+                    summary.mean_rank_error,
+                    summary.p99_rank_error,
+                    summary.max_rank_error
+                );
+            }
+        }
+        Test::Exhaustive {
+            subqueues,
+            sample_nbr,
+            heuristic,
+            uniques,
+            prefill,
+            ops_pattern,
+            max_states,
+        } => {
+            let operations =
+                gen_pattern_ops(&ops_pattern, ops_pattern.len()).unwrap_or_else(|err| {
+                    eprintln!("{err}. Exiting program.");
+                    process::exit(1);
+                });
+            if subqueues == 0 || subqueues > EXHAUSTIVE_MAX_SUBQUEUES {
+                eprintln!(
+                    "Can only explore 1 to {EXHAUSTIVE_MAX_SUBQUEUES} sub-queues. Exiting program."
+                );
+                process::exit(1);
+            }
+            if prefill + operations.len() > EXHAUSTIVE_MAX_OPS {
+                eprintln!("Can only explore up to {EXHAUSTIVE_MAX_OPS} prefill items and operations. Exiting program.");
+                process::exit(1);
+            }
+            if sample_nbr == 0 || (uniques && sample_nbr > subqueues) {
+                eprintln!("Must sample between 1 and the number of sub-queues. Exiting program.");
+                process::exit(1);
+            }
+            if heuristic == Heuristic::EstimatedOldest {
+                eprintln!(
+                    "The front age estimates are not part of the explored states. Exiting program."
+                );
+                process::exit(1);
+            }
+            if heuristic == Heuristic::CoarseCounter {
+                eprintln!(
+                    "The global counter is not part of the explored states. Exiting program."
+                );
+                process::exit(1);
+            }
+            let config = ExhaustiveConfig {
+                subqueues,
+                d: sample_nbr,
+                heuristic: match heuristic {
+                    Heuristic::Length => crate::Heuristic::Length,
+                    Heuristic::Operation => crate::Heuristic::Operation,
+                    // Any temperature can choose any candidate
+                    Heuristic::Softmax => crate::Heuristic::Softmax { temperature: 1.0 },
+                    Heuristic::OldestFront => crate::Heuristic::OldestFront,
+                    Heuristic::EstimatedOldest | Heuristic::CoarseCounter => {
+                        unreachable!("Rejected above")
+                    }
+                },
+                uniques,
+                max_states,
+            };
+
+            match analyze_exhaustive(&config, prefill, &operations) {
+                Ok(result) => {
+                    println!(
+                        "Max rank error: {} ({} states explored)",
+                        result.max_rank_error, result.states
+                    );
+                    for step in result.witness.iter() {
+                        let item = step
+                            .item
+                            .map_or("empty".to_string(), |item| item.to_string());
+                        match step.kind {
+                            OpKind::Enqueue => {
+                                println!("Enqueue {item} into sub-queue {}", step.subqueue)
+                            }
+                            OpKind::Dequeue => println!(
+                                "Dequeue {item} choosing sub-queue {}, rank error {}",
+                                step.subqueue, step.rank_error
+                            ),
+                        }
+                    }
+                }
+                Err(err) => {
+                    eprintln!("{err}, try a smaller configuration. Exiting program.");
+                    process::exit(1);
+                }
+            }
+        }
+        Test::EvalHeuristic {
+            subqueues,
+            sample_nbr,
+            heuristic,
+            temperature,
+            gaps,
+            gap,
+            alpha,
+            length,
+            states,
+            samples,
+            output_name,
+        } => {
+            let config = HeuristicEvalConfig {
+                subqueues,
+                d: sample_nbr,
+                heuristic: match heuristic {
+                    Heuristic::Length => crate::Heuristic::Length,
+                    Heuristic::Operation => crate::Heuristic::Operation,
+                    Heuristic::Softmax => crate::Heuristic::Softmax { temperature },
+                    Heuristic::OldestFront => crate::Heuristic::OldestFront,
+                    Heuristic::EstimatedOldest => {
+                        // Rejected by the evaluation, as it does not choose by the counters
+                        crate::Heuristic::EstimatedOldest { weight: 1.0 }
+                    }
+                    Heuristic::CoarseCounter => {
+                        // Rejected by the evaluation, as it also chooses by the global counter
+                        crate::Heuristic::CoarseCounter { granularity: 1 }
+                    }
+                },
+                gaps: match gaps {
+                    GapDistribution::Uniform => CounterGaps::Uniform { max_gap: gap },
+                    GapDistribution::HeavyTailed => CounterGaps::HeavyTailed {
+                        scale: gap as f64,
+                        alpha,
+                    },
+                    GapDistribution::Straggler => CounterGaps::Straggler { lag: gap },
+                },
+                length,
+                states,
+                samples,
+            };
+            let eval = evaluate_heuristic(&config, &mut stream_rng(seed, SeedStream::Queue, &[0]))
+                .unwrap_or_else(|reason| {
+                    eprintln!("{reason}. Exiting program.");
+                    process::exit(1);
+                });
+
+            println!(
+                "{:<10}{:>10}{:>10}{:>10}{:>10}{:>10}",
+                "Kind", "Optimal", "Mean", "p99", "Max", "Global"
+            );
+            for (kind, summary) in [("Enqueue", &eval.enqueues), ("Dequeue", &eval.dequeues)] {
+                println!(
+                    "{:<10}{:>10.3}{:>10.3}{:>10}{:>10}{:>10.3}",
+                    kind,
+                    summary.optimal_fraction,
+                    summary.mean_deviation,
+                    summary.p99_deviation,
+                    summary.max_deviation,
+                    summary.mean_global_deviation
+                );
+            }
+            write_output(&output_name, output, &metadata, &eval);
+        }
+        Test::DescribeOps {
+            operations,
+            enqueues,
+            dequeues,
+            phases,
+            prefill,
+            workload,
+            from_log,
+            output_name,
+        } => {
+            let (prefill, operations) = match from_log {
+                Some(file) => {
+                    let reader = open_input(&file).unwrap_or_else(|err| {
+                        eprintln!(
+                            "Could not read {}: {err}. Exiting program.",
+                            file.to_string_lossy()
+                        );
+                        process::exit(1);
+                    });
+                    let (mut prefill, mut operations) = (0, vec![]);
+                    for (ind, record) in RunLogReader::new(reader).enumerate() {
+                        let record = record.unwrap_or_else(|err| {
+                            eprintln!("Could not read record {ind}: {err}. Exiting program.");
+                            process::exit(1);
+                        });
+                        match record.kind {
+                            OpKind::Enqueue if record.prefill => prefill += 1,
+                            kind => operations.push(kind == OpKind::Enqueue),
+                        }
+                    }
+                    (prefill, operations)
+                }
+                None => {
+                    let prefill = prefill.expect("The prefill is required without a run log");
+                    workload.assert_describable(true);
+                    let count = OpsCount::new(operations, enqueues, dequeues, phases);
+                    let mut rng = stream_rng(seed, SeedStream::Operations, &[0]);
+                    (prefill, workload.gen(count, prefill, &mut rng).0)
+                }
+            };
+            let description = describe_operations(prefill, &operations);
+
+            println!(
+                "Operations: {} ({} enqueues, {} dequeues, enqueue fraction {:.3})",
+                description.operations,
+                description.enqueues,
+                description.dequeues,
+                description.enqueue_fraction
+            );
+            println!(
+                "Longest runs: {} enqueues, {} dequeues",
+                description.longest_enqueue_run, description.longest_dequeue_run
+            );
+            println!(
+                "Queue size: starts at {prefill}, between {} and {}, ends at {}",
+                description.min_size, description.max_size, description.final_size
+            );
+            match description.first_empty {
+                Some(ops) => println!(
+                    "Empties the queue after {ops} operations, with {} empty dequeues",
+                    description.empty_dequeues
+                ),
+                None => println!("Never empties the queue"),
+            }
+            write_output(&output_name, output, &metadata, &description);
+        }
+        Test::Pareto { files, output_name } => {
+            let points: Vec<SweepPoint> = files
+                .iter()
+                .flat_map(|file| {
+                    read_sweep_points(file).unwrap_or_else(|err| {
+                        eprintln!(
+                            "Could not read {}: {err}. Exiting program.",
+                            file.to_string_lossy()
+                        );
+                        process::exit(1);
+                    })
+                })
+                .collect();
+            let front = pareto_front(
+                &points
+                    .iter()
+                    .map(|point| (point.error, point.cost))
+                    .collect::<Vec<_>>(),
+            );
+
+            for pareto in front.iter().filter(|pareto| !pareto.dominated) {
+                let point = &points[pareto.index];
+                println!(
+                    "{} {}: rank error {}, touches per operation {}",
+                    point.file, point.key, pareto.error, pareto.cost
+                );
+            }
+            println!(
+                "{} of {} points are dominated",
+                front.iter().filter(|pareto| pareto.dominated).count(),
+                front.len()
+            );
+
+            let results: Vec<serde_json::Value> = front
+                .iter()
+                .map(|pareto| {
+                    let point = &points[pareto.index];
+                    serde_json::json!({
+                        "file": point.file,
+                        "key": point.key,
+                        "error": pareto.error,
+                        "cost": pareto.cost,
+                        "dominated": pareto.dominated,
+                    })
+                })
+                .collect();
+            write_output(&output_name, output, &metadata, &results);
+        }
+        Test::Merge { files, output_name } => {
+            let outputs: Vec<serde_json::Value> = files
+                .iter()
+                .map(|file| {
+                    load_results(file)
+                        .map(ResultFile::into_json)
+                        .unwrap_or_else(|err| {
+                            eprintln!(
+                                "Could not read {}: {err}. Exiting program.",
+                                file.to_string_lossy()
+                            );
+                            process::exit(1);
+                        })
+                })
+                .collect();
+            let mut merged = merge_sweep_outputs(&outputs).unwrap_or_else(|errors| {
+                for error in errors {
+                    eprintln!("{}: {error}", files[error.output()].to_string_lossy());
+                }
+                eprintln!("The results are not compatible. Exiting program.");
+                process::exit(1);
+            });
+            merged["metadata"]["merged_from"] = serde_json::json!(files);
+            write_json(&output_name, output, &merged);
+        }
+        Test::Replay { file, tolerance } => {
+            let original = load_results(&file)
+                .map(ResultFile::into_json)
+                .unwrap_or_else(|err| {
+                    eprintln!(
+                        "Could not read {}: {err}. Exiting program.",
+                        file.to_string_lossy()
+                    );
+                    process::exit(1);
+                });
+            let (mut test, seed) =
+                replayable_test(&original["metadata"]).unwrap_or_else(|reason| {
+                    eprintln!(
+                        "Can not replay {}, as it {reason}. Exiting program.",
+                        file.to_string_lossy()
+                    );
+                    process::exit(1);
+                });
+            if let Test::OpsAndPrefill { output_name, .. }
+            | Test::SubqueuesAndPrefill { output_name, .. } = &mut test
+            {
+                *output_name = format!("{}-replay", output_name.trim_end_matches(".gz"));
+            }
+
+            let output = OutputOptions {
+                output_dir: output
+                    .output_dir
+                    .clone()
+                    .or_else(|| file.parent().map(Path::to_path_buf)),
+                ..output.clone()
+            };
+            let path =
+                run_test(test, seed, &output, None, false).expect("Only json sweeps are replayed");
+            let replayed = load_results(&path)
+                .expect("Could not read the replayed output")
+                .into_json();
+            let divergences = compare_sweep_outputs(&original, &replayed, tolerance);
+            for divergence in divergences.iter() {
+                println!("{divergence}");
+            }
+            if !divergences.is_empty() {
+                eprintln!(
+                    "{} values diverged from the original. Exiting program.",
+                    divergences.len()
+                );
+                process::exit(1);
+            }
+            println!(
+                "All {} points reproduced",
+                original["results"].as_array().map_or(0, Vec::len)
+            );
+        }
+        Test::LogStats {
+            file,
+            error_readout,
+        } => {
+            if !error_readout.reads_rank_errors() {
+                eprintln!(
+                    "The run log can only be read out by the rank error readouts. Exiting program."
+                );
+                process::exit(1);
+            }
+            let mut sidecar_path = file.as_os_str().to_owned();
+            sidecar_path.push(".json");
+            let sidecar = read_json_output(Path::new(&sidecar_path)).unwrap_or_else(|err| {
+                eprintln!(
+                    "Could not read the sidecar {}: {err}. Exiting program.",
+                    sidecar_path.to_string_lossy()
+                );
+                process::exit(1);
+            });
+            let reader = open_input(&file).unwrap_or_else(|err| {
+                eprintln!(
+                    "Could not read {}: {err}. Exiting program.",
+                    file.to_string_lossy()
+                );
+                process::exit(1);
+            });
+
+            let mut records = 0;
+            let (mut prefill, mut enqueues, mut empty_returns, mut fallbacks) = (0, 0, 0, 0);
+            let mut rank_errors = vec![];
+            for record in RunLogReader::new(reader) {
+                let record = record.unwrap_or_else(|err| {
+                    eprintln!("Could not read record {records}: {err}. Exiting program.");
+                    process::exit(1);
+                });
+                records += 1;
+                match record.kind {
+                    OpKind::Enqueue if record.prefill => prefill += 1,
+                    OpKind::Enqueue => enqueues += 1,
+                    OpKind::Dequeue => {
+                        empty_returns += usize::from(record.item.is_none());
+                        fallbacks += usize::from(record.fell_back);
+                        rank_errors.push(record.rank_error.expect("Dequeues log their rank error"));
+                    }
+                }
+            }
+            if sidecar["records"].as_u64() != Some(records as u64) {
+                eprintln!(
+                    "The log holds {records} records, but the sidecar expects {}. Exiting program.",
+                    sidecar["records"]
+                );
+                process::exit(1);
+            }
+            println!(
+                "Records: {records} ({prefill} prefill enqueues, {enqueues} enqueues, {} dequeues, {empty_returns} empty returns, {fallbacks} fallbacks)",
+                rank_errors.len()
+            );
+            println!("{}", error_readout.readout(&rank_errors));
+        }
+        Test::MinMaxGaps {
+            operations,
+            bins,
+            sample_nbr,
+            output_name,
+            // runs,
+        } => {
+            let (vec_ind, vec_mean, vec_max): (Vec<usize>, Vec<f32>, Vec<usize>) = bins
+                .par_iter()
+                .map(|bins| {
+                    let (_, max, mean) = analyze_minmax_gap(*bins, operations, sample_nbr);
+                    (*bins, mean, max)
+                })
+                // thread local
+                .fold(
+                    || (Vec::new(), Vec::new(), Vec::new()),
+                    |mut acc, (a, b, c)| {
+                        acc.0.push(a);
+                        acc.1.push(b);
+                        acc.2.push(c);
+                        acc
+                    },
+                )
+                // total reduction
+                .reduce(
+                    || (Vec::new(), Vec::new(), Vec::new()),
+                    |mut a, mut b| {
+                        a.0.append(&mut b.0);
+                        a.1.append(&mut b.1);
+                        a.2.append(&mut b.2);
+                        a
+                    },
+                );
+
+            let json_data = serde_json::json!({
+                "operations" : operations,
+                "bins": vec_ind,
+                "mean" : vec_mean,
+                "max": vec_max,
+            });
+            write_output(&output_name, output, &metadata, &json_data);
+        }
+    }
+    sweep_output
+}
+
+/// The sweep and seed of the metadata of a json output, or why it can not be replayed
+fn replayable_test(metadata: &serde_json::Value) -> Result<(Test, u64), String> {
+    if metadata.is_null() {
+        return Err("predates the output metadata".to_string());
+    }
+    let version = env!("CARGO_PKG_VERSION");
+    if metadata["version"] != version {
+        return Err(format!(
+            "was written by version {} and this is {version}",
+            metadata["version"]
+        ));
+    }
+    if metadata["seed_scheme"] != SEED_SCHEME {
+        return Err(format!(
+            "derives its seeds by scheme {} and this by {SEED_SCHEME}",
+            metadata["seed_scheme"]
+        ));
+    }
+    if metadata.get("merged_from").is_some() {
+        return Err("was merged from several sweeps".to_string());
+    }
+    let seed = metadata["seed"].as_u64().ok_or("has no seed")?;
+    let test: Test = serde_json::from_value(metadata["config"].clone())
+        .map_err(|err| format!("has an unreadable config ({err})"))?;
+    match test {
+        Test::OpsAndPrefill {
+            format: OutputFormat::Json,
+            ..
+        }
+        | Test::SubqueuesAndPrefill {
+            format: OutputFormat::Json,
+            ..
+        } => Ok((test, seed)),
+        _ => Err("is not a json sweep".to_string()),
+    }
+}
+
+/// The largest configurations the exhaustive search accepts
+const EXHAUSTIVE_MAX_SUBQUEUES: usize = 8;
+const EXHAUSTIVE_MAX_OPS: usize = 24;
+
+/// How many operations to generate
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+enum OpsCount {
+    /// A total number of operations, split according to the operation distribution
+    Total(usize),
+
+    /// Exact numbers of enqueues and dequeues
+    Split { enqueues: usize, dequeues: usize },
+
+    /// An enqueue ramp, operations from the distribution and then a dequeue drain
+    Phases(Phases),
+}
+
+/// The numbers of operations of each phase of a phased run
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+struct Phases {
+    ramp: usize,
+    steady: usize,
+    drain: usize,
+}
+
+impl Phases {
+    /// The names of the phases, in order
+    const NAMES: [&'static str; 3] = ["Ramp", "Steady", "Drain"];
+
+    fn lengths(&self) -> Vec<usize> {
+        vec![self.ramp, self.steady, self.drain]
+    }
+}
+
+impl OpsCount {
+    /// Resolves the count from the command line, where clap ensures exactly one option is used
+    fn new(
+        operations: Option<usize>,
+        enqueues: Option<usize>,
+        dequeues: Option<usize>,
+        phases: Option<Phases>,
+    ) -> Self {
+        match (operations, enqueues, dequeues, phases) {
+            (Some(operations), _, _, _) => OpsCount::Total(operations),
+            (None, Some(enqueues), Some(dequeues), _) => OpsCount::Split { enqueues, dequeues },
+            (None, None, _, Some(phases)) => OpsCount::Phases(phases),
+            _ => unreachable!("Clap requires either operations, enqueues and dequeues or phases"),
+        }
+    }
+
+    /// Resolves a list of counts from the command line, pairing up enqueues with dequeues
+    fn new_list(operations: Vec<usize>, enqueues: Vec<usize>, dequeues: Vec<usize>) -> Vec<Self> {
+        if enqueues.len() != dequeues.len() {
+            eprintln!("Expected as many enqueue counts as dequeue counts. Exiting program.");
+            process::exit(1);
+        }
+        operations
+            .into_iter()
+            .map(OpsCount::Total)
+            .chain(
+                enqueues
+                    .into_iter()
+                    .zip(dequeues)
+                    .map(|(enqueues, dequeues)| OpsCount::Split { enqueues, dequeues }),
+            )
+            .collect()
+    }
+
+    /// Scales the operations with the prefill, but always runs at least two operations
+    fn per_prefill(ratio: f64, prefill: usize) -> Self {
+        OpsCount::Total(((ratio * prefill as f64).round() as usize).max(2))
+    }
+
+    fn total(&self) -> usize {
+        match self {
+            OpsCount::Total(operations) => *operations,
+            OpsCount::Split { enqueues, dequeues } => enqueues + dequeues,
+            OpsCount::Phases(phases) => phases.ramp + phases.steady + phases.drain,
+        }
+    }
+
+    /// The key of the results, which orders points with the same total by their split
+    fn sort_key(&self) -> (usize, usize, usize) {
+        match self {
+            OpsCount::Total(operations) => (*operations, 0, 0),
+            OpsCount::Split { enqueues, dequeues } => (enqueues + dequeues, *enqueues, *dequeues),
+            OpsCount::Phases(phases) => (self.total(), phases.ramp, phases.steady),
+        }
+    }
+
+    /// A sweep output record, which also holds the exact counts if they were given
+    fn record(&self, key: String, value: f32) -> serde_json::Value {
+        match self {
+            OpsCount::Total(_) => serde_json::json!([key, value]),
+            OpsCount::Split { enqueues, dequeues } => serde_json::json!([
+                key,
+                value,
+                { "enqueues": enqueues, "dequeues": dequeues }
+            ]),
+            OpsCount::Phases(phases) => serde_json::json!([key, value, phases]),
+        }
+    }
+}
+
+impl OpsConfig {
+    /// Generates the operations, together with the issuing thread of each if using a thread model
+    ///
+    /// Exits the program if the configuration is invalid.
+    fn gen(
+        &self,
+        count: OpsCount,
+        prefill: usize,
+        rng: &mut StdRng,
+    ) -> (Vec<bool>, Option<Vec<usize>>) {
+        let (operations, threads) = self.gen_unprioritized(count, prefill, rng);
+        match self.deq_priority {
+            // Conflicts with the thread model, so there are no threads to reorder with them
+            Some(window) => (prioritize_dequeues(&operations, prefill, window), threads),
+            None => (operations, threads),
+        }
+    }
+
+    /// As gen, but without moving the dequeues earlier by the dequeue priority
+    fn gen_unprioritized(
+        &self,
+        count: OpsCount,
+        prefill: usize,
+        rng: &mut StdRng,
+    ) -> (Vec<bool>, Option<Vec<usize>>) {
+        let operations = match count {
+            OpsCount::Total(operations) => operations,
+            OpsCount::Split { enqueues, dequeues } => {
+                return (gen_split_ops(enqueues, dequeues, rng), None)
+            }
+            OpsCount::Phases(phases) => {
+                let steady = self.gen_stream(phases.steady, prefill + phases.ramp, rng);
+                let ops = std::iter::repeat_n(true, phases.ramp)
+                    .chain(steady)
+                    .chain(std::iter::repeat_n(false, phases.drain))
+                    .collect();
+                return (ops, None);
+            }
+        };
+        let Some(threads) = self.threads else {
+            return (self.gen_stream(operations, prefill, rng), None);
+        };
+        if threads == 0 {
+            eprintln!("The thread model needs at least one thread. Exiting program.");
+            process::exit(1);
+        }
+        if !self.thread_enq_fractions.is_empty() && self.thread_enq_fractions.len() != threads {
+            eprintln!("Expected one enqueue fraction per thread. Exiting program.");
+            process::exit(1);
+        }
+
+        let streams: Vec<Vec<bool>> = (0..threads)
+            .map(|thread| {
+                let len = operations / threads + usize::from(thread < operations % threads);
+                match self.thread_enq_fractions.get(thread) {
+                    Some(fraction) => {
+                        let enqueues = (fraction * len as f64).round() as usize;
+                        let mut stream: Vec<bool> = std::iter::repeat_n(true, enqueues)
+                            .chain(std::iter::repeat_n(false, len - enqueues.min(len)))
+                            .collect();
+                        stream.shuffle(rng);
+                        stream
+                    }
+                    None => self.gen_stream(len, prefill, rng),
+                }
+            })
+            .collect();
+
+        let (threads, ops) = interleave_threads(streams, self.quantum, rng)
+            .into_iter()
+            .unzip();
+        (ops, Some(threads))
+    }
+
+    /// If the operations depend on how the queue behaves, so must be generated during the analysis
+    fn is_online(&self) -> bool {
+        self.ops_pattern.is_none()
+            && matches!(
+                self.distribution,
+                OperationDistribution::Frontier | OperationDistribution::Occupancy
+            )
+    }
+
+    /// Exits the program if the operations should be described, but are generated online
+    fn assert_describable(&self, describe_ops: bool) {
+        if describe_ops && self.is_online() {
+            eprintln!("The operations of the online distributions depend on the queue, so cannot be described. Exiting program.");
+            process::exit(1);
+        }
+    }
+
+    /// As gen, but None for the online distributions, which cannot be generated up front
+    fn gen_fixed(&self, count: OpsCount, prefill: usize, rng: &mut StdRng) -> Option<Vec<bool>> {
+        (!self.is_online()).then(|| self.gen(count, prefill, rng).0)
+    }
+
+    /// The source of the operations of an online distribution, or None for the others
+    ///
+    /// Exits the program if the configuration is invalid.
+    fn online(&self, count: OpsCount, prefill: usize, rng: StdRng) -> Option<Box<dyn OpSource>> {
+        if !self.is_online() {
+            return None;
+        }
+        let OpsCount::Total(operations) = count else {
+            eprintln!(
+                "The closed-loop distributions need a total number of operations. Exiting program."
+            );
+            process::exit(1);
+        };
+        if self.deq_priority.is_some() {
+            eprintln!(
+                "The closed-loop distributions cannot prioritize their dequeues. Exiting program."
+            );
+            process::exit(1);
+        }
+        if self.threads.is_some() {
+            eprintln!(
+                "The closed-loop distributions cannot be attributed to threads. Exiting program."
+            );
+            process::exit(1);
+        }
+        match self.distribution {
+            OperationDistribution::Frontier => {
+                if prefill == 0 {
+                    eprintln!("The frontier distribution requires a prefill of at least 1. Exiting program.");
+                    process::exit(1);
+                }
+                Some(Box::new(FrontierOps::new(
+                    operations,
+                    prefill,
+                    self.branching,
+                    self.max_branching,
+                    self.branching_exponent,
+                    rng,
+                )))
+            }
+            OperationDistribution::Occupancy => {
+                if self.control_window == 0 {
+                    eprintln!("The control window must be positive. Exiting program.");
+                    process::exit(1);
+                }
+                Some(Box::new(OccupancyOps {
+                    remaining: operations,
+                    live: prefill,
+                    target_size: self.target_size,
+                    control_window: self.control_window,
+                    rng,
+                }))
+            }
+            _ => unreachable!("Only the closed-loop distributions are online"),
+        }
+    }
+
+    /// Exits the program if the item capacity is set with a readout or distribution which does
+    /// not support it, or where unsupported is set as the subcommand does not
+    fn assert_bounded_supported(&self, readout: ErrorReadout, unsupported: bool) {
+        let Some(max_items) = self.max_items else {
+            return;
+        };
+        if unsupported {
+            eprintln!(
+                "The item capacity is only supported by single and the prefill sweeps. Exiting program."
+            );
+            process::exit(1);
+        }
+        if max_items == 0 {
+            eprintln!("The item capacity must be positive. Exiting program.");
+            process::exit(1);
+        }
+        if self.is_online() || !readout.reads_rank_errors() {
+            eprintln!(
+                "The item capacity does not support the closed-loop distributions or the {} readout. Exiting program.",
+                readout.name()
+            );
+            process::exit(1);
+        }
+    }
+
+    /// If the enqueues or dequeues are batched
+    fn is_batched(&self) -> bool {
+        self.enqueue_batch.is_some() || self.dequeue_batch.is_some()
+    }
+
+    /// Exits the program if the enqueues or dequeues are batched with a readout or distribution
+    /// which does not support it, or where unsupported is set as the subcommand does not
+    fn assert_batch_supported(&self, readout: ErrorReadout, unsupported: bool) {
+        if !self.is_batched() {
+            return;
+        }
+        if unsupported {
+            eprintln!(
+                "The batches are only supported by single and the prefill sweeps. Exiting program."
+            );
+            process::exit(1);
+        }
+        if self.enqueue_batch == Some(0) || self.dequeue_batch == Some(0) {
+            eprintln!("A batch must hold at least one item. Exiting program.");
+            process::exit(1);
+        }
+        if self.is_online() || !readout.reads_rank_errors() {
+            eprintln!(
+                "The batches do not support the closed-loop distributions or the {} readout. Exiting program.",
+                readout.name()
+            );
+            process::exit(1);
+        }
+    }
+
+    /// Exits the program if an online distribution is used with an invalid (prefill, operations)
+    /// point, or a readout which needs more than the rank errors
+    fn assert_online_supported(
+        &self,
+        readout: ErrorReadout,
+        points: impl IntoIterator<Item = (usize, OpsCount)>,
+    ) {
+        if !self.is_online() {
+            return;
+        }
+        if !readout.reads_rank_errors() {
+            eprintln!(
+                "The closed-loop distributions do not support the {} readout. Exiting program.",
+                readout.name()
+            );
+            process::exit(1);
+        }
+        // The sources are only validated, so their seed does not matter
+        for (prefill, count) in points {
+            self.online(count, prefill, StdRng::seed_from_u64(0));
+        }
+    }
+
+    /// Generates a single stream of operations, exiting the program if the configuration is invalid
+    fn gen_stream(&self, operations: usize, prefill: usize, rng: &mut StdRng) -> Vec<bool> {
+        let ops = match (&self.ops_pattern, self.distribution) {
+            (Some(pattern), _) => gen_pattern_ops(pattern, operations),
+            (None, OperationDistribution::Periodic) => {
+                if self.period == 0 {
+                    Err("The period must be positive".to_string())
+                } else {
+                    Ok(gen_periodic_ops(
+                        operations,
+                        prefill,
+                        self.period,
+                        self.amplitude,
+                        self.wave,
+                        self.jitter,
+                        rng,
+                    ))
+                }
+            }
+            (None, OperationDistribution::Alternating) => gen_alternating_ops(
+                operations,
+                prefill,
+                self.pattern_period,
+                self.pattern_enqueues,
+                self.start_with_dequeue,
+            ),
+            (None, OperationDistribution::NeverEmpty) => {
+                gen_never_empty_ops(operations, prefill, rng)
+            }
+            (None, OperationDistribution::Reflecting) => {
+                Ok(gen_reflecting_ops(operations, prefill, self.floor, rng))
+            }
+            (None, OperationDistribution::Frontier | OperationDistribution::Occupancy) => Err(
+                "The closed-loop distributions are only supported by single and the prefill sweeps"
+                    .to_string(),
+            ),
+            (None, distr) => Ok(gen_ops(distr, operations, rng)),
+        };
+        ops.unwrap_or_else(|err| {
+            eprintln!("{err}. Exiting program.");
+            process::exit(1);
+        })
+    }
+}
+
+/// Interleaves per-thread operation streams into one sequence of (thread id, is enqueue) pairs
+///
+/// With quantum 0, the interleaving is uniformly random, otherwise the threads take turns doing
+/// quantum operations each.
+fn interleave_threads(
+    streams: Vec<Vec<bool>>,
+    quantum: usize,
+    rng: &mut StdRng,
+) -> Vec<(usize, bool)> {
+    let total = streams.iter().map(|stream| stream.len()).sum();
+    let mut schedule: Vec<usize> = Vec::with_capacity(total);
+    if quantum == 0 {
+        for (thread, stream) in streams.iter().enumerate() {
+            schedule.extend(std::iter::repeat_n(thread, stream.len()));
+        }
+        schedule.shuffle(rng);
+    } else {
+        let mut remaining: Vec<usize> = streams.iter().map(|stream| stream.len()).collect();
+        while schedule.len() < total {
+            for (thread, left) in remaining.iter_mut().enumerate() {
+                let turn = quantum.min(*left);
+                schedule.extend(std::iter::repeat_n(thread, turn));
+                *left -= turn;
+            }
+        }
+    }
+
+    let mut positions = vec![0; streams.len()];
+    schedule
+        .into_iter()
+        .map(|thread| {
+            let op = streams[thread][positions[thread]];
+            positions[thread] += 1;
+            (thread, op)
+        })
+        .collect()
+}
+
+/// File formats for dumping rank errors
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum DumpFormat {
+    Csv,
+    Json,
+    /// Little-endian u64 values, one record after another
+    Bin,
+}
+
+impl DumpFormat {
+    /// The format from the extension, ignoring any .gz suffix
+    fn from_path(path: &Path) -> Option<Self> {
+        let path = match path.extension()?.to_str()? {
+            "gz" => Path::new(path.file_stem()?),
+            _ => path,
+        };
+        match path.extension()?.to_str()? {
+            "csv" => Some(Self::Csv),
+            "json" => Some(Self::Json),
+            "bin" => Some(Self::Bin),
+            _ => None,
+        }
+    }
+}
+
+/// Streams the rank errors to a file, or the full dequeue records if tags are given. In the binary
+/// format a record is rank_error, enq_nbr, deq_nbr, sub_nbr and thread, with missing values as
+/// u64::MAX. Paths ending in .gz are gzipped.
+fn dump_rank_errors(
+    path: &Path,
+    format: DumpFormat,
+    rank_errors: &[usize],
+    tags: Option<&[ErrorTag]>,
+) -> io::Result<()> {
+    let compress = path.extension().is_some_and(|extension| extension == "gz");
+    let mut writer = create_output(path, compress)?;
+    let missing = |value: Option<usize>| value.map_or(String::new(), |value| value.to_string());
+
+    match (format, tags) {
+        (DumpFormat::Csv, None) => {
+            writeln!(writer, "rank_error")?;
+            for error in rank_errors {
+                writeln!(writer, "{error}")?;
+            }
+        }
+        (DumpFormat::Csv, Some(tags)) => {
+            writeln!(writer, "rank_error,enq_nbr,deq_nbr,sub_nbr,thread")?;
+            for record in tags.iter().map(ErrorTag::to_record) {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{}",
+                    record.rank_error,
+                    missing(record.enq_nbr),
+                    record.deq_nbr,
+                    record.sub_nbr,
+                    missing(record.thread)
+                )?;
+            }
+        }
+        (DumpFormat::Json, None) => serde_json::to_writer(&mut writer, rank_errors)?,
+        (DumpFormat::Json, Some(tags)) => {
+            // Write the records one by one, to not build the whole string in memory
+            write!(writer, "[")?;
+            for (ind, tag) in tags.iter().enumerate() {
+                if ind > 0 {
+                    write!(writer, ",")?;
+                }
+                serde_json::to_writer(&mut writer, tag)?;
+            }
+            write!(writer, "]")?;
+        }
+        (DumpFormat::Bin, None) => {
+            for error in rank_errors {
+                writer.write_all(&(*error as u64).to_le_bytes())?;
+            }
+        }
+        (DumpFormat::Bin, Some(tags)) => {
+            let bin = |value: Option<usize>| value.map_or(u64::MAX, |value| value as u64);
+            for record in tags.iter().map(ErrorTag::to_record) {
+                for value in [
+                    Some(record.rank_error),
+                    record.enq_nbr,
+                    Some(record.deq_nbr),
+                    Some(record.sub_nbr),
+                    record.thread,
+                ] {
+                    writer.write_all(&bin(value).to_le_bytes())?;
+                }
+            }
+        }
+    }
+
+    writer.finish()
+}
+
+/// Writes the results with metadata as json to "{output_dir}/{output_name}-{datetime}.json",
+/// gzipped as "{...}.json.gz" if compress is set or the output name ends in ".gz"
+fn write_output<T: Serialize>(
+    output_name: &str,
+    output: &OutputOptions,
+    metadata: &serde_json::Value,
+    results: &T,
+) -> PathBuf {
+    write_json(
+        output_name,
+        output,
+        &serde_json::json!({
+            "schema_version": SCHEMA_VERSION,
+            "metadata": metadata,
+            "results": results,
+        }),
+    )
+}
+
+/// Prints the warnings about the sweep points to stderr
+fn report_warnings(warnings: &[PointWarning]) {
+    for warning in warnings {
+        eprintln!("Warning: point {}: {}", warning.point, warning.message);
+    }
+}
+
+/// The descriptions of the operations of sweep points, keyed by their prefill and operations
+fn ops_descriptions_json(
+    descriptions: impl Iterator<Item = ((usize, OpsCount), OpsDescription)>,
+) -> serde_json::Value {
+    descriptions
+        .map(|((pre, ops), description)| {
+            serde_json::json!([format!("({pre}, {})", ops.total()), description])
+        })
+        .collect()
+}
+
+/// As write_output, but also writes the power law fits under "fits" and the point warnings
+/// under "warnings" if there are any
+fn write_sweep_output(
+    output_name: &str,
+    output: &OutputOptions,
+    metadata: &serde_json::Value,
+    results: &[serde_json::Value],
+    fits: Option<serde_json::Value>,
+    warnings: &[PointWarning],
+) -> PathBuf {
+    let file = ResultFile {
+        schema_version: SCHEMA_VERSION,
+        metadata: metadata.clone(),
+        results: serde_json::json!(results),
+        fits,
+        warnings: (!warnings.is_empty()).then(|| serde_json::json!(warnings)),
+    };
+    write_json(output_name, output, &file.into_json())
+}
+
+/// Fits error = c * x^a to the (fixed, x, error) points, for each value of the fixed dimension
+///
+/// Groups without three positive errors get a null fit.
+fn fit_groups(
+    fixed_name: &str,
+    points: impl Iterator<Item = (usize, usize, f32)>,
+) -> serde_json::Value {
+    let mut groups: BTreeMap<usize, Vec<(f64, f64)>> = BTreeMap::new();
+    for (fixed, x, error) in points {
+        groups
+            .entry(fixed)
+            .or_default()
+            .push((x as f64, error as f64));
+    }
+    groups
+        .into_iter()
+        .map(|(fixed, points)| {
+            serde_json::json!({
+                fixed_name: fixed,
+                "fit": fit_power_law(&points),
+            })
+        })
+        .collect()
+}
+
+/// Writes the json value to "{output_dir}/{output_name}-{datetime}.json", gzipped if compress is
+/// set, and returns the path
+fn write_json(output_name: &str, output: &OutputOptions, value: &serde_json::Value) -> PathBuf {
+    let (output_name, compress) = match output_name.strip_suffix(".gz") {
+        Some(stripped) => (stripped, true),
+        None => (output_name, output.compress),
+    };
+    let path = output_path(
+        output,
+        output_name,
+        if compress { "json.gz" } else { "json" },
+    );
+    let written = create_output(&path, compress).and_then(|mut writer| {
+        serde_json::to_writer_pretty(&mut writer, value)?;
+        writer.finish()
+    });
+    if let Err(err) = written {
+        eprintln!(
+            "Could not write the output to {}: {err}. Exiting program.",
+            path.to_string_lossy()
+        );
+        process::exit(1);
+    }
+
+    println!("Writing output to: {}", path.to_string_lossy());
+    path
+}
+
+/// Writes the sweep rows as Parquet to "{output_dir}/{output_name}-{datetime}.parquet"
+#[cfg(feature = "arrow")]
+fn write_parquet_output(
+    output_name: &str,
+    output: &OutputOptions,
+    metadata: &serde_json::Value,
+    rows: &[SweepRow],
+) {
+    let path = output_path(output, output_name.trim_end_matches(".gz"), "parquet");
+    if let Err(err) = crate::write_sweep_parquet(&path, metadata, rows) {
+        eprintln!("Could not write the parquet output: {err}. Exiting program.");
+        process::exit(1);
+    }
+    println!("Writing output to: {}", path.to_string_lossy());
+}
+
+#[cfg(not(feature = "arrow"))]
+fn write_parquet_output(
+    _output_name: &str,
+    _output: &OutputOptions,
+    _metadata: &serde_json::Value,
+    _rows: &[SweepRow],
+) {
+    unreachable!("Checked by OutputFormat::assert_available");
+}
+
+/// The mean of the per-run values, summed in run order so that it does not depend on scheduling
+fn sequential_mean(values: &[f32]) -> f32 {
+    values.iter().fold(0.0, |acc, value| acc + value) / values.len() as f32
+}
+
+/// The simulated operations per wall-clock second, where online workloads also count the time
+/// of generating their operations as they go
+fn ops_per_second(operations: usize, elapsed: Duration) -> f64 {
+    operations as f64 / elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+}
+
+/// The readout of a single sweep run, from ErrorReadout::analyze
+struct RunReadout {
+    value: f32,
+
+    /// The rank errors, unless they were streamed into a histogram
+    rank_errors: Option<Vec<usize>>,
+
+    /// The failed enqueues, if there is an item capacity
+    enqueue_failures: Option<EnqueueFailures>,
+}
+
+/// The value, average d, cost, virtual time and rank error histogram of a single sweep run
+struct RunResult {
+    value: f32,
+    average_d: Option<f32>,
+    cost: CostReport,
+    virtual_time: Option<VirtualTime>,
+    histogram: Option<LogHistogram>,
+    enqueue_failures: Option<EnqueueFailures>,
+
+    /// The rank errors, only kept to pool them with the other runs
+    rank_errors: Option<Vec<usize>>,
+
+    seeds: RunSeeds,
+
+    /// The measured dequeues, and how many of them returned empty
+    dequeues: usize,
+    empty_returns: usize,
+
+    /// The prefill and measured operations simulated per wall-clock second
+    ops_per_second: f32,
+}
+
+/// The concrete seeds of one run of a sweep, which single reproduces it from, see --exact-seed
+#[derive(Copy, Clone, Debug, Serialize)]
+struct RunSeeds {
+    queue: u64,
+
+    /// The seed of the operations, which are shared by the runs of a point unless generated online
+    operations: u64,
+
+    /// The seed of the sub-queue layout if it was permuted, filled in from the queue of the run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    layout: Option<u64>,
+}
+
+impl RunResult {
+    fn new(
+        readout: RunReadout,
+        queue: &DChoiceQueue<usize>,
+        histogram: Option<LogHistogram>,
+        aggregate: Aggregate,
+        seeds: RunSeeds,
+        prefill: usize,
+        elapsed: Duration,
+    ) -> Self {
+        let cost = queue.cost_report();
+        let (dequeues, empty_returns) = queue.dequeue_counts();
+        Self {
+            value: readout.value,
+            average_d: queue.average_d().map(|d| d as f32),
+            ops_per_second: ops_per_second(prefill + cost.operations, elapsed) as f32,
+            cost,
+            virtual_time: queue.virtual_time(),
+            histogram,
+            enqueue_failures: readout.enqueue_failures,
+            rank_errors: readout
+                .rank_errors
+                .filter(|_| aggregate == Aggregate::PooledReadout),
+            seeds: RunSeeds {
+                layout: queue.layout_seed(),
+                ..seeds
+            },
+            dequeues,
+            empty_returns,
+        }
+    }
+}
+
+/// Runs a sweep run on a queue registered with register_queue, which only has the rank errors and
+/// measured dequeues, so the costs are left empty
+fn external_run(
+    mut queue: Box<dyn RelaxedFifo<usize>>,
+    seeds: RunSeeds,
+    prefill: usize,
+    operations: &[bool],
+    error_readout: ErrorReadout,
+    aggregate: Aggregate,
+) -> RunResult {
+    let started = Instant::now();
+    let (rank_errors, oracle) =
+        or_exit(analyze_simple_checked(queue.as_mut(), prefill, operations));
+    let elapsed = started.elapsed();
+    let dequeues = operations.iter().filter(|is_enqueue| !**is_enqueue).count();
+    RunResult {
+        value: error_readout.readout(&rank_errors),
+        average_d: None,
+        cost: CostReport::default(),
+        virtual_time: None,
+        histogram: None,
+        enqueue_failures: None,
+        rank_errors: Some(rank_errors).filter(|_| aggregate == Aggregate::PooledReadout),
+        seeds,
+        dequeues,
+        empty_returns: dequeues - oracle.dead,
+        ops_per_second: ops_per_second(prefill + operations.len(), elapsed) as f32,
+    }
+}
+
+/// The combined runs of one sweep point
+struct PointResult {
+    /// The run values combined by the aggregate
+    value: f32,
+
+    /// The value of each run, kept so that points of several sweeps can be merged
+    run_values: Vec<f32>,
+
+    /// The seeds of each run, in the order of the run values
+    run_seeds: Vec<RunSeeds>,
+
+    /// The measured dequeues of each run, and how many of them returned empty
+    run_dequeues: Vec<usize>,
+    run_empty_returns: Vec<usize>,
+
+    /// The spread of the run values
+    summary: RunSummary,
+
+    /// The mean average d of the runs, which is None unless every run adapted d
+    average_d: Option<f32>,
+
+    /// The costs summed over all runs
+    cost: CostReport,
+
+    /// The virtual times summed over all runs, if there is a latency model
+    virtual_time: Option<VirtualTime>,
+
+    /// The rank error histograms merged over all runs, if they were kept
+    histogram: Option<LogHistogram>,
+
+    /// The failed enqueues merged over all runs, if there is an item capacity
+    enqueue_failures: Option<EnqueueFailures>,
+
+    /// The mean simulation speed of the runs, which share the cores with the other runs
+    ops_per_second: f32,
+}
+
+impl PointResult {
+    /// Combines the runs into the value of the point by the aggregate, where pooling reads out the
+    /// merged histograms if they were kept, and otherwise the concatenated rank errors
+    fn combine(runs: Vec<RunResult>, aggregate: Aggregate, readout: ErrorReadout) -> Self {
+        let values: Vec<f32> = runs.iter().map(|run| run.value).collect();
+        let run_seeds = runs.iter().map(|run| run.seeds).collect();
+        let run_dequeues = runs.iter().map(|run| run.dequeues).collect();
+        let run_empty_returns = runs.iter().map(|run| run.empty_returns).collect();
+        let speeds: Vec<f32> = runs.iter().map(|run| run.ops_per_second).collect();
+        let ops_per_second = sequential_mean(&speeds);
+        let average_d = runs
+            .iter()
+            .map(|run| run.average_d)
+            .collect::<Option<Vec<f32>>>()
+            .map(|average_ds| sequential_mean(&average_ds));
+        let mut cost = CostReport::default();
+        runs.iter().for_each(|run| cost.merge(&run.cost));
+        let virtual_time = runs
+            .iter()
+            .map(|run| run.virtual_time)
+            .collect::<Option<Vec<VirtualTime>>>()
+            .map(|times| {
+                let mut total = VirtualTime::default();
+                times.iter().for_each(|time| total.merge(time));
+                total
+            });
+        let histogram = runs.iter().filter_map(|run| run.histogram.as_ref()).fold(
+            None,
+            |merged: Option<LogHistogram>, histogram| {
+                let mut merged =
+                    merged.unwrap_or_else(|| LogHistogram::new(histogram.significant_bits()));
+                merged.merge(histogram);
+                Some(merged)
+            },
+        );
+        let enqueue_failures = runs
+            .iter()
+            .map(|run| run.enqueue_failures.as_ref())
+            .collect::<Option<Vec<&EnqueueFailures>>>()
+            .map(|failures| {
+                let mut total = EnqueueFailures::default();
+                failures
+                    .iter()
+                    .for_each(|run_failures| total.merge(run_failures));
+                total
+            });
+        let summary = summarize_runs(&values);
+        let value = match aggregate {
+            Aggregate::MeanOfReadouts => summary.mean,
+            Aggregate::MedianOfReadouts => median(&values),
+            Aggregate::PooledReadout => match &histogram {
+                Some(histogram) => readout.histogram_readout(histogram),
+                None => {
+                    let pooled: Vec<usize> = runs
+                        .into_iter()
+                        .flat_map(|run| run.rank_errors.expect("Kept for pooling"))
+                        .collect();
+                    readout.readout(&pooled)
+                }
+            },
+        };
+        Self {
+            value,
+            run_values: values,
+            run_seeds,
+            run_dequeues,
+            run_empty_returns,
+            summary,
+            average_d,
+            cost,
+            virtual_time,
+            histogram,
+            enqueue_failures,
+            ops_per_second,
+        }
+    }
+
+    /// Fills in the value, average d and cost columns of the row, and repeats it for each run
+    fn sweep_rows(&self, row: SweepRow) -> Vec<SweepRow> {
+        let row = SweepRow {
+            value: self.value,
+            std: self.summary.std,
+            ci95: self.summary.ci95,
+            average_d: self.average_d,
+            cost_operations: self.cost.operations,
+            cost_samples: self.cost.samples,
+            cost_scans: self.cost.scans,
+            cost_scanned: self.cost.scanned,
+            cost_moves: self.cost.moves,
+            touches_per_operation: self.cost.touches_per_operation() as f32,
+            enqueue_time: self.virtual_time.map(|time| time.enqueue_time as f32),
+            dequeue_time: self.virtual_time.map(|time| time.dequeue_time as f32),
+            throughput: self.virtual_time.map(|time| time.throughput() as f32),
+            failed_enqueues: self
+                .enqueue_failures
+                .as_ref()
+                .map(|failures| failures.count),
+            ops_per_second: self.ops_per_second,
+            ..row
+        };
+        self.run_values
+            .iter()
+            .zip(&self.run_seeds)
+            .enumerate()
+            .map(|(run, (run_value, seeds))| SweepRow {
+                run,
+                run_value: *run_value,
+                seed: seeds.queue,
+                operations_seed: seeds.operations,
+                ..row.clone()
+            })
+            .collect()
+    }
+
+    /// The warnings if the point is likely noise, see diagnose_point
+    fn diagnose(
+        &self,
+        point: &str,
+        readout: ErrorReadout,
+        aggregate: Aggregate,
+        diagnostics: &DiagnosticsConfig,
+    ) -> Vec<PointWarning> {
+        let runs = PointRuns {
+            run_values: &self.run_values,
+            dequeues: &self.run_dequeues,
+            empty_returns: &self.run_empty_returns,
+            tail_fraction: readout.tail_fraction(),
+            pooled: aggregate == Aggregate::PooledReadout,
+        };
+        diagnose_point(point, &runs, &diagnostics.thresholds())
+    }
+
+    /// Adds the run values and seeds, their spread, the cost and simulation speed, and the average
+    /// d, virtual time, histogram and failed enqueues if kept, to the extras of a sweep record
+    fn with_extras(&self, mut record: serde_json::Value) -> serde_json::Value {
+        let Some(record_items) = record.as_array_mut() else {
+            return record;
+        };
+        if record_items.len() < 3 {
+            record_items.push(serde_json::json!({}));
+        }
+        let extras = record_items[2]
+            .as_object_mut()
+            .expect("The record extras are an object");
+        extras.insert("run_values".to_string(), serde_json::json!(self.run_values));
+        extras.insert("run_seeds".to_string(), serde_json::json!(self.run_seeds));
+        extras.insert("std".to_string(), serde_json::json!(self.summary.std));
+        extras.insert("ci95".to_string(), serde_json::json!(self.summary.ci95));
+        if let Some(average_d) = self.average_d {
+            extras.insert("average_d".to_string(), serde_json::json!(average_d));
+        }
+        if let Some(time) = self.virtual_time {
+            let mut time_json = serde_json::json!(time);
+            time_json["throughput"] = serde_json::json!(time.throughput());
+            extras.insert("virtual_time".to_string(), time_json);
+        }
+        if let Some(histogram) = &self.histogram {
+            extras.insert("histogram".to_string(), serde_json::json!(histogram));
+        }
+        if let Some(failures) = &self.enqueue_failures {
+            extras.insert("enqueue_failures".to_string(), serde_json::json!(failures));
+        }
+        extras.insert("cost".to_string(), serde_json::json!(self.cost));
+        extras.insert(
+            "touches_per_operation".to_string(),
+            serde_json::json!(self.cost.touches_per_operation()),
+        );
+        extras.insert(
+            "ops_per_second".to_string(),
+            serde_json::json!(self.ops_per_second),
+        );
+        record
+    }
+}
+
+/// A data point of a sweep result file, with its mean rank error and touches per operation
+struct SweepPoint {
+    file: String,
+    key: serde_json::Value,
+    error: f64,
+    cost: f64,
+}
+
+/// Summarizes the rank errors of the operations with and without the dequeues moved earlier, each
+/// given with the rank error tags of its run, and counts the dequeues which moved
+fn priority_comparison(
+    prioritized: (&[ErrorTag], &[bool]),
+    unprioritized: (&[ErrorTag], &[bool]),
+) -> serde_json::Value {
+    let summary = |(tags, operations): (&[ErrorTag], &[bool])| {
+        analyze_phases(tags, operations, &[operations.len()])
+            .pop()
+            .expect("One phase of all operations")
+    };
+    let dequeue_positions = |operations: &[bool]| -> Vec<usize> {
+        (0..operations.len())
+            .filter(|ind| !operations[*ind])
+            .collect()
+    };
+    let moved = dequeue_positions(prioritized.1)
+        .iter()
+        .zip(dequeue_positions(unprioritized.1))
+        .filter(|(prioritized, unprioritized)| **prioritized != *unprioritized)
+        .count();
+    let (prioritized, unprioritized) = (summary(prioritized), summary(unprioritized));
+    serde_json::json!({
+        "mean_rank_error_change": prioritized.mean_rank_error - unprioritized.mean_rank_error,
+        "prioritized": prioritized,
+        "unprioritized": unprioritized,
+        "moved_dequeues": moved,
+    })
+}
+
+/// The share of the enqueues and dequeues each sub-queue served, next to its share of the service
+/// weights, where the enqueues include the prefill
+fn service_rates(weights: &[f64], queue: &DChoiceQueue<usize>) -> Vec<serde_json::Value> {
+    let share = |counts: Vec<usize>| -> Vec<f64> {
+        let total = counts.iter().sum::<usize>().max(1) as f64;
+        counts
+            .into_iter()
+            .map(|count| count as f64 / total)
+            .collect()
+    };
+    let total_weight: f64 = weights.iter().sum();
+    let (enqueue_shares, dequeue_shares) = (
+        share(queue.subqueue_enqueue_counts()),
+        share(queue.subqueue_dequeue_counts()),
+    );
+    weights
+        .iter()
+        .zip(enqueue_shares.iter().zip(dequeue_shares))
+        .map(|(weight, (enqueue_share, dequeue_share))| {
+            serde_json::json!({
+                "weight": weight,
+                "weight_share": weight / total_weight,
+                "enqueue_share": enqueue_share,
+                "dequeue_share": dequeue_share,
+            })
+        })
+        .collect()
+}
+
+/// Summarizes the rank errors of the same operations with and without the empty dequeue fallback,
+/// and counts the dequeues which returned different items, counting empty returns as no item
+fn fallback_comparison(
+    with_tags: &[ErrorTag],
+    without_tags: &[ErrorTag],
+    operations: &[bool],
+) -> serde_json::Value {
+    let summary = |tags: &[ErrorTag]| {
+        analyze_phases(tags, operations, &[operations.len()])
+            .pop()
+            .expect("One phase of all operations")
+    };
+    let differing = with_tags
+        .iter()
+        .zip(without_tags)
+        .filter(|(with, without)| with.enq_nbr() != without.enq_nbr())
+        .count();
+    serde_json::json!({
+        "with_fallback": summary(with_tags),
+        "without_fallback": summary(without_tags),
+        "differing_dequeues": differing,
+    })
+}
+
+/// Reads a json output file, which is gzipped if it ends with ".gz"
+fn read_json_output(path: &Path) -> Result<serde_json::Value, String> {
+    let reader = open_input(path).map_err(|err| err.to_string())?;
+    serde_json::from_reader(reader).map_err(|err| err.to_string())
+}
+
+/// Opens a file for buffered reading, decompressing it if it ends with ".gz"
+fn open_input(path: &Path) -> io::Result<Box<dyn Read>> {
+    let file = File::open(path)?;
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        Ok(Box::new(GzDecoder::new(BufReader::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// Reads the points of a json sweep result file
+fn read_sweep_points(path: &Path) -> Result<Vec<SweepPoint>, String> {
+    let output = load_results(path)?;
+    let results = output
+        .results
+        .as_array()
+        .ok_or("Has no results array, only json sweep outputs are supported")?;
+    results
+        .iter()
+        .map(|record| {
+            let error = record[1].as_f64().filter(|error| !error.is_nan());
+            let cost = record[2]["touches_per_operation"].as_f64();
+            match (error, cost) {
+                (Some(error), Some(cost)) => Ok(SweepPoint {
+                    file: path.to_string_lossy().to_string(),
+                    key: record[0].clone(),
+                    error,
+                    cost,
+                }),
+                _ => Err(format!(
+                    "The point {} has no rank error and touches per operation",
+                    record[0]
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Creates the output dir and returns "{output_dir}/{output_name}-{datetime}.{extension}", or
+/// without the datetime if no_timestamp is set
+fn output_path(output: &OutputOptions, output_name: &str, extension: &str) -> PathBuf {
+    let output_dir = output.output_dir.as_deref().unwrap_or(Path::new("results"));
+    create_dir_all(output_dir).expect("Could not create the output dir");
+    let file_name = if output.no_timestamp {
+        format!("{output_name}.{extension}")
+    } else {
+        let timestamp = Local::now().format("%Y%m%d-%H%M%S");
+        format!("{output_name}-{timestamp}.{extension}")
+    };
+    output_dir.join(file_name)
+}
+
+/// A buffered output file, which is gzipped if created with compress
+enum OutputFile {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl OutputFile {
+    /// Writes what is left, including the gzip trailer, so that the errors of the last writes
+    /// are returned instead of lost when dropping the file
+    fn finish(self) -> io::Result<()> {
+        let writer = match self {
+            OutputFile::Plain(writer) => writer,
+            OutputFile::Gzip(encoder) => encoder.finish()?,
+        };
+        writer
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?;
+        Ok(())
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputFile::Plain(writer) => writer.write(buf),
+            OutputFile::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputFile::Plain(writer) => writer.flush(),
+            OutputFile::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Creates a buffered file writer, which gzips everything written if compress is set. Call finish
+/// on it once written
+fn create_output(path: &Path, compress: bool) -> io::Result<OutputFile> {
+    let writer = BufWriter::new(File::create(path)?);
+    if compress {
+        Ok(OutputFile::Gzip(GzEncoder::new(
+            writer,
+            Compression::default(),
+        )))
+    } else {
+        Ok(OutputFile::Plain(writer))
+    }
+}
+
+/// A random generator of the stream at the point and run given by the indexes, see derive_seed
+fn stream_rng(seed: u64, stream: SeedStream, indexes: &[u64]) -> StdRng {
+    StdRng::seed_from_u64(derive_seed(seed, stream, indexes))
+}
+
+fn gen_ops(distr: OperationDistribution, operations: usize, rng: &mut StdRng) -> Vec<bool> {
+    match distr {
+        OperationDistribution::RandomBalanced => {
+            let mut ops_vec: Vec<bool> = std::iter::repeat_n(true, operations / 2)
+                .chain(std::iter::repeat_n(false, operations / 2))
+                .collect();
+            ops_vec.shuffle(rng);
+            ops_vec
+        }
+        OperationDistribution::Alternating
+        | OperationDistribution::Periodic
+        | OperationDistribution::NeverEmpty
+        | OperationDistribution::Reflecting
+        | OperationDistribution::Frontier
+        | OperationDistribution::Occupancy => {
+            unreachable!("The distribution depends on more than the operation count")
+        }
+    }
+}
+
+/// Repeats periods of the given numbers of operations and enqueues, with the enqueues first unless
+/// starting with the dequeues. The last period is cut short to fit the operations. Starting with
+/// the dequeues needs a prefill for them, so that the first period does not dequeue from empty
+fn gen_alternating_ops(
+    operations: usize,
+    prefill: usize,
+    period: usize,
+    enqueues: usize,
+    start_with_dequeue: bool,
+) -> Result<Vec<bool>, String> {
+    if period == 0 {
+        return Err("The pattern period must be positive".to_string());
+    }
+    if enqueues > period {
+        return Err(
+            "The pattern can not have more enqueues than operations per period".to_string(),
+        );
+    }
+    let dequeues = period - enqueues;
+    if start_with_dequeue && dequeues.min(operations) > prefill {
+        return Err(format!(
+            "Starting with the dequeues needs a prefill of at least the {} dequeues of the first period",
+            dequeues.min(operations)
+        ));
+    }
+    Ok((0..operations)
+        .map(|op| {
+            let phase = op % period;
+            if start_with_dequeue {
+                phase >= dequeues
+            } else {
+                phase < enqueues
+            }
+        })
+        .collect())
+}
+
+/// Generates randomly balanced operations where the queue size never reaches zero
+///
+/// Starts from a uniformly shuffled sequence and repairs it by swapping each dequeue that would
+/// empty the queue with the next enqueue. Such an enqueue always exists, as the final size is the
+/// prefill. The result is not exactly uniform among all valid sequences, but close for prefills
+/// that are not tiny compared to the operations.
+fn gen_never_empty_ops(
+    operations: usize,
+    prefill: usize,
+    rng: &mut StdRng,
+) -> Result<Vec<bool>, String> {
+    if prefill == 0 {
+        return Err("The never-empty distribution requires a prefill of at least 1".to_string());
+    }
+
+    let mut ops = gen_ops(OperationDistribution::RandomBalanced, operations, rng);
+    let mut size = prefill;
+    let mut next_enqueue = 0;
+    for i in 0..ops.len() {
+        if !ops[i] && size == 1 {
+            next_enqueue = next_enqueue.max(i + 1);
+            while !ops[next_enqueue] {
+                next_enqueue += 1;
+            }
+            ops.swap(i, next_enqueue);
+        }
+        if ops[i] {
+            size += 1;
+        } else {
+            size -= 1;
+        }
+    }
+
+    debug_assert!(
+        size == prefill,
+        "The repair must keep the sequence balanced"
+    );
+    Ok(ops)
+}
+
+/// Generates a random walk of operations, reflected upwards at the floor
+///
+/// Each operation is a fair coin flip, except that an enqueue is forced whenever a dequeue would
+/// make the queue size (prefill + enqueues - successful dequeues) drop below the floor.
+fn gen_reflecting_ops(
+    operations: usize,
+    prefill: usize,
+    floor: usize,
+    rng: &mut StdRng,
+) -> Vec<bool> {
+    let mut size = prefill;
+    (0..operations)
+        .map(|_| {
+            let enqueue = size <= floor || rng.gen_bool(0.5);
+            if enqueue {
+                size += 1;
+            } else {
+                size -= 1;
+            }
+            enqueue
+        })
+        .collect()
+}
+
+/// Generates operations making the queue size follow a periodic target around the prefill
+///
+/// Enqueues while the current size is below the (jittered) target, and dequeues otherwise. Dequeues
+/// from an empty queue are assumed to return empty, so they do not shrink the size.
+fn gen_periodic_ops(
+    operations: usize,
+    prefill: usize,
+    period: usize,
+    amplitude: usize,
+    wave: Wave,
+    jitter: usize,
+    rng: &mut StdRng,
+) -> Vec<bool> {
+    let mut size = prefill as i64;
+    (0..operations)
+        .map(|op| {
+            let phase = (op % period) as f64 / period as f64;
+            let offset = match wave {
+                Wave::Sine => (2.0 * std::f64::consts::PI * phase).sin(),
+                Wave::Sawtooth => 2.0 * phase - 1.0,
+            };
+            let noise = rng.gen_range(-(jitter as i64)..=jitter as i64);
+            let target = prefill as i64 + (amplitude as f64 * offset).round() as i64 + noise;
+
+            let enqueue = target > size;
+            if enqueue {
+                size += 1;
+            } else {
+                size = (size - 1).max(0);
+            }
+            enqueue
+        })
+        .collect()
+}
+
+/// Operations processing a BFS frontier, where each dequeued node enqueues its children
+///
+/// Dequeues while no children are left to enqueue, drawing the number of children of each
+/// successfully dequeued node. Stops after the operations, or when the queue is empty with no
+/// children left.
+struct FrontierOps {
+    remaining: usize,
+
+    /// The children left to enqueue of the last dequeued node
+    children: usize,
+
+    /// The number of items in the queue
+    live: usize,
+
+    /// The weight of each number of children
+    branching: WeightedIndex<f64>,
+    rng: StdRng,
+}
+
+impl FrontierOps {
+    fn new(
+        operations: usize,
+        prefill: usize,
+        branching: Branching,
+        max_branching: usize,
+        exponent: f64,
+        rng: StdRng,
+    ) -> Self {
+        let weights = (0..=max_branching).map(|k| match branching {
+            Branching::Constant => f64::from(k == max_branching),
+            Branching::Uniform => 1.0,
+            Branching::PowerLaw => ((k + 1) as f64).powf(-exponent),
+        });
+        Self {
+            remaining: operations,
+            children: 0,
+            live: prefill,
+            branching: WeightedIndex::new(weights).unwrap_or_else(|err| {
+                eprintln!("Invalid branching: {err}. Exiting program.");
+                process::exit(1);
+            }),
+            rng,
+        }
+    }
+}
+
+impl OpSource for FrontierOps {
+    fn next_op(&mut self) -> Option<bool> {
+        if self.remaining == 0 || (self.children == 0 && self.live == 0) {
+            return None;
+        }
+        self.remaining -= 1;
+        if self.children > 0 {
+            self.children -= 1;
+            Some(true)
+        } else {
+            Some(false)
+        }
+    }
+
+    fn observe(&mut self, outcome: OpOutcome) {
+        self.live = outcome.live;
+        if outcome.dequeued {
+            self.children = self.branching.sample(&mut self.rng);
+        }
+    }
+}
+
+/// Operations keeping the queue size hovering just below a target size
+///
+/// The probability of an enqueue falls linearly from 1 to 0 as the size goes from the target size
+/// minus the control window to the target size.
+struct OccupancyOps {
+    remaining: usize,
+
+    /// The number of items in the queue
+    live: usize,
+
+    target_size: usize,
+    control_window: usize,
+    rng: StdRng,
+}
+
+impl OpSource for OccupancyOps {
+    fn next_op(&mut self) -> Option<bool> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let below = self
+            .target_size
+            .saturating_sub(self.live)
+            .min(self.control_window);
+        Some(self.rng.gen_bool(below as f64 / self.control_window as f64))
+    }
+
+    fn observe(&mut self, outcome: OpOutcome) {
+        self.live = outcome.live;
+    }
+}
+
+/// Moves each dequeue past up to window of the enqueues right before it, where the queue starts
+/// with prefill items
+///
+/// A dequeue is only moved past an enqueue if the queue is still non-empty before it, so no
+/// dequeue which found an item comes to find the queue empty. The dequeues keep their order, as do
+/// the enqueues, and the queue sizes after each dequeue's original position are unchanged.
+fn prioritize_dequeues(operations: &[bool], prefill: usize, window: usize) -> Vec<bool> {
+    let mut prioritized = Vec::with_capacity(operations.len());
+    // The queue size before each of the prioritized operations
+    let mut sizes = Vec::with_capacity(operations.len());
+    let mut size = prefill;
+    for is_enqueue in operations.iter() {
+        if *is_enqueue {
+            prioritized.push(true);
+            sizes.push(size);
+            size += 1;
+            continue;
+        }
+        let mut ind = prioritized.len();
+        while prioritized.len() - ind < window
+            && ind > 0
+            && prioritized[ind - 1]
+            && sizes[ind - 1] > 0
+        {
+            ind -= 1;
+        }
+        let size_before = sizes.get(ind).copied().unwrap_or(size);
+        prioritized.insert(ind, false);
+        sizes.insert(ind, size_before);
+        if size_before > 0 {
+            // The enqueues moved past now come after the item is gone
+            sizes[ind + 1..].iter_mut().for_each(|size| *size -= 1);
+            size -= 1;
+        }
+    }
+    prioritized
+}
+
+/// Randomly shuffles exactly the given numbers of enqueues and dequeues
+fn gen_split_ops(enqueues: usize, dequeues: usize, rng: &mut StdRng) -> Vec<bool> {
+    let mut ops_vec: Vec<bool> = std::iter::repeat_n(true, enqueues)
+        .chain(std::iter::repeat_n(false, dequeues))
+        .collect();
+    ops_vec.shuffle(rng);
+    ops_vec
+}
+
+/// Repeats a pattern of enqueues (E) and dequeues (D) until there are the given number of operations
+fn gen_pattern_ops(pattern: &str, operations: usize) -> Result<Vec<bool>, String> {
+    let pattern = pattern
+        .chars()
+        .map(|c| match c {
+            'E' => Ok(true),
+            'D' => Ok(false),
+            other => Err(format!(
+                "Invalid character '{other}' in operation pattern, expected 'E' or 'D'"
+            )),
+        })
+        .collect::<Result<Vec<bool>, String>>()?;
+    if pattern.is_empty() {
+        return Err("The operation pattern cannot be empty".to_string());
+    }
+
+    Ok(pattern.into_iter().cycle().take(operations).collect())
+}
+
+/// Parses the "base:sample:scan:retry" costs of a latency model, in nanoseconds
+fn parse_latency_model(model: &str) -> Result<LatencyModel, String> {
+    let costs = model
+        .split(':')
+        .map(|cost| match cost.parse::<f64>() {
+            Ok(cost) if cost >= 0.0 => Ok(cost),
+            _ => Err(format!(
+                "Invalid cost '{cost}', must be a non-negative number"
+            )),
+        })
+        .collect::<Result<Vec<f64>, String>>()?;
+    let [base, sample, scan, retry] = costs[..] else {
+        return Err(format!(
+            "Expected base:sample:scan:retry, but got '{model}'"
+        ));
+    };
+    Ok(LatencyModel {
+        base,
+        sample,
+        scan,
+        retry,
+    })
+}
+
+fn parse_phases(phases: &str) -> Result<Phases, String> {
+    let numbers = phases
+        .split(':')
+        .map(|number| {
+            number
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid number '{number}' in phases '{phases}'"))
+        })
+        .collect::<Result<Vec<usize>, String>>()?;
+    let [ramp, steady, drain] = numbers[..] else {
+        return Err(format!("Expected ramp:steady:drain, but got '{phases}'"));
+    };
+    Ok(Phases {
+        ramp,
+        steady,
+        drain,
+    })
+}
+
+/// The service weights of the sub-queues, see ServiceWeights
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum SubqueueWeights {
+    Uniform,
+
+    /// Sub-queue 0 has this weight, and the others 1
+    OneSlow(f64),
+
+    /// Sub-queue i has weight (i + 1) / the number of sub-queues
+    Linear,
+
+    /// The weight of each sub-queue
+    List(Vec<f64>),
+}
+
+impl SubqueueWeights {
+    /// The weight of each of the sub-queues
+    ///
+    /// Exits the program if a list does not have one weight per sub-queue.
+    fn weights(&self, subqueues: usize) -> Vec<f64> {
+        match self {
+            SubqueueWeights::Uniform => vec![1.0; subqueues],
+            SubqueueWeights::OneSlow(weight) => (0..subqueues)
+                .map(|ind| if ind == 0 { *weight } else { 1.0 })
+                .collect(),
+            SubqueueWeights::Linear => (1..=subqueues)
+                .map(|ind| ind as f64 / subqueues as f64)
+                .collect(),
+            SubqueueWeights::List(weights) => {
+                if weights.len() != subqueues {
+                    eprintln!(
+                        "Expected one service weight per sub-queue, but got {} for {subqueues}. Exiting program.",
+                        weights.len()
+                    );
+                    process::exit(1);
+                }
+                weights.clone()
+            }
+        }
+    }
+}
+
+fn parse_subqueue_weights(weights: &str) -> Result<SubqueueWeights, String> {
+    let parse = |weight: &str| {
+        weight
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid weight '{weight}' in service weights '{weights}'"))
+    };
+    match weights.split_once(':') {
+        _ if weights == "uniform" => Ok(SubqueueWeights::Uniform),
+        _ if weights == "linear" => Ok(SubqueueWeights::Linear),
+        Some(("one-slow", weight)) => Ok(SubqueueWeights::OneSlow(parse(weight)?)),
+        Some(_) => Err(format!(
+            "Expected uniform, one-slow:x, linear or a comma separated list, but got '{weights}'"
+        )),
+        None => Ok(SubqueueWeights::List(
+            weights.split(',').map(parse).collect::<Result<_, _>>()?,
+        )),
+    }
+}
+
+fn parse_blackout(blackout: &str) -> Result<Blackout, String> {
+    let numbers = blackout
+        .split(':')
+        .map(|number| {
+            number
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid number '{number}' in blackout '{blackout}'"))
+        })
+        .collect::<Result<Vec<usize>, String>>()?;
+    let [subqueue, start, end] = numbers[..] else {
+        return Err(format!(
+            "Expected sub-queue:start:end, but got '{blackout}'"
+        ));
+    };
+    if start >= end {
+        return Err(format!(
+            "The blackout window must be non-empty, but got '{blackout}'"
+        ));
+    }
+    Ok(Blackout {
+        subqueue,
+        start,
+        end,
+    })
+}
+
+/// The constructor of the queue of --queue external:<name>, as registered by a binary wrapping
+/// this one
+///
+/// Exits the program if no queue is registered under the name, or if the test needs more than the
+/// rank errors of operations generated up front, which is all the external queues support.
+fn external_factory(
+    name: &str,
+    readout: ErrorReadout,
+    workload: &OpsConfig,
+    budget_epoch: Option<usize>,
+) -> QueueFactory {
+    let factory = registered_queue(name).unwrap_or_else(|| {
+        eprintln!(
+            "No queue is registered as {name}, the registered ones are: [{}]. Exiting program.",
+            registered_queue_names().join(", ")
+        );
+        process::exit(1);
+    });
+    if !readout.reads_rank_errors()
+        || workload.is_online()
+        || workload.is_batched()
+        || workload.max_items.is_some()
+        || budget_epoch.is_some()
+    {
+        eprintln!("The external queues only support the rank error readouts, without closed-loop distributions, batches, an item capacity or a budget. Exiting program.");
+        process::exit(1);
+    }
+    factory
+}
+
+fn parse_external_queue(queue: &str) -> Result<String, String> {
+    match queue.strip_prefix("external:") {
+        Some(name) if !name.is_empty() => Ok(name.to_string()),
+        _ => Err(format!("Expected external:<name>, but got '{queue}'")),
+    }
+}
+
+fn parse_runs(runs: &str) -> Result<usize, String> {
+    match runs.parse::<usize>() {
+        Ok(0) => Err("Needs at least one run".to_string()),
+        Ok(runs) => Ok(runs),
+        Err(err) => Err(format!("Invalid number of runs '{runs}': {err}")),
+    }
+}
+
+fn parse_heatmap_op_buckets(buckets: &str) -> Result<usize, String> {
+    match buckets.parse::<usize>() {
+        Ok(0) => Err("The heatmap needs at least one operation bucket".to_string()),
+        Ok(buckets) => Ok(buckets),
+        Err(err) => Err(format!("Invalid bucket count '{buckets}': {err}")),
+    }
+}
+
+fn parse_heatmap_error_buckets(buckets: &str) -> Result<usize, String> {
+    match buckets.parse::<usize>() {
+        Ok(buckets @ 1..=RankErrorHeatmap::MAX_ERROR_BUCKETS) => Ok(buckets),
+        Ok(_) => Err(format!(
+            "The heatmap has between 1 and {} error buckets",
+            RankErrorHeatmap::MAX_ERROR_BUCKETS
+        )),
+        Err(err) => Err(format!("Invalid bucket count '{buckets}': {err}")),
+    }
+}
+
+/// Parses a "min:max" range of d
+fn parse_d_range(range: &str) -> Result<(usize, usize), String> {
+    let (min, max) = range
+        .split_once(':')
+        .ok_or_else(|| format!("Expected min:max, but got '{range}'"))?;
+    let parse = |d: &str| {
+        d.parse::<usize>()
+            .map_err(|err| format!("Invalid d '{d}': {err}"))
+    };
+    Ok((parse(min)?, parse(max)?))
+}
+
+/// Exits the program if any exact operation count has no dequeues to read out rank errors from.
+fn assert_has_dequeues(counts: impl IntoIterator<Item = OpsCount>) {
+    for count in counts {
+        if let OpsCount::Split { dequeues: 0, .. } = count {
+            eprintln!("Cannot read out rank errors without dequeues. Exiting program.");
+            process::exit(1);
+        }
+    }
+}
+
+/// Exits the program if the recovery readout is used outside of the prefill sweeps
+fn assert_not_recovery(readout: ErrorReadout) {
+    if readout == ErrorReadout::Recovery {
+        eprintln!("The recovery readout is only supported by the prefill sweeps. Exiting program.");
+        process::exit(1);
+    }
+}
+
+/// Exits the program if the runs are pooled with a readout of the whole run
+fn assert_aggregate_supported(aggregate: Aggregate, readout: ErrorReadout) {
+    if aggregate == Aggregate::PooledReadout && !readout.reads_rank_errors() {
+        eprintln!(
+            "The {} readout can not pool the runs. Exiting program.",
+            readout.name()
+        );
+        process::exit(1);
+    }
+}
+
+/// Exits the program if the histogram precision is invalid, or the readout needs the whole run
+fn assert_histogram_supported(histogram: Option<u32>, readout: ErrorReadout) {
+    let Some(bits) = histogram else {
+        return;
+    };
+    if bits == 0 || bits > LogHistogram::MAX_SIGNIFICANT_BITS {
+        eprintln!(
+            "The histogram needs 1 to {} significant bits. Exiting program.",
+            LogHistogram::MAX_SIGNIFICANT_BITS
+        );
+        process::exit(1);
+    }
+    if !readout.reads_rank_errors() {
+        eprintln!(
+            "The {} readout can not use a histogram. Exiting program.",
+            readout.name()
+        );
+        process::exit(1);
+    }
+}
+
+/// Exits if any (prefill, operations) point enqueues more items than the analyses support
+fn assert_item_capacity(points: impl IntoIterator<Item = (usize, OpsCount)>) {
+    for (prefill, count) in points {
+        if prefill + count.total() > MAX_ANALYSIS_ITEMS {
+            eprintln!(
+                "Cannot analyze more than {MAX_ANALYSIS_ITEMS} items, but prefill {prefill} with {} operations could enqueue more. Exiting program.",
+                count.total()
+            );
+            process::exit(1);
+        }
+    }
+}
+
+/// Adds the per-thread summaries of one of the runs, averaging the means and p99s over the runs
+/// but keeping the worst max. The dequeues are summed.
+fn add_thread_summaries(totals: &mut Vec<ThreadSummary>, new: Vec<ThreadSummary>, runs: usize) {
+    totals.resize(new.len().max(totals.len()), Default::default());
+    for (summary, new_summary) in totals.iter_mut().zip(new) {
+        summary.dequeues += new_summary.dequeues;
+        summary.mean_rank_error += new_summary.mean_rank_error / runs as f32;
+        summary.p99_rank_error += new_summary.p99_rank_error / runs as f32;
+        summary.max_rank_error = summary.max_rank_error.max(new_summary.max_rank_error);
+    }
+}
+
+/// Adds the per-phase summaries of one of the runs, like add_thread_summaries. The empty returns
+/// are summed as well.
+fn add_phase_summaries(totals: &mut Vec<PhaseSummary>, new: Vec<PhaseSummary>, runs: usize) {
+    totals.resize(new.len().max(totals.len()), Default::default());
+    for (summary, new_summary) in totals.iter_mut().zip(new) {
+        summary.operations = new_summary.operations;
+        summary.dequeues += new_summary.dequeues;
+        summary.empty_returns += new_summary.empty_returns;
+        summary.mean_rank_error += new_summary.mean_rank_error / runs as f32;
+        summary.p99_rank_error += new_summary.p99_rank_error / runs as f32;
+        summary.max_rank_error = summary.max_rank_error.max(new_summary.max_rank_error);
+    }
+}
+
+/// Adds the per-sub-queue ages of one of the runs, averaging the means and p99s over the runs.
+/// The dequeues are summed.
+fn add_subqueue_ages(totals: &mut Vec<SubqueueAges>, new: Vec<SubqueueAges>, runs: usize) {
+    totals.resize(new.len().max(totals.len()), Default::default());
+    for (ages, new_ages) in totals.iter_mut().zip(new) {
+        ages.dequeues += new_ages.dequeues;
+        ages.mean_age += new_ages.mean_age / runs as f32;
+        ages.p99_age += new_ages.p99_age / runs as f32;
+    }
+}
+
+/// Analyzes the operations into the sink, failing enqueues at the item capacity or batching the
+/// operations if the workload does, and budgeting the sub-queues by epochs of the budget if given.
+/// Returns the failed enqueues if there is a capacity
+fn analyze_into(
+    queue: &mut DChoiceQueue<usize>,
+    prefill: usize,
+    operations: &[bool],
+    workload: &OpsConfig,
+    budget: Option<usize>,
+    sink: &mut impl ErrorSink,
+) -> Option<EnqueueFailures> {
+    if let Some(epoch) = budget {
+        // The batches are rejected with a budget, as they bypass the RelaxedFifo operations
+        let mut budgeted = BudgetedQueue::new(queue, epoch);
+        return match workload.max_items {
+            Some(max_items) => Some(or_exit(analyze_bounded(
+                &mut budgeted,
+                prefill,
+                operations,
+                max_items,
+                sink,
+            ))),
+            None => {
+                or_exit(analyze_streaming(&mut budgeted, prefill, operations, sink));
+                None
+            }
+        };
+    }
+    if let Some(max_items) = workload.max_items {
+        return Some(or_exit(analyze_bounded(
+            queue, prefill, operations, max_items, sink,
+        )));
+    }
+    if workload.is_batched() {
+        or_exit(analyze_batched(
+            queue,
+            prefill,
+            operations,
+            workload.enqueue_batch.unwrap_or(1),
+            workload.dequeue_batch.unwrap_or(1),
+            sink,
+        ));
+    } else {
+        or_exit(analyze_streaming(queue, prefill, operations, sink));
+    }
+    None
+}
+
+/// The rank errors of a run approximated by simulating its operations in parallel chunks, see
+/// Single --chunked, together with the warmup operations simulated on top of the run
+///
+/// The first chunk runs exactly from the prefill. Each later chunk starts the warmup operations
+/// before its boundary, from the balanced steady state of the strict queue at that point, and
+/// discards the rank errors of the dequeues before the boundary. Without a given warmup, it is
+/// twice the strict queue size at the boundary plus 100 per sub-queue, as the items of the steady
+/// state are placed without relaxation and so have to be flushed out first. The chunks get their
+/// own queue seeds.
+fn chunked_rank_errors(
+    queue: &QueueArg,
+    prefill: usize,
+    operations: &[bool],
+    chunks: usize,
+    warmup: Option<usize>,
+    queue_seed: u64,
+) -> (Vec<usize>, usize) {
+    let bounds: Vec<usize> = (0..=chunks)
+        .map(|chunk| chunk * operations.len() / chunks)
+        .collect();
+    let starts: Vec<usize> = strict_states(prefill, operations, &bounds[1..chunks])
+        .into_iter()
+        .zip(&bounds[1..chunks])
+        .map(|((_, size), bound)| {
+            bound.saturating_sub(warmup.unwrap_or(2 * size + 100 * queue.subqueues))
+        })
+        .collect();
+    let states: Vec<SteadyState> = strict_states(prefill, operations, &starts)
+        .into_iter()
+        .map(|(dequeued, size)| SteadyState::balanced(queue.subqueues, dequeued, size))
+        .collect();
+
+    let chunk_errors: Vec<Vec<usize>> = (0..chunks)
+        .into_par_iter()
+        .map(|chunk| {
+            let end = bounds[chunk + 1];
+            if chunk == 0 {
+                let mut queue = queue.init(queue_seed);
+                return or_exit(analyze_simple(&mut queue, prefill, &operations[..end]));
+            }
+            let start = starts[chunk - 1];
+            let mut queue = queue.init(derive_seed(queue_seed, SeedStream::Queue, &[chunk as u64]));
+            let rank_errors = or_exit(analyze_from_steady_state(
+                &mut queue,
+                &states[chunk - 1],
+                &operations[start..end],
+            ));
+            let warmup_dequeues = operations[start..bounds[chunk]]
+                .iter()
+                .filter(|op| !**op)
+                .count();
+            rank_errors[warmup_dequeues..].to_vec()
+        })
+        .collect();
+    let warmup_ops = (1..chunks)
+        .map(|chunk| bounds[chunk] - starts[chunk - 1])
+        .sum();
+    (chunk_errors.concat(), warmup_ops)
+}
+
+/// The items dequeued from and the size of the strict queue before each of the operation
+/// indexes, in the order of the indexes, where dequeues of the empty queue return empty
+fn strict_states(prefill: usize, operations: &[bool], indexes: &[usize]) -> Vec<(usize, usize)> {
+    let mut order: Vec<usize> = (0..indexes.len()).collect();
+    order.sort_by_key(|ind| indexes[*ind]);
+    let mut states = vec![(0, 0); indexes.len()];
+    let mut next = order.iter().peekable();
+    let (mut dequeued, mut size) = (0, prefill);
+    for (op_ind, op) in operations.iter().enumerate() {
+        while let Some(ind) = next.next_if(|ind| indexes[**ind] == op_ind) {
+            states[*ind] = (dequeued, size);
+        }
+        if *op {
+            size += 1;
+        } else if size > 0 {
+            size -= 1;
+            dequeued += 1;
+        }
+    }
+    for ind in next {
+        states[*ind] = (dequeued, size);
+    }
+    states
+}
+
+/// Unwraps the result of an analysis, or reports why the queue broke it and exits
+fn or_exit<T>(result: Result<T, AnalysisError>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("The analysis failed: {err}. Exiting program.");
+        process::exit(1);
+    })
+}
+
+/// The mean, min, max and standard deviation over the runs of each point of sorted
+/// distributions, for confidence bands around the averaged curves
+#[derive(Debug, Default, Serialize)]
+struct DistributionBand {
+    mean: Vec<f32>,
+    min: Vec<f32>,
+    max: Vec<f32>,
+    std: Vec<f32>,
+}
+
+impl DistributionBand {
+    /// Bands the sorted distributions of the runs point by point, only keeping the points all runs
+    /// have. If samples is set, each run is first resampled to that many points on a fixed
+    /// quantile grid, so that runs of different lengths line up
+    fn new(runs: Vec<Vec<f32>>, samples: Option<usize>) -> Self {
+        let runs: Vec<Vec<f32>> = match samples {
+            Some(samples) => runs
+                .iter()
+                .map(|values| resample_quantiles(values, samples))
+                .collect(),
+            None => runs,
+        };
+        let points = runs.iter().map(Vec::len).min().unwrap_or(0);
+        let mut band = Self::default();
+        for point in 0..points {
+            let values: Vec<f32> = runs.iter().map(|values| values[point]).collect();
+            let mean = values.iter().sum::<f32>() / values.len() as f32;
+            let variance = values
+                .iter()
+                .map(|value| (value - mean).powi(2))
+                .sum::<f32>()
+                / values.len() as f32;
+            band.mean.push(mean);
+            band.min
+                .push(values.iter().copied().fold(f32::INFINITY, f32::min));
+            band.max
+                .push(values.iter().copied().fold(f32::NEG_INFINITY, f32::max));
+            band.std.push(variance.sqrt());
+        }
+        band
+    }
+}
+
+/// The values of a sorted distribution at the given number of evenly spaced quantiles, from the
+/// smallest to the largest value, by the nearest rank
+fn resample_quantiles(sorted: &[f32], samples: usize) -> Vec<f32> {
+    if sorted.is_empty() || samples == 0 {
+        return vec![];
+    }
+    let last = sorted.len() - 1;
+    (0..samples)
+        .map(|sample| {
+            let quantile = sample as f64 / (samples - 1).max(1) as f64;
+            sorted[(quantile * last as f64).round() as usize]
+        })
+        .collect()
+}
+
+/// Exits the program if the sent in vector has any duplicates.
+fn assert_uniques<I, T>(iter: I)
+where
+    I: IntoIterator<Item = T>,
+    T: PartialEq + Eq + std::hash::Hash,
+{
+    let mut seen = HashSet::new();
+    for item in iter {
+        if !seen.insert(item) {
+            eprintln!("Duplicate found. Exiting program.");
+            process::exit(1); // Exit with an error code.
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn gzip_output_is_complete() {
+        let path =
+            std::env::temp_dir().join(format!("relaxation-analysis-output-{}.gz", process::id()));
+        let mut file = create_output(&path, true).unwrap();
+        let text = "rank_error\n".repeat(10000);
+        file.write_all(text.as_bytes()).unwrap();
+        file.finish().unwrap();
+
+        // A missing trailer fails the decoding with an unexpected end of file
+        let mut decoded = String::new();
+        GzDecoder::new(File::open(&path).unwrap())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(decoded, text);
+    }
+
+    #[test]
+    fn never_empty_ops_never_empty_the_queue() {
+        for seed in 0..200 {
+            for (operations, prefill) in [(1000, 1), (999, 3), (10, 1), (1, 1)] {
+                let ops =
+                    gen_never_empty_ops(operations, prefill, &mut StdRng::seed_from_u64(seed))
+                        .unwrap();
+                assert_eq!(ops.len(), operations / 2 * 2);
+                let mut size = prefill;
+                for op in &ops {
+                    if *op {
+                        size += 1;
+                    } else {
+                        size -= 1;
+                    }
+                    assert!(size > 0, "Seed {seed} emptied the queue");
+                }
+                assert_eq!(size, prefill);
+
+                // So an empty-linearizable queue never returns empty
+                let mut queue = DChoiceQueueBuilder::new(4).seed(Some(seed)).build();
+                let (_, oracle) = analyze_simple_checked(&mut queue, prefill, &ops).unwrap();
+                let dequeues = ops.iter().filter(|op| !**op).count();
+                assert_eq!(oracle.dead, dequeues, "Seed {seed} returned empty");
+            }
+        }
+        assert!(gen_never_empty_ops(10, 0, &mut StdRng::seed_from_u64(1)).is_err());
+    }
+
+    fn parse_preset(args: &[&str]) -> Cli {
+        let args = ["relaxation-analysis"]
+            .iter()
+            .chain(args)
+            .map(OsString::from);
+        Cli::try_parse_from(expand_preset(args.collect())).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    #[test]
+    fn presets_parse() {
+        for preset in PRESETS {
+            let cli = parse_preset(&["--preset", preset.name]);
+            assert_eq!(cli.preset.as_deref(), Some(preset.name));
+            // As the subcommand is its first argument, clap picks the same one
+            let subcommand = Cli::command()
+                .try_get_matches_from(
+                    ["relaxation-analysis"]
+                        .iter()
+                        .chain(preset.args)
+                        .collect::<Vec<_>>(),
+                )
+                .unwrap()
+                .subcommand_name()
+                .map(str::to_string);
+            assert_eq!(subcommand.as_deref(), Some(preset.args[0]));
+        }
+    }
+
+    #[test]
+    fn configs_survive_serde() {
+        for preset in PRESETS {
+            let config =
+                serde_json::to_value(parse_preset(&["--preset", preset.name]).test).unwrap();
+            let test: Test = serde_json::from_value(config.clone())
+                .unwrap_or_else(|err| panic!("{}: {err}", preset.name));
+            assert_eq!(
+                serde_json::to_value(test).unwrap(),
+                config,
+                "{}",
+                preset.name
+            );
+        }
+    }
+
+    #[test]
+    fn later_arguments_override_presets() {
+        for args in [
+            &[
+                "--preset",
+                "ppopp-d-ra",
+                "ops-and-prefill",
+                "-r",
+                "3",
+                "-i",
+                "8",
+            ][..],
+            &["--preset=ppopp-d-ra", "-r", "3", "-i", "8"],
+        ] {
+            let Test::OpsAndPrefill {
+                queue,
+                runs,
+                prefill,
+                operations,
+                ..
+            } = parse_preset(args).test
+            else {
+                panic!("The preset is not an ops-and-prefill test");
+            };
+            assert_eq!(runs, 3);
+            assert_eq!(prefill, [8]);
+            // While the arguments not given keep their preset values
+            assert_eq!(operations.len(), 17);
+            assert_eq!(queue.subqueues, 64);
+            assert!(matches!(queue.config.heuristic, Heuristic::Length));
+        }
+    }
+
+    fn parse_workload(args: &[&str]) -> OpsConfig {
+        let args: Vec<&str> = ["single", "--subqueues", "4", "--prefill", "0", "-o", "1"]
+            .iter()
+            .chain(args)
+            .copied()
+            .collect();
+        let Test::Single { workload, .. } = parse_preset(&args).test else {
+            panic!("Not a single test");
+        };
+        workload
+    }
+
+    #[test]
+    fn ed_pattern_is_the_alternating_sequence() {
+        let pattern = parse_workload(&["--ops-pattern", "ED"]);
+        let alternating = parse_workload(&["--ops-distr", "alternating"]);
+        for operations in [0, 1, 2, 7, 100] {
+            let ops = pattern.gen_stream(operations, 0, &mut StdRng::seed_from_u64(0));
+            assert_eq!(
+                ops,
+                alternating.gen_stream(operations, 0, &mut StdRng::seed_from_u64(0))
+            );
+            let expected: Vec<bool> = (0..operations).map(|i| i % 2 == 0).collect();
+            assert_eq!(ops, expected);
+        }
+    }
+
+    #[test]
+    fn periodic_sizes_track_the_target() {
+        // A slope below one item per operation, which the size can follow one step at a time
+        let (period, amplitude) = (400, 50);
+        for (seed, prefill, jitter) in [(0, 100, 0), (1, 100, 3), (2, 0, 3)] {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let operations = gen_periodic_ops(
+                2000,
+                prefill,
+                period,
+                amplitude,
+                Wave::Sine,
+                jitter,
+                &mut rng,
+            );
+            // A single sub-queue is a strict queue, and dequeues of it return empty when empty
+            let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(1).build();
+            for item in 0..prefill {
+                queue.enqueue(item);
+            }
+            for (op, enqueue) in operations.iter().enumerate() {
+                if *enqueue {
+                    queue.enqueue(op);
+                } else {
+                    queue.dequeue();
+                }
+                let phase = (op % period) as f64 / period as f64;
+                let target = prefill as f64
+                    + (amplitude as f64 * (2.0 * std::f64::consts::PI * phase).sin()).round();
+                // The size can not follow a target below empty, and steps one past the jittered
+                // target before the next operation turns back
+                let distance = (queue.len() as f64 - target.max(0.0)).abs();
+                assert!(
+                    distance <= (jitter + 1) as f64,
+                    "Seed {seed}, period {}: size {} for target {target}",
+                    op / period,
+                    queue.len()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn alternating_periods_have_their_composition() {
+        // Three enqueues and a dequeue per period, where the last period is cut after 3
+        let ops = gen_alternating_ops(11, 0, 4, 3, false).unwrap();
+        let periods: Vec<&[bool]> = ops.chunks(4).collect();
+        for period in &periods[..2] {
+            assert_eq!(*period, [true, true, true, false]);
+        }
+        assert_eq!(periods[2], [true, true, true]);
+
+        // Starting with the dequeues flips each period, and the last one is cut after 2
+        let ops = gen_alternating_ops(10, 3, 4, 1, true).unwrap();
+        let periods: Vec<&[bool]> = ops.chunks(4).collect();
+        for period in &periods[..2] {
+            assert_eq!(*period, [false, false, false, true]);
+        }
+        assert_eq!(periods[2], [false, false]);
+
+        assert!(gen_alternating_ops(10, 0, 0, 0, false).is_err());
+        assert!(gen_alternating_ops(10, 0, 2, 3, false).is_err());
+    }
+
+    #[test]
+    fn starting_with_dequeues_never_under_runs() {
+        // The first period dequeues 3 items before its enqueue, which a prefill of 2 can not serve
+        assert!(gen_alternating_ops(10, 2, 4, 1, true).is_err());
+        // Unless the operations end before the third dequeue
+        assert!(gen_alternating_ops(2, 2, 4, 1, true).is_ok());
+
+        // A balanced pattern then never dequeues from empty
+        let ops = gen_alternating_ops(996, 3, 6, 3, true).unwrap();
+        assert!(!ops[0]);
+        let mut size: usize = 3;
+        for enqueue in ops {
+            if enqueue {
+                size += 1;
+            } else {
+                size = size.checked_sub(1).expect("Dequeued from an empty queue");
+            }
+        }
+        assert_eq!(size, 3);
+    }
+
+    #[test]
+    fn occupancy_keeps_the_size_below_the_target() {
+        let workload = parse_workload(&[
+            "--ops-distr",
+            "occupancy",
+            "--target-size",
+            "50",
+            "--control-window",
+            "8",
+        ]);
+        for (seed, prefill) in [(0, 0), (1, 20), (2, 80)] {
+            let mut source = workload
+                .online(OpsCount::Total(5000), prefill, StdRng::seed_from_u64(seed))
+                .unwrap();
+            let mut live = prefill;
+            let mut reached = false;
+            let mut ops = 0;
+            while let Some(enqueue) = source.next_op() {
+                ops += 1;
+                let dequeued = !enqueue && live > 0;
+                if enqueue {
+                    live += 1;
+                } else if dequeued {
+                    live -= 1;
+                }
+                source.observe(OpOutcome {
+                    enqueue,
+                    dequeued,
+                    live,
+                });
+                // Every operation below the window enqueues and every one at the target
+                // dequeues, so once in the window the size stays there
+                reached |= (42..=50).contains(&live);
+                if reached {
+                    assert!(
+                        (42..=50).contains(&live),
+                        "Seed {seed} left the window: {live}"
+                    );
+                }
+            }
+            assert_eq!(ops, 5000);
+            assert!(reached, "Seed {seed} never reached the target");
+        }
+    }
+
+    #[test]
+    fn reflecting_sizes_stay_above_the_floor() {
+        let workload = parse_workload(&["--ops-distr", "reflecting", "--floor", "20"]);
+        for seed in 0..50 {
+            for prefill in [0, 20, 35] {
+                let ops = workload.gen_stream(2000, prefill, &mut StdRng::seed_from_u64(seed));
+                assert_eq!(ops.len(), 2000);
+                let mut size = prefill;
+                let mut lowest = prefill;
+                for enqueue in ops {
+                    if enqueue {
+                        size += 1;
+                    } else {
+                        // Dequeues only start once the floor is reached
+                        assert!(size > 20, "Seed {seed} dequeued at size {size}");
+                        size -= 1;
+                    }
+                    lowest = lowest.min(size);
+                }
+                assert!(
+                    lowest >= prefill.min(20),
+                    "Seed {seed} went below the floor"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn prioritized_dequeues_move_past_enqueues() {
+        let ops = |text: &str| -> Vec<bool> { text.chars().map(|op| op == 'E').collect() };
+        for (prefill, window, original, expected) in [
+            (0, 1, "EED", "EDE"),
+            // Not past the first enqueue, as the queue is empty before it
+            (0, 5, "EED", "EDE"),
+            (1, 5, "EED", "DEE"),
+            (1, 1, "EEDEED", "EDEEDE"),
+            (0, 0, "EED", "EED"),
+            // Empty returns stay in place
+            (0, 3, "DEDD", "DEDD"),
+        ] {
+            assert_eq!(
+                prioritize_dequeues(&ops(original), prefill, window),
+                ops(expected),
+                "{original} with prefill {prefill} and window {window}"
+            );
+        }
+    }
+
+    #[test]
+    fn prioritized_dequeues_never_under_run() {
+        // The number of items in the queue after each operation, and the empty returns
+        let run = |ops: &[bool], prefill: usize| {
+            let mut size = prefill;
+            let mut empty = 0;
+            for op in ops {
+                match (op, size) {
+                    (true, _) => size += 1,
+                    (false, 0) => empty += 1,
+                    (false, _) => size -= 1,
+                }
+            }
+            (size, empty)
+        };
+        for seed in 0..100 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let operations = gen_ops(OperationDistribution::RandomBalanced, 300, &mut rng);
+            for (prefill, window) in [(0, 1), (0, 8), (5, 3), (20, 50)] {
+                let prioritized = prioritize_dequeues(&operations, prefill, window);
+                assert_eq!(run(&prioritized, prefill), run(&operations, prefill));
+
+                // The k:th dequeue moves up by at most the window
+                let positions = |ops: &[bool]| -> Vec<usize> {
+                    (0..ops.len()).filter(|ind| !ops[*ind]).collect()
+                };
+                for (moved, original) in positions(&prioritized)
+                    .into_iter()
+                    .zip(positions(&operations))
+                {
+                    assert!(
+                        moved <= original && original - moved <= window,
+                        "Seed {seed}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn chunked_runs_approximate_the_exact_run() {
+        let Test::Single { queue, .. } =
+            parse_preset(&["single", "--subqueues", "8", "--prefill", "500", "-o", "1"]).test
+        else {
+            panic!("Not a single test");
+        };
+        let mean = |errors: &[usize]| errors.iter().sum::<usize>() as f64 / errors.len() as f64;
+        for seed in 0..3 {
+            let operations = gen_ops(
+                OperationDistribution::RandomBalanced,
+                100_000,
+                &mut StdRng::seed_from_u64(seed),
+            );
+            let exact = analyze_simple(&mut queue.init(seed), 500, &operations).unwrap();
+            let (chunked, warmup_ops) =
+                chunked_rank_errors(&queue, 500, &operations, 4, None, seed);
+            // Every dequeue is kept once, and the warmups are simulated on top
+            assert_eq!(chunked.len(), exact.len());
+            assert!(warmup_ops > 3 * 800);
+            // Within the tolerance the readme states
+            let (exact, chunked) = (mean(&exact), mean(&chunked));
+            assert!(
+                (chunked - exact).abs() <= 0.05 * exact,
+                "Seed {seed}: chunked {chunked}, exact {exact}"
+            );
+        }
+    }
+
+    #[test]
+    fn latency_model_charges_the_counted_work() {
+        let model = parse_latency_model("10:2:3:100").unwrap();
+        // Sampling both sub-queues spreads the two items, so the first two dequeues find them
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(2)
+            .d(2)
+            .uniques(true)
+            .latency_model(Some(model))
+            .seed(Some(1))
+            .build();
+        analyze_simple(&mut queue, 0, &[true, true, false, false, false]).unwrap();
+
+        // Every operation costs 10 and 2 per sample, and the last dequeue also scans the other
+        // sub-queue for 3 and retries once for 100
+        let time = queue.virtual_time().unwrap();
+        assert_eq!((time.enqueues, time.dequeues), (2, 3));
+        assert_eq!(time.enqueue_time, 2.0 * 14.0);
+        assert_eq!(time.dequeue_time, 3.0 * 14.0 + 3.0 + 100.0);
+        assert_eq!(time.throughput(), 5e9 / 173.0);
+
+        assert!(parse_latency_model("10:2:3").is_err());
+        assert!(parse_latency_model("10:2:-3:100").is_err());
+    }
+
+    #[test]
+    fn constant_offsets_give_exact_bands() {
+        let base = [0.0, 1.0, 2.0, 3.0, 4.0];
+        let runs: Vec<Vec<f32>> = [0.0, 2.0, 4.0]
+            .iter()
+            .map(|offset| base.iter().map(|value| value + offset).collect())
+            .collect();
+        // Offsets of 0, 2 and 4 are 2 below, at and 2 above their mean
+        let std = (8.0f32 / 3.0).sqrt();
+        let band = DistributionBand::new(runs.clone(), None);
+        assert_eq!(band.mean, [2.0, 3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(band.min, base);
+        assert_eq!(band.max, [4.0, 5.0, 6.0, 7.0, 8.0]);
+        assert_eq!(band.std, [std; 5]);
+
+        // Resampled to the quantile grid, the bands keep the offsets
+        let band = DistributionBand::new(runs, Some(3));
+        assert_eq!(band.mean, [2.0, 4.0, 6.0]);
+        assert_eq!(band.min, [0.0, 2.0, 4.0]);
+        assert_eq!(band.max, [4.0, 6.0, 8.0]);
+        assert_eq!(band.std, [std; 3]);
+    }
+
+    #[test]
+    fn quantiles_round_to_the_nearest_rank() {
+        let sorted: Vec<f32> = (0..6).map(|value| value as f32).collect();
+        // The quantiles 1/3 and 2/3 of 6 values fall at the ranks 1.67 and 3.33
+        assert_eq!(resample_quantiles(&sorted, 4), [0.0, 2.0, 3.0, 5.0]);
+        assert_eq!(
+            resample_quantiles(&sorted[..3], 5),
+            [0.0, 1.0, 1.0, 2.0, 2.0]
+        );
+        assert_eq!(resample_quantiles(&sorted, 1), [0.0]);
+        assert!(resample_quantiles(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn pooling_reads_out_the_runs_together() {
+        // One run is nearly error free apart from a single outlier, the other has a constant error
+        let mut outlier = vec![0; 99];
+        outlier.push(1000);
+        let runs = [outlier, vec![10; 100]];
+        let combine = |aggregate: Aggregate| {
+            let runs = runs
+                .iter()
+                .map(|rank_errors| RunResult {
+                    value: ErrorReadout::WorstOnePercent.readout(rank_errors),
+                    average_d: None,
+                    cost: CostReport::default(),
+                    virtual_time: None,
+                    histogram: None,
+                    enqueue_failures: None,
+                    rank_errors: Some(rank_errors.clone()),
+                    seeds: RunSeeds {
+                        queue: 0,
+                        operations: 0,
+                        layout: None,
+                    },
+                    dequeues: rank_errors.len(),
+                    empty_returns: 0,
+                    ops_per_second: 0.0,
+                })
+                .collect();
+            PointResult::combine(runs, aggregate, ErrorReadout::WorstOnePercent).value
+        };
+        // The worst percent of each run is its outlier and 10, while the worst two of the pooled
+        // 200 errors are the outlier and a 10
+        assert_eq!(combine(Aggregate::MeanOfReadouts), 505.0);
+        assert_eq!(combine(Aggregate::PooledReadout), 10.0);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn output_errors_are_returned() {
+        for compress in [false, true] {
+            let mut file = create_output(Path::new("/dev/full"), compress).unwrap();
+            // Small enough to stay in the buffers until finishing
+            file.write_all(b"[1, 2, 3]").unwrap();
+            assert!(file.finish().is_err(), "compress: {compress}");
+        }
+    }
+
+    #[test]
+    fn service_rates_track_the_weights() {
+        for pattern in ["linear", "one-slow:0.25", "1,0.5,0.5,2"] {
+            let weights = parse_subqueue_weights(pattern).unwrap().weights(4);
+            let service = ServiceWeights::new(&weights, 20).unwrap();
+            // With one candidate, the rejections alone decide which sub-queue serves
+            let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(4)
+                .d(1)
+                .candidate_filter(Some(Box::new(service)))
+                .seed(Some(20))
+                .build();
+            for item in 0..1000 {
+                queue.enqueue(item);
+            }
+            for item in 1000..41000 {
+                queue.enqueue(item);
+                queue.dequeue();
+            }
+            for rate in service_rates(&weights, &queue) {
+                let weight_share = rate["weight_share"].as_f64().unwrap();
+                for share in ["enqueue_share", "dequeue_share"] {
+                    let share = rate[share].as_f64().unwrap();
+                    assert!(
+                        (share - weight_share).abs() < 0.02,
+                        "{pattern}: {share} is not {weight_share}"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -51,7 +51,7 @@ struct MinTracker {
 }
 
 impl MinTracker {
-    pub fn new(initial: &Vec<usize>) -> Self {
+    pub fn new(initial: &[usize]) -> Self {
         let mut sorted = BTreeSet::new();
         for (i, v) in initial.iter().enumerate() {
             sorted.insert((*v, i));
@@ -1,262 +1,4037 @@
-use rand::{seq::SliceRandom, Rng};
-use std::collections::VecDeque;
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    rngs::StdRng,
+    seq::SliceRandom,
+    Rng, SeedableRng,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    ops::{Deref, DerefMut},
+};
 
-use crate::relaxed_fifo::RelaxedFifo;
+use crate::{
+    relaxed_fifo::{InstrumentedFifo, RelaxedFifo},
+    AnalysisError,
+};
 
-// Singlethreaded implementation of a d-Choice relaxed queue
+/// How to choose between the sampled sub-queues
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Heuristic {
+    /// Length-based heuristic, as in the original d-RA load balancer
+    Length,
+
+    /// Operation-based heuristic, comparing the enqueue or dequeue counters of the sub-queues
+    Operation,
+
+    /// Draws among the candidates with probability softmax(-counter / temperature), using the
+    /// counters of the operation-based heuristic. Approaches it as the temperature goes to zero,
+    /// and uniformly random choices as it grows.
+    Softmax { temperature: f64 },
+
+    /// Dequeues from the candidate whose front item was enqueued first, by peeking at the items.
+    /// Enqueues as the operation-based heuristic. Only available for sequenced items.
+    OldestFront,
+
+    /// Dequeues from the candidate with the lowest estimated front age, without peeking. Each
+    /// sub-queue keeps an exponentially weighted average of the global enqueue indices of its
+    /// items, restarting at the index of an item enqueued into it while empty. Enqueues as the
+    /// operation-based heuristic.
+    EstimatedOldest { weight: f64 },
+
+    /// Chooses the candidate whose counter lags the most behind a shared global counter, which
+    /// only publishes the largest counter of the operation kind every granularity operations of
+    /// it, as relaxed counter implementations would. Candidates ahead of the published value tie.
+    /// A granularity of 1 is the operation-based heuristic.
+    CoarseCounter { granularity: usize },
+}
+
+/// How an empty dequeue chooses the sub-queue to fall back to, if scanning for empty dequeues
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum FallbackPolicy {
+    /// Scans forward from the chosen sub-queue and takes the first non-empty one
+    #[default]
+    FirstNonEmpty,
+
+    /// Scans all the sub-queues and takes the longest one
+    LongestQueue,
+
+    /// Scans all the sub-queues and takes the non-empty one with the lowest head counter
+    OldestHead,
+
+    /// Scans forward from the sub-queue the last fallback took its item from, instead of from the
+    /// chosen one, and takes the first non-empty one. Near emptiness the items are in few
+    /// sub-queues, so this finds one after a short scan
+    Hinted,
+}
+
+/// Items which carry the order they were enqueued in, so their age can be compared
+pub trait Sequenced {
+    /// The enqueue sequence number of the item, lower is older
+    fn sequence(&self) -> usize;
+}
+
+impl Sequenced for usize {
+    fn sequence(&self) -> usize {
+        *self
+    }
+}
+
+/// The two kinds of queue operations
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OpKind {
+    Enqueue = 0,
+    Dequeue = 1,
+}
+
+/// Adapts the d of each operation kind between min and max, from an exponentially weighted
+/// average of the counter skew of the chosen sub-queues. The skew of a choice is how many
+/// operations the chosen sub-queue is ahead of the least used one.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct AdaptiveD {
+    pub min: usize,
+    pub max: usize,
+
+    /// Samples one more sub-queue while the average skew is above this
+    pub upper: f64,
+
+    /// Samples one fewer sub-queue while the average skew is below this
+    pub lower: f64,
+
+    /// The weight of the newest skew in the average, between 0 and 1
+    pub weight: f64,
+}
+
+/// Constrains which sub-queues an operation may choose, consulted for each sampled candidate
+/// before the heuristic compares them
+pub trait CandidateFilter: Send {
+    /// If an operation of the kind may choose the sub-queue. The operation counts the operations
+    /// since the cost counters were last reset, from 0
+    fn allows(&mut self, subqueue: usize, kind: OpKind, operation: usize) -> bool;
+}
+
+/// A sub-queue which can not be chosen during a window of operations, as if the thread serving
+/// it was descheduled. The window counts the operations since the cost counters were last reset,
+/// which the analyses do after the prefill
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Blackout {
+    pub subqueue: usize,
+
+    /// The first operation of the window
+    pub start: usize,
+
+    /// The operation after the window
+    pub end: usize,
+}
+
+impl CandidateFilter for Vec<Blackout> {
+    fn allows(&mut self, subqueue: usize, _kind: OpKind, operation: usize) -> bool {
+        !self.iter().any(|blackout| {
+            blackout.subqueue == subqueue && (blackout.start..blackout.end).contains(&operation)
+        })
+    }
+}
+
+/// Both filters must allow the sub-queue, where the second is only consulted if the first does
+impl<A: CandidateFilter, B: CandidateFilter> CandidateFilter for (A, B) {
+    fn allows(&mut self, subqueue: usize, kind: OpKind, operation: usize) -> bool {
+        self.0.allows(subqueue, kind, operation) && self.1.allows(subqueue, kind, operation)
+    }
+}
+
+/// Sub-queues served at different speeds, as if owned by threads of different speeds. Each time a
+/// sub-queue is a candidate it is rejected with probability 1 - weight / the largest weight, so
+/// slower sub-queues serve fewer operations
+pub struct ServiceWeights {
+    /// The probability each sub-queue is allowed as a candidate
+    acceptance: Vec<f64>,
+    rng: StdRng,
+}
+
+impl ServiceWeights {
+    /// Returns an error unless the weights are finite and non-negative, with at least one positive
+    pub fn new(weights: &[f64], seed: u64) -> Result<Self, String> {
+        if weights
+            .iter()
+            .any(|weight| !weight.is_finite() || *weight < 0.0)
+        {
+            return Err("The service weights must be finite and non-negative".to_string());
+        }
+        let max = weights.iter().copied().fold(0.0, f64::max);
+        if max == 0.0 {
+            return Err("At least one service weight must be positive".to_string());
+        }
+        Ok(Self {
+            acceptance: weights.iter().map(|weight| weight / max).collect(),
+            rng: StdRng::seed_from_u64(seed),
+        })
+    }
+}
+
+impl CandidateFilter for ServiceWeights {
+    fn allows(&mut self, subqueue: usize, _kind: OpKind, _operation: usize) -> bool {
+        let acceptance = self.acceptance[subqueue];
+        acceptance >= 1.0 || self.rng.gen::<f64>() < acceptance
+    }
+}
+
+/// The counters and lengths of the sub-queues to start a queue from, as if it had already run for
+/// a while, see DChoiceQueue::init_steady_state
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SteadyState {
+    /// The dequeue counter of each sub-queue
+    pub heads: Vec<usize>,
+
+    /// The number of items in each sub-queue, so that its enqueue counter is its head plus this
+    pub lens: Vec<usize>,
+}
+
+impl SteadyState {
+    /// The state after the dequeues with the items left, where both the enqueue and the dequeue
+    /// counters are spread as evenly as possible, as the operation heuristic keeps them
+    pub fn balanced(subqueues: usize, dequeues: usize, items: usize) -> Self {
+        let spread = |total: usize| -> Vec<usize> {
+            (0..subqueues)
+                .map(|ind| total / subqueues + usize::from(ind < total % subqueues))
+                .collect()
+        };
+        let heads = spread(dequeues);
+        let lens = spread(dequeues + items)
+            .into_iter()
+            .zip(heads.iter())
+            .map(|(tail, head)| tail - head)
+            .collect();
+        Self { heads, lens }
+    }
+
+    /// The number of items in the sub-queues
+    pub fn items(&self) -> usize {
+        self.lens.iter().sum()
+    }
+}
+
+/// How many times an operation resamples its candidates when the filter rejects all of them,
+/// before scanning all sub-queues for the allowed ones
+const FILTER_RESAMPLES: usize = 4;
+
+/// The most sub-queues the adversarial choice enumerates the candidate sets of
+pub const ADVERSARY_MAX_SUBQUEUES: usize = 64;
+
+/// The result of DChoiceQueue::dequeue_with_info
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DequeueInfo<T> {
+    /// The dequeued item, or None for empty returns
+    pub item: Option<T>,
+
+    /// The number of successful dequeues on the sub-queue the item came from, or on the chosen
+    /// sub-queue for empty returns
+    pub sub_nbr: usize,
+
+    /// If the chosen sub-queue was empty, so the item was found by the round-robin scan
+    pub fell_back: bool,
+
+    /// The sub-queue the item came from, or the chosen sub-queue for empty returns
+    pub subqueue: usize,
+}
+
+/// What one queue operation did to the sub-queues, as recorded in the choice trace
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TraceEvent {
+    /// An item was enqueued into the sub-queue
+    Enqueue { subqueue: usize },
+
+    /// An item was dequeued from the sub-queue, or None if the dequeue returned empty
+    Dequeue { subqueue: Option<usize> },
+
+    /// An extra item was dequeued from the lagging sub-queue into the help buffer
+    Help { subqueue: usize },
+
+    /// The dequeue returned the item in the help buffer
+    Buffered,
+}
+
+/// How one queue operation chose its sub-queue, as recorded in the choice log
+#[derive(Clone, PartialEq, Debug)]
+pub struct ChoiceRecord {
+    pub kind: OpKind,
+
+    /// The sampled sub-queues with their heuristic scores, where lower is better. The scores do
+    /// not include the recency penalty
+    pub sampled: Vec<(usize, f64)>,
+
+    /// The chosen sub-queue, after any skew cap redirect, or None if the dequeue returned the
+    /// item in the help buffer without choosing
+    pub chosen: Option<usize>,
+
+    /// The first sampled sub-queue, which d = 1 would have chosen, or None without a choice
+    pub first: Option<usize>,
+
+    /// The rank error of dequeuing from the first sampled sub-queue instead, or None for enqueues,
+    /// if it was empty, or if the items are not sequenced
+    pub first_rank_error: Option<usize>,
+}
+
+/// The d-RA queue, as a d-choice queue of the item ids which the analyses enqueue
+pub type DRa = DChoiceQueue<usize>;
+
+/// Singlethreaded implementation of a d-Choice relaxed queue
+///
+/// # Examples
+///
+/// ```
+/// use relaxation_analysis::DChoiceQueueBuilder;
+///
+/// let mut queue = DChoiceQueueBuilder::new(4).d(2).seed(Some(1)).build();
+/// for item in 0..100 {
+///     queue.enqueue(item);
+/// }
+///
+/// // Relaxed, so the first dequeue need not return the oldest item, but it returns one of them
+/// let first = queue.dequeue().unwrap();
+/// assert!(first < 100);
+/// assert_eq!(queue.len(), 99);
+/// ```
 pub struct DChoiceQueue<T: PartialEq + Eq> {
     /// The sub-queues
     subqueues: Vec<SubQueue<T>>,
 
-    /// How many subqueues to sample per operation
-    d: usize,
+    /// How many subqueues to sample per enqueue
+    enqueue_d: usize,
+
+    /// How many subqueues to sample per dequeue
+    dequeue_d: usize,
+
+    /// If true, cannot sample the same sub-queue several times for one d-choice
+    uniques: bool,
+
+    /// How to choose between the sampled sub-queues
+    heuristic: Heuristic,
+
+    /// If true, uses round robin when finding an empty queue
+    empty_lin: bool,
+
+    /// How the round robin of empty dequeues chooses the sub-queue to fall back to
+    fallback_policy: FallbackPolicy,
+
+    /// The sub-queue the last fallback took its item from, where the hinted policy starts
+    fallback_hint: usize,
+
+    /// The number of sub-queues holding items, so an empty queue is found without scanning
+    nonempty: usize,
+
+    /// If true, dequeue_many stops a batch short when its sub-queue runs out, instead of
+    /// scanning on for the rest
+    batch_stop_short: bool,
+
+    /// If true, partitions sub-queues into d chunks and selects one queue from each each time
+    partition: bool,
+
+    /// If true, prefers left queue when tie-breaking
+    left: bool,
+
+    /// If set, samples the sub-queues from a Zipf distribution instead of uniformly
+    zipf: Option<ZipfSampler>,
+
+    /// If true, one sampled sub-queue per operation is taken from a round-robin cursor
+    round_robin: bool,
+
+    /// The next sub-queue of the round-robin cursor, per operation kind
+    cursors: [usize; 2],
+
+    /// If true, one sampled sub-queue per operation is the one chosen by the previous operation
+    memory: bool,
+
+    /// The sub-queue chosen by the previous operation, per operation kind
+    remembered: [Option<usize>; 2],
+
+    /// If true, samples pairs of adjacent sub-queues and chooses a pair before a sub-queue in it
+    paired: bool,
+
+    /// If true, chooses the worst candidate set instead of sampling one
+    adversary: bool,
+
+    /// Penalty added to the score of a sub-queue chosen t operations ago, divided by t
+    recency_penalty: f64,
+
+    /// The number of sub-queue choices made so far
+    op_count: usize,
+
+    /// The choice number when each sub-queue was last chosen, per operation kind (0 if never)
+    last_chosen: [Vec<usize>; 2],
+
+    /// If set, the maximum allowed spread of the operation counters of one kind
+    skew_cap: Option<usize>,
+
+    /// How many times the skew cap has overridden the heuristic choice
+    skew_cap_triggers: usize,
+
+    /// If set, adapts enqueue_d and dequeue_d to the counter skew
+    adaptive_d: Option<AdaptiveD>,
+
+    /// The average counter skew of the chosen sub-queues, per operation kind
+    skew_estimates: [f64; 2],
+
+    /// The sum of the d of all sub-queue choices so far, for the average d
+    d_sum: usize,
+
+    /// Reads the sequence number of an item, if the items are sequenced
+    front_sequence: Option<fn(&T) -> usize>,
+
+    /// How many sub-queue fronts have been peeked at
+    peeks: usize,
+
+    /// The number of items enqueued so far, as the index of the next enqueue
+    enqueue_count: usize,
+
+    /// The largest sequence number enqueued so far, if the items are sequenced
+    newest_sequence: Option<usize>,
+
+    /// If set, dequeues help sampled sub-queues lagging more than this many dequeues behind
+    helping_threshold: Option<usize>,
+
+    /// An item dequeued by helping, which the next dequeue returns, with its sub-queue and the head
+    /// count of that sub-queue
+    help_buffer: Option<(T, usize, usize)>,
+
+    /// How many items have been dequeued by helping
+    helps: usize,
+
+    /// The global counter of the coarse counter heuristic, per operation kind
+    coarse_counters: [CoarseCounter; 2],
+
+    /// The dequeues since the cost counters were reset, and how many of them returned empty
+    dequeues: usize,
+    empty_returns: usize,
+
+    /// The sub-queues which may not be chosen, as set by a wrapper such as BudgetedQueue
+    vetoed: Vec<bool>,
+    vetoed_count: usize,
+
+    /// If set, further constrains which sub-queues may be chosen
+    candidate_filter: Option<Box<dyn CandidateFilter>>,
+
+    /// The sub-queue chosen by the last operation, until taken by take_choice
+    last_choice: Option<usize>,
+
+    /// The source of all random choices
+    rng: StdRng,
+
+    /// If set, the permutation from the sampling indexes to the sub-queues
+    layout: Option<SubqueueLayout>,
+
+    /// The number of sub-queues minus one if it is a power of two, to sample and wrap indexes by
+    /// masking instead of the slower range sampling and modulo
+    index_mask: Option<usize>,
+
+    /// If tracing, every effect the operations had on the sub-queues
+    choice_trace: Option<Vec<TraceEvent>>,
+
+    /// If logging, the choices of the first choice_log_limit operations
+    choice_log: Option<Vec<ChoiceRecord>>,
+    choice_log_limit: usize,
+
+    /// The work done since the cost counters were last reset
+    cost: CostReport,
+
+    /// If set, charges the work of each operation as virtual time
+    latency_model: Option<LatencyModel>,
+
+    /// The virtual time of the operations since the cost counters were last reset
+    virtual_time: VirtualTime,
+}
+
+/// Configures a DChoiceQueue, by default the operation-based d-CBO with d = 2
+pub struct DChoiceQueueBuilder {
+    nbr_subqueues: usize,
+    enqueue_d: usize,
+    dequeue_d: usize,
+    uniques: bool,
+    heuristic: Heuristic,
+    empty_lin: bool,
+    fallback_policy: FallbackPolicy,
+    batch_stop_short: bool,
+    partition: bool,
+    left: bool,
+    zipf_s: Option<f64>,
+    round_robin: bool,
+    memory: bool,
+    paired: bool,
+    adversary: bool,
+    recency_penalty: f64,
+    skew_cap: Option<usize>,
+    adaptive_d: Option<AdaptiveD>,
+    helping_threshold: Option<usize>,
+    latency_model: Option<LatencyModel>,
+    candidate_filter: Option<Box<dyn CandidateFilter>>,
+    seed: Option<u64>,
+    layout_seed: Option<u64>,
+    trace_choices: bool,
+    choice_log_limit: Option<usize>,
+}
+
+impl DChoiceQueueBuilder {
+    pub fn new(nbr_subqueues: usize) -> Self {
+        Self {
+            nbr_subqueues,
+            enqueue_d: 2,
+            dequeue_d: 2,
+            uniques: false,
+            heuristic: Heuristic::Operation,
+            empty_lin: true,
+            fallback_policy: FallbackPolicy::FirstNonEmpty,
+            batch_stop_short: false,
+            partition: false,
+            left: false,
+            zipf_s: None,
+            round_robin: false,
+            memory: false,
+            paired: false,
+            adversary: false,
+            recency_penalty: 0.0,
+            skew_cap: None,
+            adaptive_d: None,
+            helping_threshold: None,
+            latency_model: None,
+            candidate_filter: None,
+            seed: None,
+            layout_seed: None,
+            trace_choices: false,
+            choice_log_limit: None,
+        }
+    }
+
+    /// How many subqueues to sample per operation
+    pub fn d(mut self, d: usize) -> Self {
+        self.enqueue_d = d;
+        self.dequeue_d = d;
+        self
+    }
+
+    /// How many sub-queues to sample per enqueue, overriding d
+    pub fn enqueue_d(mut self, d: usize) -> Self {
+        self.enqueue_d = d;
+        self
+    }
+
+    /// How many sub-queues to sample per dequeue, overriding d
+    pub fn dequeue_d(mut self, d: usize) -> Self {
+        self.dequeue_d = d;
+        self
+    }
+
+    /// If true, cannot sample the same sub-queue several times for one d-choice
+    pub fn uniques(mut self, uniques: bool) -> Self {
+        self.uniques = uniques;
+        self
+    }
+
+    /// How to choose between the sampled sub-queues
+    pub fn heuristic(mut self, heuristic: Heuristic) -> Self {
+        self.heuristic = heuristic;
+        self
+    }
+
+    /// If true, uses round robin when finding an empty queue
+    pub fn empty_lin(mut self, empty_lin: bool) -> Self {
+        self.empty_lin = empty_lin;
+        self
+    }
+
+    /// How an empty dequeue chooses the sub-queue to fall back to. The batches of dequeue_many
+    /// always take the following sub-queues in order
+    pub fn fallback_policy(mut self, policy: FallbackPolicy) -> Self {
+        self.fallback_policy = policy;
+        self
+    }
+
+    /// If true, dequeue_many stops a batch short when its sub-queue runs out, instead of taking
+    /// the rest by the round robin of empty dequeues
+    pub fn batch_stop_short(mut self, stop_short: bool) -> Self {
+        self.batch_stop_short = stop_short;
+        self
+    }
+
+    /// If true, partitions sub-queues into d chunks and selects one queue from each each time
+    pub fn partition(mut self, partition: bool) -> Self {
+        self.partition = partition;
+        self
+    }
+
+    /// If true, prefers left queue when tie-breaking
+    pub fn left(mut self, left: bool) -> Self {
+        self.left = left;
+        self
+    }
+
+    /// Samples sub-queue ranks from a Zipf distribution with exponent s, where the sub-queue of
+    /// each rank is fixed by a random permutation at construction. Duplicates are still removed
+    /// if uniques is set, but partitioning takes precedence over it.
+    pub fn zipf(mut self, s: Option<f64>) -> Self {
+        self.zipf_s = s;
+        self
+    }
+
+    /// If true, takes one of the d sampled sub-queues from a round-robin cursor per operation
+    /// kind, and the others uniformly at random. Both cursors start at the same random sub-queue,
+    /// so that with d = 1 the dequeues follow the enqueues, and advance on every sample, so each
+    /// sub-queue is sampled at least once every nbr_subqueues operations of each kind.
+    /// Partitioning takes precedence over it.
+    pub fn round_robin(mut self, round_robin: bool) -> Self {
+        self.round_robin = round_robin;
+        self
+    }
+
+    /// If true, samples d - 1 fresh sub-queues together with the one chosen by the previous
+    /// operation of the same kind (d-choice with memory), so d must be at least 2. A dequeue
+    /// finding its chosen sub-queue empty forgets it. Partitioning and round-robin take
+    /// precedence over it.
+    pub fn memory(mut self, memory: bool) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    /// If true, samples d / 2 (rounded up) pairs of the adjacent sub-queues 2i and 2i + 1, as if
+    /// their counters shared a cache line. The operation chooses the pair with the best summed
+    /// heuristic score, and then the sub-queue within that pair by the heuristic. The pairs are
+    /// distinct if uniques is set. Requires an even number of sub-queues, and can not be combined
+    /// with the other sampling schemes or the recency penalty.
+    pub fn paired(mut self, paired: bool) -> Self {
+        self.paired = paired;
+        self
+    }
+
+    /// If true, chooses the d candidates of each operation adversarially instead of at random,
+    /// as the candidate set whose heuristic choice is the worst, to bound the heuristic from
+    /// above. For dequeues the worst choice is the one whose front item has the largest rank
+    /// error, where empty sub-queues count as best, as their fallback is not modeled. For enqueues
+    /// it is the shortest sub-queue, so that new items reach the fronts early and are dequeued
+    /// ahead of older ones. Putting them where the heuristic scores worst instead piles every item
+    /// into one sub-queue, which then serves them in order without error. The candidate sets
+    /// are enumerated, so it is limited to d <= 2 and ADVERSARY_MAX_SUBQUEUES sub-queues. Needs
+    /// sequenced items and a deterministic heuristic, and can not be combined with the other
+    /// sampling schemes or the recency penalty.
+    pub fn adversary(mut self, adversary: bool) -> Self {
+        self.adversary = adversary;
+        self
+    }
+
+    /// Penalizes recently chosen sub-queues, to spread consecutive operations. The penalty is this
+    /// weight divided by the number of operations since the sub-queue was last chosen for the
+    /// same kind of operation. A weight of 0 disables it.
+    pub fn recency_penalty(mut self, weight: f64) -> Self {
+        self.recency_penalty = weight;
+        self
+    }
+
+    /// Caps the spread of the enqueue (or dequeue) counters at k. If using the chosen sub-queue
+    /// would leave it more than k operations ahead of the least used one, that one is used
+    /// instead, found by a linear scan.
+    /// Dequeues from an empty sub-queue do not advance its counter, so the dequeue bound only
+    /// holds while no sub-queue runs empty.
+    pub fn skew_cap(mut self, cap: Option<usize>) -> Self {
+        self.skew_cap = cap;
+        self
+    }
+
+    /// Adapts the d of each operation kind between the min and max, starting at the min and
+    /// overriding the configured ds. Cannot be combined with partitioning, as the partitions
+    /// depend on d.
+    pub fn adaptive_d(mut self, adaptive_d: Option<AdaptiveD>) -> Self {
+        self.adaptive_d = adaptive_d;
+        self
+    }
+
+    /// After a successful dequeue, if a sampled sub-queue has done more than h dequeues fewer than
+    /// the chosen one, an item is also dequeued from it and returned by the next dequeue
+    pub fn helping_threshold(mut self, threshold: Option<usize>) -> Self {
+        self.helping_threshold = threshold;
+        self
+    }
+
+    /// Charges the work of each operation as virtual time, see DChoiceQueue::virtual_time
+    pub fn latency_model(mut self, model: Option<LatencyModel>) -> Self {
+        self.latency_model = model;
+        self
+    }
+
+    /// If set, only lets the operations choose the sub-queues the filter allows, see
+    /// CandidateFilter. Sampled candidates it rejects are dropped, and if it rejects all of them
+    /// the operation resamples a few times before scanning all sub-queues for an allowed one. If
+    /// none is allowed, the operation chooses among its last candidates as if unfiltered.
+    pub fn candidate_filter(mut self, filter: Option<Box<dyn CandidateFilter>>) -> Self {
+        self.candidate_filter = filter;
+        self
+    }
+
+    /// Seeds the random choices, to make the queue deterministic. Otherwise seeded from entropy
+    pub fn seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// If set, maps the sampling indexes to the sub-queues by a random permutation drawn from
+    /// this seed, apart from the random choices. The structured sampling schemes, such as the
+    /// partitions, pairs and round-robin cursor, and the order of the fallback scans then follow
+    /// the permuted layout, while the sub-queues keep their indexes everywhere else, such as in
+    /// the blackouts, vetoes, traces and reports.
+    pub fn permute_subqueues(mut self, seed: Option<u64>) -> Self {
+        self.layout_seed = seed;
+        self
+    }
+
+    /// If true, records which sub-queues the operations used, see DChoiceQueue::choice_trace
+    pub fn trace_choices(mut self, trace_choices: bool) -> Self {
+        self.trace_choices = trace_choices;
+        self
+    }
+
+    /// If set, logs how the first this many operations chose, see DChoiceQueue::choice_log
+    pub fn log_choices(mut self, limit: Option<usize>) -> Self {
+        self.choice_log_limit = limit;
+        self
+    }
+
+    /// Checks that the configuration can be built, describing the first problem otherwise
+    ///
+    /// The build methods panic with the same description, so callers taking configurations from
+    /// users can check them up front.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.nbr_subqueues == 0 {
+            return Err("The queue needs at least one sub-queue".to_string());
+        }
+        if let Heuristic::EstimatedOldest { weight } = self.heuristic {
+            if !(weight > 0.0 && weight <= 1.0) {
+                return Err(format!(
+                    "The estimate weight must be in (0, 1], but got {weight}"
+                ));
+            }
+        }
+        if let Heuristic::Softmax { temperature } = self.heuristic {
+            if !(temperature > 0.0 && temperature.is_finite()) {
+                return Err(format!(
+                    "The softmax temperature must be positive and finite, but got {temperature}"
+                ));
+            }
+        }
+        if self.heuristic == (Heuristic::CoarseCounter { granularity: 0 }) {
+            return Err("The counter granularity must be at least 1".to_string());
+        }
+        let max_d = match self.adaptive_d {
+            Some(adaptive) => {
+                if !(0 < adaptive.min && adaptive.min <= adaptive.max) {
+                    return Err("The adaptive d must have 0 < min <= max".to_string());
+                }
+                if adaptive.lower > adaptive.upper {
+                    return Err(
+                        "The lower skew threshold can not be above the upper one".to_string()
+                    );
+                }
+                if self.partition {
+                    return Err("The adaptive d can not be combined with partitioning".to_string());
+                }
+                adaptive.max
+            }
+            None => {
+                if self.enqueue_d == 0 || self.dequeue_d == 0 {
+                    return Err(format!(
+                        "d must be at least 1, but got {} for enqueues and {} for dequeues",
+                        self.enqueue_d, self.dequeue_d
+                    ));
+                }
+                self.enqueue_d.max(self.dequeue_d)
+            }
+        };
+        let min_d = match self.adaptive_d {
+            Some(adaptive) => adaptive.min,
+            None => self.enqueue_d.min(self.dequeue_d),
+        };
+        if self.memory && !self.partition && !self.round_robin && min_d < 2 {
+            return Err(format!(
+                "Memory sampling needs d >= 2 to draw a fresh sub-queue, but got d = {min_d}"
+            ));
+        }
+        if self.uniques && max_d > self.nbr_subqueues {
+            return Err(format!(
+                "Unique sampling requires d <= sub-queues, but got d = {max_d} with {} sub-queues",
+                self.nbr_subqueues
+            ));
+        }
+        if self.paired {
+            if !self.nbr_subqueues.is_multiple_of(2) {
+                return Err(format!(
+                    "Paired sampling requires an even number of sub-queues, but got {}",
+                    self.nbr_subqueues
+                ));
+            }
+            if self.partition
+                || self.zipf_s.is_some()
+                || self.round_robin
+                || self.memory
+                || self.recency_penalty > 0.0
+            {
+                return Err(
+                    "Paired sampling can not be combined with partitioning, Zipf, round-robin or memory sampling, or the recency penalty"
+                        .to_string(),
+                );
+            }
+        }
+        if self.adversary {
+            if max_d > 2 || self.nbr_subqueues > ADVERSARY_MAX_SUBQUEUES {
+                return Err(format!(
+                    "The adversary enumerates the candidate sets, so it needs d <= 2 and at most {ADVERSARY_MAX_SUBQUEUES} sub-queues, but got d = {max_d} with {} sub-queues",
+                    self.nbr_subqueues
+                ));
+            }
+            if matches!(self.heuristic, Heuristic::Softmax { .. }) {
+                return Err(
+                    "The adversary needs a deterministic heuristic, not softmax".to_string()
+                );
+            }
+            if self.partition
+                || self.zipf_s.is_some()
+                || self.round_robin
+                || self.memory
+                || self.paired
+                || self.recency_penalty > 0.0
+            {
+                return Err(
+                    "The adversary can not be combined with partitioning, Zipf, round-robin, memory or paired sampling, or the recency penalty"
+                        .to_string(),
+                );
+            }
+        }
+        if self.partition {
+            for d in [self.enqueue_d, self.dequeue_d] {
+                if !partitions_nonempty(self.nbr_subqueues, d) {
+                    return Err(format!(
+                        "Partitioning {} sub-queues into {d} chunks leaves an empty chunk",
+                        self.nbr_subqueues
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the queue, panicking if the configuration is invalid, see try_build
+    pub fn build<T: PartialEq + Eq>(self) -> DChoiceQueue<T> {
+        self.try_build().unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// As build, but also allows heuristics which peek at the item ages
+    pub fn build_sequenced<T: PartialEq + Eq + Sequenced>(self) -> DChoiceQueue<T> {
+        self.try_build_sequenced()
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// As build, but returns a QueueMisconfigured error if the configuration is invalid, or needs
+    /// sequenced items
+    pub fn try_build<T: PartialEq + Eq>(self) -> Result<DChoiceQueue<T>, AnalysisError> {
+        let reason = if self.heuristic == Heuristic::OldestFront {
+            Some("The oldest-front heuristic needs sequenced items, use build_sequenced")
+        } else if self.adversary {
+            Some("The adversary needs sequenced items, use build_sequenced")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            return Err(AnalysisError::QueueMisconfigured {
+                reason: reason.to_string(),
+            });
+        }
+        self.build_with(None)
+    }
+
+    /// As build_sequenced, but returns a QueueMisconfigured error if the configuration is invalid
+    pub fn try_build_sequenced<T: PartialEq + Eq + Sequenced>(
+        self,
+    ) -> Result<DChoiceQueue<T>, AnalysisError> {
+        self.build_with(Some(T::sequence))
+    }
+
+    fn build_with<T: PartialEq + Eq>(
+        self,
+        front_sequence: Option<fn(&T) -> usize>,
+    ) -> Result<DChoiceQueue<T>, AnalysisError> {
+        self.validate()
+            .map_err(|reason| AnalysisError::QueueMisconfigured { reason })?;
+        let (enqueue_d, dequeue_d) = match self.adaptive_d {
+            Some(adaptive) => (adaptive.min, adaptive.min),
+            None => (self.enqueue_d, self.dequeue_d),
+        };
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let zipf = self
+            .zipf_s
+            .map(|s| ZipfSampler::new(self.nbr_subqueues, s, &mut rng));
+        // Only drawn when used, so other configurations keep their random choices
+        let cursor = if self.round_robin {
+            rng.gen_range(0..self.nbr_subqueues)
+        } else {
+            0
+        };
+        Ok(DChoiceQueue {
+            subqueues: (0..self.nbr_subqueues).map(|_| SubQueue::new()).collect(),
+            enqueue_d,
+            dequeue_d,
+            uniques: self.uniques,
+            heuristic: self.heuristic,
+            empty_lin: self.empty_lin,
+            fallback_policy: self.fallback_policy,
+            fallback_hint: 0,
+            nonempty: 0,
+            batch_stop_short: self.batch_stop_short,
+            partition: self.partition,
+            left: self.left,
+            zipf,
+            round_robin: self.round_robin,
+            cursors: [cursor, cursor],
+            memory: self.memory,
+            remembered: [None, None],
+            paired: self.paired,
+            adversary: self.adversary,
+            recency_penalty: self.recency_penalty,
+            op_count: 0,
+            last_chosen: [vec![0; self.nbr_subqueues], vec![0; self.nbr_subqueues]],
+            skew_cap: self.skew_cap,
+            skew_cap_triggers: 0,
+            adaptive_d: self.adaptive_d,
+            skew_estimates: [0.0, 0.0],
+            d_sum: 0,
+            front_sequence,
+            peeks: 0,
+            enqueue_count: 0,
+            newest_sequence: None,
+            helping_threshold: self.helping_threshold,
+            help_buffer: None,
+            helps: 0,
+            coarse_counters: [CoarseCounter::default(); 2],
+            dequeues: 0,
+            empty_returns: 0,
+            vetoed: vec![false; self.nbr_subqueues],
+            vetoed_count: 0,
+            candidate_filter: self.candidate_filter,
+            last_choice: None,
+            rng,
+            layout: self
+                .layout_seed
+                .map(|seed| SubqueueLayout::new(self.nbr_subqueues, seed)),
+            index_mask: self
+                .nbr_subqueues
+                .is_power_of_two()
+                .then(|| self.nbr_subqueues - 1),
+            choice_trace: self.trace_choices.then(Vec::new),
+            choice_log: self.choice_log_limit.map(|_| vec![]),
+            choice_log_limit: self.choice_log_limit.unwrap_or(0),
+            cost: CostReport::default(),
+            latency_model: self.latency_model,
+            virtual_time: VirtualTime::default(),
+        })
+    }
+}
+
+impl<T: PartialEq + Eq> DChoiceQueue<T> {
+    pub fn new(
+        nbr_subqueues: usize,
+        d: usize,
+        uniques: bool,
+        progress_heuristic: bool,
+        empty_lin: bool,
+        partition: bool,
+        left: bool,
+    ) -> Self {
+        Self::builder(nbr_subqueues)
+            .d(d)
+            .uniques(uniques)
+            .heuristic(if progress_heuristic {
+                Heuristic::Operation
+            } else {
+                Heuristic::Length
+            })
+            .empty_lin(empty_lin)
+            .partition(partition)
+            .left(left)
+            .build()
+    }
+
+    pub fn builder(nbr_subqueues: usize) -> DChoiceQueueBuilder {
+        DChoiceQueueBuilder::new(nbr_subqueues)
+    }
+
+    /// Enqueues an item into the queue
+    pub fn enqueue(&mut self, item: T) {
+        let before = self.cost;
+        self.cost.operations += 1;
+        let subqueue_ind = self.enqueue_ind();
+        self.push(subqueue_ind, item);
+        self.trace(TraceEvent::Enqueue {
+            subqueue: subqueue_ind,
+        });
+        self.charge(OpKind::Enqueue, &before);
+    }
+
+    /// Enqueues a batch of items into one chosen sub-queue, as one operation
+    ///
+    /// The items keep their order within the sub-queue, so its tail counter advances by the
+    /// length of the batch.
+    pub fn enqueue_many(&mut self, items: Vec<T>) {
+        let before = self.cost;
+        self.cost.operations += 1;
+        let subqueue_ind = self.enqueue_ind();
+        for item in items {
+            self.push(subqueue_ind, item);
+            self.trace(TraceEvent::Enqueue {
+                subqueue: subqueue_ind,
+            });
+        }
+        self.charge(OpKind::Enqueue, &before);
+    }
+
+    pub fn dequeue(&mut self) -> Option<T> {
+        self.dequeue_with_info().item
+    }
+
+    /// As dequeue, but also returns how the item was found
+    pub fn dequeue_with_info(&mut self) -> DequeueInfo<T> {
+        let before = self.cost;
+        let info = self.dequeue_uncharged();
+        self.charge(OpKind::Dequeue, &before);
+        self.count_dequeue(info.item.is_none());
+        info
+    }
+
+    fn dequeue_uncharged(&mut self) -> DequeueInfo<T> {
+        self.cost.operations += 1;
+        if let Some(buffered) = self.take_buffered() {
+            return buffered;
+        }
+
+        let (subqueue_ind, inds) = self.choose_ind(OpKind::Dequeue);
+        self.dequeue_chosen(subqueue_ind, &inds)
+    }
+
+    /// Dequeues a batch of up to b items from the chosen sub-queue, as one operation
+    ///
+    /// If the sub-queue runs out, the rest of the batch is taken from the following sub-queues by
+    /// the round robin of empty dequeues, unless the queue does not scan for empty dequeues or
+    /// stops batches short. The items are returned in the order they were taken.
+    pub fn dequeue_many(&mut self, b: usize) -> Vec<T> {
+        assert!(b > 0, "A batch must take at least one item");
+        let before = self.cost;
+        self.cost.operations += 1;
+        // An item buffered by helping is the first of the batch
+        let mut items: Vec<T> = self
+            .help_buffer
+            .take()
+            .map(|(item, _, _)| item)
+            .into_iter()
+            .collect();
+
+        if items.len() < b {
+            let (mut ind, _) = self.choose_ind(OpKind::Dequeue);
+            let scan = self.empty_lin && !self.batch_stop_short;
+            let mut scanned = 0;
+            loop {
+                while items.len() < b {
+                    let Some(item) = self.pop(ind) else {
+                        break;
+                    };
+                    self.trace(TraceEvent::Dequeue {
+                        subqueue: Some(ind),
+                    });
+                    items.push(item);
+                }
+                if scanned == 0 && items.len() < b {
+                    // A sub-queue which ran out is not worth sampling again
+                    self.remembered[OpKind::Dequeue as usize] = None;
+                }
+                if items.len() == b || !scan || scanned == self.subqueues.len() - 1 {
+                    break;
+                }
+                if scanned == 0 {
+                    self.cost.scans += 1;
+                }
+                ind = self.next_index(ind);
+                self.cost.scanned += 1;
+                scanned += 1;
+            }
+        }
+        if items.is_empty() {
+            self.trace(TraceEvent::Dequeue { subqueue: None });
+        }
+        self.charge(OpKind::Dequeue, &before);
+        self.count_dequeue(items.is_empty());
+        items
+    }
+
+    /// Chooses the sub-queue for an operation without doing it, as a thread of a concurrent
+    /// execution does from the state at the start of its operation. The operation is then done by
+    /// enqueue_at or dequeue_at, which are not charged virtual time.
+    pub fn choose_subqueue(&mut self, kind: OpKind) -> usize {
+        self.choose_ind(kind).0
+    }
+
+    /// Enqueues the item into the sub-queue, chosen earlier by choose_subqueue
+    pub fn enqueue_at(&mut self, subqueue: usize, item: T) {
+        self.cost.operations += 1;
+        self.push(subqueue, item);
+        self.trace(TraceEvent::Enqueue { subqueue });
+    }
+
+    /// Dequeues from the sub-queue, chosen earlier by choose_subqueue. Falls back as dequeue if it
+    /// has run empty since.
+    pub fn dequeue_at(&mut self, subqueue: usize) -> DequeueInfo<T> {
+        self.cost.operations += 1;
+        let info = match self.take_buffered() {
+            Some(buffered) => buffered,
+            None => self.dequeue_chosen(subqueue, &[subqueue]),
+        };
+        self.count_dequeue(info.item.is_none());
+        info
+    }
+
+    /// Counts a dequeue, and whether it returned empty
+    fn count_dequeue(&mut self, empty: bool) {
+        self.dequeues += 1;
+        self.empty_returns += usize::from(empty);
+    }
+
+    /// Returns the item dequeued by helping, if any
+    fn take_buffered(&mut self) -> Option<DequeueInfo<T>> {
+        let (item, subqueue, head) = self.help_buffer.take()?;
+        self.trace(TraceEvent::Buffered);
+        self.log_choice(|_| ChoiceRecord {
+            kind: OpKind::Dequeue,
+            sampled: vec![],
+            chosen: None,
+            first: None,
+            first_rank_error: None,
+        });
+        Some(DequeueInfo {
+            item: Some(item),
+            sub_nbr: head,
+            fell_back: false,
+            subqueue,
+        })
+    }
+
+    /// Dequeues from the chosen sub-queue, falling back if it is empty, and helps the sampled ones
+    fn dequeue_chosen(&mut self, subqueue_ind: usize, inds: &[usize]) -> DequeueInfo<T> {
+        let item = self.pop(subqueue_ind);
+        if item.is_none() {
+            // An empty sub-queue is not worth sampling again
+            self.remembered[OpKind::Dequeue as usize] = None;
+        }
+        match item {
+            None if self.empty_lin => {
+                if let Some(ind) = self.fallback_index(subqueue_ind) {
+                    self.trace(TraceEvent::Dequeue {
+                        subqueue: Some(ind),
+                    });
+                    return DequeueInfo {
+                        item: self.pop(ind),
+                        sub_nbr: self.subqueues[ind].head,
+                        fell_back: true,
+                        subqueue: ind,
+                    };
+                }
+                self.trace(TraceEvent::Dequeue { subqueue: None });
+                DequeueInfo {
+                    item: None,
+                    sub_nbr: self.subqueues[subqueue_ind].head,
+                    fell_back: true,
+                    subqueue: subqueue_ind,
+                }
+            }
+            None => {
+                self.trace(TraceEvent::Dequeue { subqueue: None });
+                DequeueInfo {
+                    item: None,
+                    sub_nbr: self.subqueues[subqueue_ind].head,
+                    fell_back: false,
+                    subqueue: subqueue_ind,
+                }
+            }
+            Some(item) => {
+                self.trace(TraceEvent::Dequeue {
+                    subqueue: Some(subqueue_ind),
+                });
+                self.help_lagging(subqueue_ind, inds);
+                DequeueInfo {
+                    item: Some(item),
+                    sub_nbr: self.subqueues[subqueue_ind].head,
+                    fell_back: false,
+                    subqueue: subqueue_ind,
+                }
+            }
+        }
+    }
+
+    /// Scans the sub-queues after the empty chosen one for the one to fall back to, by the
+    /// fallback policy. Returns None if they are all empty.
+    fn fallback_index(&mut self, chosen: usize) -> Option<usize> {
+        self.cost.scans += 1;
+        if self.nonempty == 0 {
+            // The queue would scan them all to find this out, so it is charged all the same
+            self.cost.scanned += self.subqueues.len() - 1;
+            return None;
+        }
+        if self.fallback_policy == FallbackPolicy::Hinted {
+            return self.hinted_fallback_index(chosen);
+        }
+        let mut ind = chosen;
+        let mut best: Option<usize> = None;
+        for _ in 0..self.subqueues.len() - 1 {
+            ind = self.next_index(ind);
+            self.cost.scanned += 1;
+            let subqueue = &self.subqueues[ind];
+            if subqueue.len() == 0 {
+                continue;
+            }
+            let better = match (self.fallback_policy, best) {
+                (FallbackPolicy::FirstNonEmpty | FallbackPolicy::Hinted, _) => return Some(ind),
+                (_, None) => true,
+                (FallbackPolicy::LongestQueue, Some(best)) => {
+                    subqueue.len() > self.subqueues[best].len()
+                }
+                (FallbackPolicy::OldestHead, Some(best)) => {
+                    subqueue.head < self.subqueues[best].head
+                }
+            };
+            if better {
+                best = Some(ind);
+            }
+        }
+        best
+    }
+
+    /// Scans forward from the fallback hint, skipping the empty chosen sub-queue, for the first
+    /// non-empty one, which becomes the new hint. Only called while some sub-queue holds items
+    fn hinted_fallback_index(&mut self, chosen: usize) -> Option<usize> {
+        let mut ind = self.fallback_hint;
+        for _ in 0..self.subqueues.len() {
+            if ind != chosen {
+                self.cost.scanned += 1;
+                if self.subqueues[ind].len() > 0 {
+                    self.fallback_hint = ind;
+                    return Some(ind);
+                }
+            }
+            ind = self.next_index(ind);
+        }
+        None
+    }
+
+    /// Enqueues the item into the sub-queue, counting the sub-queue as non-empty and updating its
+    /// front age estimate
+    fn push(&mut self, ind: usize, item: T) {
+        let was_empty = self.subqueues[ind].len() == 0;
+        self.nonempty += usize::from(was_empty);
+        if let Heuristic::EstimatedOldest { weight } = self.heuristic {
+            let index = self.enqueue_count as f64;
+            let estimate = &mut self.subqueues[ind].front_estimate;
+            *estimate = if was_empty {
+                index
+            } else {
+                *estimate + weight * (index - *estimate)
+            };
+        }
+        self.enqueue_count += 1;
+        if let Some(sequence) = self.front_sequence {
+            let sequence = sequence(&item);
+            self.newest_sequence = Some(
+                self.newest_sequence
+                    .map_or(sequence, |newest| newest.max(sequence)),
+            );
+        }
+        self.subqueues[ind].enqueue(item);
+    }
+
+    /// Dequeues from the sub-queue, no longer counting it as non-empty if it runs out
+    fn pop(&mut self, ind: usize) -> Option<T> {
+        let item = self.subqueues[ind].dequeue();
+        if item.is_some() && self.subqueues[ind].len() == 0 {
+            self.nonempty -= 1;
+        }
+        item
+    }
+
+    /// If a sampled sub-queue lags the chosen one by more than the helping threshold, dequeues an
+    /// item from the one lagging the most and buffers it for the next dequeue
+    fn help_lagging(&mut self, chosen: usize, inds: &[usize]) {
+        let Some(threshold) = self.helping_threshold else {
+            return;
+        };
+        let chosen_head = self.subqueues[chosen].head;
+        let lagging = inds
+            .iter()
+            .filter(|ind| self.subqueues[**ind].len() > 0)
+            .min_by_key(|ind| self.subqueues[**ind].head)
+            .copied();
+
+        if let Some(ind) = lagging {
+            // Heuristics other than the operation-based one can choose a sub-queue behind the others
+            if chosen_head.saturating_sub(self.subqueues[ind].head) > threshold {
+                let item = self
+                    .pop(ind)
+                    .expect("Only non-empty sub-queues can be helped");
+                self.help_buffer = Some((item, ind, self.subqueues[ind].head));
+                self.helps += 1;
+                self.cost.moves += 1;
+                self.trace(TraceEvent::Help { subqueue: ind });
+            }
+        }
+    }
+
+    /// Adds the virtual time of the work done since the before counts to the operation kind
+    fn charge(&mut self, kind: OpKind, before: &CostReport) {
+        let Some(model) = self.latency_model else {
+            return;
+        };
+        let time = model.time(&self.cost.since(before));
+        match kind {
+            OpKind::Enqueue => {
+                self.virtual_time.enqueues += 1;
+                self.virtual_time.enqueue_time += time;
+            }
+            OpKind::Dequeue => {
+                self.virtual_time.dequeues += 1;
+                self.virtual_time.dequeue_time += time;
+            }
+        }
+    }
+
+    fn trace(&mut self, event: TraceEvent) {
+        if let Some(trace) = self.choice_trace.as_mut() {
+            trace.push(event);
+        }
+    }
+
+    /// Logs the choice if logging and below the limit, only creating the record then
+    fn log_choice(&mut self, record: impl FnOnce(&Self) -> ChoiceRecord) {
+        if self
+            .choice_log
+            .as_ref()
+            .is_some_and(|log| log.len() < self.choice_log_limit)
+        {
+            let record = record(self);
+            if let Some(log) = self.choice_log.as_mut() {
+                log.push(record);
+            }
+        }
+    }
+
+    /// Finds the sub-queue to enqueue into
+    fn enqueue_ind(&mut self) -> usize {
+        self.choose_ind(OpKind::Enqueue).0
+    }
+
+    /// Samples sub-queues and chooses one, depending on heuristic used (this is not super optimized).
+    /// Returns the chosen index together with all sampled ones.
+    fn choose_ind(&mut self, kind: OpKind) -> (usize, Candidates) {
+        let inds = self.subqueue_inds(kind);
+        let inds = self.allowed_candidates(inds, kind);
+        if self.heuristic == Heuristic::OldestFront && kind == OpKind::Dequeue {
+            self.peeks += inds.len();
+        }
+        if let Heuristic::CoarseCounter { granularity } = self.heuristic {
+            let front = || {
+                self.subqueues
+                    .iter()
+                    .map(|subqueue| subqueue.counter(kind))
+                    .max()
+                    .unwrap_or(0)
+            };
+            let published = self.coarse_counters[kind as usize].step(granularity, front);
+            self.coarse_counters[kind as usize] = published;
+        }
+        let chosen = if self.paired {
+            self.paired_choice(&inds, kind)
+        } else if self.recency_penalty > 0.0 {
+            self.penalized_choice(&inds, kind)
+        } else {
+            self.heuristic_choice(&inds, kind)
+        }
+        .expect("Should always be able to find an index if d>0");
+        let chosen = self.apply_skew_cap(chosen, kind);
+        self.log_choice(|queue| ChoiceRecord {
+            kind,
+            sampled: inds
+                .iter()
+                .map(|ind| (*ind, queue.score(*ind, kind)))
+                .collect(),
+            chosen: Some(chosen),
+            first: inds.first().copied(),
+            first_rank_error: inds
+                .first()
+                .filter(|_| kind == OpKind::Dequeue)
+                .and_then(|first| queue.front_rank_error(*first)),
+        });
+        self.d_sum += inds.len();
+        self.cost.samples += inds.len();
+        self.adapt_d(chosen, kind);
+
+        self.op_count += 1;
+        self.last_chosen[kind as usize][chosen] = self.op_count;
+        self.last_choice = Some(chosen);
+        if self.memory {
+            self.remembered[kind as usize] = Some(chosen);
+        }
+        (chosen, inds)
+    }
+
+    /// Removes the vetoed sub-queues and those rejected by the candidate filter from the
+    /// candidates. If all of them were removed, resamples up to FILTER_RESAMPLES times, and then
+    /// scans for a random allowed sub-queue, unless none is allowed
+    fn allowed_candidates(&mut self, mut inds: Candidates, kind: OpKind) -> Candidates {
+        if self.vetoed_count == 0 && self.candidate_filter.is_none() {
+            return inds;
+        }
+        for resample in 0..=FILTER_RESAMPLES {
+            if resample > 0 {
+                // The rejected candidates were sampled all the same
+                self.cost.samples += inds.len();
+                inds = self.subqueue_inds(kind);
+            }
+            let allowed: Candidates = inds
+                .iter()
+                .copied()
+                .filter(|ind| self.allowed(*ind, kind))
+                .collect();
+            if !allowed.is_empty() {
+                return allowed;
+            }
+        }
+
+        self.cost.scans += 1;
+        self.cost.scanned += self.subqueues.len();
+        let allowed: Vec<usize> = (0..self.subqueues.len())
+            .filter(|ind| self.allowed(*ind, kind))
+            .collect();
+        match allowed.choose(&mut self.rng) {
+            Some(ind) => std::iter::once(*ind).collect(),
+            None => inds,
+        }
+    }
+
+    /// If the sub-queue is neither vetoed nor rejected by the candidate filter
+    fn allowed(&mut self, ind: usize, kind: OpKind) -> bool {
+        // The operation has already been counted
+        let operation = self.cost.operations.saturating_sub(1);
+        !self.vetoed[ind]
+            && self
+                .candidate_filter
+                .as_mut()
+                .is_none_or(|filter| filter.allows(ind, kind, operation))
+    }
+
+    /// Steps the d of the operation kind towards the skew, if adapting it
+    fn adapt_d(&mut self, chosen: usize, kind: OpKind) {
+        let Some(adaptive) = self.adaptive_d else {
+            return;
+        };
+        let counter = |subqueue: &SubQueue<T>| match kind {
+            OpKind::Enqueue => subqueue.tail,
+            OpKind::Dequeue => subqueue.head,
+        };
+        let min_counter = self.subqueues.iter().map(counter).min().unwrap_or(0);
+        let skew = (counter(&self.subqueues[chosen]) - min_counter) as f64;
+
+        let estimate = &mut self.skew_estimates[kind as usize];
+        *estimate = adaptive.weight * skew + (1.0 - adaptive.weight) * *estimate;
+        let estimate = *estimate;
+        let d = match kind {
+            OpKind::Enqueue => &mut self.enqueue_d,
+            OpKind::Dequeue => &mut self.dequeue_d,
+        };
+        // Keeps d between the thresholds, so it does not flap around a single one
+        if estimate > adaptive.upper && *d < adaptive.max {
+            *d += 1;
+        } else if estimate < adaptive.lower && *d > adaptive.min {
+            *d -= 1;
+        }
+    }
+
+    /// Redirects the choice to the sub-queue with the lowest counter if it would exceed the skew cap
+    fn apply_skew_cap(&mut self, chosen: usize, kind: OpKind) -> usize {
+        let Some(cap) = self.skew_cap else {
+            return chosen;
+        };
+        let counter = |subqueue: &SubQueue<T>| match kind {
+            OpKind::Enqueue => subqueue.tail,
+            OpKind::Dequeue => subqueue.head,
+        };
+
+        let (min_ind, min_counter) = self
+            .subqueues
+            .iter()
+            .map(counter)
+            .enumerate()
+            .min_by_key(|(_, count)| *count)
+            .expect("There is always at least one sub-queue");
+        // The skew the operation would leave, as it advances the counter of the chosen one
+        if counter(&self.subqueues[chosen]) + 1 - min_counter > cap {
+            // Only the redirects are counted as scans, assuming the minimum counter is tracked
+            self.skew_cap_triggers += 1;
+            self.cost.scans += 1;
+            self.cost.scanned += self.subqueues.len();
+            min_ind
+        } else {
+            chosen
+        }
+    }
+
+    /// Chooses among the sampled sub-queues purely by the heuristic
+    fn heuristic_choice(&mut self, inds: &[usize], kind: OpKind) -> Option<usize> {
+        match (self.heuristic, kind) {
+            (
+                Heuristic::Operation | Heuristic::OldestFront | Heuristic::EstimatedOldest { .. },
+                OpKind::Enqueue,
+            ) => inds
+                .iter()
+                .copied()
+                .min_by_key(|ind| (self.subqueues[*ind].tail, if self.left { *ind } else { 0 })),
+            (Heuristic::Operation, OpKind::Dequeue) => inds
+                .iter()
+                .copied()
+                .min_by_key(|ind| (self.subqueues[*ind].head, if self.left { *ind } else { 0 })),
+            (Heuristic::Length, OpKind::Enqueue) => inds
+                .iter()
+                .copied()
+                .min_by_key(|ind| (self.subqueues[*ind].len(), if self.left { *ind } else { 0 })),
+            (Heuristic::Length, OpKind::Dequeue) => inds.iter().copied().max_by_key(|ind| {
+                (
+                    self.subqueues[*ind].len(),
+                    if self.left {
+                        self.nbr_subqueues() - *ind
+                    } else {
+                        0
+                    },
+                )
+            }),
+            (Heuristic::OldestFront, OpKind::Dequeue) => inds.iter().copied().min_by_key(|ind| {
+                (
+                    self.front_sequence(*ind).unwrap_or(usize::MAX),
+                    if self.left { *ind } else { 0 },
+                )
+            }),
+            (Heuristic::EstimatedOldest { .. }, OpKind::Dequeue)
+            | (Heuristic::CoarseCounter { .. }, _) => {
+                inds.iter().copied().min_by(|ind_a, ind_b| {
+                    let tiebreak = if self.left {
+                        ind_a.cmp(ind_b)
+                    } else {
+                        std::cmp::Ordering::Equal
+                    };
+                    self.score(*ind_a, kind)
+                        .total_cmp(&self.score(*ind_b, kind))
+                        .then(tiebreak)
+                })
+            }
+            (Heuristic::Softmax { temperature }, _) => {
+                let scores: Vec<f64> = inds.iter().map(|ind| self.score(*ind, kind)).collect();
+                self.softmax_choice(inds, &scores, temperature)
+            }
+        }
+    }
+
+    /// Chooses the sampled pair with the lowest summed score of its two sub-queues, and then among
+    /// the candidates in that pair by the heuristic
+    fn paired_choice(&mut self, inds: &[usize], kind: OpKind) -> Option<usize> {
+        let pair_of = |ind: usize| self.sampling_index(ind) / 2;
+        let pair = inds
+            .iter()
+            .map(|ind| pair_of(*ind))
+            .min_by(|pair_a, pair_b| {
+                let pair_score = |pair: usize| {
+                    self.score(self.subqueue_index(2 * pair), kind)
+                        + self.score(self.subqueue_index(2 * pair + 1), kind)
+                };
+                let tiebreak = if self.left {
+                    pair_a.cmp(pair_b)
+                } else {
+                    std::cmp::Ordering::Equal
+                };
+                pair_score(*pair_a)
+                    .total_cmp(&pair_score(*pair_b))
+                    .then(tiebreak)
+            })?;
+        let mut members: Vec<usize> = inds
+            .iter()
+            .copied()
+            .filter(|ind| pair_of(*ind) == pair)
+            .collect();
+        members.sort_unstable();
+        members.dedup();
+        self.heuristic_choice(&members, kind)
+    }
+
+    /// Chooses among the sampled sub-queues by the heuristic score plus the recency penalty
+    fn penalized_choice(&mut self, inds: &[usize], kind: OpKind) -> Option<usize> {
+        let scores: Vec<f64> = inds
+            .iter()
+            .map(|ind| {
+                let last_chosen = self.last_chosen[kind as usize][*ind];
+                let penalty = if last_chosen == 0 {
+                    0.0
+                } else {
+                    // The counter has not been stepped for this operation yet, so the age is >= 1
+                    self.recency_penalty / (self.op_count + 1 - last_chosen) as f64
+                };
+                self.score(*ind, kind) + penalty
+            })
+            .collect();
+
+        match self.heuristic {
+            Heuristic::Softmax { temperature } => self.softmax_choice(inds, &scores, temperature),
+            _ => inds
+                .iter()
+                .zip(scores)
+                .min_by(|(ind_a, score_a), (ind_b, score_b)| {
+                    let tiebreak = if self.left {
+                        ind_a.cmp(ind_b)
+                    } else {
+                        std::cmp::Ordering::Equal
+                    };
+                    score_a.total_cmp(score_b).then(tiebreak)
+                })
+                .map(|(ind, _)| *ind),
+        }
+    }
+
+    /// The heuristic score of a sub-queue for an operation, where lower is better
+    fn score(&self, ind: usize, kind: OpKind) -> f64 {
+        let subqueue = &self.subqueues[ind];
+        match (self.heuristic, kind) {
+            (Heuristic::Length, OpKind::Enqueue) => subqueue.len() as f64,
+            (Heuristic::Length, OpKind::Dequeue) => -(subqueue.len() as f64),
+            (Heuristic::OldestFront, OpKind::Dequeue) => self
+                .front_sequence(ind)
+                .map_or(f64::INFINITY, |sequence| sequence as f64),
+            (Heuristic::EstimatedOldest { .. }, OpKind::Dequeue) => {
+                if subqueue.len() == 0 {
+                    f64::INFINITY
+                } else {
+                    subqueue.front_estimate
+                }
+            }
+            (Heuristic::CoarseCounter { .. }, _) => {
+                -(self.coarse_counters[kind as usize]
+                    .published
+                    .saturating_sub(subqueue.counter(kind)) as f64)
+            }
+            (_, OpKind::Enqueue) => subqueue.tail as f64,
+            (_, OpKind::Dequeue) => subqueue.head as f64,
+        }
+    }
+
+    /// The sequence number of the front item of a sub-queue, or None if it is empty
+    fn front_sequence(&self, ind: usize) -> Option<usize> {
+        let sequence = self
+            .front_sequence
+            .expect("Peeking heuristics are only built for sequenced items");
+        self.peek_front(ind).map(sequence)
+    }
+
+    /// Draws one of the candidates with probability proportional to exp(-score / temperature)
+    fn softmax_choice(
+        &mut self,
+        inds: &[usize],
+        scores: &[f64],
+        temperature: f64,
+    ) -> Option<usize> {
+        // Shift by the smallest score so the best candidate has weight 1, avoiding underflow
+        let min_score = scores.iter().cloned().min_by(f64::total_cmp)?;
+        let weights: Vec<f64> = scores
+            .iter()
+            .map(|score| (-(score - min_score) / temperature).exp())
+            .collect();
+
+        // The best candidate has weight 1 and validated temperatures keep every weight finite
+        let choice = WeightedIndex::new(&weights)
+            .expect("Softmax weights are finite with the best one at 1")
+            .sample(&mut self.rng);
+        Some(inds[choice])
+    }
+
+    /// Gets sub-queue inds, depending on allowing repeats of not
+    fn subqueue_inds(&mut self, kind: OpKind) -> Candidates {
+        if self.adversary {
+            let d = match kind {
+                OpKind::Enqueue => self.enqueue_d,
+                OpKind::Dequeue => self.dequeue_d,
+            };
+            return self.adversarial_inds(kind, d);
+        }
+        let mut inds = self.sampling_inds(kind);
+        if let Some(layout) = &self.layout {
+            inds.iter_mut().for_each(|ind| *ind = layout.subqueue[*ind]);
+        }
+        inds
+    }
+
+    /// Samples the candidates as sampling indexes, which the layout maps to the sub-queues
+    fn sampling_inds(&mut self, kind: OpKind) -> Candidates {
+        let nbr_subqueues = self.nbr_subqueues();
+        let d = match kind {
+            OpKind::Enqueue => self.enqueue_d,
+            OpKind::Dequeue => self.dequeue_d,
+        };
+        if self.partition {
+            let psize = nbr_subqueues.div_ceil(d);
+            let mut indexes: Candidates = (0..d)
+                .map(|part| {
+                    self.rng
+                        .gen_range(part * psize..std::cmp::min(psize * (part + 1), nbr_subqueues))
+                })
+                .collect();
+            indexes.shuffle(&mut self.rng);
+            indexes
+        } else if self.paired {
+            let nbr_pairs = nbr_subqueues / 2;
+            let pairs: Vec<usize> = if self.uniques {
+                (0..nbr_pairs)
+                    .collect::<Vec<usize>>()
+                    .choose_multiple(&mut self.rng, d.div_ceil(2))
+                    .cloned()
+                    .collect()
+            } else {
+                (0..d.div_ceil(2))
+                    .map(|_| self.rng.gen_range(0..nbr_pairs))
+                    .collect()
+            };
+            pairs
+                .into_iter()
+                .flat_map(|pair| [2 * pair, 2 * pair + 1])
+                .collect()
+        } else if self.round_robin {
+            let cursor = self.cursors[kind as usize];
+            self.cursors[kind as usize] = (cursor + 1) % nbr_subqueues;
+            std::iter::once(cursor)
+                .chain((1..d).map(|_| self.random_index()))
+                .collect()
+        } else if self.memory {
+            let remembered = self.remembered[kind as usize].map(|ind| self.sampling_index(ind));
+            let fresh = d - usize::from(remembered.is_some());
+            remembered
+                .into_iter()
+                .chain((0..fresh).map(|_| self.random_index()))
+                .collect()
+        } else if let Some(zipf) = &self.zipf {
+            if self.uniques {
+                zipf.sample_distinct(d, &mut self.rng).into_iter().collect()
+            } else {
+                (0..d).map(|_| zipf.sample(&mut self.rng)).collect()
+            }
+        } else if self.uniques {
+            (0..nbr_subqueues)
+                .collect::<Vec<usize>>()
+                .choose_multiple(&mut self.rng, d)
+                .cloned()
+                .collect()
+        } else {
+            (0..d).map(|_| self.random_index()).collect()
+        }
+    }
+
+    /// Enumerates the candidate sets of size d, which is at most 2, and returns the one whose
+    /// heuristic choice is the worst, see DChoiceQueueBuilder::adversary
+    fn adversarial_inds(&mut self, kind: OpKind, d: usize) -> Candidates {
+        let nbr_subqueues = self.nbr_subqueues();
+        let badness: Vec<f64> = (0..nbr_subqueues)
+            .map(|ind| self.badness(ind, kind))
+            .collect();
+        let mut worst: Option<(f64, Candidates)> = None;
+        for first in 0..nbr_subqueues {
+            let seconds = match (d, self.uniques) {
+                (1, _) => first..first + 1,
+                (_, true) => first + 1..nbr_subqueues,
+                (_, false) => first..nbr_subqueues,
+            };
+            for second in seconds {
+                let inds: Candidates = [first, second].into_iter().take(d).collect();
+                let chosen = self
+                    .heuristic_choice(&inds, kind)
+                    .expect("The candidates are not empty");
+                if worst
+                    .as_ref()
+                    .is_none_or(|(value, _)| badness[chosen] > *value)
+                {
+                    worst = Some((badness[chosen], inds));
+                }
+            }
+        }
+        worst.expect("There is always a candidate set").1
+    }
+
+    /// How bad choosing the sub-queue is, which the adversary maximizes. For dequeues the rank
+    /// error of its front item, or -1 if it is empty. For enqueues how short it is
+    fn badness(&self, ind: usize, kind: OpKind) -> f64 {
+        if kind == OpKind::Enqueue {
+            return -(self.subqueues[ind].len() as f64);
+        }
+        self.front_rank_error(ind)
+            .map_or(-1.0, |rank_error| rank_error as f64)
+    }
+
+    /// The rank error of dequeuing the front item of the sub-queue, counting the older items of
+    /// every sub-queue as their items are in enqueue order. None if it is empty or the items are
+    /// not sequenced
+    fn front_rank_error(&self, ind: usize) -> Option<usize> {
+        let sequence = self.front_sequence?;
+        let front = sequence(self.peek_front(ind)?);
+        Some(
+            self.subqueues
+                .iter()
+                .map(|subqueue| subqueue.fifo.partition_point(|item| sequence(item) < front))
+                .sum(),
+        )
+    }
+
+    /// A uniformly random sub-queue index
+    fn random_index(&mut self) -> usize {
+        match self.index_mask {
+            Some(mask) => self.rng.gen::<u64>() as usize & mask,
+            None => self.rng.gen_range(0..self.subqueues.len()),
+        }
+    }
+
+    /// The sub-queue after ind in the layout, wrapping around to the first
+    fn next_index(&self, ind: usize) -> usize {
+        if let Some(layout) = &self.layout {
+            return layout.subqueue[(layout.sampling[ind] + 1) % self.subqueues.len()];
+        }
+        match self.index_mask {
+            Some(mask) => (ind + 1) & mask,
+            None => (ind + 1) % self.subqueues.len(),
+        }
+    }
+
+    /// The sub-queue the sampling index maps to
+    fn subqueue_index(&self, ind: usize) -> usize {
+        self.layout
+            .as_ref()
+            .map_or(ind, |layout| layout.subqueue[ind])
+    }
+
+    /// The sampling index which maps to the sub-queue
+    fn sampling_index(&self, ind: usize) -> usize {
+        self.layout
+            .as_ref()
+            .map_or(ind, |layout| layout.sampling[ind])
+    }
+
+    /// The seed of the permutation from the sampling indexes to the sub-queues, if permuted, see
+    /// DChoiceQueueBuilder::permute_subqueues
+    pub fn layout_seed(&self) -> Option<u64> {
+        self.layout.as_ref().map(|layout| layout.seed)
+    }
+
+    pub fn print_skewness(&self) {
+        let (mean_head, std_head) = std(&self
+            .subqueues
+            .iter()
+            .map(|p| p.head)
+            .collect::<Vec<usize>>());
+        let (mean_tail, std_tail) = std(&self
+            .subqueues
+            .iter()
+            .map(|p| p.tail)
+            .collect::<Vec<usize>>());
+        println!("Mean head: {mean_head}, std: {std_head}");
+        println!("Mean tail: {mean_tail}, std: {std_tail}");
+        print!("Heads: ");
+        for p in self.subqueues.iter() {
+            print!(" {}", p.head);
+        }
+        println!();
+        print!("Tails: ");
+        for p in self.subqueues.iter() {
+            print!(" {}", p.tail);
+        }
+        println!();
+    }
+
+    /// Returns how many times the skew cap has overridden the heuristic choice
+    pub fn skew_cap_triggers(&self) -> usize {
+        self.skew_cap_triggers
+    }
+
+    /// Returns how many sub-queue fronts the heuristic has peeked at
+    pub fn peeks(&self) -> usize {
+        self.peeks
+    }
+
+    /// Returns how many items have been dequeued by helping lagging sub-queues
+    pub fn helps(&self) -> usize {
+        self.helps
+    }
+
+    /// The work done since the queue was built or the cost counters were reset
+    pub fn cost_report(&self) -> CostReport {
+        self.cost
+    }
+
+    /// Zeroes the cost counters and virtual time, e.g. to only count the work after the prefill
+    pub fn reset_cost_counters(&mut self) {
+        self.cost = CostReport::default();
+        self.virtual_time = VirtualTime::default();
+        self.dequeues = 0;
+        self.empty_returns = 0;
+    }
+
+    /// The dequeues since the cost counters were reset, and how many of them returned empty
+    pub fn dequeue_counts(&self) -> (usize, usize) {
+        (self.dequeues, self.empty_returns)
+    }
+
+    /// The virtual time of the operations since the cost counters were reset, or None if there
+    /// is no latency model
+    pub fn virtual_time(&self) -> Option<VirtualTime> {
+        self.latency_model.map(|_| self.virtual_time)
+    }
+
+    /// The number of sub-queues the next operation of the kind samples
+    pub fn current_d(&self, kind: OpKind) -> usize {
+        match kind {
+            OpKind::Enqueue => self.enqueue_d,
+            OpKind::Dequeue => self.dequeue_d,
+        }
+    }
+
+    /// The average number of sub-queues sampled per choice so far, or None if d is not adaptive
+    pub fn average_d(&self) -> Option<f64> {
+        self.adaptive_d
+            .map(|_| self.d_sum as f64 / self.op_count.max(1) as f64)
+    }
+
+    pub fn nbr_subqueues(&self) -> usize {
+        self.subqueues.len()
+    }
+
+    /// Returns the number of items in the queue, including one buffered by helping
+    pub fn len(&self) -> usize {
+        self.subqueues.iter().map(|p| p.len()).sum::<usize>()
+            + usize::from(self.help_buffer.is_some())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the front item of a sub-queue without dequeuing it, or None if the sub-queue is
+    /// empty or out of range
+    pub fn peek_front(&self, sub: usize) -> Option<&T> {
+        self.subqueues.get(sub)?.fifo.front()
+    }
+
+    /// Returns the items of a sub-queue from the front, which is empty if it is out of range
+    pub fn subqueue_items(&self, sub: usize) -> impl Iterator<Item = &T> {
+        self.subqueues
+            .get(sub)
+            .into_iter()
+            .flat_map(|subqueue| subqueue.fifo.iter())
+    }
+
+    /// Consumes the queue, returning the remaining items of each sub-queue from the front
+    ///
+    /// An item buffered by helping is put back at the front of the sub-queue it was taken from.
+    pub fn into_items(self) -> Vec<Vec<T>> {
+        let mut items: Vec<Vec<T>> = self
+            .subqueues
+            .into_iter()
+            .map(|subqueue| subqueue.fifo.into())
+            .collect();
+        if let Some((item, subqueue, _)) = self.help_buffer {
+            items[subqueue].insert(0, item);
+        }
+        items
+    }
+
+    /// The number of sub-queues holding items, which does not include the help buffer
+    pub fn nonempty_subqueues(&self) -> usize {
+        self.nonempty
+    }
+
+    /// Returns the number of items in a sub-queue, or None if it is out of range
+    pub fn subqueue_len(&self, sub: usize) -> Option<usize> {
+        self.subqueues.get(sub).map(|subqueue| subqueue.len())
+    }
+
+    /// Returns the effects of all operations so far on the sub-queues, if built to trace them
+    pub fn choice_trace(&self) -> Option<&[TraceEvent]> {
+        self.choice_trace.as_deref()
+    }
+
+    /// Returns how the first operations chose their sub-queues, including the enqueues of any
+    /// prefill, if built to log them
+    pub fn choice_log(&self) -> Option<&[ChoiceRecord]> {
+        self.choice_log.as_deref()
+    }
+
+    /// Returns the number of enqueues done on each sub-queue
+    pub fn subqueue_enqueue_counts(&self) -> Vec<usize> {
+        self.subqueues.iter().map(|p| p.tail).collect()
+    }
+
+    /// Returns the number of dequeues done on each partial queue
+    pub fn subqueue_dequeue_counts(&self) -> Vec<usize> {
+        self.subqueues.iter().map(|p| p.head).collect()
+    }
+
+    /// Returns the most items each sub-queue has held at once, including the prefill
+    pub fn subqueue_high_water_marks(&self) -> Vec<usize> {
+        self.subqueues.iter().map(|p| p.high_water).collect()
+    }
+
+    /// Reports the items left in each sub-queue. The enqueue ids are only known for sequenced
+    /// queues, which are assumed to number their items consecutively in enqueue order, from any
+    /// start such as the start_id of analyze_simple_from.
+    pub fn final_state_report(&self) -> FinalStateReport {
+        let Some(sequence) = self.front_sequence else {
+            return FinalStateReport {
+                subqueues: self
+                    .subqueues
+                    .iter()
+                    .map(|subqueue| SubQueueState {
+                        remaining: subqueue.len(),
+                        ..Default::default()
+                    })
+                    .collect(),
+                stranded: None,
+            };
+        };
+
+        // A strict queue would hold the newest items, including the one buffered by helping
+        let remaining = self
+            .subqueues
+            .iter()
+            .map(|subqueue| subqueue.len())
+            .sum::<usize>()
+            + usize::from(self.help_buffer.is_some());
+        let strict_oldest = self
+            .newest_sequence
+            .map_or(0, |newest| (newest + 1).saturating_sub(remaining));
+
+        let mut stranded = 0;
+        let subqueues = self
+            .subqueues
+            .iter()
+            .map(|subqueue| {
+                let mut ids: Vec<usize> = subqueue.fifo.iter().map(sequence).collect();
+                ids.sort_unstable();
+                stranded += ids.partition_point(|id| *id < strict_oldest);
+                SubQueueState {
+                    remaining: ids.len(),
+                    min_id: ids.first().copied(),
+                    median_id: ids.get(ids.len() / 2).copied(),
+                    max_id: ids.last().copied(),
+                }
+            })
+            .collect();
+        FinalStateReport {
+            subqueues,
+            stranded: Some(stranded),
+        }
+    }
+}
+
+/// Counts of the work the queue has done, to weigh the rank errors against
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct CostReport {
+    /// The number of enqueues and dequeues
+    pub operations: usize,
+
+    /// Sub-queues whose metadata was read by the d-choices
+    pub samples: usize,
+
+    /// Linear scans over the sub-queues, by empty dequeue fallbacks and skew cap redirects
+    pub scans: usize,
+
+    /// Sub-queues read by the scans
+    pub scanned: usize,
+
+    /// Items moved other than by the dequeue returning them, which is done by helping
+    pub moves: usize,
+}
+
+impl CostReport {
+    /// Adds the counts of another report, such as from another run
+    pub fn merge(&mut self, other: &CostReport) {
+        self.operations += other.operations;
+        self.samples += other.samples;
+        self.scans += other.scans;
+        self.scanned += other.scanned;
+        self.moves += other.moves;
+    }
+
+    /// The sub-queue reads and item moves per operation
+    pub fn touches_per_operation(&self) -> f64 {
+        (self.samples + self.scanned + self.moves) as f64 / self.operations.max(1) as f64
+    }
+
+    /// The counts added since the earlier report
+    fn since(&self, earlier: &CostReport) -> CostReport {
+        CostReport {
+            operations: self.operations - earlier.operations,
+            samples: self.samples - earlier.samples,
+            scans: self.scans - earlier.scans,
+            scanned: self.scanned - earlier.scanned,
+            moves: self.moves - earlier.moves,
+        }
+    }
+}
+
+/// Virtual costs of the work counted by a CostReport, in nanoseconds, to estimate throughput
+/// without simulating the concurrent queue
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LatencyModel {
+    /// The cost of every operation
+    pub base: f64,
+
+    /// The cost of each sampled sub-queue
+    pub sample: f64,
+
+    /// The cost of each sub-queue read by a scan, and of each item moved by helping
+    pub scan: f64,
+
+    /// The cost of each scan, as the operation retries after its chosen sub-queue was rejected,
+    /// which is where a concurrent dequeue would contend over the remaining items
+    pub retry: f64,
+}
+
+impl LatencyModel {
+    /// The virtual time of the counted work
+    pub fn time(&self, cost: &CostReport) -> f64 {
+        self.base * cost.operations as f64
+            + self.sample * cost.samples as f64
+            + self.scan * (cost.scanned + cost.moves) as f64
+            + self.retry * cost.scans as f64
+    }
+}
+
+/// The virtual time spent by each kind of operation, as charged by a LatencyModel
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct VirtualTime {
+    pub enqueues: usize,
+    pub dequeues: usize,
+    pub enqueue_time: f64,
+    pub dequeue_time: f64,
+}
+
+impl VirtualTime {
+    /// Adds the operations and time of another run
+    pub fn merge(&mut self, other: &VirtualTime) {
+        self.enqueues += other.enqueues;
+        self.dequeues += other.dequeues;
+        self.enqueue_time += other.enqueue_time;
+        self.dequeue_time += other.dequeue_time;
+    }
+
+    pub fn total(&self) -> f64 {
+        self.enqueue_time + self.dequeue_time
+    }
+
+    /// The operations per virtual second, or 0 if no time was spent
+    pub fn throughput(&self) -> f64 {
+        if self.total() > 0.0 {
+            (self.enqueues + self.dequeues) as f64 * 1e9 / self.total()
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The items left in the sub-queues after a run
+#[derive(Clone, Debug, Serialize)]
+pub struct FinalStateReport {
+    pub subqueues: Vec<SubQueueState>,
+
+    /// How many of the remaining items a strict queue would already have dequeued, if sequenced
+    pub stranded: Option<usize>,
+}
+
+/// The items left in one sub-queue, with the enqueue ids of them if the items are sequenced
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SubQueueState {
+    pub remaining: usize,
+    pub min_id: Option<usize>,
+    pub median_id: Option<usize>,
+    pub max_id: Option<usize>,
+}
+
+/// If splitting the sub-queues into d chunks of size ceil(n / d) leaves every chunk non-empty
+pub fn partitions_nonempty(nbr_subqueues: usize, d: usize) -> bool {
+    d > 0 && (d - 1) * nbr_subqueues.div_ceil(d) < nbr_subqueues
+}
+
+fn std(values: &[usize]) -> (f32, f32) {
+    let mean = values.iter().cloned().sum::<usize>() as f32 / values.len() as f32;
+    let std = (values
+        .iter()
+        .map(|val| (*val as f32 - mean) * (*val as f32 - mean))
+        .sum::<f32>()
+        / values.len() as f32)
+        .sqrt();
+
+    (mean, std)
+}
+
+/// A shared counter which only publishes a new value every granularity steps
+#[derive(Copy, Clone, Debug, Default)]
+struct CoarseCounter {
+    /// The value as of the last publish
+    published: usize,
+
+    /// The steps taken so far
+    steps: usize,
+}
+
+impl CoarseCounter {
+    /// Takes a step, first publishing the current value if the granularity steps since the last
+    /// publish have passed, including before the first step
+    fn step(self, granularity: usize, current: impl FnOnce() -> usize) -> Self {
+        Self {
+            published: if self.steps.is_multiple_of(granularity) {
+                current()
+            } else {
+                self.published
+            },
+            steps: self.steps + 1,
+        }
+    }
+}
+
+/// A random permutation between the sampling indexes and the sub-queues
+struct SubqueueLayout {
+    seed: u64,
+
+    /// The sub-queue of each sampling index
+    subqueue: Vec<usize>,
+
+    /// The sampling index of each sub-queue
+    sampling: Vec<usize>,
+}
+
+impl SubqueueLayout {
+    fn new(nbr_subqueues: usize, seed: u64) -> Self {
+        let mut subqueue: Vec<usize> = (0..nbr_subqueues).collect();
+        subqueue.shuffle(&mut StdRng::seed_from_u64(seed));
+        let mut sampling = vec![0; nbr_subqueues];
+        for (ind, sub) in subqueue.iter().enumerate() {
+            sampling[*sub] = ind;
+        }
+        Self {
+            seed,
+            subqueue,
+            sampling,
+        }
+    }
+}
+
+/// Draws sub-queue indexes with probability proportional to 1 / rank^s
+struct ZipfSampler {
+    /// The sub-queue index of each rank
+    order: Vec<usize>,
+
+    /// The cumulative weights of the ranks
+    cumulative: Vec<f64>,
+}
+
+impl ZipfSampler {
+    fn new(nbr_subqueues: usize, s: f64, rng: &mut StdRng) -> Self {
+        let mut order: Vec<usize> = (0..nbr_subqueues).collect();
+        order.shuffle(rng);
+        let cumulative = (1..=nbr_subqueues)
+            .scan(0.0, |total, rank| {
+                *total += (rank as f64).powf(-s);
+                Some(*total)
+            })
+            .collect();
+        Self { order, cumulative }
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> usize {
+        self.order[self.sample_rank(rng)]
+    }
+
+    fn sample_rank(&self, rng: &mut StdRng) -> usize {
+        let total = self
+            .cumulative
+            .last()
+            .expect("There is at least one sub-queue");
+        let target = rng.gen::<f64>() * total;
+        self.cumulative
+            .partition_point(|weight| *weight <= target)
+            .min(self.order.len() - 1)
+    }
+
+    fn weight(&self, rank: usize) -> f64 {
+        self.cumulative[rank]
+            - rank
+                .checked_sub(1)
+                .map_or(0.0, |prev| self.cumulative[prev])
+    }
+
+    /// Samples d distinct sub-queues, each by its weight among the ones not sampled yet
+    ///
+    /// Rejects repeated samples, which is fast while the sampled ranks hold little of the weight,
+    /// and after ZIPF_REJECTION_DRAWS draws per sub-queue picks the rest from the remaining
+    /// weights, so that it finishes even when d is close to the number of sub-queues.
+    fn sample_distinct(&self, d: usize, rng: &mut StdRng) -> Vec<usize> {
+        assert!(
+            d <= self.order.len(),
+            "Cannot sample more sub-queues than exist"
+        );
+        let mut ranks: Vec<usize> = Vec::with_capacity(d);
+        for _ in 0..ZIPF_REJECTION_DRAWS * d {
+            if ranks.len() == d {
+                break;
+            }
+            let rank = self.sample_rank(rng);
+            if !ranks.contains(&rank) {
+                ranks.push(rank);
+            }
+        }
+        while ranks.len() < d {
+            let remaining: f64 = self.cumulative[self.cumulative.len() - 1]
+                - ranks.iter().map(|rank| self.weight(*rank)).sum::<f64>();
+            let mut target = rng.gen::<f64>() * remaining;
+            let mut free = (0..self.order.len()).filter(|rank| !ranks.contains(rank));
+            let mut picked = free.next().expect("Fewer than d sub-queues are sampled");
+            for rank in free {
+                target -= self.weight(picked);
+                if target < 0.0 {
+                    break;
+                }
+                picked = rank;
+            }
+            ranks.push(picked);
+        }
+        ranks.into_iter().map(|rank| self.order[rank]).collect()
+    }
+}
+
+/// How many draws per sampled sub-queue the Zipf sampling rejects repeats for, before picking
+/// among the remaining weights directly
+const ZIPF_REJECTION_DRAWS: usize = 4;
+
+/// Candidate buffers up to this length are kept inline, without allocating
+const INLINE_CANDIDATES: usize = 8;
+
+/// The sampled sub-queue indexes of one operation, only allocated for large d
+enum Candidates {
+    Inline([usize; INLINE_CANDIDATES], usize),
+    Heap(Vec<usize>),
+}
+
+impl Deref for Candidates {
+    type Target = [usize];
+
+    fn deref(&self) -> &[usize] {
+        match self {
+            Candidates::Inline(inds, len) => &inds[..*len],
+            Candidates::Heap(inds) => inds,
+        }
+    }
+}
+
+impl DerefMut for Candidates {
+    fn deref_mut(&mut self) -> &mut [usize] {
+        match self {
+            Candidates::Inline(inds, len) => &mut inds[..*len],
+            Candidates::Heap(inds) => inds,
+        }
+    }
+}
+
+impl FromIterator<usize> for Candidates {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let mut iter = iter.into_iter();
+        let mut inline = [0; INLINE_CANDIDATES];
+        let mut len = 0;
+        while let Some(ind) = iter.next() {
+            if len == INLINE_CANDIDATES {
+                // Spill everything to the heap
+                let mut inds = inline.to_vec();
+                inds.push(ind);
+                inds.extend(iter);
+                return Candidates::Heap(inds);
+            }
+            inline[len] = ind;
+            len += 1;
+        }
+        Candidates::Inline(inline, len)
+    }
+}
+
+impl<T: PartialEq + Eq + Sequenced> DChoiceQueue<T> {
+    /// Returns the sequence number of the front item of each sub-queue, or None for empty ones
+    pub fn front_ages(&self) -> Vec<Option<usize>> {
+        (0..self.nbr_subqueues())
+            .map(|sub| self.peek_front(sub).map(Sequenced::sequence))
+            .collect()
+    }
+
+    /// Redistributes the items over the sub-queues in enqueue order, as a global synchronization
+    /// barrier does, resetting the relaxation accumulated so far
+    ///
+    /// The i-th oldest item goes to sub-queue i modulo the number of sub-queues, so dequeuing
+    /// from the sub-queues in turn returns the items in FIFO order. The dequeue counters are all
+    /// set to the largest of them and the enqueue counters to that plus the new lengths, so the
+    /// counters no longer sum to the operations done. The items are moved without counting as
+    /// enqueues, and as the fronts are then the oldest items, every front age estimate becomes the
+    /// oldest estimate of the fronts before. Returns the number of items which changed sub-queue,
+    /// which are also counted as moves.
+    pub fn rebalance(&mut self) -> usize {
+        let nbr_subqueues = self.subqueues.len();
+        let oldest_estimate = self
+            .subqueues
+            .iter()
+            .filter(|subqueue| subqueue.len() > 0)
+            .map(|subqueue| subqueue.front_estimate)
+            .min_by(f64::total_cmp);
+        let mut items: Vec<(usize, T)> = self
+            .subqueues
+            .iter_mut()
+            .enumerate()
+            .flat_map(|(ind, subqueue)| subqueue.fifo.drain(..).map(move |item| (ind, item)))
+            .collect();
+        items.sort_by_key(|(_, item)| item.sequence());
+
+        let head = self.subqueues.iter().map(|subqueue| subqueue.head).max();
+        for subqueue in &mut self.subqueues {
+            subqueue.head = head.unwrap_or(0);
+        }
+        let mut moved = 0;
+        for (rank, (from, item)) in items.into_iter().enumerate() {
+            let to = rank % nbr_subqueues;
+            moved += usize::from(from != to);
+            self.subqueues[to].insert(item);
+        }
+        self.nonempty = 0;
+        for subqueue in &mut self.subqueues {
+            subqueue.tail = subqueue.head + subqueue.len();
+            if subqueue.len() > 0 {
+                self.nonempty += 1;
+                subqueue.front_estimate = oldest_estimate.unwrap_or(subqueue.front_estimate);
+            }
+        }
+        self.cost.moves += moved;
+        moved
+    }
+
+    /// Starts the empty queue from the steady state, with the items given from oldest to newest
+    ///
+    /// Each item gets the enqueue counter it would have had, from the head of its sub-queue, and
+    /// the items are spread so that a lower counter holds an older item, as sampling by the
+    /// counters would have left them. Returns an error if the queue is not empty, or the state
+    /// does not match the sub-queues or the number of items.
+    pub fn init_steady_state(&mut self, state: &SteadyState, items: Vec<T>) -> Result<(), String> {
+        if !self.is_empty() {
+            return Err("Can only start an empty queue from a steady state".to_string());
+        }
+        if state.heads.len() != self.subqueues.len() || state.lens.len() != self.subqueues.len() {
+            return Err(format!(
+                "The steady state has {} heads and {} lengths, but the queue has {} sub-queues",
+                state.heads.len(),
+                state.lens.len(),
+                self.subqueues.len()
+            ));
+        }
+        if items.len() != state.items() {
+            return Err(format!(
+                "The steady state holds {} items, but got {}",
+                state.items(),
+                items.len()
+            ));
+        }
+        let mut slots: Vec<(usize, usize)> = state
+            .heads
+            .iter()
+            .zip(state.lens.iter())
+            .enumerate()
+            .flat_map(|(ind, (head, len))| (*head..head + len).map(move |tail| (tail, ind)))
+            .collect();
+        slots.sort_unstable();
+        // The items count as enqueued after the dequeued ones, for the front age estimates
+        self.enqueue_count = state.heads.iter().sum();
+        for ((_, ind), item) in slots.into_iter().zip(items) {
+            self.push(ind, item);
+        }
+        for (subqueue, (head, len)) in self
+            .subqueues
+            .iter_mut()
+            .zip(state.heads.iter().zip(state.lens.iter()))
+        {
+            subqueue.head = *head;
+            subqueue.tail = head + len;
+        }
+        Ok(())
+    }
+}
+
+struct SubQueue<T: PartialEq + Eq> {
+    head: usize,
+    tail: usize,
+    fifo: VecDeque<T>,
+
+    /// The most items the sub-queue has held at once
+    high_water: usize,
+
+    /// The estimated global enqueue index of the front item, for the estimated-oldest heuristic
+    front_estimate: f64,
+}
+
+impl<T: PartialEq + Eq> SubQueue<T> {
+    fn new() -> Self {
+        Self {
+            head: 0,
+            tail: 0,
+            fifo: VecDeque::new(),
+            high_water: 0,
+            front_estimate: 0.0,
+        }
+    }
+
+    fn enqueue(&mut self, item: T) {
+        self.tail += 1;
+        self.insert(item);
+    }
+
+    /// Appends the item without counting it as an enqueue, such as when items are moved between
+    /// the sub-queues
+    fn insert(&mut self, item: T) {
+        self.fifo.push_back(item);
+        self.high_water = self.high_water.max(self.fifo.len());
+    }
+
+    fn dequeue(&mut self) -> Option<T> {
+        let ret = self.fifo.pop_front();
+        if ret.is_some() {
+            self.head += 1;
+        }
+        ret
+    }
+
+    fn len(&self) -> usize {
+        self.fifo.len()
+    }
+
+    /// The operation counter of the kind, the tail for enqueues and the head for dequeues
+    fn counter(&self, kind: OpKind) -> usize {
+        match kind {
+            OpKind::Enqueue => self.tail,
+            OpKind::Dequeue => self.head,
+        }
+    }
+}
+
+impl<T: PartialEq + Eq> RelaxedFifo<T> for DChoiceQueue<T> {
+    fn enqueue(&mut self, item: T) {
+        self.enqueue(item)
+    }
+
+    fn dequeue(&mut self) -> Option<T> {
+        self.dequeue()
+    }
+
+    fn prefill_done(&mut self) {
+        self.reset_cost_counters()
+    }
+}
+
+impl<T: PartialEq + Eq> InstrumentedFifo<T> for DChoiceQueue<T> {
+    fn nbr_subqueues(&self) -> usize {
+        self.subqueues.len()
+    }
+
+    fn set_vetoed(&mut self, subqueue: usize, vetoed: bool) {
+        if self.vetoed[subqueue] != vetoed {
+            self.vetoed[subqueue] = vetoed;
+            if vetoed {
+                self.vetoed_count += 1;
+            } else {
+                self.vetoed_count -= 1;
+            }
+        }
+    }
+
+    fn take_choice(&mut self) -> Option<usize> {
+        self.last_choice.take()
+    }
+
+    fn subqueue_high_water_marks(&self) -> Vec<usize> {
+        self.subqueue_high_water_marks()
+    }
+
+    fn counter_gaps(&self) -> (usize, usize) {
+        let gap = |counter: fn(&SubQueue<T>) -> usize| {
+            let counters = self.subqueues.iter().map(counter);
+            counters.clone().max().unwrap_or(0) - counters.min().unwrap_or(0)
+        };
+        (gap(|subqueue| subqueue.tail), gap(|subqueue| subqueue.head))
+    }
+
+    fn final_state_report(&self) -> FinalStateReport {
+        self.final_state_report()
+    }
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn set_empty_lin(&mut self, empty_lin: bool) -> bool {
+        std::mem::replace(&mut self.empty_lin, empty_lin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn helping_queue(threshold: usize, seed: u64) -> DChoiceQueue<usize> {
+        DChoiceQueueBuilder::new(8)
+            .d(2)
+            .helping_threshold(Some(threshold))
+            .seed(Some(seed))
+            .build()
+    }
+
+    #[test]
+    fn validate_rejects_invalid_configurations() {
+        let adaptive = AdaptiveD {
+            min: 1,
+            max: 4,
+            upper: 2.0,
+            lower: 1.0,
+            weight: 0.1,
+        };
+        let invalid = [
+            (DChoiceQueueBuilder::new(0), "at least one sub-queue"),
+            (
+                DChoiceQueueBuilder::new(4).heuristic(Heuristic::EstimatedOldest { weight: 0.0 }),
+                "estimate weight",
+            ),
+            (
+                DChoiceQueueBuilder::new(4).heuristic(Heuristic::EstimatedOldest { weight: 1.5 }),
+                "estimate weight",
+            ),
+            (
+                DChoiceQueueBuilder::new(4).heuristic(Heuristic::EstimatedOldest { weight: -0.5 }),
+                "estimate weight",
+            ),
+            (
+                DChoiceQueueBuilder::new(4)
+                    .heuristic(Heuristic::EstimatedOldest { weight: f64::NAN }),
+                "estimate weight",
+            ),
+            (
+                DChoiceQueueBuilder::new(4).heuristic(Heuristic::Softmax { temperature: 0.0 }),
+                "softmax temperature",
+            ),
+            (
+                DChoiceQueueBuilder::new(4).heuristic(Heuristic::Softmax { temperature: -1.0 }),
+                "softmax temperature",
+            ),
+            (
+                DChoiceQueueBuilder::new(4).heuristic(Heuristic::Softmax {
+                    temperature: f64::NAN,
+                }),
+                "softmax temperature",
+            ),
+            (
+                DChoiceQueueBuilder::new(4).heuristic(Heuristic::Softmax {
+                    temperature: f64::INFINITY,
+                }),
+                "softmax temperature",
+            ),
+            (
+                DChoiceQueueBuilder::new(4).heuristic(Heuristic::CoarseCounter { granularity: 0 }),
+                "granularity",
+            ),
+            (
+                DChoiceQueueBuilder::new(4).adaptive_d(Some(AdaptiveD { min: 0, ..adaptive })),
+                "0 < min <= max",
+            ),
+            (
+                DChoiceQueueBuilder::new(4).adaptive_d(Some(AdaptiveD { min: 5, ..adaptive })),
+                "0 < min <= max",
+            ),
+            (
+                DChoiceQueueBuilder::new(4).adaptive_d(Some(AdaptiveD {
+                    lower: 3.0,
+                    ..adaptive
+                })),
+                "lower skew threshold",
+            ),
+            (
+                DChoiceQueueBuilder::new(4)
+                    .adaptive_d(Some(adaptive))
+                    .partition(true),
+                "adaptive d",
+            ),
+            (DChoiceQueueBuilder::new(4).d(0), "d must be at least 1"),
+            (
+                DChoiceQueueBuilder::new(4).dequeue_d(0),
+                "d must be at least 1",
+            ),
+            (
+                DChoiceQueueBuilder::new(4).d(5).uniques(true),
+                "Unique sampling",
+            ),
+            (
+                DChoiceQueueBuilder::new(4)
+                    .adaptive_d(Some(AdaptiveD { max: 5, ..adaptive }))
+                    .uniques(true),
+                "Unique sampling",
+            ),
+            (
+                DChoiceQueueBuilder::new(4).d(1).memory(true),
+                "Memory sampling needs d >= 2",
+            ),
+            (
+                DChoiceQueueBuilder::new(4).dequeue_d(1).memory(true),
+                "Memory sampling needs d >= 2",
+            ),
+            (
+                DChoiceQueueBuilder::new(4)
+                    .adaptive_d(Some(adaptive))
+                    .memory(true),
+                "Memory sampling needs d >= 2",
+            ),
+            (DChoiceQueueBuilder::new(5).paired(true), "even number"),
+            (
+                DChoiceQueueBuilder::new(4).paired(true).memory(true),
+                "Paired sampling can not",
+            ),
+            (DChoiceQueueBuilder::new(4).adversary(true).d(3), "d <= 2"),
+            (
+                DChoiceQueueBuilder::new(ADVERSARY_MAX_SUBQUEUES + 1).adversary(true),
+                "d <= 2",
+            ),
+            (
+                DChoiceQueueBuilder::new(4)
+                    .adversary(true)
+                    .heuristic(Heuristic::Softmax { temperature: 1.0 }),
+                "softmax",
+            ),
+            (
+                DChoiceQueueBuilder::new(4).adversary(true).zipf(Some(1.0)),
+                "adversary can not",
+            ),
+            (
+                DChoiceQueueBuilder::new(4).d(3).partition(true),
+                "empty chunk",
+            ),
+        ];
+        for (builder, expected) in invalid {
+            let err = builder.validate().expect_err(expected);
+            assert!(
+                err.contains(expected),
+                "{err:?} does not mention {expected:?}"
+            );
+        }
+        for valid in [
+            DChoiceQueueBuilder::new(1),
+            DChoiceQueueBuilder::new(4).d(4).uniques(true),
+            DChoiceQueueBuilder::new(4).d(2).partition(true),
+            DChoiceQueueBuilder::new(4).paired(true),
+            DChoiceQueueBuilder::new(4).adversary(true),
+            DChoiceQueueBuilder::new(4).adaptive_d(Some(adaptive)),
+            DChoiceQueueBuilder::new(4).heuristic(Heuristic::EstimatedOldest { weight: 1.0 }),
+        ] {
+            assert_eq!(valid.validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn skew_cap_bounds_the_counter_spread() {
+        let spread =
+            |counters: Vec<usize>| counters.iter().max().unwrap() - counters.iter().min().unwrap();
+        for seed in 0..10 {
+            for (cap, d) in [(1, 1), (2, 2), (5, 1)] {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(8)
+                    .d(d)
+                    .heuristic(Heuristic::Length)
+                    .skew_cap(Some(cap))
+                    .seed(Some(seed))
+                    .build();
+                // A large prefill keeps the sub-queues non-empty, so the dequeues never fall back
+                for item in 0..1000 {
+                    queue.enqueue(item);
+                }
+                for item in 1000..3000 {
+                    if rng.gen_bool(0.5) {
+                        queue.enqueue(item);
+                    } else {
+                        assert!(!queue.dequeue_with_info().fell_back);
+                    }
+                    assert!(spread(queue.subqueue_enqueue_counts()) <= cap);
+                    assert!(spread(queue.subqueue_dequeue_counts()) <= cap);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn skew_cap_counts_the_redirects() {
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(4)
+            .d(1)
+            .skew_cap(Some(2))
+            .log_choices(Some(usize::MAX))
+            .seed(Some(6))
+            .build();
+        for item in 0..500 {
+            queue.enqueue(item);
+        }
+        for _ in 0..200 {
+            queue.dequeue();
+        }
+        // A single sample is only left for the least used sub-queue by a redirect
+        let redirects = queue
+            .choice_log()
+            .unwrap()
+            .iter()
+            .filter(|record| record.chosen != Some(record.sampled[0].0))
+            .count();
+        assert!(redirects > 0);
+        assert_eq!(queue.skew_cap_triggers(), redirects);
+
+        let mut uncapped: DChoiceQueue<usize> = DChoiceQueueBuilder::new(4)
+            .d(1)
+            .skew_cap(Some(10_000))
+            .build();
+        for item in 0..500 {
+            uncapped.enqueue(item);
+        }
+        assert_eq!(uncapped.skew_cap_triggers(), 0);
+    }
+
+    #[test]
+    fn skew_cap_only_redirects_past_the_cap() {
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(3).skew_cap(Some(2)).build();
+        for subqueue in [0, 0, 2] {
+            queue.enqueue_at(subqueue, 0);
+        }
+        // A third enqueue into sub-queue 0 would leave it 3 ahead of the first least used one
+        assert_eq!(queue.apply_skew_cap(0, OpKind::Enqueue), 1);
+        assert_eq!(queue.apply_skew_cap(1, OpKind::Enqueue), 1);
+        assert_eq!(queue.apply_skew_cap(2, OpKind::Enqueue), 2);
+        // The dequeue counters are capped on their own
+        assert_eq!(queue.apply_skew_cap(0, OpKind::Dequeue), 0);
+        assert_eq!(queue.skew_cap_triggers(), 1);
+    }
+
+    #[test]
+    fn redirected_choices_reach_every_policy() {
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(8)
+            .memory(true)
+            .recency_penalty(0.5)
+            .skew_cap(Some(1))
+            .adaptive_d(Some(AdaptiveD {
+                min: 2,
+                max: 4,
+                upper: 0.5,
+                lower: 0.25,
+                weight: 0.5,
+            }))
+            .trace_choices(true)
+            .seed(Some(27))
+            .build();
+        let kind = OpKind::Enqueue as usize;
+        for item in 0..300 {
+            queue.enqueue(item);
+            let chosen = *traced_enqueues(&queue).last().unwrap();
+            assert_eq!(queue.take_choice(), Some(chosen));
+            assert_eq!(queue.remembered[kind], Some(chosen));
+            assert_eq!(queue.last_chosen[kind][chosen], queue.op_count);
+            // A cap of 1 only lets the least used sub-queues be chosen, so there is no skew to
+            // raise d for
+            assert_eq!(queue.current_d(OpKind::Enqueue), 2);
+        }
+        assert!(queue.skew_cap_triggers() > 0);
+    }
+
+    #[test]
+    fn memory_samples_the_previous_choice() {
+        for layout_seed in [None, Some(3)] {
+            let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(8)
+                .d(2)
+                .memory(true)
+                .permute_subqueues(layout_seed)
+                .log_choices(Some(400))
+                .seed(Some(4))
+                .build();
+            for item in 0..200 {
+                queue.enqueue(item);
+            }
+            for item in 200..300 {
+                queue.enqueue(item);
+                queue.dequeue();
+            }
+
+            let log = queue.choice_log().unwrap();
+            for kind in [OpKind::Enqueue, OpKind::Dequeue] {
+                let records: Vec<&ChoiceRecord> =
+                    log.iter().filter(|record| record.kind == kind).collect();
+                assert_eq!(records[0].sampled.len(), 2);
+                for pair in records.windows(2) {
+                    // No sub-queue runs empty, so the dequeues never forget their choice
+                    assert_eq!(pair[1].sampled.len(), 2);
+                    assert_eq!(pair[1].sampled[0].0, pair[0].chosen.unwrap());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn softmax_is_greedy_when_cold_and_uniform_when_hot() {
+        let inds = [3, 1, 2];
+        let scores = [2.0, 0.0, 1.0];
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(4)
+            .heuristic(Heuristic::Softmax { temperature: 1.0 })
+            .seed(Some(5))
+            .build();
+        for _ in 0..1000 {
+            assert_eq!(queue.softmax_choice(&inds, &scores, 1e-3), Some(1));
+        }
+
+        let samples = 30000;
+        let mut counts = [0; 4];
+        for _ in 0..samples {
+            counts[queue.softmax_choice(&inds, &scores, 1e6).unwrap()] += 1;
+        }
+        assert_eq!(counts[0], 0);
+        for ind in inds {
+            let frequency = counts[ind] as f64 / samples as f64;
+            assert!(
+                (frequency - 1.0 / 3.0).abs() < 0.02,
+                "Sub-queue {ind} was chosen {frequency} of the time"
+            );
+        }
+    }
+
+    #[test]
+    fn zipf_distinct_samples_are_unique() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for (subqueues, s, d) in [(64, 8.0, 64), (64, 8.0, 63), (16, 1.0, 4), (5, 0.0, 5)] {
+            let zipf = ZipfSampler::new(subqueues, s, &mut rng);
+            for _ in 0..100 {
+                let mut sampled = zipf.sample_distinct(d, &mut rng);
+                assert_eq!(sampled.len(), d);
+                sampled.sort_unstable();
+                sampled.dedup();
+                assert_eq!(sampled.len(), d, "Repeated sub-queues for s {s}");
+                assert!(sampled.iter().all(|ind| *ind < subqueues));
+            }
+        }
+    }
+
+    #[test]
+    fn zipf_distinct_samples_follow_weights() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let zipf = ZipfSampler::new(64, 8.0, &mut rng);
+        // Sampling the first two ranks in order takes many rejections, as the first one holds
+        // nearly all weight, so this also covers picking from the remaining weights
+        let total = zipf.cumulative[63];
+        let expected = zipf.weight(0) / total * zipf.weight(1) / (total - zipf.weight(0));
+        let samples = 4000;
+        let ordered = (0..samples)
+            .filter(|_| zipf.sample_distinct(2, &mut rng) == zipf.order[..2])
+            .count();
+        let frequency = ordered as f64 / samples as f64;
+        assert!(
+            (frequency - expected).abs() < 0.02,
+            "The first two ranks came out in order {frequency} of the time, not {expected}"
+        );
+    }
+
+    fn sequenced_queue(seed: u64) -> DChoiceQueue<usize> {
+        DChoiceQueueBuilder::new(4)
+            .d(2)
+            .seed(Some(seed))
+            .build_sequenced()
+    }
+
+    #[test]
+    fn final_state_counts_stranded_items() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let operations: Vec<bool> = (0..2000).map(|_| rng.gen_bool(0.5)).collect();
+        let (_, report) =
+            crate::analyze_extra_final_state(&mut sequenced_queue(2), 50, &operations, None)
+                .unwrap();
+        let remaining: usize = report.subqueues.iter().map(|state| state.remaining).sum();
+        let enqueued = 50 + operations.iter().filter(|op| **op).count();
+        assert!(remaining > 0);
+        let stranded = report.stranded.expect("The queue is sequenced");
+        assert!(stranded > 0 && stranded < remaining);
+        // The items older than the newest remaining ones are the stranded ones
+        let older = report
+            .subqueues
+            .iter()
+            .filter_map(|state| state.min_id)
+            .filter(|id| *id < enqueued - remaining)
+            .count();
+        assert!(older > 0);
+
+        // Numbering the items from elsewhere only shifts the ids
+        let mut shifted = sequenced_queue(2);
+        crate::analyze_extra_from(&mut shifted, 50, &operations, None, 1000).unwrap();
+        let shifted_report = shifted.final_state_report();
+        assert_eq!(shifted_report.stranded, Some(stranded));
+        for (state, shifted_state) in report.subqueues.iter().zip(&shifted_report.subqueues) {
+            assert_eq!(state.remaining, shifted_state.remaining);
+            assert_eq!(state.min_id.map(|id| id + 1000), shifted_state.min_id);
+            assert_eq!(state.max_id.map(|id| id + 1000), shifted_state.max_id);
+        }
+    }
+
+    #[test]
+    fn final_state_of_unsequenced_queue_has_counts() {
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(4).seed(Some(1)).build();
+        for item in 0..10 {
+            queue.enqueue(item);
+        }
+        let report = InstrumentedFifo::final_state_report(&queue);
+        assert_eq!(report.stranded, None);
+        assert_eq!(
+            report
+                .subqueues
+                .iter()
+                .map(|state| state.remaining)
+                .sum::<usize>(),
+            10
+        );
+        assert!(report.subqueues.iter().all(|state| state.min_id.is_none()));
+    }
+
+    fn queue_items(queue: &DChoiceQueue<usize>) -> Vec<usize> {
+        let mut items: Vec<usize> = (0..queue.nbr_subqueues())
+            .flat_map(|sub| queue.subqueue_items(sub).copied())
+            .collect();
+        items.sort_unstable();
+        items
+    }
+
+    #[test]
+    fn rebalance_moves_items_without_enqueuing() {
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(5)
+            .heuristic(Heuristic::EstimatedOldest { weight: 0.5 })
+            .seed(Some(1))
+            .build_sequenced();
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut next = 0;
+        for _ in 0..300 {
+            if rng.gen_bool(0.6) {
+                queue.enqueue(next);
+                next += 1;
+            } else {
+                queue.dequeue();
+            }
+        }
+        let items = queue_items(&queue);
+        let enqueue_count = queue.enqueue_count;
+
+        let moved = queue.rebalance();
+        assert!(moved > 0);
+        assert_eq!(queue_items(&queue), items);
+        assert_eq!(queue.enqueue_count, enqueue_count);
+        let lens: Vec<usize> = (0..5).map(|sub| queue.subqueue_len(sub).unwrap()).collect();
+        assert!(lens.iter().max().unwrap() - lens.iter().min().unwrap() <= 1);
+        assert_eq!(queue.nonempty_subqueues(), 5);
+        // The sub-queues now return the items in FIFO order when dequeued in turn
+        assert_eq!(
+            queue.front_ages(),
+            items[..5].iter().map(|id| Some(*id)).collect::<Vec<_>>()
+        );
+        // The fronts are equally old, so the estimates agree
+        let estimates: Vec<f64> = queue
+            .subqueues
+            .iter()
+            .map(|sub| sub.front_estimate)
+            .collect();
+        assert!(estimates.iter().all(|estimate| *estimate == estimates[0]));
+        let (enqueue_gap, dequeue_gap) = InstrumentedFifo::counter_gaps(&queue);
+        assert!(enqueue_gap <= 1);
+        assert_eq!(dequeue_gap, 0);
+    }
+
+    #[test]
+    fn helping_conserves_items() {
+        for seed in 0..10 {
+            let mut queue = helping_queue(0, seed);
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut next = 0;
+            let mut dequeued = vec![];
+            for _ in 0..2000 {
+                if rng.gen_bool(0.5) {
+                    queue.enqueue(next);
+                    next += 1;
+                } else if let Some(item) = queue.dequeue() {
+                    dequeued.push(item);
+                }
+                assert_eq!(queue.len(), next - dequeued.len());
+            }
+            assert!(queue.helps() > 0, "Helping never triggered");
+            while let Some(item) = queue.dequeue() {
+                dequeued.push(item);
+            }
+            assert!(queue.is_empty());
+            dequeued.sort_unstable();
+            assert_eq!(dequeued, (0..next).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn helping_keeps_subqueues_fifo() {
+        for seed in 0..10 {
+            let mut queue = helping_queue(0, seed);
+            let mut last_from = vec![None; queue.nbr_subqueues()];
+            for item in 0..500 {
+                queue.enqueue(item);
+            }
+            while let DequeueInfo {
+                item: Some(item),
+                subqueue,
+                ..
+            } = queue.dequeue_with_info()
+            {
+                assert!(
+                    last_from[subqueue] < Some(item),
+                    "Sub-queue {subqueue} returned {item} after {:?}",
+                    last_from[subqueue]
+                );
+                last_from[subqueue] = Some(item);
+            }
+            assert!(queue.helps() > 0, "Helping never triggered");
+        }
+    }
 
-    /// If true, cannot sample the same sub-queue several times for one d-choice
-    uniques: bool,
+    #[test]
+    fn helped_item_is_returned_next() {
+        let mut queue = helping_queue(0, 3);
+        for item in 0..100 {
+            queue.enqueue(item);
+        }
+        loop {
+            let helps = queue.helps();
+            let first = queue.dequeue_with_info();
+            assert!(first.item.is_some(), "Helping never triggered");
+            if queue.helps() > helps {
+                let buffered = queue.dequeue_with_info();
+                assert!(buffered.item.is_some());
+                assert_ne!(buffered.subqueue, first.subqueue);
+                // The buffered item is handed out without helping again
+                assert_eq!(queue.helps(), helps + 1);
+                break;
+            }
+        }
+    }
 
-    /// If true, uses the new operation-based heuristic, otherwise the length-based
-    progress_heuristic: bool,
+    #[test]
+    fn dequeue_batches_of_one_match_dequeues() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut batched = sequenced_queue(4);
+        let mut single = sequenced_queue(4);
+        for item in 0..2000 {
+            if rng.gen_bool(0.5) {
+                batched.enqueue(item);
+                single.enqueue(item);
+            } else {
+                assert_eq!(
+                    batched.dequeue_many(1),
+                    single.dequeue().into_iter().collect::<Vec<_>>()
+                );
+            }
+        }
+        assert_eq!(batched.cost_report(), single.cost_report());
+    }
 
-    /// If true, uses round robin when finding an empty queue
-    empty_lin: bool,
+    #[test]
+    fn dequeue_batches_return_every_item_once() {
+        for b in [2, 3, 8] {
+            let mut queue = sequenced_queue(b as u64);
+            let mut rng = StdRng::seed_from_u64(b as u64);
+            let mut next = 0;
+            let mut dequeued = vec![];
+            for _ in 0..2000 {
+                if rng.gen_bool(0.6) {
+                    queue.enqueue(next);
+                    next += 1;
+                } else {
+                    let len = queue.len();
+                    let batch = queue.dequeue_many(b);
+                    // Empty sub-queues are scanned past, so batches are only short when the queue runs
+                    // out
+                    assert_eq!(batch.len(), b.min(len));
+                    dequeued.extend(batch);
+                }
+            }
+            while !queue.is_empty() {
+                dequeued.extend(queue.dequeue_many(b));
+            }
+            dequeued.sort_unstable();
+            assert_eq!(dequeued, (0..next).collect::<Vec<_>>());
+        }
+    }
 
-    /// If true, partitions sub-queues into d chunks and selects one queue from each each time
-    partition: bool,
+    #[test]
+    fn enqueue_batches_of_one_match_enqueues() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut batched = sequenced_queue(5);
+        let mut single = sequenced_queue(5);
+        for item in 0..2000 {
+            if rng.gen_bool(0.5) {
+                batched.enqueue_many(vec![item]);
+                single.enqueue(item);
+            } else {
+                assert_eq!(batched.dequeue(), single.dequeue());
+            }
+        }
+        assert_eq!(queue_items(&batched), queue_items(&single));
+        assert_eq!(batched.cost_report(), single.cost_report());
+    }
 
-    /// If true, prefers left queue when tie-breaking
-    left: bool,
-}
+    #[test]
+    fn enqueue_batches_land_together_in_order() {
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(4)
+            .d(2)
+            .trace_choices(true)
+            .seed(Some(6))
+            .build();
+        for batch in 0..50 {
+            let lens: Vec<usize> = (0..4).map(|sub| queue.subqueue_len(sub).unwrap()).collect();
+            queue.enqueue_many((3 * batch..3 * batch + 3).collect());
 
-impl<T: PartialEq + Eq> DChoiceQueue<T> {
-    pub fn new(
-        nbr_subqueues: usize,
-        d: usize,
-        uniques: bool,
-        progress_heuristic: bool,
-        empty_lin: bool,
-        partition: bool,
-        left: bool,
-    ) -> Self {
-        Self {
-            subqueues: (0..nbr_subqueues).map(|_| SubQueue::new()).collect(),
-            d,
-            uniques,
-            progress_heuristic,
-            empty_lin,
-            partition,
-            left,
+            let Some(&TraceEvent::Enqueue { subqueue }) = queue.choice_trace().unwrap().last()
+            else {
+                panic!("The batch was not traced as enqueues");
+            };
+            for (sub, len) in lens.into_iter().enumerate() {
+                let added = usize::from(sub == subqueue) * 3;
+                assert_eq!(queue.subqueue_len(sub).unwrap(), len + added);
+            }
+            let tail: Vec<usize> = queue.subqueue_items(subqueue).copied().collect();
+            assert_eq!(
+                tail[tail.len() - 3..],
+                [3 * batch, 3 * batch + 1, 3 * batch + 2]
+            );
         }
+        assert_eq!(queue.cost_report().operations, 50);
+        assert_eq!(queue.choice_trace().unwrap().len(), 150);
     }
 
-    /// Enqueues an item into the queue
-    pub fn enqueue(&mut self, item: T) {
-        // Find subqueue ind, depending on heuristic used (this is not super optimized)
-        let subqueue_ind = if self.progress_heuristic {
-            self.subqueue_inds()
-                .into_iter()
-                .min_by_key(|ind| (self.subqueues[*ind].tail, if self.left { *ind } else { 0 }))
-        } else {
-            self.subqueue_inds().into_iter().min_by_key(|ind| {
-                (
-                    self.subqueues[*ind].tail - self.subqueues[*ind].head,
-                    if self.left { *ind } else { 0 },
-                )
-            })
+    #[test]
+    fn front_estimate_converges_on_a_steady_queue() {
+        // With 10 items in the queue, the enqueue indices average 9 behind the newest with weight
+        // 0.1, which is the index of the front item
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(1)
+            .heuristic(Heuristic::EstimatedOldest { weight: 0.1 })
+            .build();
+        for item in 0..10 {
+            queue.enqueue(item);
         }
-        .expect("Should always be able to find an index if d>0");
+        let error = |queue: &DChoiceQueue<usize>| {
+            (queue.subqueues[0].front_estimate - *queue.peek_front(0).unwrap() as f64).abs()
+        };
+        let initial = error(&queue);
+        assert!(initial > 1.0);
+        for item in 10..300 {
+            queue.enqueue(item);
+            queue.dequeue();
+        }
+        assert!(error(&queue) < 1e-6 * initial, "{}", error(&queue));
 
-        self.subqueues[subqueue_ind].enqueue(item);
+        // Emptying the sub-queue restarts the estimate at the next item
+        while queue.dequeue().is_some() {}
+        queue.enqueue(300);
+        assert_eq!(
+            queue.subqueues[0].front_estimate,
+            queue.enqueue_count as f64 - 1.0
+        );
     }
 
-    pub fn dequeue(&mut self) -> Option<T> {
-        // Find subqueue ind, depending on heuristic used (this is not super optimized)
-        let subqueue_ind = if self.progress_heuristic {
-            self.subqueue_inds()
-                .into_iter()
-                .min_by_key(|ind| (self.subqueues[*ind].head, if self.left { *ind } else { 0 }))
-        } else {
-            self.subqueue_inds().into_iter().max_by_key(|ind| {
-                (
-                    self.subqueues[*ind].tail - self.subqueues[*ind].head,
-                    if self.left {
-                        self.nbr_subqueues() - *ind
-                    } else {
-                        0
-                    },
-                )
+    #[test]
+    fn front_estimate_with_full_weight_is_the_newest() {
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(1)
+            .heuristic(Heuristic::EstimatedOldest { weight: 1.0 })
+            .build();
+        for item in 0..20 {
+            queue.enqueue(item);
+            assert_eq!(queue.subqueues[0].front_estimate, item as f64);
+        }
+    }
+
+    fn blacked_out_queue(blackouts: Vec<Blackout>) -> DChoiceQueue<usize> {
+        DChoiceQueueBuilder::new(8)
+            .d(2)
+            .candidate_filter(Some(Box::new(blackouts)))
+            .trace_choices(true)
+            .seed(Some(7))
+            .build()
+    }
+
+    fn traced_enqueues(queue: &DChoiceQueue<usize>) -> Vec<usize> {
+        queue
+            .choice_trace()
+            .unwrap()
+            .iter()
+            .filter_map(|event| match event {
+                TraceEvent::Enqueue { subqueue } => Some(*subqueue),
+                _ => None,
             })
+            .collect()
+    }
+
+    #[test]
+    fn filtered_candidates_fall_back_to_the_allowed_subqueue() {
+        // Only sub-queue 3 is allowed during the first 100 operations, so most samples miss it
+        let blackouts = (0..8)
+            .filter(|subqueue| *subqueue != 3)
+            .map(|subqueue| Blackout {
+                subqueue,
+                start: 0,
+                end: 100,
+            })
+            .collect();
+        let mut queue = blacked_out_queue(blackouts);
+        for item in 0..200 {
+            queue.enqueue(item);
         }
-        .expect("Should always be able to find an index if d>0");
+        let enqueues = traced_enqueues(&queue);
+        assert!(enqueues[..100].iter().all(|subqueue| *subqueue == 3));
+        assert!(enqueues[100..].iter().any(|subqueue| *subqueue != 3));
+        // Some operations resampled too often and scanned for the allowed sub-queue
+        let scans = queue.cost_report().scans;
+        assert!(scans > 0 && scans < 100, "{scans}");
+    }
 
-        match self.subqueues[subqueue_ind].dequeue() {
-            None if self.empty_lin => {
-                let mut ind = subqueue_ind;
-                for _ in 0..self.subqueues.len() - 1 {
-                    ind = (ind + 1) % self.subqueues.len();
-                    if self.subqueues[ind].len() > 0 {
-                        return self.subqueues[ind].dequeue();
-                    }
-                }
-                None
+    #[test]
+    fn filtering_out_every_subqueue_chooses_unfiltered() {
+        let blackouts = (0..8)
+            .map(|subqueue| Blackout {
+                subqueue,
+                start: 0,
+                end: 100,
+            })
+            .collect();
+        let mut queue = blacked_out_queue(blackouts);
+        for item in 0..100 {
+            queue.enqueue(item);
+        }
+        // Every operation resampled and scanned in vain, and then chose as if unfiltered
+        assert_eq!(queue.cost_report().scans, 100);
+        assert_eq!(queue.len(), 100);
+        let mut used = traced_enqueues(&queue);
+        used.sort_unstable();
+        used.dedup();
+        assert_eq!(used, (0..8).collect::<Vec<_>>());
+    }
+
+    /// Six sub-queues where 2 holds one item after a dequeue, 3 the most items after two
+    /// dequeues, and 4 two items without dequeues, so each fallback policy picks a different one
+    fn fallback_queue(policy: FallbackPolicy) -> DChoiceQueue<usize> {
+        let mut queue = DChoiceQueueBuilder::new(6)
+            .fallback_policy(policy)
+            .seed(Some(8))
+            .build();
+        for (subqueue, enqueues, dequeues) in [(2, 2, 1), (3, 5, 2), (4, 2, 0)] {
+            for item in 0..enqueues {
+                queue.enqueue_at(subqueue, item);
+            }
+            for _ in 0..dequeues {
+                queue.dequeue_at(subqueue);
             }
-            otherwise => otherwise,
         }
+        queue
     }
 
-    /// As dequeue, but also returns the number of successfull dequeues on the chosen sub-queue
-    pub fn dequeue_with_info(&mut self) -> (Option<T>, usize) {
-        // Find sub-queue ind, depending on heuristic used (this is not super optimized)
-        let subqueue_ind = if self.progress_heuristic {
-            self.subqueue_inds()
-                .into_iter()
-                .min_by_key(|ind| (self.subqueues[*ind].head, if self.left { *ind } else { 0 }))
-        } else {
-            self.subqueue_inds().into_iter().max_by_key(|ind| {
-                (
-                    self.subqueues[*ind].tail - self.subqueues[*ind].head,
-                    if self.left {
-                        self.nbr_subqueues() - *ind
-                    } else {
-                        0
-                    },
-                )
-            })
+    #[test]
+    fn fallback_policies_choose_their_subqueue() {
+        for (policy, expected) in [
+            (FallbackPolicy::FirstNonEmpty, 2),
+            (FallbackPolicy::LongestQueue, 3),
+            (FallbackPolicy::OldestHead, 4),
+            (FallbackPolicy::Hinted, 2),
+        ] {
+            let info = fallback_queue(policy).dequeue_at(0);
+            assert!(info.item.is_some() && info.fell_back, "{policy:?}");
+            assert_eq!(info.subqueue, expected, "{policy:?}");
         }
-        .expect("Should always be able to find an index if d>0");
+    }
 
-        match self.subqueues[subqueue_ind].dequeue() {
-            None if self.empty_lin => {
-                let mut ind = subqueue_ind;
-                for _ in 0..self.subqueues.len() - 1 {
-                    ind = (ind + 1) % self.subqueues.len();
-                    if self.subqueues[ind].len() > 0 {
-                        return (self.subqueues[ind].dequeue(), self.subqueues[ind].head);
+    #[test]
+    fn hinted_fallback_scans_from_the_last_fallback() {
+        for (policy, expected) in [
+            (FallbackPolicy::FirstNonEmpty, 1),
+            (FallbackPolicy::Hinted, 3),
+        ] {
+            let mut queue = fallback_queue(policy);
+            // Empties sub-queue 2, which becomes the hint
+            assert_eq!(queue.dequeue_at(0).subqueue, 2);
+            queue.enqueue_at(1, 10);
+            assert_eq!(queue.dequeue_at(0).subqueue, expected, "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn fallback_without_items_returns_empty() {
+        for policy in [
+            FallbackPolicy::FirstNonEmpty,
+            FallbackPolicy::LongestQueue,
+            FallbackPolicy::OldestHead,
+            FallbackPolicy::Hinted,
+        ] {
+            let mut queue: DChoiceQueue<usize> =
+                DChoiceQueueBuilder::new(6).fallback_policy(policy).build();
+            let info = queue.dequeue_at(3);
+            assert_eq!((info.item, info.subqueue, info.fell_back), (None, 3, true));
+        }
+    }
+
+    #[test]
+    fn adaptive_d_holds_inside_the_band() {
+        // Without averaging, the estimate is the skew of the last choice
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(4)
+            .adaptive_d(Some(AdaptiveD {
+                min: 1,
+                max: 3,
+                upper: 2.0,
+                lower: 1.0,
+                weight: 1.0,
+            }))
+            .build();
+        assert_eq!(queue.current_d(OpKind::Enqueue), 1);
+        for (subqueue, items) in [(0, 3), (2, 2), (3, 1)] {
+            for item in 0..items {
+                queue.enqueue_at(subqueue, item);
+            }
+        }
+        let mut adapt = |chosen: usize| {
+            queue.adapt_d(chosen, OpKind::Enqueue);
+            queue.current_d(OpKind::Enqueue)
+        };
+
+        // A skew of 3 is above the band, and moves d up until the max
+        assert_eq!([adapt(0), adapt(0), adapt(0)], [2, 3, 3]);
+        // Skews of 2 and 1 are on the edges of the band, which keep d
+        assert_eq!([adapt(2), adapt(3), adapt(2)], [3, 3, 3]);
+        // A skew of 0 is below the band, and moves d down until the min
+        assert_eq!([adapt(1), adapt(1), adapt(1)], [2, 1, 1]);
+        // and the band keeps it there
+        assert_eq!([adapt(3), adapt(2)], [1, 1]);
+        // The dequeue d adapts by its own counters
+        assert_eq!(queue.current_d(OpKind::Dequeue), 1);
+    }
+
+    #[test]
+    fn adaptive_d_averages_the_skews() {
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(4)
+            .adaptive_d(Some(AdaptiveD {
+                min: 1,
+                max: 4,
+                upper: 2.0,
+                lower: 1.0,
+                weight: 0.5,
+            }))
+            .build();
+        for item in 0..4 {
+            queue.enqueue_at(0, item);
+        }
+        // The estimates are 2, then 3, so only the second skew of 4 crosses the upper threshold
+        queue.adapt_d(0, OpKind::Enqueue);
+        assert_eq!(queue.current_d(OpKind::Enqueue), 1);
+        queue.adapt_d(0, OpKind::Enqueue);
+        assert_eq!(queue.current_d(OpKind::Enqueue), 2);
+        // The estimates 1.5 and 0.75 of skews of 0, where only the second is below the band
+        queue.adapt_d(1, OpKind::Enqueue);
+        assert_eq!(queue.current_d(OpKind::Enqueue), 2);
+        queue.adapt_d(1, OpKind::Enqueue);
+        assert_eq!(queue.current_d(OpKind::Enqueue), 1);
+    }
+
+    #[test]
+    fn cost_counts_are_exact() {
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(4)
+            .d(2)
+            .log_choices(Some(usize::MAX))
+            .seed(Some(9))
+            .build();
+        for item in 0..6 {
+            queue.enqueue(item);
+        }
+        let expected = CostReport {
+            operations: 6,
+            samples: 12,
+            ..Default::default()
+        };
+        assert_eq!(queue.cost_report(), expected);
+
+        // Drain the queue and dequeue twice more, which finds it empty
+        let mut infos = vec![];
+        for _ in 0..8 {
+            infos.push(queue.dequeue_with_info());
+        }
+        assert_eq!(infos.iter().filter(|info| info.item.is_some()).count(), 6);
+        let choices = &queue.choice_log().unwrap()[6..];
+        let mut scans = 0;
+        let mut scanned = 0;
+        for (info, choice) in infos.iter().zip(choices) {
+            let chosen = choice.chosen.unwrap();
+            if info.item.is_none() {
+                // An empty queue is charged a full scan past the chosen sub-queue
+                scans += 1;
+                scanned += 3;
+            } else if info.fell_back {
+                // The first non-empty sub-queue after the chosen one is this far ahead
+                scans += 1;
+                scanned += (info.subqueue + 4 - chosen) % 4;
+            }
+        }
+        assert!(scans > 2, "The seed should fall back before running empty");
+        let expected = CostReport {
+            operations: 14,
+            samples: 28,
+            scans,
+            scanned,
+            moves: 0,
+        };
+        assert_eq!(queue.cost_report(), expected);
+
+        queue.reset_cost_counters();
+        assert_eq!(queue.cost_report(), CostReport::default());
+    }
+
+    #[test]
+    fn random_indexes_are_uniform() {
+        for nbr_subqueues in [3, 5, 6, 7, 8, 12, 16, 24] {
+            let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(nbr_subqueues)
+                .seed(Some(10))
+                .build();
+            assert_eq!(
+                queue.index_mask.is_some(),
+                nbr_subqueues.is_power_of_two(),
+                "{nbr_subqueues}"
+            );
+            let draws = 2000 * nbr_subqueues;
+            let mut counts = vec![0; nbr_subqueues];
+            for _ in 0..draws {
+                counts[queue.random_index()] += 1;
+            }
+            // Far out in the tail of the chi-square distribution with n - 1 degrees of freedom
+            let expected = (draws / nbr_subqueues) as f64;
+            let chi_square: f64 = counts
+                .iter()
+                .map(|count| (*count as f64 - expected).powi(2) / expected)
+                .sum();
+            let freedom = (nbr_subqueues - 1) as f64;
+            assert!(
+                chi_square < freedom + 6.0 * (2.0 * freedom).sqrt(),
+                "{nbr_subqueues}: {counts:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn other_subqueue_counts_keep_the_range_sampling() {
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(6).seed(Some(11)).build();
+        let mut rng = StdRng::seed_from_u64(11);
+        for _ in 0..100 {
+            assert_eq!(queue.random_index(), rng.gen_range(0..6));
+        }
+        assert_eq!(queue.next_index(5), 0);
+
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(8).seed(Some(11)).build();
+        assert_eq!(queue.next_index(7), 0);
+        assert!((0..100).all(|_| queue.random_index() < 8));
+    }
+
+    #[test]
+    fn paired_choice_compares_pairs_first() {
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(6)
+            .d(4)
+            .paired(true)
+            .seed(Some(12))
+            .build();
+        // The pair sums are 5, 4 and 18, while sub-queue 1 alone has the fewest enqueues
+        for (subqueue, enqueues) in [(0, 5), (2, 1), (3, 3), (4, 9), (5, 9)] {
+            for item in 0..enqueues {
+                queue.enqueue_at(subqueue, item);
+            }
+        }
+        assert_eq!(queue.paired_choice(&[0, 1, 2, 3], OpKind::Enqueue), Some(2));
+        assert_eq!(queue.paired_choice(&[4, 5, 0, 1], OpKind::Enqueue), Some(1));
+        // The pair is scored by both its sub-queues, but only its sampled ones can be chosen
+        assert_eq!(queue.paired_choice(&[1, 3], OpKind::Enqueue), Some(3));
+    }
+
+    #[test]
+    fn paired_sampling_takes_whole_pairs() {
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(8)
+            .d(3)
+            .paired(true)
+            .seed(Some(13))
+            .build();
+        for _ in 0..100 {
+            let inds = queue.subqueue_inds(OpKind::Enqueue);
+            assert_eq!(inds.len(), 4);
+            for pair in inds.chunks(2) {
+                assert!(pair[0] % 2 == 0 && pair[1] == pair[0] + 1, "{pair:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn nonempty_count_stays_in_sync() {
+        for (policy, helping) in [
+            (FallbackPolicy::Hinted, None),
+            (FallbackPolicy::Hinted, Some(0)),
+            (FallbackPolicy::FirstNonEmpty, Some(2)),
+        ] {
+            let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(8)
+                .d(2)
+                .fallback_policy(policy)
+                .helping_threshold(helping)
+                .seed(Some(14))
+                .build();
+            let mut rng = StdRng::seed_from_u64(14);
+            let mut next = 0;
+            // Drifts between nearly empty and a few items per sub-queue
+            for step in 0..5000 {
+                let enqueue_bias = if (step / 500) % 2 == 0 { 0.45 } else { 0.6 };
+                let len = queue.len();
+                match rng.gen_range(0..10) {
+                    0 => {
+                        queue.enqueue_many(vec![next, next + 1]);
+                        next += 2;
+                    }
+                    1 => {
+                        assert_eq!(queue.dequeue_many(2).len(), len.min(2));
+                    }
+                    2 => {
+                        let info = queue.dequeue_at(rng.gen_range(0..8));
+                        assert_eq!(info.item.is_some(), len > 0);
+                    }
+                    3 if step % 100 == 3 => {
+                        queue.rebalance();
+                    }
+                    _ if rng.gen_bool(enqueue_bias) => {
+                        queue.enqueue(next);
+                        next += 1;
+                    }
+                    _ => {
+                        // A fallback finds an item if and only if there is one
+                        assert_eq!(queue.dequeue().is_some(), len > 0);
                     }
                 }
-                (None, self.subqueues[subqueue_ind].head)
+                let nonempty = (0..8)
+                    .filter(|sub| queue.subqueue_len(*sub).unwrap() > 0)
+                    .count();
+                assert_eq!(queue.nonempty_subqueues(), nonempty, "{policy:?} {step}");
             }
-            otherwise => (otherwise, self.subqueues[subqueue_ind].head),
         }
     }
 
-    /// Gets sub-queue inds, depending on allowing repeats of not
-    fn subqueue_inds(&self) -> Vec<usize> {
-        let mut rng = rand::thread_rng();
-        if self.partition {
-            let psize = (self.nbr_subqueues() + self.d - 1) / self.d;
-            let mut indexes: Vec<usize> = (0..self.d)
-                .map(|part| {
-                    rng.gen_range(
-                        part * psize..std::cmp::min(psize * (part + 1), self.nbr_subqueues()),
-                    )
-                })
-                .collect();
-            indexes.shuffle(&mut rng);
-            indexes
-        } else if self.uniques {
-            (0..self.subqueues.len())
-                .collect::<Vec<usize>>()
-                .choose_multiple(&mut rand::thread_rng(), self.d)
-                .cloned()
-                .collect()
-        } else {
-            (0..self.d)
-                .map(|_| rand::thread_rng().gen_range(0..self.subqueues.len()))
-                .collect()
+    #[test]
+    fn hinted_fallback_scans_less_near_emptiness() {
+        let scanned = |policy: FallbackPolicy| {
+            let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(64)
+                .d(2)
+                .fallback_policy(policy)
+                .seed(Some(15))
+                .build();
+            // Two items at a time, so most chosen sub-queues are empty
+            for item in 0..2000 {
+                queue.enqueue(item);
+                queue.enqueue(item);
+                queue.dequeue().expect("The queue holds items");
+                queue.dequeue().expect("The queue holds items");
+            }
+            queue.cost_report().scanned
+        };
+        let hinted = scanned(FallbackPolicy::Hinted);
+        let first = scanned(FallbackPolicy::FirstNonEmpty);
+        assert!(hinted < first, "{hinted} {first}");
+    }
+
+    #[test]
+    fn adversary_picks_the_worst_subqueue() {
+        // Sampling with replacement lets the adversary offer any sub-queue twice, forcing its choice
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(6)
+            .d(2)
+            .adversary(true)
+            .build_sequenced();
+        let mut rng = StdRng::seed_from_u64(16);
+        let mut next = 0;
+        for _ in 0..1000 {
+            let items = queue_items(&queue);
+            let lens: Vec<usize> = (0..6).map(|sub| queue.subqueue_len(sub).unwrap()).collect();
+            if rng.gen_bool(0.55) {
+                queue.enqueue(next);
+                next += 1;
+                let grown = (0..6)
+                    .find(|sub| queue.subqueue_len(*sub).unwrap() > lens[*sub])
+                    .unwrap();
+                assert_eq!(lens[grown], *lens.iter().min().unwrap());
+            } else if !items.is_empty() {
+                // The rank error of each front item is the number of older items in the queue
+                let rank_error = |item: usize| items.partition_point(|other| *other < item);
+                let worst = (0..6)
+                    .filter_map(|sub| queue.peek_front(sub).copied())
+                    .map(rank_error)
+                    .max()
+                    .unwrap();
+                let item = queue.dequeue().unwrap();
+                assert_eq!(rank_error(item), worst);
+            }
         }
     }
 
-    pub fn print_skewness(&self) {
-        let (mean_head, std_head) = std(&self
-            .subqueues
-            .iter()
-            .map(|p| p.head)
-            .collect::<Vec<usize>>());
-        let (mean_tail, std_tail) = std(&self
-            .subqueues
-            .iter()
-            .map(|p| p.tail)
-            .collect::<Vec<usize>>());
-        println!("Mean head: {mean_head}, std: {std_head}");
-        println!("Mean tail: {mean_tail}, std: {std_tail}");
-        print!("Heads: ");
-        for p in self.subqueues.iter() {
-            print!(" {}", p.head);
+    #[test]
+    fn layout_is_a_seeded_permutation() {
+        for nbr_subqueues in [1, 5, 16] {
+            let layout = SubqueueLayout::new(nbr_subqueues, 17);
+            let mut subqueues = layout.subqueue.clone();
+            subqueues.sort_unstable();
+            assert_eq!(subqueues, (0..nbr_subqueues).collect::<Vec<_>>());
+            for sub in 0..nbr_subqueues {
+                assert_eq!(layout.subqueue[layout.sampling[sub]], sub);
+            }
+            assert_eq!(
+                SubqueueLayout::new(nbr_subqueues, 17).subqueue,
+                layout.subqueue
+            );
         }
-        println!("");
-        print!("Tails: ");
-        for p in self.subqueues.iter() {
-            print!(" {}", p.tail);
+        assert_ne!(
+            SubqueueLayout::new(16, 17).subqueue,
+            SubqueueLayout::new(16, 18).subqueue
+        );
+    }
+
+    #[test]
+    fn layout_orders_the_scans() {
+        let queue = |seed: u64| -> DChoiceQueue<usize> {
+            DChoiceQueueBuilder::new(8)
+                .permute_subqueues(Some(19))
+                .seed(Some(seed))
+                .build()
+        };
+        let (first, second) = (queue(1), queue(2));
+        assert_eq!(first.layout_seed(), Some(19));
+        // The layout does not depend on the seed of the random choices
+        assert_eq!(
+            first.layout.as_ref().unwrap().subqueue,
+            second.layout.as_ref().unwrap().subqueue
+        );
+
+        // The scan visits the sub-queues in the layout order, wrapping around
+        let order = &first.layout.as_ref().unwrap().subqueue;
+        let mut ind = order[0];
+        for expected in order.iter().skip(1).chain([&order[0]]) {
+            ind = first.next_index(ind);
+            assert_eq!(ind, *expected);
         }
-        println!("");
     }
 
-    pub fn nbr_subqueues(&self) -> usize {
-        self.subqueues.len()
+    #[test]
+    fn coarse_counter_publishes_every_granularity_steps() {
+        let mut counter = CoarseCounter::default();
+        let mut published = vec![];
+        for step in 0..8 {
+            counter = counter.step(3, || 10 * step);
+            published.push(counter.published);
+        }
+        assert_eq!(published, [0, 0, 0, 30, 30, 30, 60, 60]);
+
+        // The value is only read when it is published
+        let mut reads = 0;
+        let mut counter = CoarseCounter::default();
+        for _ in 0..8 {
+            counter = counter.step(4, || {
+                reads += 1;
+                0
+            });
+        }
+        assert_eq!(reads, 2);
     }
 
-    /// Returns the number of enqueues done on each sub-queue
-    pub fn subqueue_enqueue_counts(&self) -> Vec<usize> {
-        self.subqueues.iter().map(|p| p.tail).collect()
+    #[test]
+    fn coarse_counter_of_granularity_one_is_the_operation_heuristic() {
+        let queue = |heuristic: Heuristic| -> DChoiceQueue<usize> {
+            DChoiceQueueBuilder::new(8)
+                .d(2)
+                .heuristic(heuristic)
+                .trace_choices(true)
+                .seed(Some(21))
+                .build()
+        };
+        let mut coarse = queue(Heuristic::CoarseCounter { granularity: 1 });
+        let mut exact = queue(Heuristic::Operation);
+        let mut rng = StdRng::seed_from_u64(21);
+        for item in 0..2000 {
+            if rng.gen_bool(0.55) {
+                coarse.enqueue(item);
+                exact.enqueue(item);
+            } else {
+                assert_eq!(coarse.dequeue(), exact.dequeue());
+            }
+        }
+        assert_eq!(coarse.choice_trace(), exact.choice_trace());
+
+        // Coarser counters tie the candidates ahead of the published value, changing the choices
+        let mut coarser = queue(Heuristic::CoarseCounter { granularity: 16 });
+        for item in 0..2000 {
+            coarser.enqueue(item);
+        }
+        let mut exact = queue(Heuristic::Operation);
+        for item in 0..2000 {
+            exact.enqueue(item);
+        }
+        assert_ne!(coarser.choice_trace(), exact.choice_trace());
     }
 
-    /// Returns the number of dequeues done on each partial queue
-    pub fn subqueue_dequeue_counts(&self) -> Vec<usize> {
-        self.subqueues.iter().map(|p| p.head).collect()
+    /// Runs random operations, returning the items which are enqueued but not dequeued
+    fn run_random_ops(queue: &mut DChoiceQueue<usize>, seed: u64) -> Vec<usize> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut remaining = std::collections::BTreeSet::new();
+        for item in 0..1000 {
+            if rng.gen_bool(0.6) {
+                queue.enqueue(item);
+                remaining.insert(item);
+            } else if let Some(item) = queue.dequeue() {
+                assert!(remaining.remove(&item));
+            }
+        }
+        remaining.into_iter().collect()
     }
-}
 
-fn std(values: &Vec<usize>) -> (f32, f32) {
-    let mean = values.iter().cloned().sum::<usize>() as f32 / values.len() as f32;
-    let std = (values
-        .iter()
-        .map(|val| (*val as f32 - mean) * (*val as f32 - mean))
-        .sum::<f32>()
-        / values.len() as f32)
-        .sqrt();
+    #[test]
+    fn drain_returns_the_remaining_items_once() {
+        for (empty_lin, helping) in [(false, None), (true, None), (true, Some(0))] {
+            let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(8)
+                .d(2)
+                .empty_lin(empty_lin)
+                .helping_threshold(helping)
+                .seed(Some(22))
+                .build();
+            let remaining = run_random_ops(&mut queue, 22);
+            let mut drained = InstrumentedFifo::drain(&mut queue);
+            assert!(queue.is_empty());
+            assert_eq!(queue.empty_lin, empty_lin);
+            drained.sort_unstable();
+            assert_eq!(drained, remaining);
+            assert!(InstrumentedFifo::drain(&mut queue).is_empty());
+        }
+    }
 
-    (mean, std)
-}
+    #[test]
+    fn into_items_returns_the_remaining_items_by_subqueue() {
+        let mut queue = helping_queue(0, 23);
+        let mut remaining = run_random_ops(&mut queue, 23);
+        // Dequeue until an item is left in the help buffer
+        let helps = queue.helps();
+        while queue.helps() == helps {
+            let item = queue.dequeue().expect("Helping never triggered");
+            remaining.retain(|other| *other != item);
+        }
+        let (buffered, buffered_from, _) = queue.help_buffer.unwrap();
+        let subqueues: Vec<Vec<usize>> = (0..8)
+            .map(|sub| queue.subqueue_items(sub).copied().collect())
+            .collect();
 
-struct SubQueue<T: PartialEq + Eq> {
-    head: usize,
-    tail: usize,
-    fifo: VecDeque<T>,
-}
+        let items = queue.into_items();
+        for (sub, (items, expected)) in items.iter().zip(&subqueues).enumerate() {
+            if sub == buffered_from {
+                assert_eq!(items[0], buffered);
+                assert_eq!(items[1..], expected[..]);
+            } else {
+                assert_eq!(items, expected);
+            }
+        }
+        let mut items: Vec<usize> = items.into_iter().flatten().collect();
+        items.sort_unstable();
+        assert_eq!(items, remaining);
+    }
 
-impl<T: PartialEq + Eq> SubQueue<T> {
-    fn new() -> Self {
-        Self {
-            head: 0,
-            tail: 0,
-            fifo: VecDeque::new(),
+    #[test]
+    fn peeking_an_empty_queue_finds_nothing() {
+        let queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(4).build();
+        for sub in 0..4 {
+            assert_eq!(queue.peek_front(sub), None);
+            assert_eq!(queue.subqueue_len(sub), Some(0));
         }
+        assert_eq!(queue.front_ages(), [None; 4]);
+        // Out of range sub-queues are not an error to peek
+        assert_eq!(queue.peek_front(4), None);
+        assert_eq!(queue.subqueue_len(4), None);
     }
 
-    fn enqueue(&mut self, item: T) {
-        self.tail += 1;
-        self.fifo.push_back(item)
+    #[test]
+    fn peeking_finds_the_fronts() {
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(4).build();
+        for (sub, item) in [(1, 5), (1, 2), (3, 7)] {
+            queue.enqueue_at(sub, item);
+        }
+        assert_eq!(queue.peek_front(0), None);
+        assert_eq!(queue.peek_front(1), Some(&5));
+        assert_eq!(queue.front_ages(), [None, Some(5), None, Some(7)]);
+        assert_eq!(queue.dequeue_at(1).item, Some(5));
+        assert_eq!(queue.front_ages(), [None, Some(2), None, Some(7)]);
     }
 
-    fn dequeue(&mut self) -> Option<T> {
-        let ret = self.fifo.pop_front();
-        if ret.is_some() {
-            self.head += 1;
+    #[test]
+    fn peeking_does_not_change_the_run() {
+        let mut peeked = sequenced_queue(24);
+        let mut untouched = sequenced_queue(24);
+        let mut rng = StdRng::seed_from_u64(24);
+        for item in 0..2000 {
+            for sub in 0..5 {
+                peeked.peek_front(sub);
+                peeked.subqueue_len(sub);
+            }
+            peeked.front_ages();
+            if rng.gen_bool(0.5) {
+                peeked.enqueue(item);
+                untouched.enqueue(item);
+            } else {
+                assert_eq!(peeked.dequeue(), untouched.dequeue());
+            }
         }
-        ret
+        assert_eq!(
+            peeked.subqueue_enqueue_counts(),
+            untouched.subqueue_enqueue_counts()
+        );
+        assert_eq!(
+            peeked.subqueue_dequeue_counts(),
+            untouched.subqueue_dequeue_counts()
+        );
+        assert_eq!(peeked.cost_report(), untouched.cost_report());
     }
 
-    fn len(&self) -> usize {
-        self.fifo.len()
+    #[test]
+    fn high_water_marks_on_handcrafted_operations() {
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(3).build();
+        for (sub, item) in [(0, 0), (0, 1), (2, 2), (0, 3)] {
+            queue.enqueue_at(sub, item);
+        }
+        queue.dequeue_at(0);
+        queue.dequeue_at(0);
+        queue.enqueue_at(2, 4);
+        assert_eq!(queue.subqueue_high_water_marks(), [3, 0, 2]);
     }
-}
 
-impl<T: PartialEq + Eq> RelaxedFifo<T> for DChoiceQueue<T> {
-    fn enqueue(&mut self, item: T) {
-        self.enqueue(item)
+    #[test]
+    fn high_water_marks_are_the_longest_observed() {
+        let mut queue = helping_queue(1, 25);
+        let mut rng = StdRng::seed_from_u64(25);
+        let mut longest = vec![0; 8];
+        for item in 0..3000 {
+            let enqueue_bias = if item < 1500 { 0.6 } else { 0.4 };
+            if rng.gen_bool(enqueue_bias) {
+                queue.enqueue(item);
+            } else {
+                queue.dequeue();
+            }
+            if item % 500 == 250 {
+                queue.rebalance();
+            }
+            for (sub, longest) in longest.iter_mut().enumerate() {
+                *longest = (*longest).max(queue.subqueue_len(sub).unwrap());
+            }
+        }
+        assert_eq!(queue.subqueue_high_water_marks(), longest);
     }
 
-    fn dequeue(&mut self) -> Option<T> {
-        self.dequeue()
+    #[test]
+    fn recency_penalty_prefers_the_least_recent() {
+        let mut queue: DChoiceQueue<usize> =
+            DChoiceQueueBuilder::new(4).recency_penalty(1.0).build();
+        // Equal enqueue counters, so only the penalties differ
+        queue.op_count = 10;
+        queue.last_chosen[OpKind::Enqueue as usize] = vec![9, 3, 7, 6];
+        assert_eq!(
+            queue.penalized_choice(&[0, 1, 2, 3], OpKind::Enqueue),
+            Some(1)
+        );
+        assert_eq!(queue.penalized_choice(&[0, 2], OpKind::Enqueue), Some(2));
+        // The dequeues keep their own recency
+        assert_eq!(queue.penalized_choice(&[2, 3], OpKind::Dequeue), Some(2));
+
+        // Sub-queues never chosen have no penalty
+        queue.last_chosen[OpKind::Enqueue as usize][3] = 0;
+        assert_eq!(
+            queue.penalized_choice(&[0, 1, 2, 3], OpKind::Enqueue),
+            Some(3)
+        );
+
+        // A penalty below the difference in scores does not change the choice
+        queue.enqueue_at(1, 0);
+        queue.last_chosen[OpKind::Enqueue as usize] = vec![10, 1, 1, 1];
+        assert_eq!(queue.penalized_choice(&[0, 1], OpKind::Enqueue), Some(0));
+    }
+
+    #[test]
+    fn recency_penalty_spreads_consecutive_choices() {
+        // The length heuristic on an empty queue ties every candidate of the enqueue
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(4)
+            .d(4)
+            .uniques(true)
+            .heuristic(Heuristic::Length)
+            .recency_penalty(0.5)
+            .trace_choices(true)
+            .seed(Some(26))
+            .build();
+        for item in 0..40 {
+            queue.enqueue(item);
+            queue.dequeue();
+        }
+        let enqueues = traced_enqueues(&queue);
+        // Round robin, as the least recently chosen sub-queue is always sampled
+        for window in enqueues.windows(4) {
+            let mut window = window.to_vec();
+            window.sort_unstable();
+            assert_eq!(window, [0, 1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn round_robin_cursor_visits_every_subqueue() {
+        for seed in 0..5 {
+            let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(5)
+                .d(2)
+                .round_robin(true)
+                .seed(Some(seed))
+                .build();
+            let start = queue.cursors;
+            // The cursor index is the first candidate, and advances whichever is chosen
+            let firsts: Vec<usize> = (0..12)
+                .map(|_| queue.subqueue_inds(OpKind::Enqueue)[0])
+                .collect();
+            let expected: Vec<usize> = (0..12).map(|step| (start[0] + step) % 5).collect();
+            assert_eq!(firsts, expected);
+            assert_eq!(queue.cursors, [(start[0] + 12) % 5, start[1]]);
+        }
+
+        // The cursors start together at a random sub-queue, drawn from the seed
+        let starts: Vec<[usize; 2]> = (0..20)
+            .map(|seed| {
+                DChoiceQueueBuilder::new(5)
+                    .round_robin(true)
+                    .seed(Some(seed))
+                    .build::<usize>()
+                    .cursors
+            })
+            .collect();
+        assert!(starts.iter().any(|start| start[0] != starts[0][0]));
+        assert!(starts.iter().all(|start| start[0] == start[1]));
+        let again: DChoiceQueue<usize> = DChoiceQueueBuilder::new(5)
+            .round_robin(true)
+            .seed(Some(3))
+            .build();
+        assert_eq!(again.cursors, starts[3]);
     }
 }
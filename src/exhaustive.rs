@@ -0,0 +1,379 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::Serialize;
+
+use crate::{Heuristic, OpKind};
+
+/// A tiny d-choice queue configuration, whose every possible execution is explored
+///
+/// Models the DChoiceQueue with round-robin fallback for empty dequeues, without the extras such
+/// as skew caps, recency penalties or helping. Ties are broken in every possible way, and the
+/// softmax heuristic can choose any of the sampled sub-queues.
+#[derive(Clone, Debug)]
+pub struct ExhaustiveConfig {
+    pub subqueues: usize,
+    pub d: usize,
+    pub heuristic: Heuristic,
+
+    /// If true, cannot sample the same sub-queue several times for one d-choice
+    pub uniques: bool,
+
+    /// Gives up when more than this many distinct states have been explored
+    pub max_states: usize,
+}
+
+/// One operation of a witness schedule
+#[derive(Clone, Debug, Serialize)]
+pub struct WitnessStep {
+    #[serde(serialize_with = "serialize_kind")]
+    pub kind: OpKind,
+
+    /// The sub-queue chosen by the heuristic, which a dequeue can fall back from if it is empty
+    pub subqueue: usize,
+
+    /// The enqueued or dequeued item, which is None for empty returns
+    pub item: Option<usize>,
+
+    /// The rank error of a dequeue, 0 for enqueues
+    pub rank_error: usize,
+}
+
+/// The worst case over all executions, with one execution reaching it
+#[derive(Clone, Debug, Serialize)]
+pub struct ExhaustiveResult {
+    pub max_rank_error: usize,
+
+    /// The prefill enqueues followed by the operations
+    pub witness: Vec<WitnessStep>,
+
+    /// The number of distinct states explored
+    pub states: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExhaustiveError {
+    /// The state space grew beyond the max states of the config
+    TooManyStates(usize),
+}
+
+impl std::fmt::Display for ExhaustiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExhaustiveError::TooManyStates(limit) => {
+                write!(f, "Explored more than {limit} states")
+            }
+        }
+    }
+}
+
+fn serialize_kind<S: serde::Serializer>(kind: &OpKind, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(match kind {
+        OpKind::Enqueue => "enqueue",
+        OpKind::Dequeue => "dequeue",
+    })
+}
+
+/// Finds the largest rank error any execution of the queue can reach
+///
+/// The queue is prefilled by prefill enqueues, which also choose sub-queues by the heuristic,
+/// before running the operations (true for enqueue). Explores the tree of all sub-queue choices
+/// depth first, memoizing the worst future error of each reached queue state.
+pub fn analyze_exhaustive(
+    config: &ExhaustiveConfig,
+    prefill: usize,
+    operations: &[bool],
+) -> Result<ExhaustiveResult, ExhaustiveError> {
+    assert!(
+        config.subqueues > 0 && config.d > 0,
+        "Needs at least one sub-queue and to sample at least one"
+    );
+    assert!(
+        !config.uniques || config.d <= config.subqueues,
+        "Can not sample more unique sub-queues than there are sub-queues"
+    );
+    let operations: Vec<bool> = std::iter::repeat_n(true, prefill)
+        .chain(operations.iter().copied())
+        .collect();
+    let mut explorer = Explorer {
+        config,
+        operations: &operations,
+        candidate_sets: candidate_sets(config.subqueues, config.d, config.uniques),
+        memo: HashMap::new(),
+    };
+
+    let initial = State::new(config.subqueues);
+    let max_rank_error = explorer.worst(&initial)?;
+
+    // Follow the memoized best choices to get a witness
+    let mut witness = vec![];
+    let mut state = initial;
+    while state.op < operations.len() {
+        let (_, choice) = explorer.memo[&state];
+        let (next, step) = state.apply(choice, operations[state.op]);
+        witness.push(step);
+        state = next;
+    }
+
+    Ok(ExhaustiveResult {
+        max_rank_error,
+        witness,
+        states: explorer.memo.len(),
+    })
+}
+
+struct Explorer<'a> {
+    config: &'a ExhaustiveConfig,
+    operations: &'a [bool],
+
+    /// All sets of sub-queues an operation can sample
+    candidate_sets: Vec<Vec<usize>>,
+
+    /// The worst future rank error of each state, with the choice reaching it
+    memo: HashMap<State, (usize, usize)>,
+}
+
+impl Explorer<'_> {
+    /// The largest rank error reachable from the state, including the rest of the operations
+    fn worst(&mut self, state: &State) -> Result<usize, ExhaustiveError> {
+        if state.op == self.operations.len() {
+            return Ok(0);
+        }
+        if let Some((worst, _)) = self.memo.get(state) {
+            return Ok(*worst);
+        }
+        if self.memo.len() >= self.config.max_states {
+            return Err(ExhaustiveError::TooManyStates(self.config.max_states));
+        }
+
+        let is_enqueue = self.operations[state.op];
+        let kind = if is_enqueue {
+            OpKind::Enqueue
+        } else {
+            OpKind::Dequeue
+        };
+        let mut best: Option<(usize, usize)> = None;
+        for choice in self.choices(state, kind) {
+            let (next, step) = state.apply(choice, is_enqueue);
+            let worst = step.rank_error.max(self.worst(&next)?);
+            if best.is_none_or(|(best_worst, _)| worst > best_worst) {
+                best = Some((worst, choice));
+            }
+        }
+        let best = best.expect("Some sub-queue can always be chosen");
+        self.memo.insert(state.clone(), best);
+        Ok(best.0)
+    }
+
+    /// The sub-queues the heuristic can choose for some sample and tie-break
+    fn choices(&self, state: &State, kind: OpKind) -> Vec<usize> {
+        let mut reachable = vec![false; self.config.subqueues];
+        for candidates in self.candidate_sets.iter() {
+            if let Heuristic::Softmax { .. } = self.config.heuristic {
+                candidates.iter().for_each(|ind| reachable[*ind] = true);
+                continue;
+            }
+            let score = |ind: usize| state.score(ind, kind, self.config.heuristic);
+            let best = candidates
+                .iter()
+                .map(|ind| score(*ind))
+                .min()
+                .expect("Samples at least one sub-queue");
+            for ind in candidates.iter().filter(|ind| score(**ind) == best) {
+                reachable[*ind] = true;
+            }
+        }
+        (0..self.config.subqueues)
+            .filter(|ind| reachable[*ind])
+            .collect()
+    }
+}
+
+/// All samples of d sub-queues, ignoring the order as the tie-breaks are explored anyway
+fn candidate_sets(subqueues: usize, d: usize, uniques: bool) -> Vec<Vec<usize>> {
+    let mut sets = vec![];
+    let mut set = vec![];
+    fn extend(
+        sets: &mut Vec<Vec<usize>>,
+        set: &mut Vec<usize>,
+        start: usize,
+        subqueues: usize,
+        d: usize,
+        uniques: bool,
+    ) {
+        if set.len() == d {
+            sets.push(set.clone());
+            return;
+        }
+        for ind in start..subqueues {
+            set.push(ind);
+            let next = if uniques { ind + 1 } else { ind };
+            extend(sets, set, next, subqueues, d, uniques);
+            set.pop();
+        }
+    }
+    extend(&mut sets, &mut set, 0, subqueues, d, uniques);
+    sets
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct State {
+    /// The (dequeue count, enqueue count, items) of each sub-queue
+    subqueues: Vec<(usize, usize, VecDeque<usize>)>,
+
+    /// The index of the next operation, which also counts the prefill
+    op: usize,
+}
+
+impl State {
+    fn new(subqueues: usize) -> Self {
+        Self {
+            subqueues: vec![(0, 0, VecDeque::new()); subqueues],
+            op: 0,
+        }
+    }
+
+    /// The heuristic score of a sub-queue, where lower is better
+    fn score(&self, ind: usize, kind: OpKind, heuristic: Heuristic) -> i64 {
+        let (head, tail, items) = &self.subqueues[ind];
+        match (heuristic, kind) {
+            (Heuristic::Length, OpKind::Enqueue) => items.len() as i64,
+            (Heuristic::Length, OpKind::Dequeue) => -(items.len() as i64),
+            (Heuristic::OldestFront, OpKind::Dequeue) => {
+                items.front().map_or(i64::MAX, |item| *item as i64)
+            }
+            (_, OpKind::Enqueue) => *tail as i64,
+            (_, OpKind::Dequeue) => *head as i64,
+        }
+    }
+
+    /// Does the operation on the chosen sub-queue, returning the new state
+    fn apply(&self, chosen: usize, is_enqueue: bool) -> (State, WitnessStep) {
+        let mut next = self.clone();
+        next.op += 1;
+        if is_enqueue {
+            let item = self.subqueues.iter().map(|(_, tail, _)| tail).sum();
+            let (_, tail, items) = &mut next.subqueues[chosen];
+            *tail += 1;
+            items.push_back(item);
+            return (
+                next,
+                WitnessStep {
+                    kind: OpKind::Enqueue,
+                    subqueue: chosen,
+                    item: Some(item),
+                    rank_error: 0,
+                },
+            );
+        }
+
+        // Fall back to the next non-empty sub-queue in round-robin order
+        let nbr_subqueues = self.subqueues.len();
+        let source = (0..nbr_subqueues)
+            .map(|offset| (chosen + offset) % nbr_subqueues)
+            .find(|ind| !self.subqueues[*ind].2.is_empty());
+        let item = source.map(|ind| {
+            let (head, _, items) = &mut next.subqueues[ind];
+            *head += 1;
+            items.pop_front().expect("Found a non-empty sub-queue")
+        });
+        // The older items left are the ones a strict queue would have dequeued first. Empty
+        // returns only happen when all sub-queues are empty, and so have no error
+        let rank_error = item.map_or(0, |item| {
+            next.subqueues
+                .iter()
+                .flat_map(|(_, _, items)| items.iter())
+                .filter(|other| **other < item)
+                .count()
+        });
+        (
+            next,
+            WitnessStep {
+                kind: OpKind::Dequeue,
+                subqueue: chosen,
+                item,
+                rank_error,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(d: usize, heuristic: Heuristic) -> ExhaustiveConfig {
+        ExhaustiveConfig {
+            subqueues: 2,
+            d,
+            heuristic,
+            uniques: true,
+            max_states: 1000,
+        }
+    }
+
+    #[test]
+    fn random_choices_reach_every_placement() {
+        // With d = 1 each of the three enqueues can go to either sub-queue, so 0 and 1 can share
+        // one while 2 is dequeued from the other, ahead of both
+        let result = analyze_exhaustive(
+            &config(1, Heuristic::Operation),
+            0,
+            &[true, true, true, false],
+        )
+        .unwrap();
+        assert_eq!(result.max_rank_error, 2);
+        // The 1 + 2 + 4 + 8 placements before each operation
+        assert_eq!(result.states, 15);
+
+        let dequeue = result.witness.last().unwrap();
+        assert_eq!(dequeue.item, Some(2));
+        assert_eq!(dequeue.rank_error, 2);
+        let placed: Vec<usize> = result.witness[..3]
+            .iter()
+            .map(|step| step.subqueue)
+            .collect();
+        assert_eq!(placed[0], placed[1]);
+        assert_ne!(placed[1], placed[2]);
+    }
+
+    #[test]
+    fn two_choices_alternate_the_enqueues() {
+        // The second enqueue must go to the other sub-queue, so each front holds 0 or 1
+        let result = analyze_exhaustive(
+            &config(2, Heuristic::Operation),
+            0,
+            &[true, true, true, false],
+        )
+        .unwrap();
+        assert_eq!(result.max_rank_error, 1);
+        // Two placements of 0, a forced one of 1 and two of 2
+        assert_eq!(result.states, 1 + 2 + 2 + 4);
+        assert_eq!(result.witness.last().unwrap().item, Some(1));
+
+        // The prefill is chosen the same way
+        let prefilled = analyze_exhaustive(&config(2, Heuristic::Operation), 3, &[false]).unwrap();
+        assert_eq!(prefilled.max_rank_error, 1);
+        assert_eq!(prefilled.witness.len(), 4);
+    }
+
+    #[test]
+    fn empty_dequeues_have_no_error() {
+        let result =
+            analyze_exhaustive(&config(1, Heuristic::Length), 0, &[false, true, false]).unwrap();
+        assert_eq!(result.max_rank_error, 0);
+        assert_eq!(result.witness[0].item, None);
+        assert_eq!(result.witness[2].item, Some(0));
+    }
+
+    #[test]
+    fn gives_up_past_the_state_limit() {
+        let config = ExhaustiveConfig {
+            max_states: 10,
+            ..config(1, Heuristic::Operation)
+        };
+        assert_eq!(
+            analyze_exhaustive(&config, 0, &[true, true, true, false]).unwrap_err(),
+            ExhaustiveError::TooManyStates(10)
+        );
+    }
+}
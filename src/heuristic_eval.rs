@@ -0,0 +1,369 @@
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+use serde::Serialize;
+
+use crate::{Heuristic, OpKind};
+
+/// How far the counters of the sub-queues lag behind the furthest one, in synthetic states
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CounterGaps {
+    /// Gaps drawn uniformly from 0 to max_gap
+    Uniform { max_gap: usize },
+
+    /// Pareto distributed gaps of the scale, where a lower tail index alpha gives heavier tails
+    HeavyTailed { scale: f64, alpha: f64 },
+
+    /// No gaps, except for one sub-queue lagging by lag
+    Straggler { lag: usize },
+}
+
+impl CounterGaps {
+    fn sample(&self, subqueues: usize, rng: &mut impl Rng) -> Vec<usize> {
+        match *self {
+            CounterGaps::Uniform { max_gap } => {
+                (0..subqueues).map(|_| rng.gen_range(0..=max_gap)).collect()
+            }
+            CounterGaps::HeavyTailed { scale, alpha } => (0..subqueues)
+                .map(|_| {
+                    let uniform: f64 = rng.gen_range(f64::EPSILON..1.0);
+                    (scale * (uniform.powf(-1.0 / alpha) - 1.0)).min(usize::MAX as f64 / 4.0)
+                        as usize
+                })
+                .collect(),
+            CounterGaps::Straggler { lag } => {
+                let mut gaps = vec![0; subqueues];
+                gaps[rng.gen_range(0..subqueues)] = lag;
+                gaps
+            }
+        }
+    }
+}
+
+/// The operation counters of one sub-queue in a synthetic state
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+pub struct CounterState {
+    pub head: usize,
+    pub tail: usize,
+}
+
+impl CounterState {
+    fn counter(&self, kind: OpKind) -> usize {
+        match kind {
+            OpKind::Enqueue => self.tail,
+            OpKind::Dequeue => self.head,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.tail - self.head
+    }
+}
+
+/// Draws the counters of the sub-queues, where the heads and tails lag behind the furthest head
+/// and tail by independent gaps
+///
+/// The furthest tail is length ahead of the furthest head, and no tail is behind its own head.
+pub fn synthetic_counters(
+    subqueues: usize,
+    gaps: CounterGaps,
+    length: usize,
+    rng: &mut impl Rng,
+) -> Vec<CounterState> {
+    let head_gaps = gaps.sample(subqueues, rng);
+    let tail_gaps = gaps.sample(subqueues, rng);
+    let max_gap = head_gaps.iter().chain(tail_gaps.iter()).max().copied();
+    // Offset the counters so that none of them go below 0
+    let top_head = max_gap.unwrap_or(0);
+    let top_tail = top_head + length;
+    head_gaps
+        .into_iter()
+        .zip(tail_gaps)
+        .map(|(head_gap, tail_gap)| {
+            let head = top_head - head_gap;
+            CounterState {
+                head,
+                tail: (top_tail - tail_gap).max(head),
+            }
+        })
+        .collect()
+}
+
+/// The candidate with the lowest counter of the operation kind, the first of them on ties
+///
+/// By the operation-based analysis, the sub-queue furthest behind in an operation kind is the one
+/// whose choice least relaxes the order, so this is the optimum any heuristic can pick.
+pub fn optimal_choice(states: &[CounterState], candidates: &[usize], kind: OpKind) -> usize {
+    *candidates
+        .iter()
+        .min_by_key(|ind| states[**ind].counter(kind))
+        .expect("There is at least one candidate")
+}
+
+/// The candidate the heuristic chooses in the state, the first of them on ties
+///
+/// Only the heuristics which choose by the counters alone can be evaluated.
+pub fn counter_choice(
+    states: &[CounterState],
+    candidates: &[usize],
+    heuristic: Heuristic,
+    kind: OpKind,
+    rng: &mut impl Rng,
+) -> usize {
+    match (heuristic, kind) {
+        (Heuristic::Operation, _) => optimal_choice(states, candidates, kind),
+        (Heuristic::Length, OpKind::Enqueue) => *candidates
+            .iter()
+            .min_by_key(|ind| states[**ind].len())
+            .expect("There is at least one candidate"),
+        (Heuristic::Length, OpKind::Dequeue) => *candidates
+            .iter()
+            .rev()
+            .max_by_key(|ind| states[**ind].len())
+            .expect("There is at least one candidate"),
+        (Heuristic::Softmax { temperature }, _) => {
+            let min_counter = states[optimal_choice(states, candidates, kind)].counter(kind);
+            let weights = candidates.iter().map(|ind| {
+                (-((states[*ind].counter(kind) - min_counter) as f64) / temperature).exp()
+            });
+            let dist = WeightedIndex::new(weights).expect("The best candidate has weight 1");
+            candidates[dist.sample(rng)]
+        }
+        (
+            Heuristic::OldestFront
+            | Heuristic::EstimatedOldest { .. }
+            | Heuristic::CoarseCounter { .. },
+            _,
+        ) => {
+            panic!("The heuristic does not choose by the counters alone")
+        }
+    }
+}
+
+/// A heuristic evaluated on synthetic counter states, without running a queue
+#[derive(Clone, Debug)]
+pub struct HeuristicEvalConfig {
+    pub subqueues: usize,
+    pub d: usize,
+    pub heuristic: Heuristic,
+    pub gaps: CounterGaps,
+
+    /// How far the furthest tail is ahead of the furthest head
+    pub length: usize,
+
+    /// The number of synthetic states to draw
+    pub states: usize,
+
+    /// The number of candidate sets to sample in each state, for each operation kind
+    pub samples: usize,
+}
+
+/// How far the choices of one operation kind were from the optimum
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DeviationSummary {
+    pub choices: usize,
+
+    /// The fraction of the choices which were an optimal candidate
+    pub optimal_fraction: f32,
+
+    /// The counter of the chosen sub-queue minus that of the optimal candidate
+    pub mean_deviation: f32,
+    pub p99_deviation: usize,
+    pub max_deviation: usize,
+
+    /// The counter of the chosen sub-queue minus the lowest counter of all sub-queues, which also
+    /// includes the loss from only sampling d of them
+    pub mean_global_deviation: f32,
+}
+
+impl DeviationSummary {
+    fn new(mut deviations: Vec<usize>, global_deviations: &[usize]) -> Self {
+        deviations.sort_unstable();
+        let choices = deviations.len();
+        let p99_ind = (choices * 99).div_ceil(100).saturating_sub(1);
+        Self {
+            choices,
+            optimal_fraction: deviations.partition_point(|deviation| *deviation == 0) as f32
+                / choices.max(1) as f32,
+            mean_deviation: deviations.iter().sum::<usize>() as f32 / choices.max(1) as f32,
+            p99_deviation: deviations.get(p99_ind).copied().unwrap_or(0),
+            max_deviation: deviations.last().copied().unwrap_or(0),
+            mean_global_deviation: global_deviations.iter().sum::<usize>() as f32
+                / choices.max(1) as f32,
+        }
+    }
+}
+
+/// The deviations of the enqueue and dequeue choices of a heuristic evaluation
+#[derive(Clone, Debug, Serialize)]
+pub struct HeuristicEval {
+    pub enqueues: DeviationSummary,
+    pub dequeues: DeviationSummary,
+}
+
+/// Applies the heuristic to uniformly sampled candidate sets in synthetic counter states, and
+/// summarizes how far its choices were from the optimal candidates
+///
+/// Isolates the quality of the choices from the dynamics of a queue, as the states do not evolve
+/// with the choices. Returns an error if the configuration is empty or the heuristic does not
+/// choose by the counters.
+pub fn evaluate_heuristic(
+    config: &HeuristicEvalConfig,
+    rng: &mut impl Rng,
+) -> Result<HeuristicEval, String> {
+    if config.subqueues == 0 || config.d == 0 {
+        return Err("Needs at least one sub-queue and to sample at least one".to_string());
+    }
+    match config.heuristic {
+        Heuristic::OldestFront
+        | Heuristic::EstimatedOldest { .. }
+        | Heuristic::CoarseCounter { .. } => {
+            return Err(
+                "Only the heuristics choosing by the counters alone can be evaluated".to_string(),
+            )
+        }
+        Heuristic::Softmax { temperature } if temperature <= 0.0 => {
+            return Err("The softmax temperature must be positive".to_string())
+        }
+        _ => {}
+    }
+    if let CounterGaps::HeavyTailed { scale, alpha } = config.gaps {
+        if scale < 0.0 || alpha <= 0.0 {
+            return Err(
+                "The heavy-tailed gaps need a non-negative scale and alpha > 0".to_string(),
+            );
+        }
+    }
+
+    let mut deviations = [vec![], vec![]];
+    let mut global_deviations = [vec![], vec![]];
+    let mut candidates = vec![0; config.d];
+    for _ in 0..config.states {
+        let states = synthetic_counters(config.subqueues, config.gaps, config.length, rng);
+        for kind in [OpKind::Enqueue, OpKind::Dequeue] {
+            let global_min = states
+                .iter()
+                .map(|state| state.counter(kind))
+                .min()
+                .expect("There is at least one sub-queue");
+            for _ in 0..config.samples {
+                candidates
+                    .iter_mut()
+                    .for_each(|ind| *ind = rng.gen_range(0..config.subqueues));
+                let chosen = states
+                    [counter_choice(&states, &candidates, config.heuristic, kind, rng)]
+                .counter(kind);
+                let optimal = states[optimal_choice(&states, &candidates, kind)].counter(kind);
+                deviations[kind as usize].push(chosen - optimal);
+                global_deviations[kind as usize].push(chosen - global_min);
+            }
+        }
+    }
+    let [enqueue_deviations, dequeue_deviations] = deviations;
+    Ok(HeuristicEval {
+        enqueues: DeviationSummary::new(enqueue_deviations, &global_deviations[0]),
+        dequeues: DeviationSummary::new(dequeue_deviations, &global_deviations[1]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn states() -> Vec<CounterState> {
+        vec![
+            CounterState { head: 5, tail: 9 },
+            CounterState { head: 2, tail: 10 },
+            CounterState { head: 7, tail: 7 },
+            CounterState { head: 2, tail: 6 },
+        ]
+    }
+
+    #[test]
+    fn optimum_has_the_lowest_counter() {
+        let states = states();
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(optimal_choice(&states, &[0, 1, 2], OpKind::Enqueue), 2);
+        assert_eq!(optimal_choice(&states, &[0, 1, 2], OpKind::Dequeue), 1);
+        // The first of the tied heads
+        assert_eq!(optimal_choice(&states, &[3, 0, 1], OpKind::Dequeue), 3);
+        assert_eq!(optimal_choice(&states, &[1, 3], OpKind::Dequeue), 1);
+
+        // The lengths are 4, 8, 0 and 4, so the length heuristic also enqueues into 2, but
+        // dequeues from the longest rather than the one furthest behind
+        let length = |candidates: &[usize], kind, rng: &mut StdRng| {
+            counter_choice(&states, candidates, Heuristic::Length, kind, rng)
+        };
+        assert_eq!(length(&[0, 1, 2], OpKind::Enqueue, &mut rng), 2);
+        assert_eq!(length(&[0, 2, 3], OpKind::Dequeue, &mut rng), 0);
+        assert_eq!(length(&[3, 0], OpKind::Dequeue, &mut rng), 3);
+        assert_eq!(
+            counter_choice(
+                &states,
+                &[0, 1],
+                Heuristic::Operation,
+                OpKind::Dequeue,
+                &mut rng
+            ),
+            1
+        );
+    }
+
+    #[test]
+    fn deviations_summarize_by_hand() {
+        let summary = DeviationSummary::new(vec![0, 3, 0, 1], &[1, 2, 3, 4]);
+        assert_eq!(summary.choices, 4);
+        assert_eq!(summary.optimal_fraction, 0.5);
+        assert_eq!(summary.mean_deviation, 1.0);
+        assert_eq!(summary.p99_deviation, 3);
+        assert_eq!(summary.max_deviation, 3);
+        assert_eq!(summary.mean_global_deviation, 2.5);
+    }
+
+    #[test]
+    fn operation_heuristic_is_always_optimal() {
+        let config = HeuristicEvalConfig {
+            subqueues: 8,
+            d: 2,
+            heuristic: Heuristic::Operation,
+            gaps: CounterGaps::Uniform { max_gap: 20 },
+            length: 50,
+            states: 20,
+            samples: 10,
+        };
+        let eval = evaluate_heuristic(&config, &mut StdRng::seed_from_u64(2)).unwrap();
+        for summary in [&eval.enqueues, &eval.dequeues] {
+            assert_eq!(summary.choices, 200);
+            assert_eq!(summary.optimal_fraction, 1.0);
+            assert_eq!(summary.max_deviation, 0);
+            assert!(summary.mean_global_deviation > 0.0);
+        }
+
+        // Sampling the only sub-queue also finds the global optimum
+        let single = HeuristicEvalConfig {
+            subqueues: 1,
+            d: 1,
+            heuristic: Heuristic::Length,
+            ..config
+        };
+        let eval = evaluate_heuristic(&single, &mut StdRng::seed_from_u64(3)).unwrap();
+        assert_eq!(eval.dequeues.mean_global_deviation, 0.0);
+
+        let invalid = [
+            HeuristicEvalConfig {
+                d: 0,
+                ..config.clone()
+            },
+            HeuristicEvalConfig {
+                heuristic: Heuristic::OldestFront,
+                ..config.clone()
+            },
+            HeuristicEvalConfig {
+                heuristic: Heuristic::Softmax { temperature: 0.0 },
+                ..config.clone()
+            },
+        ];
+        for config in invalid {
+            assert!(evaluate_heuristic(&config, &mut StdRng::seed_from_u64(4)).is_err());
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::VecDeque;
+
+use crate::relaxed_fifo::RelaxedFifo;
+
+/// A sequential model of the k-FIFO queue of Kirsch, Lippautz and Payer
+///
+/// The enqueues fill segments of k slots in order, and each dequeue takes a random item of the
+/// oldest segment which still holds any. The segments are strictly ordered, so the rank error of
+/// every dequeue is below k.
+pub struct KFifo<T> {
+    k: usize,
+
+    /// The items left in each segment, oldest first, where only the last one is still filled
+    segments: VecDeque<Vec<T>>,
+
+    /// The enqueues into the last segment, which is full at k even if items have left it
+    tail_filled: usize,
+
+    len: usize,
+    rng: StdRng,
+}
+
+impl<T> KFifo<T> {
+    /// Creates an empty queue with segments of k slots, which must be positive
+    pub fn new(k: usize, seed: u64) -> Self {
+        assert!(k > 0, "A k-FIFO segment needs at least one slot");
+        Self {
+            k,
+            segments: VecDeque::new(),
+            tail_filled: k,
+            len: 0,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T> RelaxedFifo<T> for KFifo<T> {
+    fn enqueue(&mut self, item: T) {
+        if self.tail_filled == self.k {
+            self.segments.push_back(Vec::with_capacity(self.k));
+            self.tail_filled = 0;
+        }
+        self.segments
+            .back_mut()
+            .expect("A segment was just opened")
+            .push(item);
+        self.tail_filled += 1;
+        self.len += 1;
+    }
+
+    fn dequeue(&mut self) -> Option<T> {
+        while self.segments.front()?.is_empty() {
+            if self.segments.len() == 1 {
+                // The tail segment may still get items, unless it is full
+                if self.tail_filled == self.k {
+                    self.segments.pop_front();
+                }
+                return None;
+            }
+            self.segments.pop_front();
+        }
+        let head = self.segments.front_mut().expect("Checked above");
+        let ind = self.rng.gen_range(0..head.len());
+        self.len -= 1;
+        Some(head.swap_remove(ind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyze_simple;
+
+    #[test]
+    fn rank_errors_stay_below_k() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for k in [1, 2, 7, 32] {
+            let operations: Vec<bool> = (0..5000).map(|_| rng.gen_bool(0.5)).collect();
+            let mut queue = KFifo::new(k, 2);
+            let rank_errors = analyze_simple(&mut queue, 100, &operations).unwrap();
+            assert!(rank_errors.iter().all(|error| *error < k), "k = {k}");
+            if k == 1 {
+                assert!(rank_errors.iter().all(|error| *error == 0));
+            }
+        }
+    }
+
+    #[test]
+    fn segments_dequeue_in_order() {
+        let mut queue = KFifo::new(3, 1);
+        for item in 0..8 {
+            queue.enqueue(item);
+        }
+        let mut dequeued: Vec<usize> = std::iter::from_fn(|| queue.dequeue()).collect();
+        assert!(queue.is_empty());
+        for segment in dequeued.chunks_mut(3) {
+            segment.sort_unstable();
+        }
+        assert_eq!(dequeued, (0..8).collect::<Vec<_>>());
+        // An emptied tail segment which is not full keeps taking items
+        queue.enqueue(8);
+        assert_eq!(queue.dequeue(), Some(8));
+        assert_eq!(queue.dequeue(), None);
+    }
+}
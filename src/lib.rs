@@ -1,10 +1,78 @@
+mod budgeted_queue;
+mod certificate;
+mod cli;
 mod d_choice_minmax_gap_analysis;
 mod d_choice_queue;
+mod exhaustive;
+mod heuristic_eval;
+mod k_fifo;
+mod log_histogram;
+mod power_law_fit;
+mod presets;
+mod queue_registry;
+mod rank_error_heatmap;
 mod relaxation_analysis;
 mod relaxation_simulation;
 mod relaxed_fifo;
+mod report;
+mod run_log;
+mod seeding;
+mod stats;
+mod sweep_output;
 
+pub use budgeted_queue::BudgetedQueue;
+pub use certificate::{Certificate, MAX_CERTIFICATE_OPERATIONS};
+pub use cli::run_cli;
 pub use d_choice_minmax_gap_analysis::analyze_minmax_gap;
-pub use d_choice_queue::DChoiceQueue;
-pub use relaxation_analysis::analyze_distributions;
-pub use relaxation_simulation::{analyze_extra, analyze_simple, ErrorTag};
+pub use d_choice_queue::{
+    partitions_nonempty, AdaptiveD, Blackout, CandidateFilter, ChoiceRecord, CostReport,
+    DChoiceQueue, DChoiceQueueBuilder, DRa, DequeueInfo, FallbackPolicy, FinalStateReport,
+    Heuristic, LatencyModel, OpKind, Sequenced, ServiceWeights, SteadyState, SubQueueState,
+    TraceEvent, VirtualTime, ADVERSARY_MAX_SUBQUEUES,
+};
+pub use exhaustive::{
+    analyze_exhaustive, ExhaustiveConfig, ExhaustiveError, ExhaustiveResult, WitnessStep,
+};
+pub use heuristic_eval::{
+    counter_choice, evaluate_heuristic, optimal_choice, synthetic_counters, CounterGaps,
+    CounterState, DeviationSummary, HeuristicEval, HeuristicEvalConfig,
+};
+pub use k_fifo::KFifo;
+pub use log_histogram::LogHistogram;
+pub use power_law_fit::{fit_power_law, PowerLawFit};
+pub use presets::{find_preset, Preset, PRESETS};
+pub use queue_registry::{register_queue, registered_queue, registered_queue_names, QueueFactory};
+pub use rank_error_heatmap::RankErrorHeatmap;
+pub use relaxation_analysis::{
+    analyze_age_by_subqueue, analyze_choice_impact, analyze_conditional_spread,
+    analyze_distributions, analyze_per_thread, analyze_phases, analyze_prefill_flush,
+    analyze_recovery, describe_operations, normalize_rank_errors, windowed_mean_errors,
+    AgeBySubqueue, ChoiceImpact, ChoiceImpactRow, ConditionalSpread, DistributionAnalysis,
+    DistributionOptions, Normalization, OpsDescription, PhaseSummary, PrefillFlush, Recovery,
+    SpreadBucket, SubqueueAges, ThreadSummary,
+};
+pub use relaxation_simulation::{
+    analyze_batched, analyze_bounded, analyze_concurrent, analyze_decomposed, analyze_extra,
+    analyze_extra_final_state, analyze_extra_from, analyze_from_steady_state, analyze_item_fates,
+    analyze_logged, analyze_online, analyze_simple, analyze_simple_checked, analyze_simple_from,
+    analyze_simple_with, analyze_skewed_prefill, analyze_streaming, analyze_with_barriers,
+    analyze_with_hook, dequeue_relative_errors, estimate_run_memory, gen_balanced_ops,
+    replay_decisions, AnalysisError, ConcurrentConfig, Decision, EnqueueFailures, ErrorRecord,
+    ErrorSink, ErrorTag, ItemFate, OpOutcome, OpSource, OracleStats, MAX_ANALYSIS_ITEMS,
+};
+pub use relaxed_fifo::{InstrumentedFifo, RelaxedFifo};
+pub use report::{format_choice, format_choice_trace};
+pub use run_log::{LogRecord, RunLogReader};
+pub use seeding::{derive_seed, SeedStream, SEED_SCHEME};
+pub use stats::{
+    coefficient_of_variation, detect_burn_in, diagnose_point, effective_sample_size,
+    empty_fraction, geweke_z, median, pareto_front, stationary_sample, summarize_runs,
+    tail_samples, DiagnosticThresholds, ParetoPoint, PointRuns, PointWarning, RunSummary,
+    StationarySummary, WarningKind,
+};
+#[cfg(feature = "arrow")]
+pub use sweep_output::write_sweep_parquet;
+pub use sweep_output::{
+    compare_sweep_outputs, load_results, merge_sweep_outputs, upgrade_results, Divergence,
+    MergeError, ResultFile, SweepRow, SCHEMA_VERSION,
+};
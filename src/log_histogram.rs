@@ -0,0 +1,244 @@
+use serde::{Serialize, Serializer};
+
+use crate::relaxation_simulation::ErrorSink;
+
+/// A histogram of rank errors in logarithmic buckets, using constant memory for any run length
+///
+/// As an HdrHistogram, values below 2^significant_bits are counted exactly, and larger values in
+/// buckets keeping their top significant bits. So any value read out, such as a percentile, is
+/// within a relative error of 2^(1 - significant_bits) from the exact one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogHistogram {
+    significant_bits: u32,
+
+    /// The counts of each bucket, grown to the largest recorded bucket
+    counts: Vec<u64>,
+
+    total: u64,
+    sum: u128,
+    max: usize,
+}
+
+impl LogHistogram {
+    pub const MAX_SIGNIFICANT_BITS: u32 = 16;
+
+    pub fn new(significant_bits: u32) -> Self {
+        assert!(
+            (1..=Self::MAX_SIGNIFICANT_BITS).contains(&significant_bits),
+            "The histogram needs 1 to {} significant bits",
+            Self::MAX_SIGNIFICANT_BITS
+        );
+        Self {
+            significant_bits,
+            counts: vec![],
+            total: 0,
+            sum: 0,
+            max: 0,
+        }
+    }
+
+    pub fn significant_bits(&self) -> u32 {
+        self.significant_bits
+    }
+
+    pub fn record(&mut self, value: usize) {
+        let bucket = self.bucket(value);
+        if bucket >= self.counts.len() {
+            self.counts.resize(bucket + 1, 0);
+        }
+        self.counts[bucket] += 1;
+        self.total += 1;
+        self.sum += value as u128;
+        self.max = self.max.max(value);
+    }
+
+    /// Adds the counts of another histogram, such as from another run
+    pub fn merge(&mut self, other: &LogHistogram) {
+        assert_eq!(
+            self.significant_bits, other.significant_bits,
+            "Can only merge histograms of the same precision"
+        );
+        if other.counts.len() > self.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other_count;
+        }
+        self.total += other.total;
+        self.sum += other.sum;
+        self.max = self.max.max(other.max);
+    }
+
+    /// The number of recorded values
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    /// The exact mean of the recorded values, or 0 if there are none
+    pub fn mean(&self) -> f64 {
+        self.sum as f64 / self.total.max(1) as f64
+    }
+
+    /// The exact largest recorded value
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// The smallest value such that at least the quantile (in [0, 1]) of the values are at most
+    /// it, rounded up to the end of its bucket but at most the max. Returns 0 if empty.
+    pub fn value_at_quantile(&self, quantile: f64) -> usize {
+        let rank = ((quantile.clamp(0.0, 1.0) * self.total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return self.bucket_range(bucket).1.min(self.max);
+            }
+        }
+        0
+    }
+
+    /// The (lowest value, highest value, count) of each non-empty bucket, in increasing order
+    pub fn buckets(&self) -> impl Iterator<Item = (usize, usize, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bucket, count)| {
+                let (low, high) = self.bucket_range(bucket);
+                (low, high, *count)
+            })
+    }
+
+    /// Values below 2^bits are their own buckets. Above, each doubling of the value is split into
+    /// 2^(bits - 1) buckets, by the bits below the leading one.
+    fn bucket(&self, value: usize) -> usize {
+        let bits = self.significant_bits;
+        if value < 1 << bits {
+            return value;
+        }
+        let shift = value.ilog2() + 1 - bits;
+        let half = 1usize << (bits - 1);
+        (1 << bits) + (shift as usize - 1) * half + ((value >> shift) - half)
+    }
+
+    fn bucket_range(&self, bucket: usize) -> (usize, usize) {
+        let bits = self.significant_bits;
+        if bucket < 1 << bits {
+            return (bucket, bucket);
+        }
+        let half = 1usize << (bits - 1);
+        let shift = (bucket - (1 << bits)) / half + 1;
+        let low = (half + (bucket - (1 << bits)) % half) << shift;
+        (low, low + ((1 << shift) - 1))
+    }
+}
+
+impl ErrorSink for LogHistogram {
+    fn record_error(&mut self, rank_error: usize) {
+        self.record(rank_error);
+    }
+}
+
+/// Serialized compactly as the summary and the non-empty buckets
+impl Serialize for LogHistogram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Compact {
+            significant_bits: u32,
+            count: u64,
+            mean: f64,
+            max: usize,
+            /// The (lowest value, highest value, count) of each non-empty bucket
+            buckets: Vec<(usize, usize, u64)>,
+        }
+        Compact {
+            significant_bits: self.significant_bits,
+            count: self.total,
+            mean: self.mean(),
+            max: self.max,
+            buckets: self.buckets().collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// A Pareto sample with the heavy tails of rank errors
+    fn pareto_sample(n: usize, seed: u64) -> Vec<usize> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..n)
+            .map(|_| (1.0 / (1.0 - rng.gen::<f64>()).powf(1.0 / 1.5)) as usize)
+            .collect()
+    }
+
+    #[test]
+    fn buckets_contain_their_values() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for bits in [1, 2, 5, LogHistogram::MAX_SIGNIFICANT_BITS] {
+            let histogram = LogHistogram::new(bits);
+            let values = (0..5000).chain((0..5000).map(|_| rng.gen::<u64>() as usize >> 8));
+            for value in values {
+                let (low, high) = histogram.bucket_range(histogram.bucket(value));
+                assert!(
+                    low <= value && value <= high,
+                    "{value} is outside [{low}, {high}] with {bits} bits"
+                );
+            }
+            // The buckets tile the values without gaps
+            for bucket in 0..histogram.bucket(1 << 40) {
+                assert_eq!(
+                    histogram.bucket_range(bucket).1 + 1,
+                    histogram.bucket_range(bucket + 1).0
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn quantiles_are_within_the_relative_error() {
+        let bits = 7;
+        let bound = 2f64.powi(1 - bits as i32);
+        let mut values = pareto_sample(100_000, 2);
+        let mut histogram = LogHistogram::new(bits);
+        values.iter().for_each(|value| histogram.record(*value));
+        values.sort_unstable();
+
+        for quantile in [0.0, 0.5, 0.9, 0.99, 0.999, 1.0] {
+            let rank = ((quantile * values.len() as f64).ceil() as usize).max(1);
+            let exact = values[rank - 1];
+            let read = histogram.value_at_quantile(quantile);
+            assert!(
+                exact <= read && read as f64 <= exact as f64 * (1.0 + bound),
+                "Read {read} for the {quantile} quantile {exact}"
+            );
+        }
+        assert_eq!(histogram.max(), *values.last().unwrap());
+        assert_eq!(histogram.value_at_quantile(1.0), histogram.max());
+        assert_eq!(LogHistogram::new(bits).value_at_quantile(0.5), 0);
+    }
+
+    #[test]
+    fn merging_equals_recording_everything() {
+        let values = pareto_sample(10_000, 3);
+        let (first, second) = values.split_at(3000);
+        let mut all = LogHistogram::new(5);
+        values.iter().for_each(|value| all.record(*value));
+        let mut merged = LogHistogram::new(5);
+        first.iter().for_each(|value| merged.record(*value));
+        let mut other = LogHistogram::new(5);
+        second.iter().for_each(|value| other.record(*value));
+
+        merged.merge(&other);
+        assert_eq!(merged, all);
+        assert_eq!(merged.count(), 10_000);
+        // Merging into an empty one copies it
+        let mut empty = LogHistogram::new(5);
+        empty.merge(&all);
+        assert_eq!(empty, all);
+    }
+}
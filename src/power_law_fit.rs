@@ -0,0 +1,108 @@
+use serde::Serialize;
+
+/// A power law error ≈ coefficient * x^exponent, fitted by least squares in log-log space
+#[derive(Clone, Debug, Serialize)]
+pub struct PowerLawFit {
+    pub exponent: f64,
+    pub coefficient: f64,
+
+    /// The coefficient of determination of the fit in log-log space
+    pub r_squared: f64,
+
+    /// The number of points the fit used
+    pub points: usize,
+}
+
+/// Fits a power law to the (x, error) points
+///
+/// Points where x or the error is not positive have no logarithm, so they are excluded. Returns
+/// None if fewer than three points remain, or if all of them have the same x.
+pub fn fit_power_law(points: &[(f64, f64)]) -> Option<PowerLawFit> {
+    let logs: Vec<(f64, f64)> = points
+        .iter()
+        .filter(|(x, error)| *x > 0.0 && *error > 0.0)
+        .map(|(x, error)| (x.ln(), error.ln()))
+        .collect();
+    if logs.len() < 3 {
+        return None;
+    }
+
+    let n = logs.len() as f64;
+    let mean_x = logs.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = logs.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let cov = logs
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum::<f64>();
+    let var_x = logs.iter().map(|(x, _)| (x - mean_x).powi(2)).sum::<f64>();
+    if var_x == 0.0 {
+        return None;
+    }
+
+    let exponent = cov / var_x;
+    let intercept = mean_y - exponent * mean_x;
+    let total = logs.iter().map(|(_, y)| (y - mean_y).powi(2)).sum::<f64>();
+    let residual = logs
+        .iter()
+        .map(|(x, y)| (y - intercept - exponent * x).powi(2))
+        .sum::<f64>();
+    Some(PowerLawFit {
+        exponent,
+        coefficient: intercept.exp(),
+        // All errors equal is fitted perfectly by a flat line
+        r_squared: if total > 0.0 {
+            1.0 - residual / total
+        } else {
+            1.0
+        },
+        points: logs.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn recovers_an_exact_power_law() {
+        let points: Vec<(f64, f64)> = [1.0, 2.0, 4.0, 8.0, 16.0]
+            .iter()
+            .map(|x: &f64| (*x, 3.0 * x.powf(1.5)))
+            .collect();
+        let fit = fit_power_law(&points).unwrap();
+        assert!((fit.exponent - 1.5).abs() < 1e-9);
+        assert!((fit.coefficient - 3.0).abs() < 1e-9);
+        assert!((fit.r_squared - 1.0).abs() < 1e-9);
+        assert_eq!(fit.points, 5);
+    }
+
+    #[test]
+    fn recovers_a_noisy_power_law() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let points: Vec<(f64, f64)> = (1..=200)
+            .map(|x| {
+                let x = x as f64;
+                // Multiplicative noise of up to 20%, which is additive in log space
+                (x, 0.5 * x.powf(0.8) * rng.gen_range(0.8..1.2))
+            })
+            .collect();
+        let fit = fit_power_law(&points).unwrap();
+        assert!((fit.exponent - 0.8).abs() < 0.05, "{fit:?}");
+        assert!((fit.coefficient / 0.5 - 1.0).abs() < 0.2, "{fit:?}");
+        assert!(fit.r_squared > 0.9 && fit.r_squared < 1.0, "{fit:?}");
+    }
+
+    #[test]
+    fn needs_three_points_with_different_x() {
+        assert!(fit_power_law(&[]).is_none());
+        assert!(fit_power_law(&[(1.0, 1.0), (2.0, 2.0)]).is_none());
+        // Points without logarithms do not count
+        assert!(fit_power_law(&[(1.0, 1.0), (2.0, 2.0), (0.0, 3.0), (3.0, -1.0)]).is_none());
+        assert!(fit_power_law(&[(2.0, 1.0), (2.0, 2.0), (2.0, 3.0)]).is_none());
+
+        let flat = fit_power_law(&[(1.0, 2.0), (2.0, 2.0), (3.0, 2.0)]).unwrap();
+        assert_eq!(flat.exponent, 0.0);
+        assert_eq!(flat.r_squared, 1.0);
+    }
+}
@@ -0,0 +1,61 @@
+use serde::Serialize;
+
+/// A named test configuration which is run repeatedly, as a subcommand with its arguments
+///
+/// The CLI expands `--preset <name>` into the arguments, so that any arguments given after it
+/// override those of the preset.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+
+    /// The subcommand followed by its arguments
+    pub args: &'static [&'static str],
+}
+
+const PPOPP_OPERATIONS: &str = "64 128 256 512 1024 2048 4096 8192 16384 32768 65536 131072 262144 524288 1048576 2097152 4194304";
+const PPOPP_PREFILL: &str =
+    "64 128 256 512 1024 2048 4096 8192 16384 32768 65536 131072 262144 524288 1048576";
+
+/// All presets, in the order they are listed
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "ppopp-d-ra",
+        description: "The d-RA heatmap of rank errors over operations and prefill in the PPoPP paper, as in recreate-ppopp.sh",
+        args: &[
+            "ops-and-prefill",
+            "-s",
+            "64",
+            "-o",
+            PPOPP_OPERATIONS,
+            "-i",
+            PPOPP_PREFILL,
+            "-r",
+            "100",
+            "--heuristic",
+            "length",
+        ],
+    },
+    Preset {
+        name: "ppopp-d-cbo",
+        description: "The d-CBO heatmap of rank errors over operations and prefill in the PPoPP paper, as in recreate-ppopp.sh",
+        args: &[
+            "ops-and-prefill",
+            "-s",
+            "64",
+            "-o",
+            PPOPP_OPERATIONS,
+            "-i",
+            PPOPP_PREFILL,
+            "-r",
+            "100",
+            "--heuristic",
+            "operation",
+        ],
+    },
+];
+
+/// The preset with the name, if any
+pub fn find_preset(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|preset| preset.name == name)
+}
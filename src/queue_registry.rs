@@ -0,0 +1,38 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use crate::RelaxedFifo;
+
+/// Constructs a queue from the number of sub-queues and a seed for all its randomness
+pub type QueueFactory = fn(usize, u64) -> Box<dyn RelaxedFifo<usize>>;
+
+static REGISTRY: Mutex<BTreeMap<String, QueueFactory>> = Mutex::new(BTreeMap::new());
+
+/// Registers a queue outside this crate under the name, so that a binary wrapping the analyses
+/// can construct it by name, as the CLI does for `--queue external:<name>`
+///
+/// Panics if a queue is already registered under the name.
+pub fn register_queue(name: &str, factory: QueueFactory) {
+    let mut registry = REGISTRY
+        .lock()
+        .expect("The registry is never left poisoned");
+    assert!(
+        registry.insert(name.to_string(), factory).is_none(),
+        "A queue is already registered as {name}"
+    );
+}
+
+/// The constructor of the queue registered under the name, if any
+pub fn registered_queue(name: &str) -> Option<QueueFactory> {
+    let registry = REGISTRY
+        .lock()
+        .expect("The registry is never left poisoned");
+    registry.get(name).copied()
+}
+
+/// The names of all registered queues, in sorted order
+pub fn registered_queue_names() -> Vec<String> {
+    let registry = REGISTRY
+        .lock()
+        .expect("The registry is never left poisoned");
+    registry.keys().cloned().collect()
+}
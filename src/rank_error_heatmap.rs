@@ -0,0 +1,115 @@
+use serde::Serialize;
+
+/// A 2D histogram of rank errors over time
+///
+/// The x-axis is the operation index, split into equally sized buckets. The y-axis is the rank
+/// error, bucketed logarithmically: bucket 0 holds errors of 0, and bucket k > 0 holds errors in
+/// [2^(k-1), 2^k). The last error bucket also holds all larger errors. The memory used only
+/// depends on the grid size, not on the number of operations recorded.
+#[derive(Clone, Debug, Serialize)]
+pub struct RankErrorHeatmap {
+    /// Lower edges of the operation buckets, followed by the upper edge of the last one
+    pub op_edges: Vec<usize>,
+
+    /// Lower edges of the rank error buckets, the last bucket is unbounded
+    pub error_edges: Vec<usize>,
+
+    /// The counts, indexed as counts[op_bucket][error_bucket]
+    pub counts: Vec<Vec<f32>>,
+}
+
+impl RankErrorHeatmap {
+    /// The most error buckets with distinct edges, as the last one starts at 2^(usize::BITS - 1)
+    pub const MAX_ERROR_BUCKETS: usize = usize::BITS as usize + 1;
+
+    /// Creates an empty heatmap covering operations 0..operations
+    ///
+    /// Panics if a dimension has no buckets, or if there are more than MAX_ERROR_BUCKETS error
+    /// buckets.
+    pub fn new(operations: usize, op_buckets: usize, error_buckets: usize) -> Self {
+        assert!(
+            op_buckets > 0 && error_buckets > 0,
+            "A heatmap needs at least one bucket in each dimension"
+        );
+        assert!(
+            error_buckets <= Self::MAX_ERROR_BUCKETS,
+            "A heatmap has at most {} error buckets",
+            Self::MAX_ERROR_BUCKETS
+        );
+        let op_width = operations.div_ceil(op_buckets).max(1);
+        let op_edges = (0..=op_buckets).map(|bucket| bucket * op_width).collect();
+        let error_edges = (0..error_buckets)
+            .map(|bucket| match bucket {
+                0 => 0,
+                _ => 1usize
+                    .checked_shl(bucket as u32 - 1)
+                    .expect("Checked against MAX_ERROR_BUCKETS"),
+            })
+            .collect();
+
+        Self {
+            op_edges,
+            error_edges,
+            counts: vec![vec![0.0; error_buckets]; op_buckets],
+        }
+    }
+
+    /// Counts a rank error at the given operation index
+    pub fn record(&mut self, op_index: usize, rank_error: usize) {
+        let op_width = self.op_edges[1];
+        let op_bucket = (op_index / op_width).min(self.counts.len() - 1);
+        let error_bucket = if rank_error == 0 {
+            0
+        } else {
+            (rank_error.ilog2() as usize + 1).min(self.error_edges.len() - 1)
+        };
+        self.counts[op_bucket][error_bucket] += 1.0;
+    }
+
+    /// Adds the counts of another heatmap with the same shape into this one
+    pub fn add(&mut self, other: &RankErrorHeatmap) {
+        assert_eq!(self.op_edges, other.op_edges, "Heatmap shapes must match");
+        assert_eq!(
+            self.error_edges, other.error_edges,
+            "Heatmap shapes must match"
+        );
+        for (row, other_row) in self.counts.iter_mut().zip(other.counts.iter()) {
+            for (cell, other_cell) in row.iter_mut().zip(other_row.iter()) {
+                *cell += other_cell;
+            }
+        }
+    }
+
+    /// Divides all counts, for example by the number of runs to get an average
+    pub fn scale(&mut self, divisor: f32) {
+        self.counts
+            .iter_mut()
+            .flat_map(|row| row.iter_mut())
+            .for_each(|cell| *cell /= divisor);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_edges_cover_all_errors() {
+        let mut heatmap = RankErrorHeatmap::new(10, 1, RankErrorHeatmap::MAX_ERROR_BUCKETS);
+        assert_eq!(heatmap.error_edges[1], 1);
+        assert_eq!(heatmap.error_edges.last(), Some(&(1 << (usize::BITS - 1))));
+        heatmap.record(0, usize::MAX);
+        heatmap.record(0, 3);
+        assert_eq!(
+            heatmap.counts[0][RankErrorHeatmap::MAX_ERROR_BUCKETS - 1],
+            1.0
+        );
+        assert_eq!(heatmap.counts[0][2], 1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "at most")]
+    fn rejects_too_many_error_buckets() {
+        RankErrorHeatmap::new(10, 1, RankErrorHeatmap::MAX_ERROR_BUCKETS + 1);
+    }
+}
@@ -1,36 +1,433 @@
 use rand::Rng;
+use serde::Serialize;
 
-use crate::{analyze_extra, DChoiceQueue, ErrorTag};
+use crate::{
+    analyze_decomposed, analyze_extra, stationary_sample, AnalysisError, ChoiceRecord,
+    DChoiceQueue, ErrorTag, OpKind, RankErrorHeatmap, StationarySummary,
+};
+
+/// Optional extra analyses for analyze_distributions
+#[derive(Clone, Debug)]
+pub struct DistributionOptions {
+    /// If set, also records a rank error heatmap with (operation buckets, error buckets)
+    pub heatmap: Option<(usize, usize)>,
+
+    /// If true, also splits the rank errors by if the items came from the prefill or operations
+    pub tag_prefill: bool,
+
+    /// If true, also splits the rank errors by if the dequeues fell back to the round-robin scan
+    pub split_fallback: bool,
+
+    /// If true, also summarizes the ages of the items each sub-queue served
+    pub age_by_subqueue: bool,
+
+    /// If true, runs a random number (below the sub-queue count) of extra enqueues and then as
+    /// many dequeues after the operations, to randomize the final loads. Their dequeues are not
+    /// part of the distributions
+    pub decorrelate: bool,
+
+    /// If set, also keeps the steady-state rank errors, discarding the given number of burn-in
+    /// dequeues or a detected one, and keeping every k-th of the rest, as (burn-in, k)
+    pub stationary: Option<(Option<usize>, usize)>,
+
+    /// If set, also summarizes the rank errors of consecutive phases of these numbers of operations
+    pub phases: Option<Vec<usize>>,
+
+    /// If true, also decomposes the rank errors into placement and selection errors, see
+    /// analyze_decomposed. Peeks at every sub-queue for each dequeue, and the queue must log the
+    /// choices of the prefill and all operations, including those added to decorrelate
+    pub decompose: bool,
+
+    /// If true, also summarizes how often the extra samples changed the choices, see
+    /// analyze_choice_impact. The queue must log the choices of the prefill and operations
+    pub choice_impact: bool,
+
+    /// If true, also summarizes the rank errors by the score spread of the dequeue candidates,
+    /// see analyze_conditional_spread. The queue must log the choices as for choice_impact
+    pub conditional_spread: bool,
+
+    /// If true, also records the number of items in the queue at each dequeue, as needed to
+    /// normalize the rank errors per size
+    pub track_sizes: bool,
+}
+
+impl Default for DistributionOptions {
+    fn default() -> Self {
+        Self {
+            heatmap: None,
+            tag_prefill: false,
+            split_fallback: false,
+            age_by_subqueue: false,
+            decorrelate: true,
+            stationary: None,
+            phases: None,
+            decompose: false,
+            choice_impact: false,
+            conditional_spread: false,
+            track_sizes: false,
+        }
+    }
+}
+
+/// How to scale the rank errors of a DistributionAnalysis, so that configurations of different
+/// sizes can be plotted on the same axes, see normalize_rank_errors
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Normalization {
+    /// Leaves the rank errors as they are
+    #[default]
+    None,
+
+    /// Divides the rank errors by the number of sub-queues minus one
+    PerPartial,
+
+    /// Divides the rank errors by the prefill
+    PerPrefill,
+
+    /// Divides each rank error by the number of items in the queue at its dequeue, which must be
+    /// tracked. Empty queues count as one item, as their dequeues have no rank error
+    PerSize,
+}
+
+/// The sorted distributions (and optional extras) from analyze_distributions
+pub struct DistributionAnalysis {
+    /// Rank errors
+    pub rank_errors: Vec<f32>,
+
+    /// Difference of enqueue nbr and dequeue nbr (for non-empty returns only)
+    pub enq_deq_diffs: Vec<f32>,
+
+    /// Difference between the partial queue load and average load at dequeue
+    pub subqueue_deq_diffs: Vec<f32>,
+
+    /// Difference between the partial queue load and average load at enqueue (sampled from returned items)
+    pub subqueue_enq_diffs: Vec<f32>,
+
+    /// The partial enqueue counts at the end. Subtracted by the mean load, and sorted in ascending order
+    pub subqueue_enq_counts: Vec<f32>,
+
+    /// The partial dequeue counts at the end. Subtracted by the mean load, and sorted in ascending order
+    pub subqueue_deq_counts: Vec<f32>,
+
+    /// Rank errors over operation time, if requested
+    pub heatmap: Option<RankErrorHeatmap>,
+
+    /// Rank errors of the (prefill, operation) items, if requested. Empty returns are in neither
+    pub prefill_split: Option<(Vec<f32>, Vec<f32>)>,
+
+    /// Rank errors of the (direct, fallback) dequeues, if requested. Empty returns are included
+    pub fallback_split: Option<(Vec<f32>, Vec<f32>)>,
+
+    /// The fraction of the dequeues which fell back to the round-robin scan, if requested
+    pub fallback_fraction: Option<f32>,
+
+    /// Rank error statistics per logical thread, if operations were attributed to threads
+    pub per_thread: Option<Vec<ThreadSummary>>,
+
+    /// The ages of the dequeued items by the sub-queue serving them, if requested
+    pub age_by_subqueue: Option<AgeBySubqueue>,
+
+    /// The sorted steady-state rank errors with their summary, if requested
+    pub stationary: Option<(Vec<f32>, StationarySummary)>,
+
+    /// Rank error statistics per phase of the operations, if requested
+    pub per_phase: Option<Vec<PhaseSummary>>,
+
+    /// The (placement, selection) rank errors, if requested
+    pub decomposition: Option<(Vec<f32>, Vec<f32>)>,
+
+    /// How often the extra samples changed the choices, if requested
+    pub choice_impact: Option<ChoiceImpact>,
+
+    /// The rank errors by the score spread of the dequeue candidates, if requested
+    pub conditional_spread: Option<ConditionalSpread>,
+
+    /// The number of items in the queue at each dequeue, in the order of the rank errors, if
+    /// requested
+    pub live_sizes: Option<Vec<usize>>,
+}
+
+/// Rank error statistics for the dequeues of one logical thread
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ThreadSummary {
+    /// The number of dequeues issued by the thread
+    pub dequeues: usize,
+
+    /// The mean rank error of the thread's dequeues
+    pub mean_rank_error: f32,
+
+    /// The 99th percentile rank error of the thread's dequeues, as a float to average over runs
+    pub p99_rank_error: f32,
+
+    /// The largest rank error of the thread's dequeues
+    pub max_rank_error: usize,
+}
+
+/// Rank error statistics for the dequeues of one phase of the operations
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PhaseSummary {
+    /// The number of operations in the phase
+    pub operations: usize,
+
+    /// The number of dequeues in the phase, including the empty returns
+    pub dequeues: usize,
+
+    /// The number of dequeues in the phase which found the queue empty
+    pub empty_returns: usize,
+
+    /// The mean rank error of the phase's dequeues
+    pub mean_rank_error: f32,
+
+    /// The 99th percentile rank error of the phase's dequeues, as a float to average over runs
+    pub p99_rank_error: f32,
+
+    /// The largest rank error of the phase's dequeues
+    pub max_rank_error: usize,
+}
+
+/// How often sampling more than one sub-queue changed the choices, from analyze_choice_impact
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ChoiceImpact {
+    /// The fraction of the operations which chose another sub-queue than the first sampled one
+    pub changed_fraction: f32,
+
+    /// The mean rank error improvement of the changed dequeues, see ChoiceImpactRow
+    pub mean_improvement: f32,
+
+    pub enqueues: ChoiceImpactRow,
+    pub dequeues: ChoiceImpactRow,
+}
+
+/// How often the extra samples changed the choices of one kind of operations
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ChoiceImpactRow {
+    /// The number of operations which chose a sub-queue, which excludes the buffered dequeues
+    pub operations: usize,
+
+    /// The number of operations which chose another sub-queue than the first sampled one
+    pub changed: usize,
+
+    pub changed_fraction: f32,
+
+    /// The number of changed dequeues whose first sampled sub-queue had an item, and which did
+    /// not return empty, so that their rank errors can be compared
+    pub compared: usize,
+
+    /// The mean of the rank error dequeuing from the first sampled sub-queue would have had,
+    /// minus the actual rank error, over the compared dequeues
+    pub mean_improvement: f32,
+}
+
+/// The rank errors of the dequeues by how spread out the scores of their candidates were, from
+/// analyze_conditional_spread
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ConditionalSpread {
+    /// The sorted score spreads of the dequeues, as a distribution
+    pub spreads: Vec<f32>,
+
+    /// The dequeues split into deciles by their spreads, from the least spread
+    pub deciles: Vec<SpreadBucket>,
+}
+
+/// The dequeues within a range of candidate score spreads
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SpreadBucket {
+    pub min_spread: f32,
+    pub max_spread: f32,
+    pub dequeues: usize,
+    pub mean_rank_error: f32,
+}
+
+/// The ages of the items served by each sub-queue, from analyze_age_by_subqueue
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AgeBySubqueue {
+    /// Age statistics of the items served by each sub-queue, indexed by sub-queue
+    pub subqueues: Vec<SubqueueAges>,
+
+    /// The Pearson correlation between the age of each dequeued item and the load rank of the
+    /// sub-queue serving it, where the least loaded sub-queue has rank 0. It is 0 if either does
+    /// not vary.
+    pub load_correlation: f32,
+}
+
+/// Age statistics for the items one sub-queue served
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SubqueueAges {
+    /// The number of items the sub-queue served
+    pub dequeues: usize,
+
+    /// The mean age of the items, in operations from their enqueue to their dequeue
+    pub mean_age: f32,
+
+    /// The 99th percentile age of the items, as a float to average over runs
+    pub p99_age: f32,
+}
 
 /// Analyze relaxation properties of a relaxed queue (passed empty)
 ///
-/// Returns sorted discrete probability density functions (pdf). These each have pdf_samples samples.
-/// Pdfs returned: (
-///     - Rank errors,
-///     - Difference of enqueue nbr and dequeue nbr (for non-empty returns only),
-///     - Difference between the partial queue load and average load at dequeue,
-///     - Difference between the partial queue load and average load at enqueue (sampled from returned items),
-///     - The partial enqueue counts at the end. Subtracted by the mean load, and sorted in ascending order
-///     - The partial dequeue counts at the end. Subtracted by the mean load, and sorted in ascending order
+/// Returns sorted discrete probability density functions (pdf), see DistributionAnalysis. If threads
+/// is given, it holds the logical thread issuing each operation. The rng draws the number of extra
+/// operations if decorrelating, see DistributionOptions.
+///
+/// # Examples
+///
+/// ```
+/// use rand::{rngs::StdRng, SeedableRng};
+/// use relaxation_analysis::{
+///     analyze_distributions, gen_balanced_ops, DChoiceQueueBuilder, DistributionOptions,
+/// };
+///
+/// let mut rng = StdRng::seed_from_u64(1);
+/// let operations = gen_balanced_ops(10_000, &mut rng);
+/// let mut queue = DChoiceQueueBuilder::new(8).seed(Some(1)).build();
+///
+/// let analysis = analyze_distributions(
+///     &mut queue,
+///     1_000,
+///     &operations,
+///     None,
+///     &DistributionOptions::default(),
+///     &mut rng,
 /// )
+/// .unwrap();
+/// assert_eq!(analysis.subqueue_enq_counts.len(), 8);
+/// assert!(!analysis.rank_errors.is_empty());
+/// ```
 pub fn analyze_distributions(
     relaxed_queue: &mut DChoiceQueue<usize>,
     prefill: usize,
-    operations: &Vec<bool>,
-) -> (Vec<f32>, Vec<f32>, Vec<f32>, Vec<f32>, Vec<f32>, Vec<f32>) {
-    let extra_ops = rand::thread_rng().gen_range(0..relaxed_queue.nbr_subqueues());
+    operations: &[bool],
+    threads: Option<&[usize]>,
+    options: &DistributionOptions,
+    rng: &mut impl Rng,
+) -> Result<DistributionAnalysis, AnalysisError> {
+    let extra_ops = if options.decorrelate {
+        rng.gen_range(0..relaxed_queue.nbr_subqueues())
+    } else {
+        0
+    };
     // A bit of a hack, but add some extra enqueue and dequeues at the end to get random mean values of loads
-    let extended_operations = operations
+    let extended_operations: Vec<bool> = operations
         .iter()
         .cloned()
-        .chain(std::iter::repeat(true).take(extra_ops))
-        .chain(std::iter::repeat(false).take(extra_ops))
+        .chain(std::iter::repeat_n(true, extra_ops))
+        .chain(std::iter::repeat_n(false, extra_ops))
         .collect();
 
-    let error_tags = analyze_extra(relaxed_queue, prefill, &extended_operations);
+    let (mut error_tags, mut placement_errors) = if options.decompose {
+        let (error_tags, placement_errors) =
+            analyze_decomposed(relaxed_queue, prefill, &extended_operations, threads)?;
+        (error_tags, Some(placement_errors))
+    } else {
+        let error_tags = analyze_extra(relaxed_queue, prefill, &extended_operations, threads)?;
+        (error_tags, None)
+    };
+    // The i-th tag belongs to the i-th dequeue, so only keep those of the requested operations
+    let dequeues = operations.iter().filter(|is_enqueue| !**is_enqueue).count();
+    error_tags.truncate(dequeues);
+
+    let decomposition = placement_errors.as_mut().map(|placement_errors| {
+        placement_errors.truncate(dequeues);
+        let mut selection_errors: Vec<usize> = error_tags
+            .iter()
+            .zip(placement_errors.iter())
+            .map(|(tag, placement)| tag.rank_error() - placement)
+            .collect();
+        placement_errors.sort();
+        selection_errors.sort();
+        let to_f32 = |errors: &[usize]| errors.iter().map(|val| *val as f32).collect();
+        (to_f32(placement_errors), to_f32(&selection_errors))
+    });
+
+    let heatmap = options.heatmap.map(|(op_buckets, error_buckets)| {
+        let mut heatmap = RankErrorHeatmap::new(operations.len(), op_buckets, error_buckets);
+        operations
+            .iter()
+            .enumerate()
+            .filter(|(_, is_enqueue)| !**is_enqueue)
+            .zip(error_tags.iter())
+            .for_each(|((op_index, _), tag)| heatmap.record(op_index, tag.rank_error()));
+        heatmap
+    });
+
+    let per_thread = threads.map(|_| analyze_per_thread(&error_tags));
 
-    let mut rank_errors: Vec<usize> = error_tags.iter().map(|tag| tag.rank_error()).collect();
+    let per_phase = options
+        .phases
+        .as_deref()
+        .map(|lengths| analyze_phases(&error_tags, operations, lengths));
+
+    let age_by_subqueue = options.age_by_subqueue.then(|| {
+        analyze_age_by_subqueue(
+            &error_tags,
+            &extended_operations,
+            prefill,
+            &relaxed_queue.subqueue_enqueue_counts(),
+        )
+    });
+
+    let stationary = options.stationary.map(|(burn_in, thinning)| {
+        let series: Vec<f64> = error_tags
+            .iter()
+            .map(|tag| tag.rank_error() as f64)
+            .collect();
+        let (mut kept, summary) = stationary_sample(&series, burn_in, thinning);
+        kept.sort_by(f64::total_cmp);
+        (kept.into_iter().map(|val| val as f32).collect(), summary)
+    });
+
+    let prefill_split = options.tag_prefill.then(|| {
+        let series = |prefill: bool| {
+            let mut rank_errors: Vec<usize> = error_tags
+                .iter()
+                .filter(|tag| tag.from_prefill() == Some(prefill))
+                .map(|tag| tag.rank_error())
+                .collect();
+            rank_errors.sort();
+            rank_errors.into_iter().map(|val| val as f32).collect()
+        };
+        (series(true), series(false))
+    });
+
+    let fallback_split = options.split_fallback.then(|| {
+        let series = |fell_back: bool| {
+            let mut rank_errors: Vec<usize> = error_tags
+                .iter()
+                .filter(|tag| tag.fell_back() == fell_back)
+                .map(|tag| tag.rank_error())
+                .collect();
+            rank_errors.sort();
+            rank_errors.into_iter().map(|val| val as f32).collect()
+        };
+        (series(false), series(true))
+    });
+    let fallback_fraction = options.split_fallback.then(|| {
+        let fallbacks = error_tags.iter().filter(|tag| tag.fell_back()).count();
+        fallbacks as f32 / error_tags.len().max(1) as f32
+    });
+
+    let records = (options.choice_impact || options.conditional_spread).then(|| {
+        let records = relaxed_queue
+            .choice_log()
+            .expect("The queue must log its choices for the choice impact and spread");
+        &records[..records.len().min(prefill + operations.len())]
+    });
+    let choice_impact = records
+        .filter(|_| options.choice_impact)
+        .map(|records| analyze_choice_impact(records, prefill, &error_tags));
+    let conditional_spread = records
+        .filter(|_| options.conditional_spread)
+        .map(|records| analyze_conditional_spread(records, prefill, &error_tags));
+
+    // The sizes are sorted along with the rank errors, so that they stay paired up
+    let mut rank_errors: Vec<(usize, usize)> = error_tags
+        .iter()
+        .zip(live_sizes(prefill, operations, &error_tags))
+        .map(|(tag, size)| (tag.rank_error(), size))
+        .collect();
     rank_errors.sort();
+    let (rank_errors, sizes): (Vec<usize>, Vec<usize>) = rank_errors.into_iter().unzip();
+    let live_sizes = options.track_sizes.then_some(sizes);
 
     let mut enq_deq_diffs: Vec<i64> = error_tags
         .iter()
@@ -57,9 +454,7 @@ pub fn analyze_distributions(
         .iter()
         .filter_map(|tag| match tag {
             ErrorTag::ItemDequeue {
-                enq_nbr,
-                sub_nbr,
-                ..
+                enq_nbr, sub_nbr, ..
             } => {
                 let mean = *enq_nbr as f32 / relaxed_queue.nbr_subqueues() as f32;
                 Some(*sub_nbr as f32 - 1.0 - mean)
@@ -87,13 +482,1078 @@ pub fn analyze_distributions(
         .map(|val| val as f32 - dequeue_avg)
         .collect();
 
+    Ok(DistributionAnalysis {
+        rank_errors: rank_errors.into_iter().map(|val| val as f32).collect(),
+        enq_deq_diffs: enq_deq_diffs.into_iter().map(|val| val as f32).collect(),
+        subqueue_deq_diffs: subqueue_deq_diff,
+        subqueue_enq_diffs: subqueue_enq_diff,
+        subqueue_enq_counts: enqueue_normlized_counts,
+        subqueue_deq_counts: dequeue_normlized_counts,
+        heatmap,
+        prefill_split,
+        fallback_split,
+        fallback_fraction,
+        per_thread,
+        age_by_subqueue,
+        stationary,
+        per_phase,
+        decomposition,
+        choice_impact,
+        conditional_spread,
+        live_sizes,
+    })
+}
+
+/// The number of items in the queue at each dequeue, including the dequeued one
+///
+/// The error tags are those of the dequeues of the operations, as from analyze_extra.
+fn live_sizes(prefill: usize, operations: &[bool], error_tags: &[ErrorTag]) -> Vec<usize> {
+    let mut size = prefill;
+    let mut tags = error_tags.iter();
+    let mut sizes = vec![];
+    for is_enqueue in operations.iter() {
+        if *is_enqueue {
+            size += 1;
+            continue;
+        }
+        let Some(tag) = tags.next() else {
+            break;
+        };
+        sizes.push(size);
+        if let ErrorTag::ItemDequeue { .. } = tag {
+            size -= 1;
+        }
+    }
+    sizes
+}
+
+/// Scales the rank errors of the analysis by the normalization, keeping them sorted
+///
+/// Returns an error instead of dividing by zero, if there is only one sub-queue for per-partial,
+/// no prefill for per-prefill, or the sizes were not tracked for per-size. Only the main rank
+/// error distribution is scaled, and the sizes stay paired up with it.
+pub fn normalize_rank_errors(
+    analysis: &mut DistributionAnalysis,
+    normalization: Normalization,
+    subqueues: usize,
+    prefill: usize,
+) -> Result<(), String> {
+    let divisor = match normalization {
+        Normalization::None => return Ok(()),
+        Normalization::PerPartial if subqueues < 2 => {
+            return Err("Normalizing per partial needs at least two sub-queues".to_string())
+        }
+        Normalization::PerPartial => (subqueues - 1) as f32,
+        Normalization::PerPrefill if prefill == 0 => {
+            return Err("Normalizing per prefill needs a prefill".to_string())
+        }
+        Normalization::PerPrefill => prefill as f32,
+        Normalization::PerSize => {
+            let sizes = analysis
+                .live_sizes
+                .as_ref()
+                .ok_or("Normalizing per size needs the sizes to be tracked")?;
+            let mut scaled: Vec<(f32, usize)> = analysis
+                .rank_errors
+                .iter()
+                .zip(sizes)
+                .map(|(rank_error, size)| (rank_error / (*size).max(1) as f32, *size))
+                .collect();
+            scaled.sort_by(|(error_a, _), (error_b, _)| error_a.total_cmp(error_b));
+            let (rank_errors, sizes) = scaled.into_iter().unzip();
+            analysis.rank_errors = rank_errors;
+            analysis.live_sizes = Some(sizes);
+            return Ok(());
+        }
+    };
+    analysis
+        .rank_errors
+        .iter_mut()
+        .for_each(|rank_error| *rank_error /= divisor);
+    Ok(())
+}
+
+/// Summarizes the ages of the dequeued items by the sub-queue serving them
+///
+/// The error tags are those of the dequeues of the operations, as from analyze_extra. The age of
+/// an item is the number of operations from its enqueue to its dequeue, where the prefill items
+/// are enqueued just before the first operation. The sub-queues are ranked by their loads, such as
+/// their enqueue counts, to correlate the ages with.
+pub fn analyze_age_by_subqueue(
+    error_tags: &[ErrorTag],
+    operations: &[bool],
+    prefill: usize,
+    loads: &[usize],
+) -> AgeBySubqueue {
+    // The operation after which each item was enqueued, counting the prefill as operation 0
+    let mut enqueued_at = vec![0; prefill];
+    let mut tags = error_tags.iter();
+    let mut subqueue_ages: Vec<Vec<usize>> = vec![vec![]; loads.len()];
+    for (op_index, is_enqueue) in operations.iter().enumerate() {
+        if *is_enqueue {
+            enqueued_at.push(op_index + 1);
+        } else if let Some(ErrorTag::ItemDequeue {
+            enq_nbr, subqueue, ..
+        }) = tags.next()
+        {
+            subqueue_ages[*subqueue].push(op_index + 1 - enqueued_at[*enq_nbr]);
+        }
+    }
+
+    let mut by_load: Vec<usize> = (0..loads.len()).collect();
+    by_load.sort_by_key(|subqueue| loads[*subqueue]);
+    let mut load_ranks = vec![0; loads.len()];
+    for (rank, subqueue) in by_load.into_iter().enumerate() {
+        load_ranks[subqueue] = rank;
+    }
+    let pairs: Vec<(f64, f64)> = subqueue_ages
+        .iter()
+        .zip(load_ranks)
+        .flat_map(|(ages, rank)| ages.iter().map(move |age| (*age as f64, rank as f64)))
+        .collect();
+
+    let subqueues = subqueue_ages
+        .into_iter()
+        .map(|mut ages| {
+            if ages.is_empty() {
+                return SubqueueAges::default();
+            }
+            ages.sort_unstable();
+            let p99_ind = (ages.len() * 99).div_ceil(100) - 1;
+            SubqueueAges {
+                dequeues: ages.len(),
+                mean_age: ages.iter().sum::<usize>() as f32 / ages.len() as f32,
+                p99_age: ages[p99_ind] as f32,
+            }
+        })
+        .collect();
+
+    AgeBySubqueue {
+        subqueues,
+        load_correlation: pearson_correlation(&pairs) as f32,
+    }
+}
+
+/// The Pearson correlation coefficient of the pairs, or 0 if either coordinate does not vary
+fn pearson_correlation(pairs: &[(f64, f64)]) -> f64 {
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let (mut covariance, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (x, y) in pairs {
+        covariance += (x - mean_x) * (y - mean_y);
+        var_x += (x - mean_x).powi(2);
+        var_y += (y - mean_y).powi(2);
+    }
+    if var_x > 0.0 && var_y > 0.0 {
+        covariance / (var_x * var_y).sqrt()
+    } else {
+        0.0
+    }
+}
+
+/// Summarizes the rank errors of the dequeues of each logical thread
+///
+/// Dequeues not attributed to any thread are skipped. The result is indexed by thread id.
+pub fn analyze_per_thread(error_tags: &[ErrorTag]) -> Vec<ThreadSummary> {
+    let mut thread_errors: Vec<Vec<usize>> = vec![];
+    for tag in error_tags {
+        let Some(thread) = tag.thread() else {
+            continue;
+        };
+        if thread >= thread_errors.len() {
+            thread_errors.resize(thread + 1, vec![]);
+        }
+        thread_errors[thread].push(tag.rank_error());
+    }
+
+    thread_errors
+        .into_iter()
+        .map(|mut errors| {
+            let (mean_rank_error, p99_rank_error, max_rank_error) = error_stats(&mut errors);
+            ThreadSummary {
+                dequeues: errors.len(),
+                mean_rank_error,
+                p99_rank_error,
+                max_rank_error,
+            }
+        })
+        .collect()
+}
+
+/// Summarizes the rank errors of consecutive phases of the operations, with the given numbers of
+/// operations in each
+///
+/// The error tags are those of the dequeues of the operations, as from analyze_extra. Operations
+/// past the last phase are not summarized.
+pub fn analyze_phases(
+    error_tags: &[ErrorTag],
+    operations: &[bool],
+    lengths: &[usize],
+) -> Vec<PhaseSummary> {
+    let mut tags = error_tags.iter();
+    let mut start = 0;
+    lengths
+        .iter()
+        .map(|&length| {
+            let phase_start = start;
+            let end = (start + length).min(operations.len());
+            let dequeues = operations[start..end]
+                .iter()
+                .filter(|is_enqueue| !**is_enqueue)
+                .count();
+            start = end;
+            let phase_tags: Vec<&ErrorTag> = tags.by_ref().take(dequeues).collect();
+            let mut errors: Vec<usize> = phase_tags.iter().map(|tag| tag.rank_error()).collect();
+            let (mean_rank_error, p99_rank_error, max_rank_error) = error_stats(&mut errors);
+            PhaseSummary {
+                // A phase past the end of the operations is cut short
+                operations: end - phase_start,
+                dequeues,
+                empty_returns: phase_tags
+                    .iter()
+                    .filter(|tag| matches!(tag, ErrorTag::EmptyDequeue { .. }))
+                    .count(),
+                mean_rank_error,
+                p99_rank_error,
+                max_rank_error,
+            }
+        })
+        .collect()
+}
+
+/// The (mean, p99, max) of the rank errors, which get sorted, or zeros if there are none
+fn error_stats(errors: &mut [usize]) -> (f32, f32, usize) {
+    if errors.is_empty() {
+        return (0.0, 0.0, 0);
+    }
+    errors.sort_unstable();
+    let p99_ind = (errors.len() * 99).div_ceil(100) - 1;
     (
-        rank_errors.into_iter().map(|val| val as f32).collect(),
-        enq_deq_diffs.into_iter().map(|val| val as f32).collect(),
-        subqueue_deq_diff,
-        subqueue_enq_diff,
-        enqueue_normlized_counts,
-        dequeue_normlized_counts,
+        errors.iter().sum::<usize>() as f32 / errors.len() as f32,
+        errors[p99_ind] as f32,
+        errors[errors.len() - 1],
     )
 }
 
+/// Summarizes how often sampling more than one sub-queue changed the choices, compared to
+/// choosing the first sampled sub-queue as d = 1 would, and how much it improved the rank errors
+///
+/// The records are those of the prefill enqueues followed by the operations, as logged by the
+/// queue, and the error tags are those of the dequeues of the operations, as from analyze_extra.
+/// The choices of the prefill are not summarized.
+pub fn analyze_choice_impact(
+    records: &[ChoiceRecord],
+    prefill: usize,
+    error_tags: &[ErrorTag],
+) -> ChoiceImpact {
+    let mut enqueues = ChoiceImpactRow::default();
+    let mut dequeues = ChoiceImpactRow::default();
+    let mut improvement = 0;
+    for (record, tag) in tagged_choices(records, prefill, error_tags) {
+        let (Some(chosen), Some(first)) = (record.chosen, record.first) else {
+            continue;
+        };
+        let row = match record.kind {
+            OpKind::Enqueue => &mut enqueues,
+            OpKind::Dequeue => &mut dequeues,
+        };
+        row.operations += 1;
+        if chosen == first {
+            continue;
+        }
+        row.changed += 1;
+        if let (Some(first_rank_error), Some(ErrorTag::ItemDequeue { rank_error, .. })) =
+            (record.first_rank_error, tag)
+        {
+            row.compared += 1;
+            improvement += first_rank_error as i64 - *rank_error as i64;
+        }
+    }
+
+    let fraction = |part: usize, whole: usize| part as f32 / whole.max(1) as f32;
+    for row in [&mut enqueues, &mut dequeues] {
+        row.changed_fraction = fraction(row.changed, row.operations);
+    }
+    dequeues.mean_improvement = improvement as f32 / dequeues.compared.max(1) as f32;
+    ChoiceImpact {
+        changed_fraction: fraction(
+            enqueues.changed + dequeues.changed,
+            enqueues.operations + dequeues.operations,
+        ),
+        mean_improvement: dequeues.mean_improvement,
+        enqueues,
+        dequeues,
+    }
+}
+
+/// Summarizes the rank errors of the dequeues by the spread of their candidates, the largest
+/// minus the smallest heuristic score among the sampled sub-queues
+///
+/// The records and error tags are as for analyze_choice_impact. Dequeues which did not choose,
+/// and those with an infinite score among their candidates, are left out. The deciles split the
+/// rest into ten buckets of as equal sizes as possible, by their sorted spreads.
+pub fn analyze_conditional_spread(
+    records: &[ChoiceRecord],
+    prefill: usize,
+    error_tags: &[ErrorTag],
+) -> ConditionalSpread {
+    let mut dequeues: Vec<(f64, usize)> = tagged_choices(records, prefill, error_tags)
+        .filter(|(record, _)| record.kind == OpKind::Dequeue && record.chosen.is_some())
+        .filter_map(|(record, tag)| {
+            let scores = record.sampled.iter().map(|(_, score)| *score);
+            let max = scores.clone().max_by(f64::total_cmp)?;
+            let min = scores.min_by(f64::total_cmp)?;
+            let spread = max - min;
+            let rank_error = tag.expect("Each dequeue has an error tag").rank_error();
+            spread.is_finite().then_some((spread, rank_error))
+        })
+        .collect();
+    dequeues.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let deciles = (0..10)
+        .map(|decile| &dequeues[decile * dequeues.len() / 10..(decile + 1) * dequeues.len() / 10])
+        .filter(|bucket| !bucket.is_empty())
+        .map(|bucket| SpreadBucket {
+            min_spread: bucket[0].0 as f32,
+            max_spread: bucket[bucket.len() - 1].0 as f32,
+            dequeues: bucket.len(),
+            mean_rank_error: bucket.iter().map(|(_, error)| *error).sum::<usize>() as f32
+                / bucket.len() as f32,
+        })
+        .collect();
+    ConditionalSpread {
+        spreads: dequeues.iter().map(|(spread, _)| *spread as f32).collect(),
+        deciles,
+    }
+}
+
+/// Pairs the logged choices of the operations after the prefill with the error tags of the
+/// dequeues, where enqueues have no tag
+fn tagged_choices<'a>(
+    records: &'a [ChoiceRecord],
+    prefill: usize,
+    error_tags: &'a [ErrorTag],
+) -> impl Iterator<Item = (&'a ChoiceRecord, Option<&'a ErrorTag>)> {
+    let mut tags = error_tags.iter();
+    records.iter().skip(prefill).map(move |record| {
+        let tag = match record.kind {
+            OpKind::Enqueue => None,
+            OpKind::Dequeue => tags.next(),
+        };
+        (record, tag)
+    })
+}
+
+/// How the prefill items left the queue during a run
+#[derive(Clone, Debug, Serialize)]
+pub struct PrefillFlush {
+    /// The dequeue (counting empty returns) which removed the last prefill item, if all left
+    pub flush_dequeue: Option<usize>,
+
+    /// The number of prefill items never dequeued
+    pub remaining: usize,
+
+    /// The mean rank error of the dequeued prefill items
+    pub mean_rank_error: f32,
+
+    /// The largest rank error of the dequeued prefill items
+    pub max_rank_error: usize,
+}
+
+/// Finds when the last of the prefill items were dequeued, and their rank errors
+pub fn analyze_prefill_flush(error_tags: &[ErrorTag], prefill: usize) -> PrefillFlush {
+    let mut dequeued = 0;
+    let mut last_dequeue = 0;
+    let mut error_sum = 0;
+    let mut max_rank_error = 0;
+    for tag in error_tags {
+        if tag.from_prefill() == Some(true) {
+            dequeued += 1;
+            last_dequeue = tag.deq_nbr();
+            error_sum += tag.rank_error();
+            max_rank_error = max_rank_error.max(tag.rank_error());
+        }
+    }
+
+    PrefillFlush {
+        flush_dequeue: (dequeued == prefill).then_some(last_dequeue),
+        remaining: prefill - dequeued,
+        mean_rank_error: if dequeued > 0 {
+            error_sum as f32 / dequeued as f32
+        } else {
+            0.0
+        },
+        max_rank_error,
+    }
+}
+
+/// How long a run took to recover from a skewed prefill
+#[derive(Clone, Debug, Serialize)]
+pub struct Recovery {
+    /// The mean rank error of each window of operations
+    pub windowed_means: Vec<f32>,
+
+    /// The number of operations until the end of the first window whose mean rank error is at
+    /// most the threshold times that of the first window, if any
+    pub recovery_ops: Option<usize>,
+}
+
+/// The mean rank error of the dequeues in each full window of operations, as given by
+/// analyze_skewed_prefill. A window without dequeues keeps the mean of the one before it.
+pub fn windowed_mean_errors(op_errors: &[Option<usize>], window: usize) -> Vec<f32> {
+    let mut means: Vec<f32> = vec![];
+    for ops in op_errors.chunks_exact(window) {
+        let (sum, count) = ops
+            .iter()
+            .flatten()
+            .fold((0, 0), |(sum, count), error| (sum + error, count + 1));
+        means.push(if count > 0 {
+            sum as f32 / count as f32
+        } else {
+            means.last().copied().unwrap_or(0.0)
+        });
+    }
+    means
+}
+
+/// Finds after how many operations the windowed mean rank error first drops to at most threshold
+/// times its initial value, such as 0.1 for 10%
+pub fn analyze_recovery(op_errors: &[Option<usize>], window: usize, threshold: f32) -> Recovery {
+    let windowed_means = windowed_mean_errors(op_errors, window);
+    let recovery_ops = windowed_means.first().and_then(|initial| {
+        windowed_means
+            .iter()
+            .skip(1)
+            .position(|mean| *mean <= threshold * initial)
+            .map(|ind| (ind + 2) * window)
+    });
+    Recovery {
+        windowed_means,
+        recovery_ops,
+    }
+}
+
+/// The realized statistics of an operation sequence, before running it on any queue
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct OpsDescription {
+    pub operations: usize,
+    pub enqueues: usize,
+    pub dequeues: usize,
+
+    /// The fraction of the operations which are enqueues, or 0 without operations
+    pub enqueue_fraction: f32,
+
+    /// The most consecutive enqueues and dequeues
+    pub longest_enqueue_run: usize,
+    pub longest_dequeue_run: usize,
+
+    /// The extremes and end of the queue size over the sequence, starting from the prefill
+    pub min_size: usize,
+    pub max_size: usize,
+    pub final_size: usize,
+
+    /// The length of the shortest prefix which leaves the queue empty, if any, where it is 0
+    /// without prefill
+    pub first_empty: Option<usize>,
+
+    /// The number of dequeues which find the queue empty, and so return nothing
+    pub empty_dequeues: usize,
+}
+
+/// Describes the operations, where true is an enqueue, as run after prefill items
+///
+/// The queue sizes do not depend on the queue, as every dequeue on a non-empty queue removes an
+/// item, whichever it is.
+pub fn describe_operations(prefill: usize, operations: &[bool]) -> OpsDescription {
+    let mut description = OpsDescription {
+        operations: operations.len(),
+        min_size: prefill,
+        max_size: prefill,
+        first_empty: (prefill == 0).then_some(0),
+        ..OpsDescription::default()
+    };
+    let mut size = prefill;
+    let mut run = (true, 0);
+    for (ind, is_enqueue) in operations.iter().enumerate() {
+        run = if run.0 == *is_enqueue {
+            (run.0, run.1 + 1)
+        } else {
+            (*is_enqueue, 1)
+        };
+        if *is_enqueue {
+            description.enqueues += 1;
+            description.longest_enqueue_run = description.longest_enqueue_run.max(run.1);
+            size += 1;
+        } else {
+            description.dequeues += 1;
+            description.longest_dequeue_run = description.longest_dequeue_run.max(run.1);
+            match size.checked_sub(1) {
+                Some(smaller) => size = smaller,
+                None => description.empty_dequeues += 1,
+            }
+            if size == 0 && description.first_empty.is_none() {
+                description.first_empty = Some(ind + 1);
+            }
+        }
+        description.min_size = description.min_size.min(size);
+        description.max_size = description.max_size.max(size);
+    }
+    description.final_size = size;
+    description.enqueue_fraction = if operations.is_empty() {
+        0.0
+    } else {
+        description.enqueues as f32 / operations.len() as f32
+    };
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analyze_concurrent, gen_balanced_ops, replay_decisions, ConcurrentConfig,
+        DChoiceQueueBuilder, Decision,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn record(kind: OpKind, sampled: &[(usize, f64)], chosen: Option<usize>) -> ChoiceRecord {
+        ChoiceRecord {
+            kind,
+            sampled: sampled.to_vec(),
+            chosen,
+            first: chosen.and(sampled.first().map(|(subqueue, _)| *subqueue)),
+            first_rank_error: None,
+        }
+    }
+
+    fn item_tag(rank_error: usize, subqueue: usize) -> ErrorTag {
+        ErrorTag::ItemDequeue {
+            rank_error,
+            enq_nbr: 0,
+            deq_nbr: 0,
+            sub_nbr: 0,
+            thread: None,
+            from_prefill: false,
+            fell_back: false,
+            d: 2,
+            subqueue,
+        }
+    }
+
+    fn queue(seed: u64) -> DChoiceQueue<usize> {
+        DChoiceQueueBuilder::new(8)
+            .d(2)
+            .seed(Some(seed))
+            .build_sequenced()
+    }
+
+    #[test]
+    fn without_decorrelation_the_rng_is_unused() {
+        let operations = gen_balanced_ops(500, &mut StdRng::seed_from_u64(1));
+        let options = DistributionOptions {
+            decorrelate: false,
+            ..Default::default()
+        };
+        let analyze = |rng_seed: u64| {
+            analyze_distributions(
+                &mut queue(2),
+                20,
+                &operations,
+                None,
+                &options,
+                &mut StdRng::seed_from_u64(rng_seed),
+            )
+            .unwrap()
+        };
+        let (first, second) = (analyze(3), analyze(4));
+        assert_eq!(first.rank_errors, second.rank_errors);
+        assert_eq!(first.enq_deq_diffs, second.enq_deq_diffs);
+        assert_eq!(first.subqueue_enq_counts, second.subqueue_enq_counts);
+    }
+
+    #[test]
+    fn decorrelation_runs_extra_operations_but_only_reports_the_requested() {
+        let operations = gen_balanced_ops(500, &mut StdRng::seed_from_u64(1));
+        let dequeues = operations.iter().filter(|op| !**op).count();
+        for rng_seed in 0..10 {
+            let mut relaxed_queue = queue(2);
+            let analysis = analyze_distributions(
+                &mut relaxed_queue,
+                20,
+                &operations,
+                None,
+                &DistributionOptions::default(),
+                &mut StdRng::seed_from_u64(rng_seed),
+            )
+            .unwrap();
+            let extra = StdRng::seed_from_u64(rng_seed).gen_range(0..8);
+            let subqueue_dequeues: usize = relaxed_queue.subqueue_dequeue_counts().iter().sum();
+            assert_eq!(subqueue_dequeues, dequeues + extra);
+
+            // The tags of the extra dequeues are cut off, leaving those of the operations
+            let extended: Vec<bool> = operations
+                .iter()
+                .copied()
+                .chain(std::iter::repeat_n(true, extra))
+                .chain(std::iter::repeat_n(false, extra))
+                .collect();
+            let mut expected: Vec<f32> = analyze_extra(&mut queue(2), 20, &extended, None)
+                .unwrap()
+                .iter()
+                .take(dequeues)
+                .map(|tag| tag.rank_error() as f32)
+                .collect();
+            expected.sort_by(f32::total_cmp);
+            assert_eq!(analysis.rank_errors, expected);
+        }
+    }
+
+    #[test]
+    fn draining_dequeues_split_off_the_fallbacks() {
+        // A dequeue-heavy workload, so the single sampled sub-queue is often empty and the
+        // last dequeues find the whole queue drained
+        let operations: Vec<bool> = std::iter::repeat_n(true, 4)
+            .chain(std::iter::repeat_n(false, 16))
+            .collect();
+        let sparse_queue = || {
+            DChoiceQueueBuilder::new(8)
+                .d(1)
+                .seed(Some(5))
+                .build_sequenced()
+        };
+        let options = DistributionOptions {
+            split_fallback: true,
+            decorrelate: false,
+            ..Default::default()
+        };
+        let analysis = analyze_distributions(
+            &mut sparse_queue(),
+            4,
+            &operations,
+            None,
+            &options,
+            &mut StdRng::seed_from_u64(0),
+        )
+        .unwrap();
+        let (direct, fallback) = analysis.fallback_split.unwrap();
+        // The 8 items go to 6 direct dequeues and 2 scans, and the 8 empty returns fall back
+        assert_eq!(direct, vec![0.0, 0.0, 0.0, 1.0, 3.0, 5.0]);
+        assert_eq!(fallback, vec![0.0; 10]);
+        assert_eq!(analysis.fallback_fraction, Some(10.0 / 16.0));
+
+        // The split agrees with the fallbacks of the tags on the same seed
+
+        let tags = analyze_extra(&mut sparse_queue(), 4, &operations, None).unwrap();
+        let errors_of = |fell_back: bool| {
+            let mut errors: Vec<f32> = tags
+                .iter()
+                .filter(|tag| tag.fell_back() == fell_back)
+                .map(|tag| tag.rank_error() as f32)
+                .collect();
+            errors.sort_by(f32::total_cmp);
+            errors
+        };
+        assert_eq!(direct, errors_of(false));
+        assert_eq!(fallback, errors_of(true));
+    }
+
+    #[test]
+    fn per_thread_summaries_find_the_slow_thread() {
+        let operations = gen_balanced_ops(20000, &mut StdRng::seed_from_u64(1));
+        let config = ConcurrentConfig {
+            mean_durations: vec![1.0, 1.0, 10.0, 1.0],
+            seed: Some(1),
+        };
+        let error_tags = analyze_concurrent(&mut queue(1), 100, &operations, &config).unwrap();
+        let summaries = analyze_per_thread(&error_tags);
+        assert_eq!(summaries.len(), 4);
+
+        let dequeues: usize = summaries.iter().map(|summary| summary.dequeues).sum();
+        assert_eq!(dequeues, error_tags.len());
+        let max_rank_error = error_tags.iter().map(ErrorTag::rank_error).max();
+        assert_eq!(
+            summaries.iter().map(|summary| summary.max_rank_error).max(),
+            max_rank_error
+        );
+
+        let slowest = (0..4).max_by(|a, b| {
+            summaries[*a]
+                .mean_rank_error
+                .total_cmp(&summaries[*b].mean_rank_error)
+        });
+        assert_eq!(slowest, Some(2));
+        let fewest = (0..4).min_by_key(|thread| summaries[*thread].dequeues);
+        assert_eq!(fewest, Some(2));
+    }
+
+    #[test]
+    fn sequential_dequeues_have_no_thread() {
+        let operations = gen_balanced_ops(100, &mut StdRng::seed_from_u64(1));
+        let error_tags = analyze_extra(&mut queue(1), 10, &operations, None).unwrap();
+        assert!(analyze_per_thread(&error_tags).is_empty());
+    }
+
+    #[test]
+    fn spreads_skip_unchosen_and_infinite_candidates() {
+        use OpKind::*;
+        let records = [
+            record(Enqueue, &[(0, 0.0), (1, 1.0)], Some(0)),
+            record(Enqueue, &[(0, 1.0), (1, 2.0)], Some(0)),
+            record(Dequeue, &[(0, 1.0), (1, 4.0)], Some(0)),
+            record(Dequeue, &[(0, 2.0), (1, 2.0)], Some(1)),
+            record(Dequeue, &[(1, 5.0), (2, f64::INFINITY)], Some(1)),
+            record(Dequeue, &[], None),
+            record(Dequeue, &[(0, 0.0), (1, 10.0)], Some(0)),
+        ];
+        let error_tags = [
+            item_tag(2, 0),
+            item_tag(0, 1),
+            item_tag(7, 1),
+            item_tag(9, 0),
+            item_tag(6, 0),
+        ];
+        let spread = analyze_conditional_spread(&records, 1, &error_tags);
+        assert_eq!(spread.spreads, [0.0, 3.0, 10.0]);
+        let buckets: Vec<(f32, f32, usize, f32)> = spread
+            .deciles
+            .iter()
+            .map(|bucket| {
+                (
+                    bucket.min_spread,
+                    bucket.max_spread,
+                    bucket.dequeues,
+                    bucket.mean_rank_error,
+                )
+            })
+            .collect();
+        assert_eq!(
+            buckets,
+            [(0.0, 0.0, 1, 0.0), (3.0, 3.0, 1, 2.0), (10.0, 10.0, 1, 6.0)]
+        );
+    }
+
+    #[test]
+    fn spread_deciles_split_evenly() {
+        // Dequeue i has the spread i and the rank error 2i, given in reverse order
+        let records: Vec<ChoiceRecord> = (0..20)
+            .rev()
+            .map(|i| record(OpKind::Dequeue, &[(0, 1.0), (1, 1.0 + i as f64)], Some(0)))
+            .collect();
+        let error_tags: Vec<ErrorTag> = (0..20).rev().map(|i| item_tag(2 * i, 0)).collect();
+        let spread = analyze_conditional_spread(&records, 0, &error_tags);
+        assert_eq!(spread.deciles.len(), 10);
+        for (decile, bucket) in spread.deciles.iter().enumerate() {
+            assert_eq!(bucket.dequeues, 2);
+            assert_eq!(bucket.min_spread, 2.0 * decile as f32);
+            assert_eq!(bucket.max_spread, 2.0 * decile as f32 + 1.0);
+            assert_eq!(bucket.mean_rank_error, 4.0 * decile as f32 + 1.0);
+        }
+    }
+
+    #[test]
+    fn prefill_flushes_at_its_last_dequeue() {
+        use Decision::*;
+        // Items 0 to 2 are the prefill, and item 3 overtakes 0 and 2 before an empty return
+        let decisions = [
+            EnqueueTo(0),
+            EnqueueTo(1),
+            EnqueueTo(0),
+            EnqueueTo(1),
+            DequeueFrom(1),
+            DequeueFrom(1),
+            DequeueFrom(1),
+            DequeueFrom(0),
+            DequeueFrom(0),
+        ];
+        let error_tags = replay_decisions(&decisions, 3).unwrap();
+        let flush = analyze_prefill_flush(&error_tags, 3);
+        assert_eq!(flush.flush_dequeue, Some(5));
+        assert_eq!(flush.remaining, 0);
+        assert_eq!(flush.mean_rank_error, 1.0 / 3.0);
+        assert_eq!(flush.max_rank_error, 1);
+
+        // Without the last dequeue, item 2 is never flushed
+        let error_tags = replay_decisions(&decisions[..8], 3).unwrap();
+        let flush = analyze_prefill_flush(&error_tags, 3);
+        assert_eq!(flush.flush_dequeue, None);
+        assert_eq!(flush.remaining, 1);
+        assert_eq!(flush.mean_rank_error, 0.5);
+        assert_eq!(flush.max_rank_error, 1);
+    }
+
+    #[test]
+    fn unflushed_prefill_has_no_errors() {
+        let decisions = [Decision::EnqueueTo(0), Decision::EnqueueTo(1)];
+        let error_tags = replay_decisions(&decisions, 2).unwrap();
+        let flush = analyze_prefill_flush(&error_tags, 2);
+        assert_eq!(flush.flush_dequeue, None);
+        assert_eq!(flush.remaining, 2);
+        assert_eq!(flush.mean_rank_error, 0.0);
+        assert_eq!(flush.max_rank_error, 0);
+    }
+
+    #[test]
+    fn recovery_is_the_first_window_below_the_threshold() {
+        // Each window of 4 operations has two dequeues, and the errors halve every window
+        let mut op_errors: Vec<Option<usize>> = [80, 40, 20, 10, 5, 2]
+            .into_iter()
+            .flat_map(|error| [Some(error), None, Some(error), None])
+            .collect();
+        // An unfinished window is left out
+        op_errors.push(Some(0));
+        let recovery = analyze_recovery(&op_errors, 4, 0.125);
+        assert_eq!(recovery.windowed_means, [80.0, 40.0, 20.0, 10.0, 5.0, 2.0]);
+        assert_eq!(recovery.recovery_ops, Some(16));
+
+        assert_eq!(analyze_recovery(&op_errors, 4, 0.01).recovery_ops, None);
+        assert_eq!(analyze_recovery(&[], 4, 0.5).recovery_ops, None);
+    }
+
+    #[test]
+    fn windows_without_dequeues_keep_the_last_mean() {
+        let op_errors = [None, None, Some(3), Some(4), None, None, Some(1), None];
+        assert_eq!(windowed_mean_errors(&op_errors, 2), [0.0, 3.5, 3.5, 1.0]);
+    }
+
+    /// An analysis of a tiny run, with its rank errors and sizes replaced by the given ones
+    fn analysis_of(rank_errors: &[f32], live_sizes: Option<&[usize]>) -> DistributionAnalysis {
+        let mut analysis = analyze_distributions(
+            &mut queue(1),
+            2,
+            &[false, false],
+            None,
+            &DistributionOptions::default(),
+            &mut StdRng::seed_from_u64(1),
+        )
+        .unwrap();
+        analysis.rank_errors = rank_errors.to_vec();
+        analysis.live_sizes = live_sizes.map(<[usize]>::to_vec);
+        analysis
+    }
+
+    #[test]
+    fn normalization_divides_the_rank_errors() {
+        let rank_errors = [0.0, 3.0, 6.0, 12.0];
+        let mut analysis = analysis_of(&rank_errors, None);
+        normalize_rank_errors(&mut analysis, Normalization::None, 4, 8).unwrap();
+        assert_eq!(analysis.rank_errors, rank_errors);
+        normalize_rank_errors(&mut analysis, Normalization::PerPartial, 4, 8).unwrap();
+        assert_eq!(analysis.rank_errors, [0.0, 1.0, 2.0, 4.0]);
+        normalize_rank_errors(&mut analysis, Normalization::PerPrefill, 4, 8).unwrap();
+        assert_eq!(analysis.rank_errors, [0.0, 0.125, 0.25, 0.5]);
+    }
+
+    #[test]
+    fn per_size_normalization_resorts_with_the_sizes() {
+        // Empty queues count as one item
+        let mut analysis = analysis_of(&[0.0, 2.0, 3.0, 6.0], Some(&[0, 8, 3, 24]));
+        normalize_rank_errors(&mut analysis, Normalization::PerSize, 4, 8).unwrap();
+        assert_eq!(analysis.rank_errors, [0.0, 0.25, 0.25, 1.0]);
+        assert_eq!(analysis.live_sizes.unwrap(), [0, 8, 24, 3]);
+    }
+
+    #[test]
+    fn normalization_refuses_to_divide_by_zero() {
+        let mut analysis = analysis_of(&[1.0, 2.0], None);
+        assert!(normalize_rank_errors(&mut analysis, Normalization::PerPartial, 1, 8).is_err());
+        assert!(normalize_rank_errors(&mut analysis, Normalization::PerPrefill, 4, 0).is_err());
+        assert!(normalize_rank_errors(&mut analysis, Normalization::PerSize, 4, 8).is_err());
+        assert_eq!(analysis.rank_errors, [1.0, 2.0]);
+    }
+
+    /// The operations of the decisions after the prefill, true for enqueue
+    fn decision_ops(decisions: &[Decision], prefill: usize) -> Vec<bool> {
+        decisions[prefill..]
+            .iter()
+            .map(|decision| matches!(decision, Decision::EnqueueTo(_)))
+            .collect()
+    }
+
+    #[test]
+    fn ages_count_the_operations_in_the_queue() {
+        use Decision::*;
+        // Item 0 is the prefill, and the last dequeue finds the queue empty
+        let decisions = [
+            EnqueueTo(0),
+            EnqueueTo(1),
+            DequeueFrom(0),
+            EnqueueTo(1),
+            DequeueFrom(1),
+            DequeueFrom(1),
+            DequeueFrom(2),
+        ];
+        let error_tags = replay_decisions(&decisions, 1).unwrap();
+        let operations = decision_ops(&decisions, 1);
+        let ages = analyze_age_by_subqueue(&error_tags, &operations, 1, &[3, 1, 0]);
+
+        // Sub-queue 0 serves item 0 after 2 operations, and 1 serves items 1 and 2 after 3 and 2
+        let summaries: Vec<(usize, f32, f32)> = ages
+            .subqueues
+            .iter()
+            .map(|ages| (ages.dequeues, ages.mean_age, ages.p99_age))
+            .collect();
+        assert_eq!(summaries, [(1, 2.0, 2.0), (2, 2.5, 3.0), (0, 0.0, 0.0)]);
+        // Against the load ranks 2, 1 and 1, the ages 2, 3 and 2 have the correlation -1/2
+        assert!((ages.load_correlation + 0.5).abs() < 1e-6);
+
+        // Equal loads are ranked by index, so these are the ranks 1, 2 and 2
+        let ages = analyze_age_by_subqueue(&error_tags, &operations, 1, &[1, 1, 0]);
+        assert!((ages.load_correlation - 0.5).abs() < 1e-6);
+
+        // With a single dequeue, nothing varies to correlate
+        let ages = analyze_age_by_subqueue(&error_tags[..1], &operations[..2], 1, &[3, 1, 0]);
+        assert_eq!(ages.subqueues[0].dequeues, 1);
+        assert_eq!(ages.load_correlation, 0.0);
+    }
+
+    #[test]
+    fn phases_summarize_their_own_dequeues() {
+        use Decision::*;
+        // Items 0 and 1 are the prefill, and the last phase is cut short
+        let decisions = [
+            EnqueueTo(0),
+            EnqueueTo(1),
+            // The first phase overtakes item 0 once
+            EnqueueTo(0),
+            DequeueFrom(1),
+            DequeueFrom(0),
+            // The second phase empties the queue
+            DequeueFrom(0),
+            DequeueFrom(0),
+            // And the third one only enqueues
+            EnqueueTo(1),
+        ];
+        let error_tags = replay_decisions(&decisions, 2).unwrap();
+        let summaries: Vec<(usize, usize, usize, f32, usize)> =
+            analyze_phases(&error_tags, &decision_ops(&decisions, 2), &[3, 2, 4])
+                .iter()
+                .map(|phase| {
+                    (
+                        phase.operations,
+                        phase.dequeues,
+                        phase.empty_returns,
+                        phase.mean_rank_error,
+                        phase.max_rank_error,
+                    )
+                })
+                .collect();
+        assert_eq!(
+            summaries,
+            [(3, 2, 0, 0.5, 1), (2, 2, 1, 0.0, 0), (1, 0, 0, 0.0, 0)]
+        );
+
+        // Operations past the phases are left out
+        let phases = analyze_phases(&error_tags, &decision_ops(&decisions, 2), &[1]);
+        assert_eq!(phases.len(), 1);
+        assert_eq!((phases[0].operations, phases[0].dequeues), (1, 0));
+    }
+
+    #[test]
+    fn choice_impact_compares_with_the_first_sample() {
+        use OpKind::*;
+        let dequeue = |sampled: &[(usize, f64)], chosen, first_rank_error| ChoiceRecord {
+            first_rank_error,
+            ..record(Dequeue, sampled, chosen)
+        };
+        let records = [
+            // The prefill enqueue is left out
+            record(Enqueue, &[(3, 1.0), (0, 0.0)], Some(0)),
+            record(Enqueue, &[(2, 1.0), (0, 0.0)], Some(0)),
+            record(Enqueue, &[(1, 0.0), (3, 1.0)], Some(1)),
+            // Improves the rank error from 4 to 1
+            dequeue(&[(0, 5.0), (1, 2.0)], Some(1), Some(4)),
+            // Changed, but the first sample was empty so there is nothing to compare with
+            dequeue(&[(2, 0.0), (3, 0.0)], Some(3), None),
+            // Returned the help buffer without choosing
+            dequeue(&[], None, None),
+            // Unchanged, and found the queue empty
+            dequeue(&[(1, 0.0), (2, 0.0)], Some(1), Some(0)),
+            // Worsens the rank error from 0 to 2
+            dequeue(&[(3, 1.0), (2, 0.0)], Some(2), Some(0)),
+        ];
+        let empty = ErrorTag::EmptyDequeue {
+            rank_error: 0,
+            deq_nbr: 3,
+            sub_nbr: 0,
+            thread: None,
+            fell_back: true,
+            d: 2,
+            subqueue: 1,
+        };
+        let error_tags = [
+            item_tag(1, 1),
+            item_tag(0, 3),
+            item_tag(0, 0),
+            empty,
+            item_tag(2, 2),
+        ];
+        let impact = analyze_choice_impact(&records, 1, &error_tags);
+
+        let row = |row: &ChoiceImpactRow| {
+            (
+                row.operations,
+                row.changed,
+                row.changed_fraction,
+                row.compared,
+                row.mean_improvement,
+            )
+        };
+        assert_eq!(row(&impact.enqueues), (2, 1, 0.5, 0, 0.0));
+        assert_eq!(row(&impact.dequeues), (4, 3, 0.75, 2, 0.5));
+        assert_eq!(impact.changed_fraction, 4.0 / 6.0);
+        assert_eq!(impact.mean_improvement, 0.5);
+
+        // Without operations, nothing changed
+        let impact = analyze_choice_impact(&records[..1], 1, &[]);
+        assert_eq!(row(&impact.dequeues), (0, 0, 0.0, 0, 0.0));
+        assert_eq!(impact.changed_fraction, 0.0);
+    }
+
+    #[test]
+    fn descriptions_follow_the_queue_size() {
+        // The sizes are 2, 3, 2, 1, 0, 0 and 1, where the second dequeue at size 0 finds it empty
+        let operations = [true, true, false, false, false, false, true];
+        assert_eq!(
+            describe_operations(1, &operations),
+            OpsDescription {
+                operations: 7,
+                enqueues: 3,
+                dequeues: 4,
+                enqueue_fraction: 3.0 / 7.0,
+                longest_enqueue_run: 2,
+                longest_dequeue_run: 4,
+                min_size: 0,
+                max_size: 3,
+                final_size: 1,
+                first_empty: Some(5),
+                empty_dequeues: 1,
+            }
+        );
+
+        // Without prefill the queue starts out empty
+        assert_eq!(
+            describe_operations(0, &[]),
+            OpsDescription {
+                first_empty: Some(0),
+                ..OpsDescription::default()
+            }
+        );
+        assert_eq!(
+            describe_operations(2, &[true, false, false]),
+            OpsDescription {
+                operations: 3,
+                enqueues: 1,
+                dequeues: 2,
+                enqueue_fraction: 1.0 / 3.0,
+                longest_enqueue_run: 1,
+                longest_dequeue_run: 2,
+                min_size: 1,
+                max_size: 3,
+                final_size: 1,
+                first_empty: None,
+                empty_dequeues: 0,
+            }
+        );
+    }
+}
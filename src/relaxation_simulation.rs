@@ -1,46 +1,685 @@
-use std::collections::VecDeque;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    hash::Hash,
+    io::{self, Write},
+    mem::size_of,
+};
 
-use crate::{relaxed_fifo::RelaxedFifo, DChoiceQueue};
+use serde::{Deserialize, Serialize};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{
+    relaxed_fifo::{InstrumentedFifo, RelaxedFifo},
+    DChoiceQueue, DequeueInfo, FinalStateReport, LogRecord, OpKind, SteadyState,
+};
 
 /// Analyze a relaxed queue (passed empty), returning all rank errors for the operations
+///
+/// Returns an error if the queue dequeues an item it was never given, or one twice. The analyses
+/// built on it report these too, while those of the d-choice queue only check them as invariants.
+///
+/// # Examples
+///
+/// ```
+/// use rand::{rngs::StdRng, SeedableRng};
+/// use relaxation_analysis::{analyze_simple, gen_balanced_ops, DChoiceQueueBuilder, DRa};
+///
+/// let mut rng = StdRng::seed_from_u64(1);
+/// let operations = gen_balanced_ops(10_000, &mut rng);
+/// let mut queue: DRa = DChoiceQueueBuilder::new(8).d(2).seed(Some(1)).build();
+///
+/// let rank_errors = analyze_simple(&mut queue, 1_000, &operations).unwrap();
+/// assert_eq!(rank_errors.len(), 5_000);
+///
+/// let mean = rank_errors.iter().sum::<usize>() as f64 / rank_errors.len() as f64;
+/// assert!(mean > 0.0 && mean < 100.0);
+/// ```
 pub fn analyze_simple(
-    relaxed_queue: &mut impl RelaxedFifo<usize>,
+    relaxed_queue: &mut (impl RelaxedFifo<usize> + ?Sized),
+    prefill: usize,
+    operations: &[bool],
+) -> Result<Vec<usize>, AnalysisError> {
+    analyze_with_hook(relaxed_queue, prefill, operations, 0, |_, _| {})
+}
+
+/// Randomly shuffles n / 2 enqueues with the remaining dequeues, the operations of a run which
+/// keeps the queue at about its prefilled size
+pub fn gen_balanced_ops(n: usize, rng: &mut impl Rng) -> Vec<bool> {
+    let mut operations: Vec<bool> = std::iter::repeat_n(true, n / 2)
+        .chain(std::iter::repeat_n(false, n - n / 2))
+        .collect();
+    operations.shuffle(rng);
+    operations
+}
+
+/// As analyze_simple, but also returns what the strict oracle saw, to check a new queue against,
+/// such as that it still holds the live items
+pub fn analyze_simple_checked(
+    relaxed_queue: &mut (impl RelaxedFifo<usize> + ?Sized),
+    prefill: usize,
+    operations: &[bool],
+) -> Result<(Vec<usize>, OracleStats), AnalysisError> {
+    let mut rank_errors = vec![];
+    let stats = analyze_items(
+        relaxed_queue,
+        prefill,
+        &mut FixedOps(operations.iter()),
+        0,
+        &mut Identity,
+        &mut rank_errors,
+        |_, _| {},
+        None,
+    )?;
+    Ok((rank_errors, stats))
+}
+
+/// As analyze_simple, but enqueues make_item(i) as the i:th item instead of its id i
+///
+/// Allows simulating queues of realistic payloads. The payloads must be distinct, as the oracle
+/// maps each dequeued payload back to its sequence number to find its rank error.
+pub fn analyze_simple_with<T: Clone + Eq + Hash>(
+    relaxed_queue: &mut (impl RelaxedFifo<T> + ?Sized),
+    prefill: usize,
+    operations: &[bool],
+    make_item: impl Fn(usize) -> T,
+) -> Result<Vec<usize>, AnalysisError> {
+    let mut payloads = Payloads {
+        make_item,
+        sequences: HashMap::new(),
+    };
+    let mut rank_errors = vec![];
+    analyze_items(
+        relaxed_queue,
+        prefill,
+        &mut FixedOps(operations.iter()),
+        0,
+        &mut payloads,
+        &mut rank_errors,
+        |_, _| {},
+        None,
+    )?;
+    Ok(rank_errors)
+}
+
+/// As analyze_simple, but the items are numbered from start_id instead of 0, so that the segments
+/// of a chained run can continue the ids of the one before without colliding
+///
+/// The rank errors do not depend on the offset, as the oracle tracks the items by their sequence
+/// numbers from 0. Returns an error before running if the ids would overflow.
+pub fn analyze_simple_from(
+    relaxed_queue: &mut (impl RelaxedFifo<usize> + ?Sized),
+    prefill: usize,
+    operations: &[bool],
+    start_id: usize,
+) -> Result<Vec<usize>, AnalysisError> {
+    check_id_space(start_id, prefill + operations.len())?;
+    let mut rank_errors = vec![];
+    analyze_items(
+        relaxed_queue,
+        prefill,
+        &mut FixedOps(operations.iter()),
+        0,
+        &mut Offset(start_id),
+        &mut rank_errors,
+        |_, _| {},
+        None,
+    )?;
+    Ok(rank_errors)
+}
+
+/// As analyze_simple, but starts the queue from the steady state instead of prefilling it, with
+/// the items of the state as the oldest ones, such as to simulate a chunk of a long run from where
+/// it is expected to be. The queue must be empty and match the sub-queues of the state.
+pub fn analyze_from_steady_state(
+    relaxed_queue: &mut DChoiceQueue<usize>,
+    state: &SteadyState,
+    operations: &[bool],
+) -> Result<Vec<usize>, AnalysisError> {
+    let subqueues = relaxed_queue.nbr_subqueues();
+    if !relaxed_queue.is_empty() || state.heads.len() != subqueues || state.lens.len() != subqueues
+    {
+        return Err(AnalysisError::QueueMisconfigured {
+            reason: format!(
+                "Can only start an empty queue of {subqueues} sub-queues from a steady state of {}",
+                state.heads.len()
+            ),
+        });
+    }
+    let mut start = SteadyStart {
+        queue: relaxed_queue,
+        state,
+        items: Some(vec![]),
+    };
+    analyze_simple(&mut start, state.items(), operations)
+}
+
+/// Collects the prefill, and then starts the queue from the steady state with it
+struct SteadyStart<'a> {
+    queue: &'a mut DChoiceQueue<usize>,
+    state: &'a SteadyState,
+
+    /// The prefill items, until the prefill is done
+    items: Option<Vec<usize>>,
+}
+
+impl RelaxedFifo<usize> for SteadyStart<'_> {
+    fn enqueue(&mut self, item: usize) {
+        match &mut self.items {
+            Some(items) => items.push(item),
+            None => self.queue.enqueue(item),
+        }
+    }
+
+    fn dequeue(&mut self) -> Option<usize> {
+        self.queue.dequeue()
+    }
+
+    fn prefill_done(&mut self) {
+        let items = self.items.take().expect("The prefill is only done once");
+        self.queue
+            .init_steady_state(self.state, items)
+            .expect("Checked against the queue before the analysis");
+        self.queue.prefill_done();
+    }
+}
+
+/// As analyze_simple, but calls the hook with the number of done operations and the queue every
+/// `every` operations, and once after the last operation. If every is 0, only calls it at the end.
+pub fn analyze_with_hook<Q: RelaxedFifo<usize> + ?Sized>(
+    relaxed_queue: &mut Q,
+    prefill: usize,
+    operations: &[bool],
+    every: usize,
+    hook: impl FnMut(usize, &Q),
+) -> Result<Vec<usize>, AnalysisError> {
+    let mut rank_errors = vec![];
+    analyze_items(
+        relaxed_queue,
+        prefill,
+        &mut FixedOps(operations.iter()),
+        every,
+        &mut Identity,
+        &mut rank_errors,
+        hook,
+        None,
+    )?;
+    Ok(rank_errors)
+}
+
+/// As analyze_simple, but runs a global synchronization barrier every rebalance_every operations,
+/// which redistributes the items over the sub-queues in enqueue order, see
+/// DChoiceQueue::rebalance. The prefill and the end of the operations are not followed by one.
+/// Returns the rank errors together with the items each barrier moved
+pub fn analyze_with_barriers(
+    relaxed_queue: &mut DChoiceQueue<usize>,
+    prefill: usize,
+    operations: &[bool],
+    rebalance_every: usize,
+) -> Result<(Vec<usize>, Vec<usize>), AnalysisError> {
+    assert!(rebalance_every > 0, "The barriers need a positive period");
+    let mut rank_errors = vec![];
+    let mut moves = vec![];
+    // The barriers strictly before the last operation
+    let barriers = operations.len().saturating_sub(1) / rebalance_every;
+    let mut rebalance = |queue: &mut DChoiceQueue<usize>| {
+        if moves.len() < barriers {
+            moves.push(queue.rebalance());
+        }
+    };
+    analyze_items(
+        relaxed_queue,
+        prefill,
+        &mut FixedOps(operations.iter()),
+        0,
+        &mut Identity,
+        &mut rank_errors,
+        |_, _| {},
+        Some(Barrier {
+            every: rebalance_every,
+            run: &mut rebalance,
+        }),
+    )?;
+    Ok((rank_errors, moves))
+}
+
+/// As analyze_simple, but passes the rank errors to the sink instead of collecting them, e.g. to
+/// keep a LogHistogram of very long runs in constant memory
+pub fn analyze_streaming(
+    relaxed_queue: &mut (impl RelaxedFifo<usize> + ?Sized),
+    prefill: usize,
+    operations: &[bool],
+    sink: &mut impl ErrorSink,
+) -> Result<(), AnalysisError> {
+    analyze_items(
+        relaxed_queue,
+        prefill,
+        &mut FixedOps(operations.iter()),
+        0,
+        &mut Identity,
+        sink,
+        |_, _| {},
+        None,
+    )?;
+    Ok(())
+}
+
+/// As analyze_streaming, but an enqueue fails while the queue holds max_items items, so neither
+/// the queue nor the strict oracle receives it. The prefill is not bounded, so a larger one fails
+/// the enqueues until it has drained below the capacity. Returns the failed enqueues
+pub fn analyze_bounded(
+    relaxed_queue: &mut (impl RelaxedFifo<usize> + ?Sized),
+    prefill: usize,
+    operations: &[bool],
+    max_items: usize,
+    sink: &mut impl ErrorSink,
+) -> Result<EnqueueFailures, AnalysisError> {
+    let mut bounded = Bounded {
+        source: FixedOps(operations.iter()),
+        max_items,
+        live: prefill,
+        failures: EnqueueFailures::default(),
+    };
+    analyze_items(
+        relaxed_queue,
+        prefill,
+        &mut bounded,
+        0,
+        &mut Identity,
+        sink,
+        |_, _| {},
+        None,
+    )?;
+    Ok(bounded.failures)
+}
+
+/// The enqueues which failed as the queue was at its capacity
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct EnqueueFailures {
+    pub count: usize,
+
+    /// How many enqueues failed at each number of items in the queue, which is only above the
+    /// capacity while a larger prefill drains
+    pub sizes: BTreeMap<usize, usize>,
+}
+
+impl EnqueueFailures {
+    pub fn record(&mut self, live: usize) {
+        self.count += 1;
+        *self.sizes.entry(live).or_default() += 1;
+    }
+
+    /// Adds the failures of another run
+    pub fn merge(&mut self, other: &EnqueueFailures) {
+        self.count += other.count;
+        for (live, count) in other.sizes.iter() {
+            *self.sizes.entry(*live).or_default() += count;
+        }
+    }
+}
+
+/// Why an analysis of a queue could not finish, such as a RelaxedFifo implementation returning
+/// items it was never given
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AnalysisError {
+    /// A dequeue returned an item which was never enqueued. The item is its sequence number, the
+    /// item itself for analyze_simple, or None if it maps to none, such as a payload which is not
+    /// in the queue, which includes payloads dequeued before
+    UnknownItem {
+        item: Option<usize>,
+
+        /// The index of the dequeue in the operations, not counting the prefill
+        op_index: usize,
+    },
+
+    /// A dequeue returned an item which was already dequeued, as its sequence number
+    DuplicateDequeue { item: usize, op_index: usize },
+
+    /// The queue configuration is invalid, see DChoiceQueueBuilder::try_build
+    QueueMisconfigured { reason: String },
+
+    /// The analysis enqueued more than MAX_ANALYSIS_ITEMS items, the most the strict queue can
+    /// track. The operation index does not count the prefill, which is None if the prefill was
+    /// too large
+    TooManyItems { op_index: Option<usize> },
+
+    /// The ids of the items, numbered from the start id, do not fit in a usize
+    IdOverflow { start_id: usize, items: usize },
+}
+
+impl std::fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnalysisError::UnknownItem {
+                item: Some(item),
+                op_index,
+            } => write!(
+                f,
+                "Operation {op_index} dequeued item {item}, which was never enqueued"
+            ),
+            AnalysisError::UnknownItem {
+                item: None,
+                op_index,
+            } => write!(
+                f,
+                "Operation {op_index} dequeued an item which is not in the queue"
+            ),
+            AnalysisError::DuplicateDequeue { item, op_index } => write!(
+                f,
+                "Operation {op_index} dequeued item {item}, which was already dequeued"
+            ),
+            AnalysisError::QueueMisconfigured { reason } => {
+                write!(f, "The queue is misconfigured: {reason}")
+            }
+            AnalysisError::TooManyItems { op_index } => {
+                write!(
+                    f,
+                    "The analysis supports at most {MAX_ANALYSIS_ITEMS} items, but "
+                )?;
+                match op_index {
+                    Some(op_index) => write!(f, "operation {op_index} enqueued more"),
+                    None => write!(f, "the prefill holds more"),
+                }
+            }
+            AnalysisError::IdOverflow { start_id, items } => write!(
+                f,
+                "The ids of {items} items overflow when starting them at {start_id}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AnalysisError {}
+
+/// Receives the rank error of each dequeue, in order, including empty returns
+pub trait ErrorSink {
+    fn record_error(&mut self, rank_error: usize);
+}
+
+impl ErrorSink for Vec<usize> {
+    fn record_error(&mut self, rank_error: usize) {
+        self.push(rank_error);
+    }
+}
+
+/// As analyze_simple, but asks the source for each operation, letting it observe the outcome of
+/// the previous ones. Returns the rank errors together with the operations that were run.
+pub fn analyze_online(
+    relaxed_queue: &mut (impl RelaxedFifo<usize> + ?Sized),
+    prefill: usize,
+    source: &mut (impl OpSource + ?Sized),
+) -> Result<(Vec<usize>, Vec<bool>), AnalysisError> {
+    let mut recorded = Recorded {
+        source,
+        operations: vec![],
+    };
+    let mut rank_errors = vec![];
+    analyze_items(
+        relaxed_queue,
+        prefill,
+        &mut recorded,
+        0,
+        &mut Identity,
+        &mut rank_errors,
+        |_, _| {},
+        None,
+    )?;
+    Ok((rank_errors, recorded.operations))
+}
+
+/// The outcome of one operation, as observed by an OpSource
+#[derive(Copy, Clone, Debug)]
+pub struct OpOutcome {
+    /// True for enqueues
+    pub enqueue: bool,
+
+    /// If a dequeue returned an item, always false for enqueues
+    pub dequeued: bool,
+
+    /// The number of items in the queue after the operation
+    pub live: usize,
+}
+
+/// Generates operations one at a time, for closed-loop workloads which depend on how the queue
+/// behaves and so cannot be generated up front
+pub trait OpSource {
+    /// The next operation, true for enqueue, or None when the workload is done
+    fn next_op(&mut self) -> Option<bool>;
+
+    /// Called with the outcome of each operation, before the next one is asked for
+    fn observe(&mut self, _outcome: OpOutcome) {}
+}
+
+/// A pre-generated operation sequence
+struct FixedOps<'a>(std::slice::Iter<'a, bool>);
+
+impl OpSource for FixedOps<'_> {
+    fn next_op(&mut self) -> Option<bool> {
+        self.0.next().copied()
+    }
+}
+
+/// Records the operations of the wrapped source
+struct Recorded<'a, S: ?Sized> {
+    source: &'a mut S,
+    operations: Vec<bool>,
+}
+
+impl<S: OpSource + ?Sized> OpSource for Recorded<'_, S> {
+    fn next_op(&mut self) -> Option<bool> {
+        let op = self.source.next_op()?;
+        self.operations.push(op);
+        Some(op)
+    }
+
+    fn observe(&mut self, outcome: OpOutcome) {
+        self.source.observe(outcome)
+    }
+}
+
+/// Skips the enqueues of the wrapped source while the queue is at the capacity, recording them
+struct Bounded<S> {
+    source: S,
+    max_items: usize,
+
+    /// The number of items in the queue
+    live: usize,
+
+    failures: EnqueueFailures,
+}
+
+impl<S: OpSource> OpSource for Bounded<S> {
+    fn next_op(&mut self) -> Option<bool> {
+        loop {
+            let op = self.source.next_op()?;
+            if !op || self.live < self.max_items {
+                return Some(op);
+            }
+            self.failures.record(self.live);
+            self.source.observe(OpOutcome {
+                enqueue: true,
+                dequeued: false,
+                live: self.live,
+            });
+        }
+    }
+
+    fn observe(&mut self, outcome: OpOutcome) {
+        self.live = outcome.live;
+        self.source.observe(outcome)
+    }
+}
+
+/// Translates between the sequence numbers the strict queue tracks and the enqueued items
+trait ItemIds<T> {
+    /// Creates the item with the given sequence number
+    fn item(&mut self, sequence: usize) -> T;
+
+    /// The sequence number of an item dequeued by the operation with the given index, or an
+    /// UnknownItem error if it maps to none
+    fn sequence(&mut self, item: T, op_index: usize) -> Result<usize, AnalysisError>;
+}
+
+/// The items are their own sequence numbers
+struct Identity;
+
+impl ItemIds<usize> for Identity {
+    fn item(&mut self, sequence: usize) -> usize {
+        sequence
+    }
+
+    fn sequence(&mut self, item: usize, _op_index: usize) -> Result<usize, AnalysisError> {
+        Ok(item)
+    }
+}
+
+/// The items are their sequence numbers plus a start id
+struct Offset(usize);
+
+impl ItemIds<usize> for Offset {
+    fn item(&mut self, sequence: usize) -> usize {
+        offset_id(self.0, sequence)
+    }
+
+    fn sequence(&mut self, item: usize, op_index: usize) -> Result<usize, AnalysisError> {
+        item.checked_sub(self.0).ok_or(AnalysisError::UnknownItem {
+            item: None,
+            op_index,
+        })
+    }
+}
+
+/// The id of the item with the sequence number, when the ids start at start_id
+fn offset_id(start_id: usize, sequence: usize) -> usize {
+    start_id.checked_add(sequence).unwrap_or_else(|| {
+        panic!("The id of item {sequence} overflows when starting the ids at {start_id}")
+    })
+}
+
+/// Checks up front that the ids of this many items starting at start_id do not overflow, to fail
+/// before running rather than after
+fn check_id_space(start_id: usize, items: usize) -> Result<(), AnalysisError> {
+    match items.checked_sub(1) {
+        Some(last) if start_id.checked_add(last).is_none() => {
+            Err(AnalysisError::IdOverflow { start_id, items })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Payloads created from their sequence numbers, which are kept in a map while enqueued
+struct Payloads<T, F> {
+    make_item: F,
+    sequences: HashMap<T, usize>,
+}
+
+impl<T: Clone + Eq + Hash, F: Fn(usize) -> T> ItemIds<T> for Payloads<T, F> {
+    fn item(&mut self, sequence: usize) -> T {
+        let item = (self.make_item)(sequence);
+        if let Some(other) = self.sequences.insert(item.clone(), sequence) {
+            panic!("The payloads of items {other} and {sequence} are equal, but must be distinct");
+        }
+        item
+    }
+
+    fn sequence(&mut self, item: T, op_index: usize) -> Result<usize, AnalysisError> {
+        self.sequences
+            .remove(&item)
+            .ok_or(AnalysisError::UnknownItem {
+                item: None,
+                op_index,
+            })
+    }
+}
+
+/// A global synchronization barrier, which the analysis loop runs on the queue every `every`
+/// operations
+struct Barrier<'a, Q: ?Sized> {
+    every: usize,
+    run: &'a mut dyn FnMut(&mut Q),
+}
+
+#[allow(clippy::too_many_arguments)]
+fn analyze_items<T, Q: RelaxedFifo<T> + ?Sized>(
+    relaxed_queue: &mut Q,
     prefill: usize,
-    operations: &Vec<bool>,
-) -> Vec<usize> {
+    operations: &mut impl OpSource,
+    every: usize,
+    ids: &mut impl ItemIds<T>,
+    rank_errors: &mut impl ErrorSink,
+    mut hook: impl FnMut(usize, &Q),
+    mut barrier: Option<Barrier<'_, Q>>,
+) -> Result<OracleStats, AnalysisError> {
     // Keep an ordered queue to the side
     let mut strict_queue = StrictQueue::new();
 
-    for item in 0..prefill {
+    for sequence in 0..prefill {
         // Prefill
-        strict_queue.enqueue(item);
-        relaxed_queue.enqueue(item);
+        strict_queue.enqueue(sequence, None)?;
+        relaxed_queue.enqueue(ids.item(sequence));
     }
+    relaxed_queue.prefill_done();
 
-    let mut rank_errors = vec![];
     let mut enq_nbr = prefill;
+    let mut op_count: usize = 0;
 
-    for op in operations {
-        if *op {
+    while let Some(op) = operations.next_op() {
+        let mut dequeued = false;
+        if op {
             // Enqueue
-            strict_queue.enqueue(enq_nbr);
-            relaxed_queue.enqueue(enq_nbr);
+            strict_queue.enqueue(enq_nbr, Some(op_count))?;
+            relaxed_queue.enqueue(ids.item(enq_nbr));
             enq_nbr += 1;
         } else {
             // Dequeue
             if let Some(item) = relaxed_queue.dequeue() {
-                rank_errors.push(strict_queue.relaxed_dequeue(item));
+                let sequence = ids.sequence(item, op_count)?;
+                rank_errors.record_error(strict_queue.checked_dequeue(sequence, op_count)?);
+                dequeued = true;
             } else {
                 // Treat empty returns as real operations (some queues might not be empty linearizable)
-                rank_errors.push(strict_queue.len());
+                rank_errors.record_error(strict_queue.len());
+            }
+        }
+        operations.observe(OpOutcome {
+            enqueue: op,
+            dequeued,
+            live: strict_queue.len(),
+        });
+        op_count += 1;
+        if every > 0 && op_count.is_multiple_of(every) {
+            hook(op_count, relaxed_queue);
+        }
+        if let Some(barrier) = barrier.as_mut() {
+            if op_count.is_multiple_of(barrier.every) {
+                (barrier.run)(relaxed_queue);
             }
         }
     }
+    if every == 0 || op_count == 0 || !op_count.is_multiple_of(every) {
+        hook(op_count, relaxed_queue);
+    }
+    Ok(strict_queue.oracle_stats())
+}
 
-    rank_errors
+/// Estimates the peak memory in bytes of one analyze_simple run on a d-choice queue
+///
+/// Assumes the worst case where every item is in the queues at once, and that the VecDeques have
+/// doubled past their length. The operation sequence itself is included, as it is generated per
+/// data point in the sweeps. Balanced workloads hold fewer items, so for a prefill as large as the
+/// operations the estimate is about three times the measured peak.
+pub fn estimate_run_memory(prefill: usize, operations: usize, subqueues: usize) -> usize {
+    let items = prefill + operations;
+    let relaxed = 2 * items * size_of::<usize>() + subqueues * size_of::<VecDeque<usize>>() * 2;
+    let strict = 2 * items * size_of::<u32>() + items.div_ceil(64) * size_of::<u64>() * 2;
+    let rank_errors = operations * size_of::<usize>();
+    let ops_vec = operations * size_of::<bool>();
+    relaxed + strict + rank_errors + ops_vec
 }
 
 /// Keeps extra information about each dequeue, apart from just its rank error
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
 pub enum ErrorTag {
     ItemDequeue {
         /// The rank error of the dequeued item
@@ -54,6 +693,21 @@ pub enum ErrorTag {
 
         /// The position this was enqueued at in the sub-queue
         sub_nbr: usize,
+
+        /// The logical thread issuing the dequeue, if operations are attributed to threads
+        thread: Option<usize>,
+
+        /// If the item was enqueued during the prefill, rather than by an operation
+        from_prefill: bool,
+
+        /// If the chosen sub-queue was empty, so the item was found by the round-robin fallback
+        fell_back: bool,
+
+        /// The number of sub-queues the dequeue sampled
+        d: usize,
+
+        /// The sub-queue the item came from
+        subqueue: usize,
     },
 
     EmptyDequeue {
@@ -65,10 +719,51 @@ pub enum ErrorTag {
 
         /// The position in a sub-queue that was attempted to dequeue from
         sub_nbr: usize,
+
+        /// The logical thread issuing the dequeue, if operations are attributed to threads
+        thread: Option<usize>,
+
+        /// If the round-robin fallback also found all other sub-queues empty
+        fell_back: bool,
+
+        /// The number of sub-queues the dequeue sampled
+        d: usize,
+
+        /// The sub-queue the dequeue chose
+        subqueue: usize,
     },
 }
 
+/// An ErrorTag flattened into a plain record, where the item fields are None for empty returns
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorRecord {
+    pub rank_error: usize,
+    pub enq_nbr: Option<usize>,
+    pub deq_nbr: usize,
+    pub sub_nbr: usize,
+    pub thread: Option<usize>,
+    pub from_prefill: Option<bool>,
+    pub fell_back: bool,
+    pub d: usize,
+    pub subqueue: usize,
+}
+
 impl ErrorTag {
+    /// Flattens the tag into a record, e.g. for csv export
+    pub fn to_record(&self) -> ErrorRecord {
+        ErrorRecord {
+            rank_error: self.rank_error(),
+            enq_nbr: self.enq_nbr(),
+            deq_nbr: self.deq_nbr(),
+            sub_nbr: self.sub_nbr(),
+            thread: self.thread(),
+            from_prefill: self.from_prefill(),
+            fell_back: self.fell_back(),
+            d: self.d(),
+            subqueue: self.subqueue(),
+        }
+    }
+
     pub fn rank_error(&self) -> usize {
         match self {
             ErrorTag::ItemDequeue { rank_error, .. } => *rank_error,
@@ -76,6 +771,14 @@ impl ErrorTag {
         }
     }
 
+    /// The enqueue operation of the dequeued item, or None for empty returns
+    pub fn enq_nbr(&self) -> Option<usize> {
+        match self {
+            ErrorTag::ItemDequeue { enq_nbr, .. } => Some(*enq_nbr),
+            ErrorTag::EmptyDequeue { .. } => None,
+        }
+    }
+
     pub fn deq_nbr(&self) -> usize {
         match self {
             ErrorTag::ItemDequeue { deq_nbr, .. } => *deq_nbr,
@@ -89,96 +792,1020 @@ impl ErrorTag {
             ErrorTag::EmptyDequeue { sub_nbr, .. } => *sub_nbr,
         }
     }
+
+    /// If the dequeued item came from the prefill, or None for empty returns
+    pub fn from_prefill(&self) -> Option<bool> {
+        match self {
+            ErrorTag::ItemDequeue { from_prefill, .. } => Some(*from_prefill),
+            ErrorTag::EmptyDequeue { .. } => None,
+        }
+    }
+
+    /// If the dequeue fell back to the round-robin scan, as its chosen sub-queue was empty
+    pub fn fell_back(&self) -> bool {
+        match self {
+            ErrorTag::ItemDequeue { fell_back, .. } => *fell_back,
+            ErrorTag::EmptyDequeue { fell_back, .. } => *fell_back,
+        }
+    }
+
+    pub fn d(&self) -> usize {
+        match self {
+            ErrorTag::ItemDequeue { d, .. } => *d,
+            ErrorTag::EmptyDequeue { d, .. } => *d,
+        }
+    }
+
+    /// The sub-queue the item came from, or the chosen sub-queue for empty returns
+    pub fn subqueue(&self) -> usize {
+        match self {
+            ErrorTag::ItemDequeue { subqueue, .. } => *subqueue,
+            ErrorTag::EmptyDequeue { subqueue, .. } => *subqueue,
+        }
+    }
+
+    pub fn thread(&self) -> Option<usize> {
+        match self {
+            ErrorTag::ItemDequeue { thread, .. } => *thread,
+            ErrorTag::EmptyDequeue { thread, .. } => *thread,
+        }
+    }
 }
 
 /// Analyze a relaxed queue (passed empty), returning rank error and extra information for all dequeues
+///
+/// If threads is given, it holds the logical thread issuing each operation. Operations past its
+/// end are not attributed to any thread.
 pub fn analyze_extra(
     relaxed_queue: &mut DChoiceQueue<usize>,
     prefill: usize,
-    operations: &Vec<bool>,
-) -> Vec<ErrorTag> {
+    operations: &[bool],
+    threads: Option<&[usize]>,
+) -> Result<Vec<ErrorTag>, AnalysisError> {
+    analyze_extra_with(relaxed_queue, prefill, operations, threads, 0, |_, _| {})
+}
+
+/// As analyze_extra, but the items are numbered from start_id instead of 0, see
+/// analyze_simple_from. The tags still number the enqueues from 0, so they do not depend on the
+/// offset either
+pub fn analyze_extra_from(
+    relaxed_queue: &mut DChoiceQueue<usize>,
+    prefill: usize,
+    operations: &[bool],
+    threads: Option<&[usize]>,
+    start_id: usize,
+) -> Result<Vec<ErrorTag>, AnalysisError> {
+    check_id_space(start_id, prefill + operations.len())?;
+    analyze_extra_with(
+        relaxed_queue,
+        prefill,
+        operations,
+        threads,
+        start_id,
+        |_, _| {},
+    )
+}
+
+/// As analyze_extra, but also reports the items left in the sub-queues after the run, which shows
+/// old items stranded in them that the rank errors do not
+pub fn analyze_extra_final_state(
+    relaxed_queue: &mut DChoiceQueue<usize>,
+    prefill: usize,
+    operations: &[bool],
+    threads: Option<&[usize]>,
+) -> Result<(Vec<ErrorTag>, FinalStateReport), AnalysisError> {
+    let error_tags = analyze_extra(relaxed_queue, prefill, operations, threads)?;
+    Ok((error_tags, relaxed_queue.final_state_report()))
+}
+
+/// As analyze_extra, but also decomposes the rank error of each dequeue into the error from
+/// where the items were placed and from which sub-queue the dequeue selected
+///
+/// The placement error is the least rank error among the fronts of the sampled sub-queues, which
+/// no choice among them could have avoided, as the older items were placed in other sub-queues.
+/// The selection error is the rest, from the heuristic not choosing the best sampled front.
+/// Dequeues sampling only empty sub-queues, and those returning an item buffered by helping, have
+/// no choice, so all their error is placement error. Returns the placement error of each dequeue
+/// along with its tag.
+///
+/// The queue must log the choices of the prefill and all operations, see
+/// DChoiceQueueBuilder::log_choices. Peeks at every sub-queue for each dequeue, so it is slow
+/// with many sub-queues.
+pub fn analyze_decomposed(
+    relaxed_queue: &mut DChoiceQueue<usize>,
+    prefill: usize,
+    operations: &[bool],
+    threads: Option<&[usize]>,
+) -> Result<(Vec<ErrorTag>, Vec<usize>), AnalysisError> {
+    // The ranks of the fronts before the last dequeue, which are compared with its sampled
+    // sub-queues once it has logged them, at the next dequeue or after the run
+    let mut pending: Option<Vec<Option<usize>>> = None;
+    let mut decomposer = SampledFronts::default();
+    let error_tags = analyze_extra_with(
+        relaxed_queue,
+        prefill,
+        operations,
+        threads,
+        0,
+        |relaxed_queue, strict_queue| {
+            if let Some(ranks) = pending.take() {
+                decomposer.resolve(relaxed_queue, &ranks);
+            }
+            pending = Some(
+                relaxed_queue
+                    .front_ages()
+                    .into_iter()
+                    // Items the oracle does not hold fail the dequeue returning them instead
+                    .map(|front| {
+                        front
+                            .filter(|item| {
+                                matches!(strict_queue.membership(*item), Membership::Live)
+                            })
+                            .map(|item| strict_queue.rank(item))
+                    })
+                    .collect(),
+            );
+        },
+    )?;
+    if let Some(ranks) = pending {
+        decomposer.resolve(relaxed_queue, &ranks);
+    }
+
+    let placement_errors = error_tags
+        .iter()
+        .zip(decomposer.best)
+        .map(|(tag, best)| {
+            // A skew cap can redirect the dequeue to a better sub-queue than those sampled
+            best.map_or(tag.rank_error(), |best| best.min(tag.rank_error()))
+        })
+        .collect();
+    Ok((error_tags, placement_errors))
+}
+
+/// The least rank error among the fronts of the sampled sub-queues of each dequeue, as found from
+/// the choice log by analyze_decomposed
+#[derive(Default)]
+struct SampledFronts {
+    /// The choice records read so far
+    logged: usize,
+
+    /// The least rank error of each dequeue, or None if it sampled no non-empty sub-queue
+    best: Vec<Option<usize>>,
+}
+
+impl SampledFronts {
+    /// Finds the best of the sampled fronts of the next logged dequeue, given the ranks of the
+    /// fronts of all sub-queues before it
+    fn resolve(&mut self, relaxed_queue: &DChoiceQueue<usize>, ranks: &[Option<usize>]) {
+        let log = relaxed_queue
+            .choice_log()
+            .expect("The queue must log its choices to decompose the rank errors");
+        let offset = log[self.logged..]
+            .iter()
+            .position(|record| record.kind == OpKind::Dequeue)
+            .expect("The queue must log the choices of all operations to decompose them");
+        let record = &log[self.logged + offset];
+        self.logged += offset + 1;
+        self.best.push(
+            record
+                .sampled
+                .iter()
+                .filter_map(|(ind, _)| ranks[*ind])
+                .min(),
+        );
+    }
+}
+
+/// Where one item followed by analyze_item_fates went, and how it left the queue
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ItemFate {
+    pub item: usize,
+
+    /// The sub-queue the item was enqueued into
+    pub subqueue: usize,
+
+    /// The items ahead of it in the sub-queue when it was enqueued
+    pub position: usize,
+
+    /// The operation which enqueued it, or None if it was part of the prefill
+    pub enqueued_at: Option<usize>,
+
+    /// The tag of the dequeue which returned it, or None if it was never dequeued
+    pub dequeue: Option<ErrorTag>,
+
+    /// The operations from its enqueue to its dequeue, counting prefill items from the first
+    /// operation, or None if it was never dequeued
+    pub waited: Option<usize>,
+
+    /// The sub-queue and position from the front the item was left at after the operations, or
+    /// None if it was dequeued, or is held in the help buffer
+    pub final_location: Option<(usize, usize)>,
+}
+
+impl ItemFate {
+    /// The fate of the item just enqueued into the queue
+    fn enqueued(
+        relaxed_queue: &mut DChoiceQueue<usize>,
+        item: usize,
+        enqueued_at: Option<usize>,
+    ) -> Self {
+        let subqueue = relaxed_queue
+            .take_choice()
+            .expect("Every enqueue chooses a sub-queue");
+        Self {
+            item,
+            subqueue,
+            position: relaxed_queue.subqueue_len(subqueue).unwrap_or(1) - 1,
+            enqueued_at,
+            dequeue: None,
+            waited: None,
+            final_location: None,
+        }
+    }
+}
+
+/// As analyze_extra, but also follows each item with the given probability from its enqueue to
+/// its dequeue, or to where it was left after the operations
+///
+/// Whether to follow an item is drawn from the rng as it is enqueued, and only the followed items
+/// are kept, apart from one pass over the items left in the queue at the end. Returns the fates
+/// in the order the items were enqueued.
+pub fn analyze_item_fates(
+    relaxed_queue: &mut DChoiceQueue<usize>,
+    prefill: usize,
+    operations: &[bool],
+    threads: Option<&[usize]>,
+    probability: f64,
+    rng: &mut impl Rng,
+) -> Result<(Vec<ErrorTag>, Vec<ItemFate>), AnalysisError> {
     // Keep an ordered queue to the side
     let mut strict_queue = StrictQueue::new();
 
+    // The followed items, mapped to their index in the fates
+    let mut followed: HashMap<usize, usize> = HashMap::new();
+    let mut fates: Vec<ItemFate> = vec![];
+
     for item in 0..prefill {
         // Prefill
-        strict_queue.enqueue(item);
+        strict_queue.enqueue(item, None)?;
         relaxed_queue.enqueue(item);
+        if rng.gen_bool(probability) {
+            followed.insert(item, fates.len());
+            fates.push(ItemFate::enqueued(relaxed_queue, item, None));
+        }
     }
+    relaxed_queue.reset_cost_counters();
 
     let mut error_tags = vec![];
     let mut enq_nbr = prefill;
-
     let mut deq_nbr = 0;
-    for op in operations {
+    let mut dequeued_at = HashMap::new();
+    for (op_nbr, op) in operations.iter().enumerate() {
+        let thread = threads.and_then(|threads| threads.get(op_nbr).copied());
         if *op {
             // Enqueue
-            strict_queue.enqueue(enq_nbr);
+            strict_queue.enqueue(enq_nbr, Some(op_nbr))?;
             relaxed_queue.enqueue(enq_nbr);
+            if rng.gen_bool(probability) {
+                followed.insert(enq_nbr, fates.len());
+                fates.push(ItemFate::enqueued(relaxed_queue, enq_nbr, Some(op_nbr)));
+            }
             enq_nbr += 1;
         } else {
             // Dequeue
             deq_nbr += 1;
-            match relaxed_queue.dequeue_with_info() {
-                (Some(item), sub_nbr) => error_tags.push(ErrorTag::ItemDequeue {
-                    rank_error: strict_queue.relaxed_dequeue(item),
-                    enq_nbr: item,
-                    deq_nbr,
-                    sub_nbr,
-                }),
-                (None, sub_nbr) => error_tags.push(ErrorTag::EmptyDequeue {
-                    rank_error: strict_queue.len(),
-                    deq_nbr,
-                    sub_nbr,
-                }),
+            let d = relaxed_queue.current_d(OpKind::Dequeue);
+            let info = relaxed_queue.dequeue_with_info();
+            let tag = tag_dequeue(&mut strict_queue, info, op_nbr, deq_nbr, thread, prefill, d)?;
+            if let Some(item) = tag.enq_nbr().filter(|item| followed.contains_key(item)) {
+                dequeued_at.insert(item, (op_nbr, error_tags.len()));
             }
+            error_tags.push(tag);
         }
     }
 
-    error_tags
-}
-
-struct StrictQueue {
-    deque: VecDeque<(usize, bool)>,
-    len: usize,
-}
-
-impl StrictQueue {
-    fn new() -> Self {
-        Self {
-            deque: VecDeque::new(),
-            len: 0,
+    for (item, (op_nbr, tag_ind)) in dequeued_at {
+        let fate = &mut fates[followed[&item]];
+        fate.waited = Some(op_nbr - fate.enqueued_at.unwrap_or(0));
+        fate.dequeue = Some(error_tags[tag_ind].clone());
+    }
+    if !followed.is_empty() {
+        for subqueue in 0..relaxed_queue.nbr_subqueues() {
+            for (position, item) in relaxed_queue.subqueue_items(subqueue).enumerate() {
+                if let Some(ind) = followed.get(item) {
+                    fates[*ind].final_location = Some((subqueue, position));
+                }
+            }
         }
     }
+    Ok((error_tags, fates))
+}
 
-    fn enqueue(&mut self, item: usize) {
-        self.deque.push_back((item, true));
-        self.len += 1;
-    }
+/// As analyze_extra_from, but calls the hook with the queues before each dequeue
+fn analyze_extra_with(
+    relaxed_queue: &mut DChoiceQueue<usize>,
+    prefill: usize,
+    operations: &[bool],
+    threads: Option<&[usize]>,
+    start_id: usize,
+    mut before_dequeue: impl FnMut(&DChoiceQueue<usize>, &StrictQueue),
+) -> Result<Vec<ErrorTag>, AnalysisError> {
+    // Keep an ordered queue to the side
+    let mut strict_queue = StrictQueue::new();
+
+    for item in 0..prefill {
+        // Prefill
+        strict_queue.enqueue(item, None)?;
+        relaxed_queue.enqueue(offset_id(start_id, item));
+    }
+    relaxed_queue.reset_cost_counters();
+
+    let mut error_tags = vec![];
+    let mut enq_nbr = prefill;
+
+    let mut deq_nbr = 0;
+    for (op_nbr, op) in operations.iter().enumerate() {
+        let thread = threads.and_then(|threads| threads.get(op_nbr).copied());
+        if *op {
+            // Enqueue
+            strict_queue.enqueue(enq_nbr, Some(op_nbr))?;
+            relaxed_queue.enqueue(offset_id(start_id, enq_nbr));
+            enq_nbr += 1;
+        } else {
+            // Dequeue
+            deq_nbr += 1;
+            before_dequeue(relaxed_queue, &strict_queue);
+            // Read before dequeuing, as an adaptive d can change with the choice
+            let d = relaxed_queue.current_d(OpKind::Dequeue);
+            let info = relaxed_queue.dequeue_with_info();
+            let item = match info.item {
+                Some(item) => Some(item.checked_sub(start_id).ok_or(
+                    AnalysisError::UnknownItem {
+                        item: None,
+                        op_index: op_nbr,
+                    },
+                )?),
+                None => None,
+            };
+            error_tags.push(tag_dequeue(
+                &mut strict_queue,
+                DequeueInfo { item, ..info },
+                op_nbr,
+                deq_nbr,
+                thread,
+                prefill,
+                d,
+            )?);
+        }
+    }
+
+    Ok(error_tags)
+}
+
+/// As analyze_simple, but writes a LogRecord of every operation to the log, including the prefill
+/// enqueues, from which any readout can be recomputed offline. Returns the rank errors, or the
+/// first error writing the log, where an AnalysisError is wrapped as an io error of kind Other
+pub fn analyze_logged(
+    relaxed_queue: &mut DChoiceQueue<usize>,
+    prefill: usize,
+    operations: &[bool],
+    log: &mut impl Write,
+) -> io::Result<Vec<usize>> {
+    // Keep an ordered queue to the side
+    let mut strict_queue = StrictQueue::new();
+
+    for item in 0..prefill {
+        // Prefill
+        log_enqueue(relaxed_queue, &mut strict_queue, item, None, log)?;
+    }
+    relaxed_queue.reset_cost_counters();
+
+    let mut rank_errors = Vec::with_capacity(operations.len());
+    let mut enq_nbr = prefill;
+    for (op_nbr, op) in operations.iter().enumerate() {
+        if *op {
+            // Enqueue
+            log_enqueue(relaxed_queue, &mut strict_queue, enq_nbr, Some(op_nbr), log)?;
+            enq_nbr += 1;
+        } else {
+            // Dequeue
+            let info = relaxed_queue.dequeue_with_info();
+            let rank_error = match info.item {
+                Some(item) => strict_queue
+                    .checked_dequeue(item, op_nbr)
+                    .map_err(io::Error::other)?,
+                None => strict_queue.len(),
+            };
+            rank_errors.push(rank_error);
+            LogRecord {
+                kind: OpKind::Dequeue,
+                prefill: false,
+                subqueue: info.subqueue,
+                fell_back: info.fell_back,
+                item: info.item,
+                rank_error: Some(rank_error),
+            }
+            .write_to(log)?;
+        }
+    }
+    Ok(rank_errors)
+}
+
+/// Enqueues the item into both queues, and logs which sub-queue it went to. The operation index
+/// is None for the prefill
+fn log_enqueue(
+    relaxed_queue: &mut DChoiceQueue<usize>,
+    strict_queue: &mut StrictQueue,
+    item: usize,
+    op_index: Option<usize>,
+    log: &mut impl Write,
+) -> io::Result<()> {
+    strict_queue
+        .enqueue(item, op_index)
+        .map_err(io::Error::other)?;
+    relaxed_queue.enqueue(item);
+    LogRecord {
+        kind: OpKind::Enqueue,
+        prefill: op_index.is_none(),
+        subqueue: relaxed_queue
+            .take_choice()
+            .expect("Every enqueue chooses a sub-queue"),
+        fell_back: false,
+        item: Some(item),
+        rank_error: None,
+    }
+    .write_to(log)
+}
+
+/// Removes the dequeued item from the strict queue, and tags the dequeue with its rank error
+fn tag_dequeue(
+    strict_queue: &mut StrictQueue,
+    info: DequeueInfo<usize>,
+    op_index: usize,
+    deq_nbr: usize,
+    thread: Option<usize>,
+    prefill: usize,
+    d: usize,
+) -> Result<ErrorTag, AnalysisError> {
+    let (sub_nbr, fell_back, subqueue) = (info.sub_nbr, info.fell_back, info.subqueue);
+    Ok(match info.item {
+        Some(item) => ErrorTag::ItemDequeue {
+            rank_error: strict_queue.checked_dequeue(item, op_index)?,
+            enq_nbr: item,
+            deq_nbr,
+            sub_nbr,
+            thread,
+            // The prefill items are the ids below the boundary
+            from_prefill: item < prefill,
+            fell_back,
+            d,
+            subqueue,
+        },
+        None => ErrorTag::EmptyDequeue {
+            rank_error: strict_queue.len(),
+            deq_nbr,
+            sub_nbr,
+            thread,
+            fell_back,
+            d,
+            subqueue,
+        },
+    })
+}
+
+/// One operation of a queue policy outside this crate, with the sub-queue it chose
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Decision {
+    /// Enqueues the next item into the sub-queue
+    EnqueueTo(usize),
+
+    /// Dequeues the front item of the sub-queue, or returns empty if the sub-queue is empty
+    DequeueFrom(usize),
+}
+
+/// Computes the tags of the dequeues of a sequence of decisions, without any queue making choices
+///
+/// Keeps the sub-queue contents and the strict oracle itself, so the rank errors only depend on
+/// the decisions. The items are numbered in enqueue order, where the first prefill enqueues are
+/// the prefill, and the sub-queues are created as the decisions name them. The decisions do not
+/// say how the sub-queues were chosen, so every dequeue is tagged as sampling d = 1 sub-queue
+/// without falling back, and without a thread. A queue which helps lagging sub-queues moves
+/// items outside of the decisions, so its runs can not be replayed.
+pub fn replay_decisions(
+    decisions: &[Decision],
+    prefill: usize,
+) -> Result<Vec<ErrorTag>, AnalysisError> {
+    let mut strict_queue = StrictQueue::new();
+    // The head counter and items of each sub-queue
+    let mut subqueues: Vec<(usize, VecDeque<usize>)> = vec![];
+
+    let mut error_tags = vec![];
+    let mut enq_nbr = 0;
+    let mut deq_nbr = 0;
+    for (decision_nbr, decision) in decisions.iter().enumerate() {
+        let (Decision::EnqueueTo(ind) | Decision::DequeueFrom(ind)) = *decision;
+        if ind >= subqueues.len() {
+            subqueues.resize_with(ind + 1, || (0, VecDeque::new()));
+        }
+        match *decision {
+            Decision::EnqueueTo(ind) => {
+                let op_index = (enq_nbr >= prefill).then(|| decision_nbr - prefill);
+                strict_queue.enqueue(enq_nbr, op_index)?;
+                subqueues[ind].1.push_back(enq_nbr);
+                enq_nbr += 1;
+            }
+            Decision::DequeueFrom(ind) => {
+                deq_nbr += 1;
+                let (head, items) = &mut subqueues[ind];
+                let item = items.pop_front();
+                *head += usize::from(item.is_some());
+                let info = DequeueInfo {
+                    item,
+                    sub_nbr: *head,
+                    fell_back: false,
+                    subqueue: ind,
+                };
+                error_tags.push(tag_dequeue(
+                    &mut strict_queue,
+                    info,
+                    decision_nbr.saturating_sub(prefill),
+                    deq_nbr,
+                    None,
+                    prefill,
+                    1,
+                )?);
+            }
+        }
+    }
+    Ok(error_tags)
+}
+
+/// As analyze_streaming, but each enqueue inserts enqueue_batch sequential items by enqueue_many,
+/// and each dequeue takes a batch of up to dequeue_batch items by dequeue_many
+///
+/// The strict queue receives the enqueued items one by one in order. Each dequeued item gets its
+/// own rank error, in the order the batch returned them. A batch which returns no items counts as
+/// one empty return, while a batch stopped short only records the items it returned.
+pub fn analyze_batched(
+    relaxed_queue: &mut DChoiceQueue<usize>,
+    prefill: usize,
+    operations: &[bool],
+    enqueue_batch: usize,
+    dequeue_batch: usize,
+    sink: &mut impl ErrorSink,
+) -> Result<(), AnalysisError> {
+    // Keep an ordered queue to the side
+    let mut strict_queue = StrictQueue::new();
+
+    for item in 0..prefill {
+        // Prefill
+        strict_queue.enqueue(item, None)?;
+        relaxed_queue.enqueue(item);
+    }
+    relaxed_queue.reset_cost_counters();
+
+    let mut enq_nbr = prefill;
+    for (op_nbr, op) in operations.iter().enumerate() {
+        if *op {
+            // Enqueue a batch
+            let items: Vec<usize> = (enq_nbr..enq_nbr + enqueue_batch).collect();
+            for item in &items {
+                strict_queue.enqueue(*item, Some(op_nbr))?;
+            }
+            relaxed_queue.enqueue_many(items);
+            enq_nbr += enqueue_batch;
+        } else {
+            // Dequeue a batch
+            let items = relaxed_queue.dequeue_many(dequeue_batch);
+            if items.is_empty() {
+                sink.record_error(strict_queue.len());
+            }
+            for item in items {
+                sink.record_error(strict_queue.checked_dequeue(item, op_nbr)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// As analyze_streaming, but places every prefill item in the first sub-queue, and returns the
+/// rank error of each operation, None for the enqueues
+///
+/// The skewed prefill is the worst placement for a balanced queue, so the rank errors start high
+/// and decay as the operations even out the sub-queues, see analyze_recovery.
+pub fn analyze_skewed_prefill(
+    relaxed_queue: &mut DChoiceQueue<usize>,
+    prefill: usize,
+    operations: &[bool],
+) -> Result<Vec<Option<usize>>, AnalysisError> {
+    // Keep an ordered queue to the side
+    let mut strict_queue = StrictQueue::new();
+
+    for item in 0..prefill {
+        // Prefill
+        strict_queue.enqueue(item, None)?;
+        relaxed_queue.enqueue_at(0, item);
+    }
+    relaxed_queue.reset_cost_counters();
+
+    let mut enq_nbr = prefill;
+    operations
+        .iter()
+        .enumerate()
+        .map(|(op_nbr, op)| {
+            if *op {
+                // Enqueue
+                strict_queue.enqueue(enq_nbr, Some(op_nbr))?;
+                relaxed_queue.enqueue(enq_nbr);
+                enq_nbr += 1;
+                Ok(None)
+            } else {
+                // Dequeue, treating empty returns as real operations
+                Ok(Some(match relaxed_queue.dequeue() {
+                    Some(item) => strict_queue.checked_dequeue(item, op_nbr)?,
+                    None => strict_queue.len(),
+                }))
+            }
+        })
+        .collect()
+}
+
+/// The threads of a discrete-event concurrent execution
+#[derive(Clone, Debug)]
+pub struct ConcurrentConfig {
+    /// The mean duration of the operations of each thread, in virtual time
+    pub mean_durations: Vec<f64>,
+
+    /// Seeds the operation durations, otherwise seeded from entropy
+    pub seed: Option<u64>,
+}
+
+/// An operation a thread has started but not completed
+struct InFlight {
+    /// The index of the operation in the operations
+    op_index: usize,
+    completion: f64,
+    enqueue: bool,
+    subqueue: usize,
+    d: usize,
+}
+
+/// As analyze_extra, but the operations are run by concurrent threads in a discrete-event
+/// simulation
+///
+/// Each thread repeatedly takes the next of the operations, chooses its sub-queue from the queue
+/// state at the start, and applies it to that sub-queue at its completion after an exponentially
+/// distributed duration. The operations are linearized by completion time, so a slow thread can
+/// apply a choice which has gone stale, e.g. dequeue from a sub-queue others have emptied. The
+/// error tags are attributed to the threads.
+pub fn analyze_concurrent(
+    relaxed_queue: &mut DChoiceQueue<usize>,
+    prefill: usize,
+    operations: &[bool],
+    config: &ConcurrentConfig,
+) -> Result<Vec<ErrorTag>, AnalysisError> {
+    assert!(
+        !config.mean_durations.is_empty(),
+        "Needs at least one thread"
+    );
+    let mut rng = match config.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut strict_queue = StrictQueue::new();
+    for item in 0..prefill {
+        strict_queue.enqueue(item, None)?;
+        relaxed_queue.enqueue(item);
+    }
+    relaxed_queue.reset_cost_counters();
+
+    let mut remaining = operations.iter().enumerate();
+    let mut start = |relaxed_queue: &mut DChoiceQueue<usize>, thread: usize, now: f64| {
+        let (op_index, enqueue) = remaining.next()?;
+        let enqueue = *enqueue;
+        let kind = if enqueue {
+            OpKind::Enqueue
+        } else {
+            OpKind::Dequeue
+        };
+        let d = relaxed_queue.current_d(kind);
+        let subqueue = relaxed_queue.choose_subqueue(kind);
+        // Inverse transform sampling, where 1 - u is in (0, 1] to avoid ln(0)
+        let duration = -(1.0 - rng.gen::<f64>()).ln() * config.mean_durations[thread];
+        Some(InFlight {
+            op_index,
+            completion: now + duration,
+            enqueue,
+            subqueue,
+            d,
+        })
+    };
+    let mut in_flight: Vec<Option<InFlight>> = (0..config.mean_durations.len())
+        .map(|thread| start(relaxed_queue, thread, 0.0))
+        .collect();
+
+    let mut error_tags = vec![];
+    let mut enq_nbr = prefill;
+    let mut deq_nbr = 0;
+    while let Some(thread) = (0..in_flight.len())
+        .filter(|thread| in_flight[*thread].is_some())
+        .min_by(|a, b| {
+            let completion = |thread: usize| in_flight[thread].as_ref().unwrap().completion;
+            completion(*a).total_cmp(&completion(*b))
+        })
+    {
+        let op = in_flight[thread]
+            .take()
+            .expect("Chose an operation in flight");
+        if op.enqueue {
+            // The item gets its id when it is linearized, so the ids follow the strict order
+            strict_queue.enqueue(enq_nbr, Some(op.op_index))?;
+            relaxed_queue.enqueue_at(op.subqueue, enq_nbr);
+            enq_nbr += 1;
+        } else {
+            deq_nbr += 1;
+            let info = relaxed_queue.dequeue_at(op.subqueue);
+            error_tags.push(tag_dequeue(
+                &mut strict_queue,
+                info,
+                op.op_index,
+                deq_nbr,
+                Some(thread),
+                prefill,
+                op.d,
+            )?);
+        }
+        in_flight[thread] = start(relaxed_queue, thread, op.completion);
+    }
+
+    Ok(error_tags)
+}
+
+/// The dequeue-relative rank errors of the item dequeues: how many items enqueued after each item
+/// were dequeued before it, in contrast to how many older items it overtook. Empty returns are
+/// skipped, as no item was overtaken by them.
+///
+/// The two definitions sum to the same total, apart from older items never dequeued, but differ
+/// in which dequeue the error is attributed to.
+pub fn dequeue_relative_errors(error_tags: &[ErrorTag]) -> Vec<usize> {
+    let items = error_tags
+        .iter()
+        .filter_map(|tag| tag.enq_nbr())
+        .max()
+        .map_or(0, |max| max + 1);
+    let mut dequeued = FenwickTree::new(items);
+    let mut dequeue_count = 0;
+
+    error_tags
+        .iter()
+        .filter_map(|tag| tag.enq_nbr())
+        .map(|item| {
+            // All earlier dequeued items which are not older than this one were enqueued after it
+            let overtaking = dequeue_count - dequeued.prefix_sum(item);
+            dequeued.add(item);
+            dequeue_count += 1;
+            overtaking
+        })
+        .collect()
+}
+
+/// Counts of the items, with prefix sums in logarithmic time
+struct FenwickTree {
+    tree: Vec<usize>,
+}
+
+impl FenwickTree {
+    fn new(len: usize) -> Self {
+        Self {
+            tree: vec![0; len + 1],
+        }
+    }
+
+    fn add(&mut self, item: usize) {
+        let mut ind = item + 1;
+        while ind < self.tree.len() {
+            self.tree[ind] += 1;
+            ind += ind & ind.wrapping_neg();
+        }
+    }
+
+    /// The number of added items below item
+    fn prefix_sum(&self, item: usize) -> usize {
+        let mut sum = 0;
+        let mut ind = item;
+        while ind > 0 {
+            sum += self.tree[ind];
+            ind -= ind & ind.wrapping_neg();
+        }
+        sum
+    }
+}
+
+/// The most items an analysis can enqueue, as the strict queue stores the item ids as u32
+pub const MAX_ANALYSIS_ITEMS: usize = u32::MAX as usize + 1;
+
+/// The items the strict oracle has seen during an analysis
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct OracleStats {
+    /// The items enqueued, including the prefill
+    pub enqueued: usize,
+
+    /// The items still in the queue
+    pub live: usize,
+
+    /// The items dequeued
+    pub dead: usize,
+}
+
+/// If an item is in the strict queue
+enum Membership {
+    Live,
+
+    /// Enqueued and then dequeued
+    Dead,
+
+    /// Never enqueued
+    Unknown,
+}
+
+/// The strict FIFO order the rank errors are measured against
+///
+/// Stores 4 byte item ids with a separate liveness bitset, instead of (usize, bool) entries.
+struct StrictQueue {
+    /// The item ids in enqueue order, where the front is at position head
+    ids: VecDeque<u32>,
+
+    /// One liveness bit per position, where the first word holds the word of position head
+    live: VecDeque<u64>,
 
-    /// Returns the relaxation distance of the dequeued item
+    /// The position of the front of ids, counting all items ever enqueued
+    head: usize,
+
+    len: usize,
+}
+
+impl StrictQueue {
+    fn new() -> Self {
+        Self {
+            ids: VecDeque::new(),
+            live: VecDeque::new(),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Enqueues the item, which must be the next position, as the item ids are their positions.
+    /// Returns a TooManyItems error for the operation with the index, or the prefill if None, if
+    /// the item does not fit in the ids
+    fn enqueue(&mut self, item: usize, op_index: Option<usize>) -> Result<(), AnalysisError> {
+        let id = u32::try_from(item).map_err(|_| AnalysisError::TooManyItems { op_index })?;
+        let position = self.head + self.ids.len();
+        assert_eq!(
+            item, position,
+            "The strict queue items must be enqueued in order from 0"
+        );
+        if position / 64 - self.head / 64 == self.live.len() {
+            self.live.push_back(0);
+        }
+        self.ids.push_back(id);
+        self.set_live(position, true);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// As relaxed_dequeue, but returns an error for items which were never enqueued or are
+    /// already dequeued, instead of panicking
+    fn checked_dequeue(&mut self, item: usize, op_index: usize) -> Result<usize, AnalysisError> {
+        match self.membership(item) {
+            Membership::Live => Ok(self.relaxed_dequeue(item)),
+            Membership::Dead => Err(AnalysisError::DuplicateDequeue { item, op_index }),
+            Membership::Unknown => Err(AnalysisError::UnknownItem {
+                item: Some(item),
+                op_index,
+            }),
+        }
+    }
+
+    /// Returns the relaxation distance of the dequeued item, which must be live
     fn relaxed_dequeue(&mut self, item: usize) -> usize {
-        // Always decrease len by 1 when dequeueing an item
-        assert!(self.len > 0, "Cannot dequeue from an empty strict queue");
+        match self.membership(item) {
+            Membership::Live => {}
+            Membership::Dead => panic!("Dequeued item {item}, which was already dequeued"),
+            Membership::Unknown => panic!("Dequeued item {item}, which was never enqueued"),
+        }
         self.len -= 1;
 
-        if self.deque.front().expect("Item must exist").0 == item {
+        if item == self.head {
             // Relaxation error is 0, and we can empty the deque
-            self.deque.pop_front();
-            loop {
-                match self.deque.front() {
-                    Some((_item, false)) => self.deque.pop_front(),
-                    _ => return 0,
-                };
+            self.pop_front();
+            while !self.ids.is_empty() && !self.is_live(self.head) {
+                self.pop_front();
             }
+            0
         } else {
             // The item is not first, so don't have to worry about removing old garbage
+            let rank_error = self.rank(item);
+            self.set_live(item, false);
+            rank_error
+        }
+    }
+
+    /// If the item is in the queue, found by its position in the liveness bitset
+    fn membership(&self, item: usize) -> Membership {
+        if item >= self.head + self.ids.len() {
+            Membership::Unknown
+        } else if item < self.head || !self.is_live(item) {
+            Membership::Dead
+        } else {
+            Membership::Live
+        }
+    }
+
+    fn oracle_stats(&self) -> OracleStats {
+        let enqueued = self.head + self.ids.len();
+        OracleStats {
+            enqueued,
+            live: self.len,
+            dead: enqueued - self.len,
+        }
+    }
+
+    /// The rank error dequeuing the live item would have, without dequeuing it. The item ids are
+    /// their positions, as they are enqueued in order from 0.
+    fn rank(&self, item: usize) -> usize {
+        (self.head..item)
+            .filter(|position| self.is_live(*position))
+            .count()
+    }
+
+    /// Removes the front position, dropping its bitset word once all of its positions are gone
+    fn pop_front(&mut self) {
+        self.ids.pop_front();
+        self.head += 1;
+        if self.head.is_multiple_of(64) {
+            self.live.pop_front();
+        }
+    }
+
+    fn is_live(&self, position: usize) -> bool {
+        self.live[position / 64 - self.head / 64] & (1 << (position % 64)) != 0
+    }
+
+    fn set_live(&mut self, position: usize, live: bool) {
+        let word = &mut self.live[position / 64 - self.head / 64];
+        if live {
+            *word |= 1 << (position % 64);
+        } else {
+            *word &= !(1 << (position % 64));
+        }
+    }
+
+    /// Returns the number of live items in the queue
+    fn len(&mut self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DChoiceQueueBuilder, TraceEvent};
+
+    #[test]
+    fn balanced_ops_split_evenly() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for n in [0, 1, 2, 10, 1001] {
+            let operations = gen_balanced_ops(n, &mut rng);
+            let enqueues = operations.iter().filter(|is_enqueue| **is_enqueue).count();
+            assert_eq!(operations.len(), n);
+            assert_eq!(enqueues, n / 2);
+            // Odd counts get the extra operation as a dequeue
+            assert_eq!(operations.len() - enqueues, n.div_ceil(2));
+        }
+    }
+
+    /// The strict queue as it was before the compact ids, a deque of (item, live) entries
+    struct ReferenceQueue {
+        deque: VecDeque<(usize, bool)>,
+        len: usize,
+    }
+
+    impl ReferenceQueue {
+        fn relaxed_dequeue(&mut self, item: usize) -> usize {
+            self.len -= 1;
+            if self.deque.front().unwrap().0 == item {
+                self.deque.pop_front();
+                while self.deque.front().is_some_and(|(_, live)| !live) {
+                    self.deque.pop_front();
+                }
+                return 0;
+            }
             let mut rank_error = 0;
-            for (other, exists) in self.deque.iter_mut() {
+            for (other, live) in self.deque.iter_mut() {
                 if *other == item {
-                    *exists = false;
+                    *live = false;
                     return rank_error;
-                } else if *exists {
+                } else if *live {
                     rank_error += 1;
                 }
             }
@@ -186,8 +1813,644 @@ impl StrictQueue {
         }
     }
 
-    /// Returns the number of live items in the queue
-    fn len(&mut self) -> usize {
-        self.len
+    #[test]
+    fn compact_oracle_matches_reference() {
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut strict_queue = StrictQueue::new();
+            let mut reference = ReferenceQueue {
+                deque: VecDeque::new(),
+                len: 0,
+            };
+            let mut live: Vec<usize> = vec![];
+            let mut next = 0;
+            for op_index in 0..3000 {
+                if live.is_empty() || rng.gen_bool(0.5) {
+                    strict_queue.enqueue(next, Some(op_index)).unwrap();
+                    reference.deque.push_back((next, true));
+                    reference.len += 1;
+                    live.push(next);
+                    next += 1;
+                } else {
+                    // Relaxed, mostly among the oldest items, so that the fronts get cleaned up
+                    let ind = rng.gen_range(0..live.len().min(8));
+                    let item = live.remove(ind);
+                    assert_eq!(
+                        strict_queue.checked_dequeue(item, op_index),
+                        Ok(reference.relaxed_dequeue(item)),
+                        "Item {item} with seed {seed}"
+                    );
+                }
+                assert_eq!(strict_queue.len(), reference.len);
+            }
+            assert_eq!(strict_queue.oracle_stats().enqueued, next);
+        }
+    }
+
+    #[test]
+    fn oracle_tells_duplicates_from_unknown_items() {
+        let mut strict_queue = StrictQueue::new();
+        for item in 0..5 {
+            strict_queue.enqueue(item, None).unwrap();
+        }
+        assert_eq!(strict_queue.checked_dequeue(2, 0), Ok(2));
+        assert_eq!(strict_queue.checked_dequeue(0, 1), Ok(0));
+        // Item 0 has left the front, while item 2 is only marked dead
+        for (item, op_index) in [(0, 2), (2, 3)] {
+            assert_eq!(
+                strict_queue.checked_dequeue(item, op_index),
+                Err(AnalysisError::DuplicateDequeue { item, op_index })
+            );
+        }
+        assert_eq!(
+            strict_queue.checked_dequeue(5, 4),
+            Err(AnalysisError::UnknownItem {
+                item: Some(5),
+                op_index: 4
+            })
+        );
+        // The failed dequeues change nothing
+        assert_eq!(
+            strict_queue.oracle_stats(),
+            OracleStats {
+                enqueued: 5,
+                live: 3,
+                dead: 2
+            }
+        );
+        assert_eq!(strict_queue.checked_dequeue(1, 5), Ok(0));
+
+        // As the analyses find it, from a queue returning the same item twice
+        let mut repeating = RepeatingQueue(VecDeque::new());
+        assert_eq!(
+            analyze_simple_checked(&mut repeating, 0, &[true, false, true, false]).map(drop),
+            Err(AnalysisError::DuplicateDequeue {
+                item: 0,
+                op_index: 3
+            })
+        );
+    }
+
+    #[test]
+    fn oracle_stats_count_the_items() {
+        // A single sub-queue is strict, so every item is live until dequeued once
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(1).build();
+        let operations = [false, true, false, false, true, true, false];
+        let (rank_errors, stats) = analyze_simple_checked(&mut queue, 3, &operations).unwrap();
+        assert_eq!(rank_errors, [0; 4]);
+        // The prefill of 3 and 3 enqueues, of which 4 are dequeued
+        assert_eq!(
+            stats,
+            OracleStats {
+                enqueued: 6,
+                live: 2,
+                dead: 4
+            }
+        );
+        assert_eq!(queue.len(), stats.live);
+    }
+
+    #[test]
+    fn oracle_rejects_items_past_the_id_space() {
+        let mut strict_queue = StrictQueue::new();
+        strict_queue.head = MAX_ANALYSIS_ITEMS - 1;
+        assert_eq!(strict_queue.enqueue(MAX_ANALYSIS_ITEMS - 1, None), Ok(()));
+        assert_eq!(
+            strict_queue.enqueue(MAX_ANALYSIS_ITEMS, Some(7)),
+            Err(AnalysisError::TooManyItems { op_index: Some(7) })
+        );
+        assert_eq!(strict_queue.len(), 1);
+    }
+
+    #[test]
+    fn decomposition_splits_by_the_sampled_fronts() {
+        let operations = gen_balanced_ops(400, &mut StdRng::seed_from_u64(1));
+        let decompose = |d: usize, seed: u64| {
+            let mut queue = DChoiceQueueBuilder::new(2)
+                .d(d)
+                .uniques(true)
+                .log_choices(Some(usize::MAX))
+                .seed(Some(seed))
+                .build();
+            analyze_decomposed(&mut queue, 10, &operations, None).unwrap()
+        };
+        for seed in 0..5 {
+            // Sampling both sub-queues always finds the oldest item at one of the fronts, so any
+            // error comes from choosing the other one
+            let (error_tags, placement_errors) = decompose(2, seed);
+            assert!(placement_errors.iter().all(|error| *error == 0));
+            assert!(error_tags.iter().any(|tag| tag.rank_error() > 0));
+
+            // While a single sample leaves nothing to select between, unless it was empty
+            let (error_tags, placement_errors) = decompose(1, seed);
+            assert!(error_tags.iter().any(|tag| tag.rank_error() > 0));
+            for (tag, placement) in error_tags.iter().zip(placement_errors) {
+                if !tag.fell_back() {
+                    assert_eq!(placement, tag.rank_error());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dequeues_of_unknown_items_are_errors() {
+        // The queue holds items the analyses never enqueued, which the dequeues then return
+        let preloaded = || {
+            let mut queue = DChoiceQueueBuilder::new(2)
+                .log_choices(Some(usize::MAX))
+                .seed(Some(1))
+                .build();
+            for item in 100..104 {
+                queue.enqueue(item);
+            }
+            queue
+        };
+        let operations = [false; 3];
+        let unknown = |result: Result<(), AnalysisError>| {
+            assert!(
+                matches!(result, Err(AnalysisError::UnknownItem { op_index: 0, .. })),
+                "{result:?}"
+            );
+        };
+
+        unknown(analyze_simple(&mut preloaded(), 0, &operations).map(drop));
+        unknown(analyze_streaming(
+            &mut preloaded(),
+            0,
+            &operations,
+            &mut vec![],
+        ));
+        unknown(analyze_bounded(&mut preloaded(), 0, &operations, 10, &mut vec![]).map(drop));
+        unknown(analyze_extra(&mut preloaded(), 0, &operations, None).map(drop));
+        unknown(analyze_extra_from(&mut preloaded(), 0, &operations, None, 200).map(drop));
+        unknown(analyze_extra_final_state(&mut preloaded(), 0, &operations, None).map(drop));
+        unknown(analyze_decomposed(&mut preloaded(), 0, &operations, None).map(drop));
+        let mut rng = StdRng::seed_from_u64(2);
+        unknown(
+            analyze_item_fates(&mut preloaded(), 0, &operations, None, 1.0, &mut rng).map(drop),
+        );
+        unknown(analyze_batched(
+            &mut preloaded(),
+            0,
+            &operations,
+            1,
+            2,
+            &mut vec![],
+        ));
+        unknown(analyze_skewed_prefill(&mut preloaded(), 0, &operations).map(drop));
+        let config = ConcurrentConfig {
+            mean_durations: vec![1.0, 2.0],
+            seed: Some(3),
+        };
+        unknown(analyze_concurrent(&mut preloaded(), 0, &operations, &config).map(drop));
+        let logged = analyze_logged(&mut preloaded(), 0, &operations, &mut io::sink());
+        assert_eq!(logged.unwrap_err().kind(), io::ErrorKind::Other);
+    }
+
+    /// A broken queue whose dequeues return the front item without removing it
+    struct RepeatingQueue(VecDeque<usize>);
+
+    impl RelaxedFifo<usize> for RepeatingQueue {
+        fn enqueue(&mut self, item: usize) {
+            self.0.push_back(item);
+        }
+
+        fn dequeue(&mut self) -> Option<usize> {
+            self.0.front().copied()
+        }
+    }
+
+    #[test]
+    fn repeated_dequeues_are_errors() {
+        // The first dequeue takes item 0, which the second one returns again
+        let operations = [true, false, false, true];
+        let duplicate = Err(AnalysisError::DuplicateDequeue {
+            item: 0,
+            op_index: 2,
+        });
+        let repeating = || RepeatingQueue(VecDeque::new());
+
+        assert_eq!(
+            analyze_simple(&mut repeating(), 2, &operations).map(drop),
+            duplicate
+        );
+        assert_eq!(
+            analyze_simple_checked(&mut repeating(), 2, &operations).map(drop),
+            duplicate
+        );
+        assert_eq!(
+            analyze_streaming(&mut repeating(), 2, &operations, &mut vec![]),
+            duplicate
+        );
+        assert_eq!(
+            analyze_bounded(&mut repeating(), 2, &operations, 10, &mut vec![]).map(drop),
+            duplicate
+        );
+        // The item is reported by its sequence number, not its offset id
+        assert_eq!(
+            analyze_simple_from(&mut repeating(), 2, &operations, 100).map(drop),
+            duplicate
+        );
+    }
+
+    #[test]
+    fn overflowing_ids_are_errors() {
+        let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(2).build();
+        assert_eq!(
+            analyze_simple_from(&mut queue, 2, &[true], usize::MAX - 1),
+            Err(AnalysisError::IdOverflow {
+                start_id: usize::MAX - 1,
+                items: 3
+            })
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn payloads_keep_the_rank_errors() {
+        let operations = gen_balanced_ops(2000, &mut StdRng::seed_from_u64(7));
+        let rank_errors = analyze_simple(
+            &mut DChoiceQueueBuilder::new(8).d(2).seed(Some(7)).build(),
+            30,
+            &operations,
+        )
+        .unwrap();
+        let mut queue = DChoiceQueueBuilder::new(8).d(2).seed(Some(7)).build();
+        assert_eq!(
+            analyze_simple_with(&mut queue, 30, &operations, |i| i).unwrap(),
+            rank_errors
+        );
+        // The queue choices do not depend on the items, so neither do the rank errors
+        let mut queue: DChoiceQueue<[u64; 8]> =
+            DChoiceQueueBuilder::new(8).d(2).seed(Some(7)).build();
+        assert_eq!(
+            analyze_simple_with(&mut queue, 30, &operations, |i| [i as u64; 8]).unwrap(),
+            rank_errors
+        );
+    }
+
+    #[test]
+    fn offset_ids_keep_the_rank_errors() {
+        let operations = gen_balanced_ops(2000, &mut StdRng::seed_from_u64(6));
+        let queue = || DChoiceQueueBuilder::new(8).d(2).seed(Some(6)).build();
+        let rank_errors = analyze_simple(&mut queue(), 30, &operations).unwrap();
+        assert_eq!(
+            analyze_simple_from(&mut queue(), 30, &operations, 0).unwrap(),
+            rank_errors
+        );
+        for start_id in [1, 12345, usize::MAX - 2029] {
+            let mut offset_queue = queue();
+            assert_eq!(
+                analyze_simple_from(&mut offset_queue, 30, &operations, start_id).unwrap(),
+                rank_errors
+            );
+            // The items left over are the offset ones
+            assert!(offset_queue.drain().iter().all(|item| *item >= start_id));
+        }
+    }
+
+    #[test]
+    fn replaying_a_trace_reproduces_the_run() {
+        for seed in 0..5 {
+            let operations = gen_balanced_ops(1000, &mut StdRng::seed_from_u64(seed));
+            let mut queue = DChoiceQueueBuilder::new(4)
+                .d(2)
+                .trace_choices(true)
+                .seed(Some(seed))
+                .build();
+            let error_tags = analyze_extra(&mut queue, 10, &operations, None).unwrap();
+
+            // Empty returns only happen when all sub-queues are empty, so any of them will do
+            let decisions: Vec<Decision> = queue
+                .choice_trace()
+                .unwrap()
+                .iter()
+                .map(|event| match *event {
+                    TraceEvent::Enqueue { subqueue } => Decision::EnqueueTo(subqueue),
+                    TraceEvent::Dequeue { subqueue } => {
+                        Decision::DequeueFrom(subqueue.unwrap_or(0))
+                    }
+                    TraceEvent::Help { .. } | TraceEvent::Buffered => {
+                        panic!("The queue does not help")
+                    }
+                })
+                .collect();
+            let replayed = replay_decisions(&decisions, 10).unwrap();
+            assert_eq!(replayed.len(), error_tags.len());
+            for (replayed, tag) in replayed.iter().zip(&error_tags) {
+                assert_eq!(replayed.rank_error(), tag.rank_error());
+                assert_eq!(replayed.enq_nbr(), tag.enq_nbr());
+                // Which empty sub-queue an empty return chose is not traced
+                if tag.enq_nbr().is_some() {
+                    assert_eq!(replayed.sub_nbr(), tag.sub_nbr());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn relative_errors_blame_the_overtaken_dequeue() {
+        use Decision::*;
+        // Item 0 goes alone to sub-queue 0, and is dequeued after the two newer items
+        let decisions = [
+            EnqueueTo(0),
+            EnqueueTo(1),
+            EnqueueTo(1),
+            DequeueFrom(1),
+            DequeueFrom(2),
+            DequeueFrom(1),
+            DequeueFrom(0),
+        ];
+        let error_tags = replay_decisions(&decisions, 0).unwrap();
+        let rank_errors: Vec<usize> = error_tags
+            .iter()
+            .filter(|tag| tag.enq_nbr().is_some())
+            .map(ErrorTag::rank_error)
+            .collect();
+        assert_eq!(rank_errors, [1, 1, 0]);
+        assert_eq!(dequeue_relative_errors(&error_tags), [0, 0, 2]);
+
+        // Nothing is overtaken in FIFO order
+        let decisions = [EnqueueTo(0), EnqueueTo(0), DequeueFrom(0), DequeueFrom(0)];
+        let error_tags = replay_decisions(&decisions, 0).unwrap();
+        assert_eq!(dequeue_relative_errors(&error_tags), [0, 0]);
+    }
+
+    fn run_concurrent(mean_durations: Vec<f64>, seed: u64) -> Vec<ErrorTag> {
+        let operations = gen_balanced_ops(20000, &mut StdRng::seed_from_u64(seed));
+        let mut queue = DChoiceQueueBuilder::new(8)
+            .d(2)
+            .seed(Some(seed))
+            .build_sequenced();
+        let config = ConcurrentConfig {
+            mean_durations,
+            seed: Some(seed),
+        };
+        analyze_concurrent(&mut queue, 100, &operations, &config).unwrap()
+    }
+
+    /// The mean rank error of the dequeues of the threads
+    fn mean_error(error_tags: &[ErrorTag], threads: impl Fn(usize) -> bool) -> f64 {
+        let errors: Vec<usize> = error_tags
+            .iter()
+            .filter(|tag| tag.thread().is_some_and(&threads))
+            .map(ErrorTag::rank_error)
+            .collect();
+        errors.iter().sum::<usize>() as f64 / errors.len() as f64
+    }
+
+    #[test]
+    fn concurrent_threads_of_equal_speed_share_the_operations() {
+        let error_tags = run_concurrent(vec![1.0; 4], 3);
+        assert_eq!(error_tags.len(), 10000);
+        assert_eq!(error_tags, run_concurrent(vec![1.0; 4], 3));
+        for thread in 0..4 {
+            let dequeues = error_tags
+                .iter()
+                .filter(|tag| tag.thread() == Some(thread))
+                .count();
+            assert!((2300..2700).contains(&dequeues), "{thread}: {dequeues}");
+        }
+
+        // Every item is dequeued at most once
+        let mut items: Vec<usize> = error_tags.iter().filter_map(ErrorTag::enq_nbr).collect();
+        let dequeued = items.len();
+        items.sort_unstable();
+        items.dedup();
+        assert_eq!(items.len(), dequeued);
+    }
+
+    #[test]
+    fn slow_threads_have_larger_errors() {
+        for seed in 0..3 {
+            let error_tags = run_concurrent(vec![10.0, 1.0, 1.0, 1.0], seed);
+            let slow = mean_error(&error_tags, |thread| thread == 0);
+            let fast = mean_error(&error_tags, |thread| thread != 0);
+            assert!(slow > fast, "{seed}: {slow} <= {fast}");
+        }
+    }
+
+    #[test]
+    fn batches_of_one_match_the_unbatched_analysis() {
+        let operations = gen_balanced_ops(2000, &mut StdRng::seed_from_u64(2));
+        let queue = || {
+            DChoiceQueueBuilder::new(8)
+                .d(2)
+                .seed(Some(2))
+                .build_sequenced()
+        };
+        let mut batched = vec![];
+        analyze_batched(&mut queue(), 20, &operations, 1, 1, &mut batched).unwrap();
+        let mut streamed = vec![];
+        analyze_streaming(&mut queue(), 20, &operations, &mut streamed).unwrap();
+        assert_eq!(batched, streamed);
+    }
+
+    #[test]
+    fn batches_dequeue_every_item_once() {
+        let operations = gen_balanced_ops(2000, &mut StdRng::seed_from_u64(3));
+        for (enqueue_batch, dequeue_batch) in [(3, 3), (2, 5), (4, 1)] {
+            let mut queue: DChoiceQueue<usize> = DChoiceQueueBuilder::new(8)
+                .d(2)
+                .seed(Some(3))
+                .build_sequenced();
+            let mut rank_errors = vec![];
+            // The oracle would reject a repeated or unknown item
+            analyze_batched(
+                &mut queue,
+                20,
+                &operations,
+                enqueue_batch,
+                dequeue_batch,
+                &mut rank_errors,
+            )
+            .unwrap();
+            let enqueued = 20 + enqueue_batch * 1000;
+            let dequeued: usize = queue.subqueue_dequeue_counts().iter().sum();
+            assert_eq!(dequeued + queue.len(), enqueued);
+            assert!(rank_errors.len() >= dequeued);
+            let mut remaining: Vec<usize> = (0..queue.nbr_subqueues())
+                .flat_map(|sub| queue.subqueue_items(sub).copied())
+                .collect();
+            remaining.sort_unstable();
+            remaining.dedup();
+            assert_eq!(remaining.len(), queue.len());
+            assert!(remaining.iter().all(|item| *item < enqueued));
+        }
+    }
+
+    #[test]
+    fn barriers_skip_the_end_of_the_operations() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let operations = gen_balanced_ops(10, &mut rng);
+        for (every, barriers) in [(3, 3), (5, 1), (10, 0), (20, 0)] {
+            let mut queue: DChoiceQueue<usize> = crate::DChoiceQueueBuilder::new(4)
+                .seed(Some(1))
+                .build_sequenced();
+            let (rank_errors, moves) =
+                analyze_with_barriers(&mut queue, 20, &operations, every).unwrap();
+            assert_eq!(rank_errors.len(), 5);
+            assert_eq!(moves.len(), barriers, "Barriers every {every}");
+        }
+    }
+
+    #[test]
+    fn balanced_ops_are_seeded() {
+        let gen = |seed| gen_balanced_ops(1000, &mut StdRng::seed_from_u64(seed));
+        assert_eq!(gen(7), gen(7));
+        assert_ne!(gen(7), gen(8));
+    }
+
+    #[test]
+    fn error_tags_survive_serde() {
+        let operations = gen_balanced_ops(2000, &mut StdRng::seed_from_u64(5));
+        let threads: Vec<usize> = (0..operations.len()).map(|i| i % 3).collect();
+        let mut queue = DChoiceQueueBuilder::new(8).seed(Some(5)).build();
+        let mut error_tags = analyze_extra(&mut queue, 10, &operations, Some(&threads)).unwrap();
+        error_tags.extend(run_concurrent(vec![1.0, 2.0], 5));
+        let empty = error_tags
+            .iter()
+            .filter(|tag| tag.enq_nbr().is_none())
+            .count();
+        assert!(empty > 0 && empty < error_tags.len());
+
+        for tag in &error_tags {
+            let json = serde_json::to_string(tag).unwrap();
+            assert_eq!(&serde_json::from_str::<ErrorTag>(&json).unwrap(), tag);
+            let record = tag.to_record();
+            let json = serde_json::to_string(&record).unwrap();
+            assert_eq!(serde_json::from_str::<ErrorRecord>(&json).unwrap(), record);
+            // Only the item dequeues fill in the item fields
+            assert_eq!(record.enq_nbr.is_some(), record.from_prefill.is_some());
+            assert_eq!(record.enq_nbr, tag.enq_nbr());
+        }
+        let all = serde_json::to_string(&error_tags).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<ErrorTag>>(&all).unwrap(),
+            error_tags
+        );
+    }
+
+    /// A queue which keeps track of how many items it holds, and the most after the prefill
+    struct CountedQueue {
+        queue: DChoiceQueue<usize>,
+        live: usize,
+        max_live: usize,
+    }
+
+    impl RelaxedFifo<usize> for CountedQueue {
+        fn enqueue(&mut self, item: usize) {
+            self.queue.enqueue(item);
+            self.live += 1;
+            self.max_live = self.max_live.max(self.live);
+        }
+
+        fn dequeue(&mut self) -> Option<usize> {
+            let item = self.queue.dequeue();
+            if item.is_some() {
+                self.live -= 1;
+            }
+            item
+        }
+
+        fn prefill_done(&mut self) {
+            self.max_live = self.live;
+        }
+    }
+
+    #[test]
+    fn bounded_queues_never_exceed_their_capacity() {
+        for (seed, prefill, max_items) in [(0, 0, 4), (1, 5, 8), (2, 20, 8), (3, 8, 8)] {
+            // Mostly enqueues, so the capacity is reached often
+            let mut rng = StdRng::seed_from_u64(seed);
+            let operations: Vec<bool> = (0..3000).map(|_| rng.gen_bool(0.7)).collect();
+            let mut queue = CountedQueue {
+                queue: DChoiceQueueBuilder::new(4).seed(Some(seed)).build(),
+                live: 0,
+                max_live: 0,
+            };
+            let mut rank_errors = vec![];
+            let failures = analyze_bounded(
+                &mut queue,
+                prefill,
+                &operations,
+                max_items,
+                &mut rank_errors,
+            )
+            .unwrap();
+            assert!(queue.max_live <= max_items.max(prefill), "Seed {seed}");
+
+            // The sizes only depend on the operations
+            let mut expected = EnqueueFailures::default();
+            let mut live = prefill;
+            for enqueue in &operations {
+                if !enqueue {
+                    live = live.saturating_sub(1);
+                } else if live >= max_items {
+                    expected.record(live);
+                } else {
+                    live += 1;
+                }
+            }
+            assert!(expected.count > 0);
+            assert_eq!(failures, expected, "Seed {seed}");
+            // A larger prefill fails the enqueues above the capacity while it drains
+            assert_eq!(
+                failures.sizes.keys().any(|live| *live > max_items),
+                prefill > max_items
+            );
+            assert_eq!(queue.live, live);
+            let dequeues = operations.iter().filter(|op| !**op).count();
+            assert_eq!(rank_errors.len(), dequeues);
+        }
+    }
+
+    #[test]
+    fn sampled_fates_match_the_full_run() {
+        // More enqueues, so that items are left in the queue
+        let mut rng = StdRng::seed_from_u64(4);
+        let operations: Vec<bool> = (0..3000).map(|_| rng.gen_bool(0.55)).collect();
+        let fates = |probability| {
+            let mut queue = DChoiceQueueBuilder::new(8).d(2).seed(Some(4)).build();
+            let mut rng = StdRng::seed_from_u64(5);
+            analyze_item_fates(&mut queue, 40, &operations, None, probability, &mut rng).unwrap()
+        };
+        let (all_tags, all_fates) = fates(1.0);
+        let mut queue = DChoiceQueueBuilder::new(8).d(2).seed(Some(4)).build();
+        assert_eq!(
+            all_tags,
+            analyze_extra(&mut queue, 40, &operations, None).unwrap()
+        );
+
+        // Every item is followed, and its dequeue is the tag which returned it
+        let enqueues = operations.iter().filter(|op| **op).count();
+        assert_eq!(all_fates.len(), 40 + enqueues);
+        for (item, fate) in all_fates.iter().enumerate() {
+            assert_eq!(fate.item, item);
+            assert_eq!(fate.enqueued_at.is_none(), item < 40);
+            assert_eq!(fate.dequeue.is_some(), fate.final_location.is_none());
+            if let Some(tag) = &fate.dequeue {
+                assert_eq!(tag.enq_nbr(), Some(item));
+                assert_eq!(tag.subqueue(), fate.subqueue);
+            }
+        }
+        let dequeued = all_tags
+            .iter()
+            .filter(|tag| tag.enq_nbr().is_some())
+            .count();
+        let left = all_fates
+            .iter()
+            .filter(|fate| fate.final_location.is_some())
+            .count();
+        assert_eq!(left, 40 + enqueues - dequeued);
+        assert!(left > 0);
+
+        // While sampling follows some of the same items to the same fates
+        let (tags, sampled) = fates(0.2);
+        assert_eq!(tags, all_tags);
+        assert!(sampled.len() > 100 && sampled.len() < all_fates.len() / 2);
+        for fate in &sampled {
+            assert_eq!(fate, &all_fates[fate.item]);
+        }
     }
 }
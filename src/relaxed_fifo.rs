@@ -1,4 +1,118 @@
+use crate::d_choice_queue::FinalStateReport;
+
+/// A relaxed FIFO queue of items, as run by the analyses
+///
+/// The trait is object safe, so that the analyses also run queues behind `dyn RelaxedFifo<T>`,
+/// such as those registered by register_queue. Keep it that way by not adding generic methods or
+/// methods taking or returning Self by value.
 pub trait RelaxedFifo<T> {
     fn enqueue(&mut self, item: T);
     fn dequeue(&mut self) -> Option<T>;
+
+    /// Called by the analyses after the prefill, before the measured operations
+    fn prefill_done(&mut self) {}
+}
+
+impl<T, Q: RelaxedFifo<T> + ?Sized> RelaxedFifo<T> for &mut Q {
+    fn enqueue(&mut self, item: T) {
+        (**self).enqueue(item)
+    }
+
+    fn dequeue(&mut self) -> Option<T> {
+        (**self).dequeue()
+    }
+
+    fn prefill_done(&mut self) {
+        (**self).prefill_done()
+    }
+}
+
+/// A relaxed FIFO queue choosing a sub-queue for each operation, which exposes its choices and
+/// sub-queue sizes and lets a wrapper veto sub-queues from being chosen, such as BudgetedQueue
+pub trait InstrumentedFifo<T>: RelaxedFifo<T> {
+    fn nbr_subqueues(&self) -> usize;
+
+    /// Vetoes or allows choosing the sub-queue. If every sampled candidate of an operation is
+    /// vetoed, the queue chooses an allowed sub-queue some other way
+    fn set_vetoed(&mut self, subqueue: usize, vetoed: bool);
+
+    /// The sub-queue chosen by the last operation, or None if it did not choose one or it has
+    /// already been taken
+    fn take_choice(&mut self) -> Option<usize>;
+
+    /// The most items each sub-queue has held at once, a bound on the buffer it needs
+    fn subqueue_high_water_marks(&self) -> Vec<usize>;
+
+    /// The spread (max - min) of the enqueue and of the dequeue counters of the sub-queues
+    fn counter_gaps(&self) -> (usize, usize);
+
+    /// The items left in each sub-queue, with their enqueue ids if the queue can read them
+    fn final_state_report(&self) -> FinalStateReport;
+
+    /// The number of items in the queue
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Sets if a dequeue of an empty sub-queue scans for a non-empty one, instead of returning
+    /// empty. Returns the previous setting
+    fn set_empty_lin(&mut self, empty_lin: bool) -> bool;
+
+    /// Dequeues all the remaining items in the relaxed order of the queue, scanning past empty
+    /// sub-queues so that none are left behind, and then restores the empty dequeue setting
+    ///
+    /// Panics if the queue still holds items once a dequeue returns empty.
+    fn drain(&mut self) -> Vec<T> {
+        let empty_lin = self.set_empty_lin(true);
+        let mut items = vec![];
+        while let Some(item) = self.dequeue() {
+            items.push(item);
+        }
+        assert!(
+            self.is_empty(),
+            "A scanning dequeue returned empty from a non-empty queue"
+        );
+        self.set_empty_lin(empty_lin);
+        items
+    }
+}
+
+impl<T, Q: InstrumentedFifo<T> + ?Sized> InstrumentedFifo<T> for &mut Q {
+    fn nbr_subqueues(&self) -> usize {
+        (**self).nbr_subqueues()
+    }
+
+    fn set_vetoed(&mut self, subqueue: usize, vetoed: bool) {
+        (**self).set_vetoed(subqueue, vetoed)
+    }
+
+    fn take_choice(&mut self) -> Option<usize> {
+        (**self).take_choice()
+    }
+
+    fn subqueue_high_water_marks(&self) -> Vec<usize> {
+        (**self).subqueue_high_water_marks()
+    }
+
+    fn counter_gaps(&self) -> (usize, usize) {
+        (**self).counter_gaps()
+    }
+
+    fn final_state_report(&self) -> FinalStateReport {
+        (**self).final_state_report()
+    }
+
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+
+    fn set_empty_lin(&mut self, empty_lin: bool) -> bool {
+        (**self).set_empty_lin(empty_lin)
+    }
+
+    fn drain(&mut self) -> Vec<T> {
+        (**self).drain()
+    }
 }
@@ -0,0 +1,59 @@
+use std::fmt::Write;
+
+use crate::{ChoiceRecord, ErrorTag, OpKind};
+
+/// Formats the logged choices of the operations after the prefill, one line per operation
+///
+/// The records are those of the prefill enqueues followed by the operations, as logged by the
+/// queue, and the error tags are those of the dequeues of the operations, as from analyze_extra.
+pub fn format_choice_trace(
+    records: &[ChoiceRecord],
+    prefill: usize,
+    error_tags: &[ErrorTag],
+) -> Vec<String> {
+    let mut tags = error_tags.iter();
+    records
+        .iter()
+        .skip(prefill)
+        .enumerate()
+        .map(|(op, record)| {
+            let tag = match record.kind {
+                OpKind::Enqueue => None,
+                OpKind::Dequeue => tags.next(),
+            };
+            format_choice(op, record, tag)
+        })
+        .collect()
+}
+
+/// Formats one operation as e.g. "7 deq [2:5 0:3] -> 0 error 4", with each sampled sub-queue as
+/// index:score, followed by the rank error of a dequeue given its error tag
+pub fn format_choice(op: usize, record: &ChoiceRecord, tag: Option<&ErrorTag>) -> String {
+    let mut line = match record.kind {
+        OpKind::Enqueue => format!("{op} enq"),
+        OpKind::Dequeue => format!("{op} deq"),
+    };
+    match record.chosen {
+        Some(chosen) => {
+            // Adding zero turns the -0 of negated empty lengths into 0
+            let sampled: Vec<String> = record
+                .sampled
+                .iter()
+                .map(|(ind, score)| format!("{ind}:{}", score + 0.0))
+                .collect();
+            write!(line, " [{}] -> {chosen}", sampled.join(" ")).expect("Writes to a string");
+        }
+        None => line.push_str(" buffered"),
+    }
+    match tag {
+        Some(ErrorTag::EmptyDequeue { .. }) => line.push_str(" empty"),
+        Some(tag) => {
+            write!(line, " error {}", tag.rank_error()).expect("Writes to a string");
+            if tag.fell_back() {
+                line.push_str(" (fallback)");
+            }
+        }
+        None => {}
+    }
+    line
+}
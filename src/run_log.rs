@@ -0,0 +1,208 @@
+use std::io::{self, Read, Write};
+
+use crate::OpKind;
+
+/// One operation of a run log, as written by analyze_logged
+///
+/// Each record takes RECORD_BYTES bytes: a flag byte (bit 0 set for dequeues, bit 1 for prefill
+/// enqueues, bit 2 for dequeues which fell back), the sub-queue as a little-endian u32, and the
+/// item and rank error as little-endian u64s, which are u64::MAX if missing.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct LogRecord {
+    pub kind: OpKind,
+
+    /// If the enqueue was part of the prefill, rather than of the measured operations
+    pub prefill: bool,
+
+    /// The sub-queue the item went to or came from, or the chosen one for empty returns
+    pub subqueue: usize,
+
+    /// If the chosen sub-queue was empty, so the dequeue fell back to the round-robin scan
+    pub fell_back: bool,
+
+    /// The enqueued or dequeued item, which is None for empty returns
+    pub item: Option<usize>,
+
+    /// The rank error of a dequeue, where empty returns count the items left in the queue
+    pub rank_error: Option<usize>,
+}
+
+impl LogRecord {
+    pub const RECORD_BYTES: usize = 21;
+
+    const DEQUEUE: u8 = 1;
+    const PREFILL: u8 = 2;
+    const FELL_BACK: u8 = 4;
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut flags = 0;
+        if self.kind == OpKind::Dequeue {
+            flags |= Self::DEQUEUE;
+        }
+        if self.prefill {
+            flags |= Self::PREFILL;
+        }
+        if self.fell_back {
+            flags |= Self::FELL_BACK;
+        }
+        let bin = |value: Option<usize>| value.map_or(u64::MAX, |value| value as u64);
+
+        let mut record = [0; Self::RECORD_BYTES];
+        record[0] = flags;
+        record[1..5].copy_from_slice(&(self.subqueue as u32).to_le_bytes());
+        record[5..13].copy_from_slice(&bin(self.item).to_le_bytes());
+        record[13..21].copy_from_slice(&bin(self.rank_error).to_le_bytes());
+        writer.write_all(&record)
+    }
+
+    /// Reads the next record, or None at the end of the log. A log ending inside a record is an
+    /// error, as it was cut off
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Option<Self>> {
+        let mut record = [0; Self::RECORD_BYTES];
+        let mut filled = 0;
+        while filled < Self::RECORD_BYTES {
+            match reader.read(&mut record[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "The run log ends inside a record",
+                    ))
+                }
+                Ok(read) => filled += read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        let read_u64 = |bytes: &[u8]| {
+            let value = u64::from_le_bytes(bytes.try_into().expect("Eight bytes"));
+            (value != u64::MAX).then_some(value as usize)
+        };
+        let flags = record[0];
+        Ok(Some(Self {
+            kind: if flags & Self::DEQUEUE != 0 {
+                OpKind::Dequeue
+            } else {
+                OpKind::Enqueue
+            },
+            prefill: flags & Self::PREFILL != 0,
+            subqueue: u32::from_le_bytes(record[1..5].try_into().expect("Four bytes")) as usize,
+            fell_back: flags & Self::FELL_BACK != 0,
+            item: read_u64(&record[5..13]),
+            rank_error: read_u64(&record[13..21]),
+        }))
+    }
+
+    /// Describes the record layout, for the json sidecar of a log
+    pub fn schema() -> serde_json::Value {
+        serde_json::json!({
+            "record_bytes": Self::RECORD_BYTES,
+            "byte_order": "little-endian",
+            "missing": "u64::MAX",
+            "fields": [
+                {"name": "flags", "type": "u8", "bits": {"dequeue": 0, "prefill": 1, "fell_back": 2}},
+                {"name": "subqueue", "type": "u32"},
+                {"name": "item", "type": "u64"},
+                {"name": "rank_error", "type": "u64"},
+            ],
+        })
+    }
+}
+
+/// Reads the records of a run log one by one, so that logs larger than memory can be analyzed
+pub struct RunLogReader<R> {
+    reader: R,
+}
+
+impl<R: Read> RunLogReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+}
+
+impl<R: Read> Iterator for RunLogReader<R> {
+    type Item = io::Result<LogRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        LogRecord::read_from(&mut self.reader).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analyze_extra, analyze_logged, gen_balanced_ops, DChoiceQueueBuilder};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn logs_read_back_as_written() {
+        let operations = gen_balanced_ops(3000, &mut StdRng::seed_from_u64(2));
+        let prefill = 20;
+        let mut log = Vec::new();
+        let mut queue = DChoiceQueueBuilder::new(8).seed(Some(2)).build();
+        let rank_errors = analyze_logged(&mut queue, prefill, &operations, &mut log).unwrap();
+        assert_eq!(
+            log.len(),
+            (prefill + operations.len()) * LogRecord::RECORD_BYTES
+        );
+
+        let records: Vec<LogRecord> = RunLogReader::new(log.as_slice())
+            .collect::<io::Result<_>>()
+            .unwrap();
+        let (prefills, records) = records.split_at(prefill);
+        for (item, record) in prefills.iter().enumerate() {
+            assert_eq!(record.kind, OpKind::Enqueue);
+            assert!(record.prefill);
+            assert_eq!(record.item, Some(item));
+        }
+
+        // The same queue run without the log makes the same choices
+        let mut queue = DChoiceQueueBuilder::new(8).seed(Some(2)).build();
+        let error_tags = analyze_extra(&mut queue, prefill, &operations, None).unwrap();
+        let mut tags = error_tags.iter();
+        let mut next_item = prefill;
+        for (op, record) in operations.iter().zip(records) {
+            assert!(!record.prefill);
+            assert!(record.subqueue < 8);
+            if *op {
+                assert_eq!(record.kind, OpKind::Enqueue);
+                assert_eq!(record.item, Some(next_item));
+                assert_eq!(record.rank_error, None);
+                next_item += 1;
+            } else {
+                let tag = tags.next().unwrap();
+                assert_eq!(record.kind, OpKind::Dequeue);
+                assert_eq!(record.item, tag.enq_nbr());
+                assert_eq!(record.rank_error, Some(tag.rank_error()));
+                assert_eq!(record.subqueue, tag.subqueue());
+                assert_eq!(record.fell_back, tag.fell_back());
+            }
+        }
+        assert!(tags.next().is_none());
+        let logged: Vec<usize> = records
+            .iter()
+            .filter_map(|record| record.rank_error)
+            .collect();
+        assert_eq!(logged, rank_errors);
+    }
+
+    #[test]
+    fn cut_off_logs_are_errors() {
+        let record = LogRecord {
+            kind: OpKind::Dequeue,
+            prefill: false,
+            subqueue: 3,
+            fell_back: true,
+            item: None,
+            rank_error: Some(7),
+        };
+        let mut log = Vec::new();
+        record.write_to(&mut log).unwrap();
+        record.write_to(&mut log).unwrap();
+        let mut reader = RunLogReader::new(&log[..log.len() - 1]);
+        assert_eq!(reader.next().unwrap().unwrap(), record);
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
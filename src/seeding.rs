@@ -0,0 +1,129 @@
+/// The version of how the random streams are derived from the seed of an experiment, which must
+/// match for a replay to reproduce it. Bump it whenever derive_seed, the streams used by a
+/// subcommand or how the queue draws from its stream change
+pub const SEED_SCHEME: u32 = 3;
+
+/// The independent random streams of an experiment
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SeedStream {
+    /// The operations generated up front for a data point
+    Operations = 0,
+
+    /// The random choices of the queue of a run
+    Queue = 1,
+
+    /// The operations generated during a run, by the closed-loop distributions
+    OnlineOperations = 2,
+
+    /// The operation durations of a concurrent run
+    Durations = 3,
+
+    /// The number of extra operations decorrelating the final loads of a distributions run
+    Decorrelation = 4,
+
+    /// Which items a single run follows, see analyze_item_fates
+    ItemSampling = 5,
+
+    /// The permutation of the sub-queue layout of a queue, derived from the seed of the queue
+    Layout = 6,
+
+    /// The rejections of the sub-queues served slower by their service weights, derived from the
+    /// seed of the queue
+    Service = 7,
+}
+
+/// Derives the seed of a stream of an experiment, at the data point and run given by the indexes
+///
+/// Mixes in the stream and each index in turn by the SplitMix64 finalizer, so the derived seeds
+/// are independent of each other and of the order the runs are scheduled in.
+pub fn derive_seed(seed: u64, stream: SeedStream, indexes: &[u64]) -> u64 {
+    indexes
+        .iter()
+        .fold(mix(mix(seed) ^ stream as u64), |state, index| {
+            mix(state ^ index)
+        })
+}
+
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analyze_simple, gen_balanced_ops, DChoiceQueueBuilder};
+    use rand::{rngs::StdRng, SeedableRng};
+    use rayon::prelude::*;
+
+    const STREAMS: [SeedStream; 8] = [
+        SeedStream::Operations,
+        SeedStream::Queue,
+        SeedStream::OnlineOperations,
+        SeedStream::Durations,
+        SeedStream::Decorrelation,
+        SeedStream::ItemSampling,
+        SeedStream::Layout,
+        SeedStream::Service,
+    ];
+
+    /// The rank errors of each of the runs, run in the given order on the given number of threads
+    fn run_all(seed: u64, runs: &[u64], threads: usize) -> Vec<(u64, Vec<usize>)> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let mut errors: Vec<(u64, Vec<usize>)> = pool.install(|| {
+            runs.par_iter()
+                .map(|&run| {
+                    let ops_seed = derive_seed(seed, SeedStream::Operations, &[run]);
+                    let ops = gen_balanced_ops(2000, &mut StdRng::seed_from_u64(ops_seed));
+                    let mut queue = DChoiceQueueBuilder::new(8)
+                        .seed(Some(derive_seed(seed, SeedStream::Queue, &[run])))
+                        .build();
+                    (run, analyze_simple(&mut queue, 50, &ops).unwrap())
+                })
+                .collect()
+        });
+        errors.sort_by_key(|(run, _)| *run);
+        errors
+    }
+
+    #[test]
+    fn runs_are_independent_of_threads_and_order() {
+        let runs: Vec<u64> = (0..16).collect();
+        let reversed: Vec<u64> = runs.iter().rev().copied().collect();
+        let reference = run_all(3, &runs, 1);
+        assert_eq!(run_all(3, &runs, 4), reference);
+        assert_eq!(run_all(3, &reversed, 1), reference);
+        assert_eq!(run_all(3, &reversed, 3), reference);
+        // While another seed gives other runs
+        assert_ne!(run_all(4, &runs, 1), reference);
+    }
+
+    #[test]
+    fn streams_get_different_seeds() {
+        for seed in [0, 1, u64::MAX] {
+            for indexes in [&[][..], &[0], &[3, 7]] {
+                let mut seeds: Vec<u64> = STREAMS
+                    .iter()
+                    .map(|&stream| derive_seed(seed, stream, indexes))
+                    .collect();
+                seeds.sort_unstable();
+                seeds.dedup();
+                assert_eq!(
+                    seeds.len(),
+                    STREAMS.len(),
+                    "Seed {seed}, indexes {indexes:?}"
+                );
+            }
+        }
+        // Nor do the indexes of a stream collide when reordered
+        assert_ne!(
+            derive_seed(0, SeedStream::Queue, &[0, 1]),
+            derive_seed(0, SeedStream::Queue, &[1, 0])
+        );
+    }
+}
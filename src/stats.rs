@@ -0,0 +1,582 @@
+use serde::Serialize;
+
+/// A point of a Pareto analysis over (error, cost), where lower is better for both
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ParetoPoint {
+    /// The index of the point in the analyzed slice
+    pub index: usize,
+    pub error: f64,
+    pub cost: f64,
+
+    /// If some other point is at least as good in both error and cost, and better in one
+    pub dominated: bool,
+}
+
+/// Finds the Pareto-optimal points of the (error, cost) points
+///
+/// Returns all points, with the front first, sorted by increasing cost and so decreasing error,
+/// followed by the dominated points sorted the same way. Duplicate points do not dominate each
+/// other, so they are either all on the front or all dominated.
+pub fn pareto_front(points: &[(f64, f64)]) -> Vec<ParetoPoint> {
+    assert!(
+        points
+            .iter()
+            .all(|(error, cost)| !error.is_nan() && !cost.is_nan()),
+        "Can not compare NaN points"
+    );
+    let mut sorted: Vec<ParetoPoint> = points
+        .iter()
+        .enumerate()
+        .map(|(index, (error, cost))| ParetoPoint {
+            index,
+            error: *error,
+            cost: *cost,
+            dominated: false,
+        })
+        .collect();
+    sorted.sort_by(|a, b| {
+        a.cost
+            .total_cmp(&b.cost)
+            .then(a.error.total_cmp(&b.error))
+            .then(a.index.cmp(&b.index))
+    });
+
+    // Every earlier point with other values has at most the same cost, and at most the same
+    // error if the cost is the same, so it dominates if its error is at most as large
+    let mut best_earlier: Option<f64> = None;
+    let mut group_start = 0;
+    while group_start < sorted.len() {
+        let (error, cost) = (sorted[group_start].error, sorted[group_start].cost);
+        let group_end = sorted[group_start..]
+            .iter()
+            .position(|point| point.error != error || point.cost != cost)
+            .map_or(sorted.len(), |offset| group_start + offset);
+        let dominated = best_earlier.is_some_and(|best| best <= error);
+        sorted[group_start..group_end]
+            .iter_mut()
+            .for_each(|point| point.dominated = dominated);
+        best_earlier = Some(best_earlier.map_or(error, |best| best.min(error)));
+        group_start = group_end;
+    }
+
+    // The sort is stable, so both parts stay sorted by cost
+    sorted.sort_by_key(|point| point.dominated);
+    sorted
+}
+
+/// The mean, sample standard deviation and 95% confidence interval of per-run values
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct RunSummary {
+    /// Summed in run order, so that it does not depend on how the runs were scheduled
+    pub mean: f32,
+
+    /// The sample standard deviation, 0 for a single run
+    pub std: f32,
+
+    /// The half-width of the 95% confidence interval of the mean, by the normal approximation
+    pub ci95: f32,
+}
+
+/// The median of the values, the mean of the middle two for an even number of them
+pub fn median(values: &[f32]) -> f32 {
+    assert!(!values.is_empty(), "Needs at least one value");
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+/// The mean and spread of the values of the runs of a point
+///
+/// Panics if there are no runs, which have no mean.
+pub fn summarize_runs(values: &[f32]) -> RunSummary {
+    assert!(!values.is_empty(), "Needs at least one run");
+    let n = values.len();
+    let mean = values.iter().fold(0.0, |acc, value| acc + value) / n as f32;
+    let std = if n > 1 {
+        let squares = values
+            .iter()
+            .map(|value| (*value as f64 - mean as f64).powi(2))
+            .sum::<f64>();
+        (squares / (n - 1) as f64).sqrt()
+    } else {
+        0.0
+    };
+    RunSummary {
+        mean,
+        std: std as f32,
+        ci95: (1.96 * std / (n as f64).sqrt()) as f32,
+    }
+}
+
+/// The most autocorrelation lags effective_sample_size sums over
+const MAX_AUTOCORRELATION_LAG: usize = 1000;
+
+/// Estimates the number of independent samples the autocorrelated series is worth
+///
+/// Divides the length by the integrated autocorrelation time 1 + 2 * sum(rho_k), summing the
+/// lag-k autocorrelations until the first non-positive one, or at most MAX_AUTOCORRELATION_LAG of
+/// them. A constant series is worth all its samples.
+pub fn effective_sample_size(series: &[f64]) -> f64 {
+    let n = series.len();
+    if n < 2 {
+        return n as f64;
+    }
+    let mean = series.iter().sum::<f64>() / n as f64;
+    let variance = series.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n as f64;
+    if variance == 0.0 {
+        return n as f64;
+    }
+    let mut time = 1.0;
+    for lag in 1..(n / 2).min(MAX_AUTOCORRELATION_LAG + 1) {
+        let covariance = series
+            .iter()
+            .zip(&series[lag..])
+            .map(|(a, b)| (a - mean) * (b - mean))
+            .sum::<f64>()
+            / n as f64;
+        let rho = covariance / variance;
+        if rho <= 0.0 {
+            break;
+        }
+        time += 2.0 * rho;
+    }
+    n as f64 / time
+}
+
+/// The mean of the series and the variance of that mean, corrected for the autocorrelation
+fn mean_and_variance(series: &[f64]) -> (f64, f64) {
+    let n = series.len() as f64;
+    let mean = series.iter().sum::<f64>() / n;
+    let variance = series.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+    (mean, variance / effective_sample_size(series))
+}
+
+/// The Geweke z-score comparing the mean of the first 10% of the series to the last 50%
+pub fn geweke_z(series: &[f64]) -> f64 {
+    let early = &series[..series.len() / 10];
+    let late = &series[series.len() / 2..];
+    if early.is_empty() {
+        return 0.0;
+    }
+    let (early_mean, early_variance) = mean_and_variance(early);
+    let (late_mean, late_variance) = mean_and_variance(late);
+    let spread = (early_variance + late_variance).sqrt();
+    if spread == 0.0 {
+        if early_mean == late_mean {
+            0.0
+        } else {
+            f64::INFINITY
+        }
+    } else {
+        (early_mean - late_mean) / spread
+    }
+}
+
+/// Detects how long a prefix of the series is transient, by Geweke's diagnostic
+///
+/// Tries discarding 0%, 10%, ... 50% of the series, and returns the first length whose rest
+/// has an early mean within two standard errors of its late mean. Returns None if the series
+/// does not settle within its first half.
+pub fn detect_burn_in(series: &[f64]) -> Option<usize> {
+    (0..=5)
+        .map(|tenths| series.len() * tenths / 10)
+        .find(|&burn_in| geweke_z(&series[burn_in..]).abs() < 2.0)
+}
+
+/// The steady state of a series, from stationary_sample
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StationarySummary {
+    /// The discarded transient prefix
+    pub burn_in: usize,
+
+    /// If the burn-in was given or detected. Otherwise half the series was discarded
+    pub converged: bool,
+
+    /// Only every thinning-th value after the burn-in was kept
+    pub thinning: usize,
+
+    /// The number of kept values
+    pub samples: usize,
+
+    /// The number of independent values the kept ones are worth
+    pub effective_samples: f64,
+
+    /// The mean of the kept values
+    pub mean: f64,
+}
+
+/// Discards the transient prefix of the series, given or detected by detect_burn_in, and keeps
+/// every thinning-th of the rest to reduce their autocorrelation. Returns the kept values
+pub fn stationary_sample(
+    series: &[f64],
+    burn_in: Option<usize>,
+    thinning: usize,
+) -> (Vec<f64>, StationarySummary) {
+    assert!(
+        thinning > 0,
+        "Must keep every thinning-th value for some thinning > 0"
+    );
+    let (burn_in, converged) = match burn_in {
+        Some(burn_in) => (burn_in.min(series.len()), true),
+        None => match detect_burn_in(series) {
+            Some(burn_in) => (burn_in, true),
+            None => (series.len() / 2, false),
+        },
+    };
+    let kept: Vec<f64> = series[burn_in..]
+        .iter()
+        .step_by(thinning)
+        .copied()
+        .collect();
+    let summary = StationarySummary {
+        burn_in,
+        converged,
+        thinning,
+        samples: kept.len(),
+        effective_samples: effective_sample_size(&kept),
+        mean: kept.iter().sum::<f64>() / kept.len().max(1) as f64,
+    };
+    (kept, summary)
+}
+
+/// The thresholds beyond which diagnose_point flags a sweep point as statistically meaningless
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct DiagnosticThresholds {
+    /// The largest coefficient of variation of the run values, std / |mean|
+    pub max_cv: f64,
+
+    /// The fewest rank errors a tail readout may read its value from
+    pub min_tail_samples: usize,
+
+    /// The largest fraction of the dequeues which may return empty
+    pub max_empty_fraction: f64,
+}
+
+/// The per-run data of one sweep point that diagnose_point checks
+#[derive(Clone, Copy, Debug)]
+pub struct PointRuns<'a> {
+    pub run_values: &'a [f32],
+
+    /// The measured dequeues of each run, including the empty returns
+    pub dequeues: &'a [usize],
+    pub empty_returns: &'a [usize],
+
+    /// The fraction of the rank errors a tail readout reads its value from, such as 0.01 for the
+    /// worst one percent, or None if the readout is not a tail
+    pub tail_fraction: Option<f64>,
+
+    /// If the rank errors of the runs are pooled before the readout, rather than read per run
+    pub pooled: bool,
+}
+
+/// Why a sweep point is flagged
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WarningKind {
+    /// A single run, so the variation between runs is unknown
+    SingleRun,
+    HighVariation,
+    FewTailSamples,
+    ManyEmptyReturns,
+}
+
+/// A flagged sweep point, with a readable description of the problem
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PointWarning {
+    pub point: String,
+    pub kind: WarningKind,
+    pub message: String,
+}
+
+/// The standard deviation of the values over the absolute value of their mean, or None for fewer
+/// than two values or a zero mean
+pub fn coefficient_of_variation(values: &[f32]) -> Option<f64> {
+    if values.len() < 2 {
+        return None;
+    }
+    let summary = summarize_runs(values);
+    (summary.mean != 0.0).then(|| summary.std as f64 / (summary.mean as f64).abs())
+}
+
+/// The number of rank errors the tail readout of a point reads its value from, which is the
+/// smallest of the runs unless they are pooled
+pub fn tail_samples(dequeues: &[usize], tail_fraction: f64, pooled: bool) -> usize {
+    let samples = |dequeues: usize| (dequeues as f64 * tail_fraction).ceil() as usize;
+    if pooled {
+        samples(dequeues.iter().sum())
+    } else {
+        dequeues
+            .iter()
+            .map(|count| samples(*count))
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+/// The fraction of all dequeues of the runs which returned empty, 0 without dequeues
+pub fn empty_fraction(dequeues: &[usize], empty_returns: &[usize]) -> f64 {
+    empty_returns.iter().sum::<usize>() as f64 / dequeues.iter().sum::<usize>().max(1) as f64
+}
+
+/// Flags the point if its value is likely noise: a single run or runs which vary too much, a tail
+/// readout from too few rank errors, or too many empty returns
+pub fn diagnose_point(
+    point: &str,
+    runs: &PointRuns,
+    thresholds: &DiagnosticThresholds,
+) -> Vec<PointWarning> {
+    let mut warnings = vec![];
+    let mut warn = |kind: WarningKind, message: String| {
+        warnings.push(PointWarning {
+            point: point.to_string(),
+            kind,
+            message,
+        })
+    };
+    if runs.run_values.len() == 1 {
+        warn(
+            WarningKind::SingleRun,
+            "only one run, so the variation between runs is unknown".to_string(),
+        );
+    } else if let Some(cv) = coefficient_of_variation(runs.run_values) {
+        if cv > thresholds.max_cv {
+            warn(
+                WarningKind::HighVariation,
+                format!(
+                    "the coefficient of variation of the runs is {cv:.3}, above {}",
+                    thresholds.max_cv
+                ),
+            );
+        }
+    }
+    if let Some(tail_fraction) = runs.tail_fraction {
+        let samples = tail_samples(runs.dequeues, tail_fraction, runs.pooled);
+        if samples < thresholds.min_tail_samples {
+            warn(
+                WarningKind::FewTailSamples,
+                format!(
+                    "the tail readout is read from {samples} rank errors, fewer than {}",
+                    thresholds.min_tail_samples
+                ),
+            );
+        }
+    }
+    let empty = empty_fraction(runs.dequeues, runs.empty_returns);
+    if empty > thresholds.max_empty_fraction {
+        warn(
+            WarningKind::ManyEmptyReturns,
+            format!(
+                "{:.1}% of the dequeues returned empty, above {:.1}%",
+                100.0 * empty,
+                100.0 * thresholds.max_empty_fraction
+            ),
+        );
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// An AR(1) series x_t = phi * x_(t-1) + e_t, with uniform noise e_t in [-1, 1)
+    fn ar1(phi: f64, n: usize, seed: u64) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut x = 0.0;
+        (0..n)
+            .map(|_| {
+                x = phi * x + rng.gen_range(-1.0..1.0);
+                x
+            })
+            .collect()
+    }
+
+    /// The (index, dominated) pairs of the Pareto analysis, in its order
+    fn front(points: &[(f64, f64)]) -> Vec<(usize, bool)> {
+        pareto_front(points)
+            .iter()
+            .map(|point| (point.index, point.dominated))
+            .collect()
+    }
+
+    #[test]
+    fn pareto_front_of_a_single_point() {
+        assert_eq!(
+            pareto_front(&[(2.5, 1.0)]),
+            vec![ParetoPoint {
+                index: 0,
+                error: 2.5,
+                cost: 1.0,
+                dominated: false,
+            }]
+        );
+        assert_eq!(pareto_front(&[]), vec![]);
+    }
+
+    #[test]
+    fn pareto_front_breaks_equal_costs_by_error() {
+        assert_eq!(
+            front(&[(2.0, 1.0), (1.0, 1.0), (0.5, 3.0)]),
+            vec![(1, false), (2, false), (0, true)]
+        );
+        // Equal errors are split by the cost the same way
+        assert_eq!(
+            front(&[(1.0, 2.0), (1.0, 1.0)]),
+            vec![(1, false), (0, true)]
+        );
+    }
+
+    #[test]
+    fn pareto_front_keeps_duplicates_together() {
+        // Neither copy dominates the other, so both stay on the front
+        assert_eq!(
+            front(&[(1.0, 1.0), (0.0, 2.0), (1.0, 1.0), (2.0, 0.0)]),
+            vec![(3, false), (0, false), (2, false), (1, false)]
+        );
+        // And both are dominated by the same better point
+        assert_eq!(
+            front(&[(2.0, 2.0), (1.0, 1.0), (2.0, 2.0)]),
+            vec![(1, false), (0, true), (2, true)]
+        );
+    }
+
+    #[test]
+    fn effective_sample_size_of_ar1() {
+        let n = 20000;
+        for phi in [0.0, 0.5, 0.8] {
+            let expected = n as f64 * (1.0 - phi) / (1.0 + phi);
+            let ess = effective_sample_size(&ar1(phi, n, 1));
+            assert!(
+                (ess / expected - 1.0).abs() < 0.15,
+                "ESS {ess} for phi {phi}, expected about {expected}"
+            );
+        }
+        assert_eq!(effective_sample_size(&[3.0; 10]), 10.0);
+        assert_eq!(effective_sample_size(&[1.0]), 1.0);
+    }
+
+    #[test]
+    fn detects_an_injected_transient() {
+        for seed in 0..5 {
+            let mut series = ar1(0.0, 10000, seed);
+            assert_eq!(detect_burn_in(&series), Some(0), "seed {seed}");
+
+            // The first fifth decays from far above the steady state
+            for (t, x) in series[..2000].iter_mut().enumerate() {
+                *x += 5.0 * (1.0 - t as f64 / 2000.0);
+            }
+            assert_eq!(detect_burn_in(&series), Some(2000), "seed {seed}");
+
+            let (kept, summary) = stationary_sample(&series, None, 2);
+            assert!(summary.converged);
+            assert_eq!(summary.burn_in, 2000);
+            assert_eq!(kept.len(), 4000);
+            assert!(summary.mean.abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn point_statistics_by_hand() {
+        let cv = coefficient_of_variation(&[2.0, 4.0]).unwrap();
+        assert!((cv - 2f64.sqrt() / 3.0).abs() < 1e-6);
+        assert_eq!(coefficient_of_variation(&[]), None);
+        assert_eq!(coefficient_of_variation(&[1.0]), None);
+        assert_eq!(coefficient_of_variation(&[1.0, -1.0]), None);
+
+        assert_eq!(tail_samples(&[100, 250], 0.01, false), 1);
+        assert_eq!(tail_samples(&[100, 250], 0.01, true), 4);
+        assert_eq!(tail_samples(&[], 0.01, false), 0);
+        assert_eq!(tail_samples(&[], 0.01, true), 0);
+
+        assert_eq!(empty_fraction(&[10, 30], &[1, 3]), 0.1);
+        assert_eq!(empty_fraction(&[], &[]), 0.0);
+    }
+
+    #[test]
+    fn diagnose_point_flags_each_problem() {
+        let thresholds = DiagnosticThresholds {
+            max_cv: 0.4,
+            min_tail_samples: 2,
+            max_empty_fraction: 0.05,
+        };
+        let kinds = |runs: &PointRuns| -> Vec<WarningKind> {
+            diagnose_point("point", runs, &thresholds)
+                .into_iter()
+                .map(|warning| warning.kind)
+                .collect()
+        };
+        let clean = PointRuns {
+            run_values: &[3.0, 3.5],
+            dequeues: &[200, 300],
+            empty_returns: &[0, 10],
+            tail_fraction: Some(0.01),
+            pooled: false,
+        };
+        assert_eq!(kinds(&clean), vec![]);
+
+        let warnings = diagnose_point(
+            "point",
+            &PointRuns {
+                run_values: &[2.0, 4.0],
+                dequeues: &[100, 250],
+                empty_returns: &[10, 25],
+                ..clean
+            },
+            &thresholds,
+        );
+        assert_eq!(
+            warnings
+                .iter()
+                .map(|warning| warning.kind)
+                .collect::<Vec<_>>(),
+            vec![
+                WarningKind::HighVariation,
+                WarningKind::FewTailSamples,
+                WarningKind::ManyEmptyReturns
+            ]
+        );
+        assert_eq!(
+            warnings[1].message,
+            "the tail readout is read from 1 rank errors, fewer than 2"
+        );
+        assert_eq!(
+            warnings[2].message,
+            "10.0% of the dequeues returned empty, above 5.0%"
+        );
+        assert!(warnings.iter().all(|warning| warning.point == "point"));
+
+        // Pooling the tails gives them enough samples
+        assert_eq!(
+            kinds(&PointRuns {
+                dequeues: &[100, 250],
+                pooled: true,
+                ..clean
+            }),
+            vec![]
+        );
+        assert_eq!(
+            kinds(&PointRuns {
+                run_values: &[3.0],
+                tail_fraction: None,
+                ..clean
+            }),
+            vec![WarningKind::SingleRun]
+        );
+        // Without runs there is nothing to read a tail from, but no variation to flag
+        assert_eq!(
+            kinds(&PointRuns {
+                run_values: &[],
+                dequeues: &[],
+                empty_returns: &[],
+                ..clean
+            }),
+            vec![WarningKind::FewTailSamples]
+        );
+    }
+}
@@ -0,0 +1,1082 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{median, summarize_runs};
+
+/// One run of a sweep data point, as a flat row with typed columns, where the columns of the point
+/// repeat over its runs
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SweepRow {
+    pub subqueues: usize,
+    pub enqueue_d: usize,
+    pub dequeue_d: usize,
+    pub prefill: usize,
+    pub operations: usize,
+
+    /// The number of runs the value is averaged over
+    pub runs: usize,
+
+    /// The run of the row, its value, and the queue and operations seeds which reproduce it, see
+    /// --exact-seed
+    pub run: usize,
+    pub run_value: f32,
+    pub seed: u64,
+    pub operations_seed: u64,
+
+    /// How the rank error of each run was read out
+    pub readout: String,
+
+    pub value: f32,
+
+    /// The sample standard deviation of the run values, and the 95% confidence interval of the
+    /// value
+    pub std: f32,
+    pub ci95: f32,
+
+    /// The average number of sub-queues sampled per operation, if d was adaptive
+    pub average_d: Option<f32>,
+
+    /// The cost counts summed over the runs, see CostReport
+    pub cost_operations: usize,
+    pub cost_samples: usize,
+    pub cost_scans: usize,
+    pub cost_scanned: usize,
+    pub cost_moves: usize,
+
+    /// The sub-queue reads and item moves per operation, over all runs
+    pub touches_per_operation: f32,
+
+    /// The virtual nanoseconds of the enqueues and dequeues summed over the runs, and the
+    /// operations per virtual second, if there is a latency model
+    pub enqueue_time: Option<f32>,
+    pub dequeue_time: Option<f32>,
+    pub throughput: Option<f32>,
+
+    /// The enqueues which failed at the item capacity, summed over the runs, if there is one
+    pub failed_enqueues: Option<usize>,
+
+    /// The mean simulated operations per wall-clock second of the runs
+    pub ops_per_second: f32,
+
+    /// The queue kind, if the sweep compared several
+    pub queue: Option<String>,
+}
+
+/// Writes the sweep rows as a Parquet file, with the metadata stored as json under "metadata"
+#[cfg(feature = "arrow")]
+pub fn write_sweep_parquet(
+    path: &std::path::Path,
+    metadata: &serde_json::Value,
+    rows: &[SweepRow],
+) -> Result<(), Box<dyn std::error::Error>> {
+    use std::{fs::File, sync::Arc};
+
+    use arrow::{
+        array::{ArrayRef, Float32Array, StringArray, UInt64Array},
+        record_batch::RecordBatch,
+    };
+    use parquet::{
+        arrow::ArrowWriter,
+        file::{metadata::KeyValue, properties::WriterProperties},
+    };
+
+    let column = |get: fn(&SweepRow) -> usize| -> ArrayRef {
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| get(row) as u64),
+        ))
+    };
+    let seeds = |get: fn(&SweepRow) -> u64| -> ArrayRef {
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(get)))
+    };
+    let batch = RecordBatch::try_from_iter([
+        ("subqueues", column(|row| row.subqueues)),
+        ("enqueue_d", column(|row| row.enqueue_d)),
+        ("dequeue_d", column(|row| row.dequeue_d)),
+        ("prefill", column(|row| row.prefill)),
+        ("operations", column(|row| row.operations)),
+        ("runs", column(|row| row.runs)),
+        ("run", column(|row| row.run)),
+        (
+            "run_value",
+            Arc::new(Float32Array::from_iter_values(
+                rows.iter().map(|row| row.run_value),
+            )) as ArrayRef,
+        ),
+        ("seed", seeds(|row| row.seed)),
+        ("operations_seed", seeds(|row| row.operations_seed)),
+        (
+            "readout",
+            Arc::new(StringArray::from_iter_values(
+                rows.iter().map(|row| row.readout.as_str()),
+            )) as ArrayRef,
+        ),
+        (
+            "value",
+            Arc::new(Float32Array::from_iter_values(
+                rows.iter().map(|row| row.value),
+            )) as ArrayRef,
+        ),
+        (
+            "std",
+            Arc::new(Float32Array::from_iter_values(
+                rows.iter().map(|row| row.std),
+            )) as ArrayRef,
+        ),
+        (
+            "ci95",
+            Arc::new(Float32Array::from_iter_values(
+                rows.iter().map(|row| row.ci95),
+            )) as ArrayRef,
+        ),
+        (
+            "average_d",
+            Arc::new(Float32Array::from_iter(
+                rows.iter().map(|row| row.average_d),
+            )) as ArrayRef,
+        ),
+        ("cost_operations", column(|row| row.cost_operations)),
+        ("cost_samples", column(|row| row.cost_samples)),
+        ("cost_scans", column(|row| row.cost_scans)),
+        ("cost_scanned", column(|row| row.cost_scanned)),
+        ("cost_moves", column(|row| row.cost_moves)),
+        (
+            "touches_per_operation",
+            Arc::new(Float32Array::from_iter_values(
+                rows.iter().map(|row| row.touches_per_operation),
+            )) as ArrayRef,
+        ),
+        (
+            "enqueue_time",
+            Arc::new(Float32Array::from_iter(
+                rows.iter().map(|row| row.enqueue_time),
+            )) as ArrayRef,
+        ),
+        (
+            "dequeue_time",
+            Arc::new(Float32Array::from_iter(
+                rows.iter().map(|row| row.dequeue_time),
+            )) as ArrayRef,
+        ),
+        (
+            "throughput",
+            Arc::new(Float32Array::from_iter(
+                rows.iter().map(|row| row.throughput),
+            )) as ArrayRef,
+        ),
+        (
+            "failed_enqueues",
+            Arc::new(UInt64Array::from_iter(
+                rows.iter()
+                    .map(|row| row.failed_enqueues.map(|count| count as u64)),
+            )) as ArrayRef,
+        ),
+        (
+            "ops_per_second",
+            Arc::new(Float32Array::from_iter_values(
+                rows.iter().map(|row| row.ops_per_second),
+            )) as ArrayRef,
+        ),
+        (
+            "queue",
+            Arc::new(StringArray::from_iter(
+                rows.iter().map(|row| row.queue.as_deref()),
+            )) as ArrayRef,
+        ),
+    ])?;
+
+    let properties = WriterProperties::builder()
+        .set_key_value_metadata(Some(vec![KeyValue::new(
+            "metadata".to_string(),
+            metadata.to_string(),
+        )]))
+        .build();
+    let mut writer = ArrowWriter::try_new(File::create(path)?, batch.schema(), Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+/// The version of the json outputs written now, stored under "schema_version"
+pub const SCHEMA_VERSION: u64 = 2;
+
+/// A json output in the current schema
+///
+/// The results and fits stay json, as their layout depends on the subcommand. Sweeps key their
+/// points by stringified tuples such as "(4, 100)", followed by the value and any extras.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ResultFile {
+    pub schema_version: u64,
+    pub metadata: Value,
+    pub results: Value,
+
+    /// The power law fits of a sweep, if it had any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fits: Option<Value>,
+
+    /// The points of a sweep flagged as statistically meaningless, see diagnose_point
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warnings: Option<Value>,
+}
+
+impl ResultFile {
+    /// The output as the json it is written as
+    pub fn into_json(self) -> Value {
+        serde_json::to_value(self).expect("The output is valid json")
+    }
+}
+
+/// Version 1, which wrapped the results together with their metadata, but had no version
+#[derive(Deserialize)]
+struct ResultFileV1 {
+    metadata: Value,
+    results: Value,
+    #[serde(default)]
+    fits: Option<Value>,
+}
+
+/// Version 0, which were only the results of a sweep, as [key, value] pairs
+#[derive(Deserialize)]
+struct ResultFileV0(Vec<(String, Value)>);
+
+/// Upgrades a json output of any known version to the current schema
+///
+/// Outputs of version 0 have no metadata, so it is null. Returns an error for outputs of unknown
+/// versions, such as written by a newer version of the program.
+pub fn upgrade_results(output: Value) -> Result<ResultFile, String> {
+    let version = match &output {
+        Value::Array(_) => 0,
+        Value::Object(fields) => match fields.get("schema_version") {
+            None => 1,
+            Some(version) => version
+                .as_u64()
+                .ok_or(format!("Has the invalid schema version {version}"))?,
+        },
+        _ => return Err("Is not a json output".to_string()),
+    };
+    let invalid =
+        |err: serde_json::Error| format!("Is not a valid output of version {version}: {err}");
+    match version {
+        0 => {
+            let ResultFileV0(points) = serde_json::from_value(output).map_err(invalid)?;
+            Ok(ResultFile {
+                schema_version: SCHEMA_VERSION,
+                metadata: Value::Null,
+                results: points
+                    .into_iter()
+                    .map(|(key, value)| serde_json::json!([key, value]))
+                    .collect(),
+                fits: None,
+                warnings: None,
+            })
+        }
+        1 => {
+            let ResultFileV1 {
+                metadata,
+                results,
+                fits,
+            } = serde_json::from_value(output).map_err(invalid)?;
+            Ok(ResultFile {
+                schema_version: SCHEMA_VERSION,
+                metadata,
+                results,
+                fits,
+                warnings: None,
+            })
+        }
+        SCHEMA_VERSION => serde_json::from_value(output).map_err(invalid),
+        _ => Err(format!(
+            "Has the unknown schema version {version}, where {SCHEMA_VERSION} is the latest known"
+        )),
+    }
+}
+
+/// Reads a json output of any known version, which can be gzipped, and upgrades it to the current
+/// schema as by upgrade_results
+pub fn load_results(path: &Path) -> Result<ResultFile, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let reader: Box<dyn Read> = if path.extension().is_some_and(|ext| ext == "gz") {
+        Box::new(GzDecoder::new(BufReader::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    let output = serde_json::from_reader(reader).map_err(|err| err.to_string())?;
+    upgrade_results(output)
+}
+
+/// The config fields of a sweep which may differ between the outputs merged
+const MERGEABLE_FIELDS: [&str; 4] = ["runs", "output_name", "limits", "fit"];
+
+/// Why sweep outputs could not be merged
+#[derive(Clone, Debug, PartialEq)]
+pub enum MergeError {
+    /// A metadata field differs between the first output and another one
+    Conflict {
+        /// The index of the output that differs from the first one
+        output: usize,
+
+        /// The path of the field, such as "metadata.config.ops-and-prefill.prefill"
+        path: String,
+        first: Value,
+        other: Value,
+    },
+
+    /// An output is not a sweep output, or has a point without the run values
+    Malformed { output: usize, reason: String },
+
+    /// An output has the same seed or run seeds as an earlier one, so that merging them would
+    /// count the same runs twice, such as when merging an output with itself
+    DuplicateRuns { output: usize, earlier: usize },
+}
+
+impl MergeError {
+    /// The index of the output the error is about
+    pub fn output(&self) -> usize {
+        match self {
+            MergeError::Conflict { output, .. }
+            | MergeError::Malformed { output, .. }
+            | MergeError::DuplicateRuns { output, .. } => *output,
+        }
+    }
+}
+
+/// Describes the error without the output, e.g. to prefix with its file
+impl std::fmt::Display for MergeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeError::Conflict {
+                path, first, other, ..
+            } => write!(f, "Has {path} = {other}, but the first has {first}"),
+            MergeError::Malformed { reason, .. } => write!(f, "Is not mergeable, as it {reason}"),
+            MergeError::DuplicateRuns { earlier, .. } => write!(
+                f,
+                "Repeats the runs of input {}, as they have the same seeds",
+                earlier + 1
+            ),
+        }
+    }
+}
+
+/// Merges json sweep outputs of the same configuration, such as run on different machines
+///
+/// The metadata must be the same apart from the seed, the number of runs, the output name, the run
+/// limits and the fit, while the seeds and the run seeds of the points must differ, so that no run
+/// is counted twice. The merged metadata has no seed, as its runs come from several. The run
+/// values of each point are concatenated in output order and the value, std and ci95 recomputed
+/// from them, the costs, virtual times, histograms and failed enqueues are summed and the average
+/// d weighted by the runs. Fits are dropped, as they were of the unmerged values.
+///
+/// The value is recomputed by the aggregate of the sweep, where sweeps pooling the rank errors of
+/// their runs can not be merged, as the rank errors are not in the outputs.
+pub fn merge_sweep_outputs(outputs: &[Value]) -> Result<Value, Vec<MergeError>> {
+    assert!(!outputs.is_empty(), "Needs at least one output to merge");
+    let mut errors = vec![];
+    for (output, other) in outputs.iter().enumerate().skip(1) {
+        diff_metadata(
+            "metadata",
+            &outputs[0]["metadata"],
+            &other["metadata"],
+            0,
+            output,
+            &mut errors,
+        );
+    }
+
+    let aggregate = sweep_config(&outputs[0]["metadata"])
+        .and_then(|config| config["aggregate"].as_str())
+        .unwrap_or("mean-of-readouts");
+    if aggregate == "pooled-readout" {
+        errors.push(MergeError::Malformed {
+            output: 0,
+            reason: "pools the rank errors of its runs".to_string(),
+        });
+    }
+
+    let mut points: Vec<MergePoint> = vec![];
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for (output, sweep) in outputs.iter().enumerate() {
+        let Some(records) = sweep["results"].as_array() else {
+            errors.push(MergeError::Malformed {
+                output,
+                reason: "has no results array".to_string(),
+            });
+            continue;
+        };
+        for record in records {
+            let key = record[0].to_string();
+            let Some(extras) = record[2].as_object().filter(|extras| {
+                extras["run_values"]
+                    .as_array()
+                    .is_some_and(|values| !values.is_empty())
+            }) else {
+                errors.push(MergeError::Malformed {
+                    output,
+                    reason: format!("has no run values for the point {key}"),
+                });
+                continue;
+            };
+            match index.get(&key) {
+                Some(point) => points[*point].extras[output] = Some(extras),
+                None if output == 0 => {
+                    index.insert(key, points.len());
+                    let mut extras_per_output = vec![None; outputs.len()];
+                    extras_per_output[0] = Some(extras);
+                    points.push(MergePoint {
+                        key: &record[0],
+                        value: &record[1],
+                        extras: extras_per_output,
+                    });
+                }
+                None => errors.push(MergeError::Conflict {
+                    output,
+                    path: format!("results[{key}]"),
+                    first: Value::Null,
+                    other: record[1].clone(),
+                }),
+            }
+        }
+    }
+    errors.extend(duplicate_runs(outputs, &points));
+    for point in points.iter() {
+        let missing = point
+            .extras
+            .iter()
+            .enumerate()
+            .filter(|(_, extras)| extras.is_none());
+        for (output, _) in missing {
+            errors.push(MergeError::Conflict {
+                output,
+                path: format!("results[{}]", point.key),
+                first: point.value.clone(),
+                other: Value::Null,
+            });
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let results: Vec<Value> = points
+        .into_iter()
+        .map(|point| {
+            let extras: Vec<&Map<String, Value>> = point.extras.into_iter().flatten().collect();
+            let (value, extras) = merge_extras(&extras, aggregate == "median-of-readouts");
+            serde_json::json!([point.key, value, extras])
+        })
+        .collect();
+    let mut metadata = outputs[0]["metadata"].clone();
+    if let Some(metadata) = metadata.as_object_mut() {
+        metadata.remove("seed");
+    }
+    let total_runs: u64 = outputs
+        .iter()
+        .filter_map(|output| sweep_config(&output["metadata"])?["runs"].as_u64())
+        .sum();
+    if let Some(config) = metadata["config"]
+        .as_object_mut()
+        .and_then(|config| config.values_mut().next())
+        .and_then(|sweep| sweep.as_object_mut())
+    {
+        config.insert("runs".to_string(), total_runs.into());
+    }
+    Ok(serde_json::json!({
+        "schema_version": SCHEMA_VERSION,
+        "metadata": metadata,
+        "results": results,
+    }))
+}
+
+/// A point of the first output to merge
+struct MergePoint<'a> {
+    key: &'a Value,
+    value: &'a Value,
+
+    /// The extras of the point in each output, None where it is missing
+    extras: Vec<Option<&'a Map<String, Value>>>,
+}
+
+/// Finds the outputs which repeat runs of an earlier one, by the seed of the sweep or the queue
+/// seeds of the runs of a point, where older outputs without run seeds are only checked by seed
+fn duplicate_runs(outputs: &[Value], points: &[MergePoint]) -> Vec<MergeError> {
+    let mut duplicates: BTreeSet<(usize, usize)> = BTreeSet::new();
+    for (output, sweep) in outputs.iter().enumerate() {
+        let seed = &sweep["metadata"]["seed"];
+        for (earlier, earlier_sweep) in outputs[..output].iter().enumerate() {
+            if !seed.is_null() && *seed == earlier_sweep["metadata"]["seed"] {
+                duplicates.insert((output, earlier));
+            }
+        }
+    }
+    for point in points {
+        let mut seen: HashMap<u64, usize> = HashMap::new();
+        for (output, extras) in point.extras.iter().enumerate() {
+            let queue_seeds = extras
+                .and_then(|extras| extras.get("run_seeds"))
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(|seeds| seeds["queue"].as_u64());
+            for queue_seed in queue_seeds {
+                match seen.get(&queue_seed) {
+                    Some(&earlier) if earlier != output => {
+                        duplicates.insert((output, earlier));
+                    }
+                    Some(_) => {}
+                    None => {
+                        seen.insert(queue_seed, output);
+                    }
+                }
+            }
+        }
+    }
+    duplicates
+        .into_iter()
+        .map(|(output, earlier)| MergeError::DuplicateRuns { output, earlier })
+        .collect()
+}
+
+/// The config of the sweep subcommand, stored under its name
+fn sweep_config(metadata: &Value) -> Option<&Value> {
+    metadata["config"].as_object()?.values().next()
+}
+
+/// Records where the values differ, skipping the mergeable fields of the sweep config at depth 3
+fn diff_metadata(
+    path: &str,
+    first: &Value,
+    other: &Value,
+    depth: usize,
+    output: usize,
+    errors: &mut Vec<MergeError>,
+) {
+    match (first, other) {
+        (Value::Object(first), Value::Object(other)) => {
+            let keys: BTreeSet<&String> = first.keys().chain(other.keys()).collect();
+            for key in keys {
+                if (depth == 0 && key == "seed")
+                    || (depth == 2 && MERGEABLE_FIELDS.contains(&key.as_str()))
+                {
+                    continue;
+                }
+                diff_metadata(
+                    &format!("{path}.{key}"),
+                    first.get(key).unwrap_or(&Value::Null),
+                    other.get(key).unwrap_or(&Value::Null),
+                    depth + 1,
+                    output,
+                    errors,
+                );
+            }
+        }
+        _ if first != other => errors.push(MergeError::Conflict {
+            output,
+            path: path.to_string(),
+            first: first.clone(),
+            other: other.clone(),
+        }),
+        _ => {}
+    }
+}
+
+/// The merged value and extras of one point, from its extras in each output, where the value is
+/// the median of the run values if set and otherwise their mean
+fn merge_extras(points: &[&Map<String, Value>], median_value: bool) -> (Value, Map<String, Value>) {
+    let run_values: Vec<f32> = points
+        .iter()
+        .flat_map(|extras| extras["run_values"].as_array().into_iter().flatten())
+        .filter_map(|value| value.as_f64())
+        .map(|value| value as f32)
+        .collect();
+    let summary = summarize_runs(&run_values);
+
+    // Fields which are not merged, such as the operations, are the same in all outputs
+    let mut extras = points[0].clone();
+    extras.insert("run_values".to_string(), serde_json::json!(run_values));
+    let run_seeds: Option<Vec<Value>> = points
+        .iter()
+        .map(|extras| extras.get("run_seeds").and_then(Value::as_array))
+        .collect::<Option<Vec<&Vec<Value>>>>()
+        .map(|seeds| seeds.into_iter().flatten().cloned().collect());
+    match run_seeds {
+        Some(run_seeds) => extras.insert("run_seeds".to_string(), Value::Array(run_seeds)),
+        // Outputs from before the seeds were recorded can not attribute them to the runs
+        None => extras.remove("run_seeds"),
+    };
+    extras.insert("std".to_string(), serde_json::json!(summary.std));
+    extras.insert("ci95".to_string(), serde_json::json!(summary.ci95));
+
+    let runs: Vec<f64> = points
+        .iter()
+        .map(|extras| extras["run_values"].as_array().map_or(0, Vec::len) as f64)
+        .collect();
+    let average_ds: Option<Vec<f64>> = points
+        .iter()
+        .map(|extras| extras.get("average_d").and_then(Value::as_f64))
+        .collect();
+    if let Some(average_ds) = average_ds {
+        let weighted = average_ds.iter().zip(runs.iter()).map(|(d, n)| d * n);
+        let average_d = weighted.sum::<f64>() / runs.iter().sum::<f64>().max(1.0);
+        extras.insert("average_d".to_string(), serde_json::json!(average_d as f32));
+    }
+    let speeds: Option<Vec<f64>> = points
+        .iter()
+        .map(|extras| extras.get("ops_per_second").and_then(Value::as_f64))
+        .collect();
+    match speeds {
+        Some(speeds) => {
+            let weighted = speeds.iter().zip(runs.iter()).map(|(speed, n)| speed * n);
+            let speed = weighted.sum::<f64>() / runs.iter().sum::<f64>().max(1.0);
+            extras.insert(
+                "ops_per_second".to_string(),
+                serde_json::json!(speed as f32),
+            );
+        }
+        None => {
+            extras.remove("ops_per_second");
+        }
+    }
+
+    if points.iter().all(|extras| extras.contains_key("cost")) {
+        let mut cost: Map<String, Value> = Map::new();
+        for extras in points.iter() {
+            for (field, count) in extras["cost"].as_object().into_iter().flatten() {
+                let sum = cost.get(field).and_then(Value::as_u64).unwrap_or(0)
+                    + count.as_u64().unwrap_or(0);
+                cost.insert(field.clone(), sum.into());
+            }
+        }
+        let count = |field: &str| cost.get(field).and_then(Value::as_u64).unwrap_or(0);
+        let touches = (count("samples") + count("scanned") + count("moves")) as f64
+            / count("operations").max(1) as f64;
+        extras.insert("touches_per_operation".to_string(), touches.into());
+        extras.insert("cost".to_string(), Value::Object(cost));
+    }
+
+    if points
+        .iter()
+        .all(|extras| extras.contains_key("virtual_time"))
+    {
+        let sum = |field: &str| {
+            points
+                .iter()
+                .filter_map(|extras| extras["virtual_time"][field].as_f64())
+                .sum::<f64>()
+        };
+        let (enqueues, dequeues) = (sum("enqueues"), sum("dequeues"));
+        let total = sum("enqueue_time") + sum("dequeue_time");
+        extras.insert(
+            "virtual_time".to_string(),
+            serde_json::json!({
+                "enqueues": enqueues as u64,
+                "dequeues": dequeues as u64,
+                "enqueue_time": sum("enqueue_time"),
+                "dequeue_time": sum("dequeue_time"),
+                "throughput": if total > 0.0 { (enqueues + dequeues) * 1e9 / total } else { 0.0 },
+            }),
+        );
+    }
+
+    if points
+        .iter()
+        .all(|extras| extras.contains_key("enqueue_failures"))
+    {
+        let mut sizes: BTreeMap<u64, u64> = BTreeMap::new();
+        for extras in points.iter() {
+            let failures = &extras["enqueue_failures"]["sizes"];
+            for (live, count) in failures.as_object().into_iter().flatten() {
+                let live = live.parse().unwrap_or(0);
+                *sizes.entry(live).or_default() += count.as_u64().unwrap_or(0);
+            }
+        }
+        extras.insert(
+            "enqueue_failures".to_string(),
+            serde_json::json!({
+                "count": sizes.values().sum::<u64>(),
+                "sizes": sizes,
+            }),
+        );
+    }
+
+    if points.iter().all(|extras| extras.contains_key("histogram")) {
+        let histograms: Vec<&Value> = points.iter().map(|extras| &extras["histogram"]).collect();
+        extras.insert("histogram".to_string(), merge_histograms(&histograms));
+    }
+
+    let value = if median_value {
+        median(&run_values)
+    } else {
+        summary.mean
+    };
+    (serde_json::json!(value), extras)
+}
+
+/// Merges serialized LogHistograms of the same precision by summing the counts of each bucket
+fn merge_histograms(histograms: &[&Value]) -> Value {
+    let mut buckets: BTreeMap<(u64, u64), u64> = BTreeMap::new();
+    let (mut count, mut max, mut sum) = (0, 0, 0.0);
+    for histogram in histograms {
+        let histogram_count = histogram["count"].as_u64().unwrap_or(0);
+        count += histogram_count;
+        max = max.max(histogram["max"].as_u64().unwrap_or(0));
+        sum += histogram["mean"].as_f64().unwrap_or(0.0) * histogram_count as f64;
+        for bucket in histogram["buckets"].as_array().into_iter().flatten() {
+            let low = bucket[0].as_u64().unwrap_or(0);
+            let high = bucket[1].as_u64().unwrap_or(0);
+            *buckets.entry((low, high)).or_default() += bucket[2].as_u64().unwrap_or(0);
+        }
+    }
+    serde_json::json!({
+        "significant_bits": histograms[0]["significant_bits"],
+        "count": count,
+        "mean": sum / count.max(1) as f64,
+        "max": max,
+        "buckets": buckets
+            .into_iter()
+            .map(|((low, high), count)| (low, high, count))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// A value of a replayed json sweep output which differs from the original
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    /// The path of the value, such as "results["(4, 100)"].ci95"
+    pub path: String,
+
+    /// The original value, or null if the replay has a value not in the original
+    pub original: Value,
+
+    /// The replayed value, or null if the replay is missing the value
+    pub replayed: Value,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} was {} but replayed as {}",
+            self.path, self.original, self.replayed
+        )
+    }
+}
+
+/// Compares the points and fits of a replayed json sweep output to the original
+///
+/// Numbers may differ by the relative tolerance, so 0 requires them to be the same bit for bit,
+/// while all other values must be equal. Points missing from either output are divergences too.
+/// The metadata is not compared, as the replay is written with its own, and neither are the
+/// TIMING_FIELDS, as they are measured on the wall clock rather than simulated.
+pub fn compare_sweep_outputs(
+    original: &Value,
+    replayed: &Value,
+    tolerance: f64,
+) -> Vec<Divergence> {
+    let points = |output: &Value| -> BTreeMap<String, Value> {
+        output["results"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|record| (record[0].to_string(), record.clone()))
+            .collect()
+    };
+    let (original_points, replayed_points) = (points(original), points(replayed));
+    let keys: BTreeSet<&String> = original_points
+        .keys()
+        .chain(replayed_points.keys())
+        .collect();
+
+    let mut divergences = vec![];
+    for key in keys {
+        let path = format!("results[{key}]");
+        match (original_points.get(key), replayed_points.get(key)) {
+            (Some(original), Some(replayed)) => {
+                let value_path = format!("{path}.value");
+                diff_values(
+                    &value_path,
+                    &original[1],
+                    &replayed[1],
+                    tolerance,
+                    &mut divergences,
+                );
+                diff_values(
+                    &path,
+                    &original[2],
+                    &replayed[2],
+                    tolerance,
+                    &mut divergences,
+                );
+            }
+            (original, replayed) => divergences.push(Divergence {
+                path,
+                original: original.cloned().unwrap_or(Value::Null),
+                replayed: replayed.cloned().unwrap_or(Value::Null),
+            }),
+        }
+    }
+    diff_values(
+        "fits",
+        &original["fits"],
+        &replayed["fits"],
+        tolerance,
+        &mut divergences,
+    );
+    divergences
+}
+
+/// The fields of the sweep extras which are wall-clock measurements, and so differ between runs
+const TIMING_FIELDS: [&str; 1] = ["ops_per_second"];
+
+/// Records where the replayed value differs from the original, recursing into objects and arrays
+fn diff_values(
+    path: &str,
+    original: &Value,
+    replayed: &Value,
+    tolerance: f64,
+    divergences: &mut Vec<Divergence>,
+) {
+    match (original, replayed) {
+        (Value::Object(original), Value::Object(replayed)) => {
+            let keys: BTreeSet<&String> = original
+                .keys()
+                .chain(replayed.keys())
+                .filter(|key| !TIMING_FIELDS.contains(&key.as_str()))
+                .collect();
+            for key in keys {
+                diff_values(
+                    &format!("{path}.{key}"),
+                    original.get(key).unwrap_or(&Value::Null),
+                    replayed.get(key).unwrap_or(&Value::Null),
+                    tolerance,
+                    divergences,
+                );
+            }
+        }
+        (Value::Array(original), Value::Array(replayed)) if original.len() == replayed.len() => {
+            for (ind, (original, replayed)) in original.iter().zip(replayed.iter()).enumerate() {
+                diff_values(
+                    &format!("{path}[{ind}]"),
+                    original,
+                    replayed,
+                    tolerance,
+                    divergences,
+                );
+            }
+        }
+        (Value::Number(original_nbr), Value::Number(replayed_nbr)) => {
+            let (a, b) = (
+                original_nbr.as_f64().unwrap_or(f64::NAN),
+                replayed_nbr.as_f64().unwrap_or(f64::NAN),
+            );
+            if original_nbr != replayed_nbr && (a - b).abs() > tolerance * a.abs().max(b.abs()) {
+                divergences.push(Divergence {
+                    path: path.to_string(),
+                    original: original.clone(),
+                    replayed: replayed.clone(),
+                });
+            }
+        }
+        _ if original != replayed => divergences.push(Divergence {
+            path: path.to_string(),
+            original: original.clone(),
+            replayed: replayed.clone(),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sweep output of one point, with a queue seed per run from the first one
+    fn sweep(seed: u64, run_values: &[f32], first_queue_seed: u64) -> Value {
+        let run_seeds: Vec<Value> = (0..run_values.len() as u64)
+            .map(|run| serde_json::json!({"queue": first_queue_seed + run, "operations": 1}))
+            .collect();
+        serde_json::json!({
+            "metadata": {
+                "seed": seed,
+                "config": {"ops-and-prefill": {"runs": run_values.len(), "prefill": [10]}},
+            },
+            "results": [["(10, 100)", 0.0, {"run_values": run_values, "run_seeds": run_seeds}]],
+        })
+    }
+
+    #[test]
+    fn merge_rejects_same_output_twice() {
+        let output = sweep(1, &[1.0, 2.0], 100);
+        assert_eq!(
+            merge_sweep_outputs(&[output.clone(), output]),
+            Err(vec![MergeError::DuplicateRuns {
+                output: 1,
+                earlier: 0
+            }])
+        );
+    }
+
+    #[test]
+    fn merge_rejects_shared_run_seeds() {
+        // Different sweep seeds, such as after a merge dropped it, but one run seed in common
+        let mut merged = sweep(1, &[1.0, 2.0], 100);
+        merged["metadata"]["seed"] = Value::Null;
+        let errors = merge_sweep_outputs(&[sweep(2, &[3.0], 200), merged, sweep(3, &[4.0], 101)])
+            .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![MergeError::DuplicateRuns {
+                output: 2,
+                earlier: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn merge_pools_run_values() {
+        let mut first = sweep(1, &[1.0, 2.0], 100);
+        first["results"][0][2]["average_d"] = serde_json::json!(2.0);
+        let mut second = sweep(2, &[6.0], 200);
+        second["results"][0][2]["average_d"] = serde_json::json!(5.0);
+        let merged = merge_sweep_outputs(&[first, second]).unwrap();
+
+        let point = &merged["results"][0];
+        assert_eq!(point[0], "(10, 100)");
+        assert_eq!(point[1], 3.0);
+        assert_eq!(point[2]["run_values"], serde_json::json!([1.0, 2.0, 6.0]));
+        let queue_seeds: Vec<u64> = point[2]["run_seeds"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|seeds| seeds["queue"].as_u64().unwrap())
+            .collect();
+        assert_eq!(queue_seeds, [100, 101, 200]);
+        // Weighted by the runs of each output
+        assert_eq!(point[2]["average_d"], 3.0);
+        assert!((point[2]["std"].as_f64().unwrap() - 7f64.sqrt()).abs() < 1e-6);
+        assert_eq!(merged["metadata"]["config"]["ops-and-prefill"]["runs"], 3);
+        assert!(merged["metadata"].get("seed").is_none());
+    }
+
+    #[test]
+    fn merge_takes_median_of_median_sweeps() {
+        let with_median = |mut output: Value| {
+            output["metadata"]["config"]["ops-and-prefill"]["aggregate"] =
+                serde_json::json!("median-of-readouts");
+            output
+        };
+        let merged = merge_sweep_outputs(&[
+            with_median(sweep(1, &[1.0, 2.0], 100)),
+            with_median(sweep(2, &[30.0], 200)),
+        ])
+        .unwrap();
+        assert_eq!(merged["results"][0][1], 2.0);
+    }
+
+    #[test]
+    fn merge_rejects_mismatched_outputs() {
+        let mut other_prefill = sweep(2, &[1.0], 200);
+        other_prefill["metadata"]["config"]["ops-and-prefill"]["prefill"] = serde_json::json!([20]);
+        let errors = merge_sweep_outputs(&[sweep(1, &[1.0], 100), other_prefill]).unwrap_err();
+        assert!(matches!(
+            &errors[..],
+            [MergeError::Conflict { output: 1, path, .. }]
+                if path == "metadata.config.ops-and-prefill.prefill"
+        ));
+
+        let mut other_point = sweep(2, &[1.0], 200);
+        other_point["results"][0][0] = serde_json::json!("(10, 200)");
+        let errors = merge_sweep_outputs(&[sweep(1, &[1.0], 100), other_point]).unwrap_err();
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert!(errors.iter().all(|err| err.output() == 1));
+    }
+
+    #[test]
+    fn merge_rejects_points_without_runs() {
+        let errors = merge_sweep_outputs(&[sweep(1, &[1.0], 100), sweep(2, &[], 200)]).unwrap_err();
+        assert!(errors.contains(&MergeError::Malformed {
+            output: 1,
+            reason: "has no run values for the point \"(10, 100)\"".to_string(),
+        }));
+
+        let mut pooled = sweep(1, &[1.0], 100);
+        pooled["metadata"]["config"]["ops-and-prefill"]["aggregate"] =
+            serde_json::json!("pooled-readout");
+        assert!(merge_sweep_outputs(&[pooled]).is_err());
+        assert!(merge_sweep_outputs(&[serde_json::json!({"metadata": {}})]).is_err());
+    }
+
+    #[test]
+    fn compare_skips_timing() {
+        let output = |value: f64, speed: f64| {
+            serde_json::json!({
+                "results": [["(0, 100)", value, {"ops_per_second": speed, "std": 0.5}]],
+            })
+        };
+        assert!(compare_sweep_outputs(&output(1.5, 1e6), &output(1.5, 2e6), 0.0).is_empty());
+
+        let divergences = compare_sweep_outputs(&output(1.5, 1e6), &output(1.25, 1e6), 0.0);
+        assert_eq!(divergences.len(), 1);
+        assert_eq!(divergences[0].path, "results[\"(0, 100)\"].value");
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn parquet_reads_back() {
+        let rows: Vec<SweepRow> = (0..3)
+            .map(|run| SweepRow {
+                subqueues: 4,
+                prefill: 100,
+                operations: 1000,
+                runs: 3,
+                run,
+                run_value: run as f32,
+                seed: 10 + run as u64,
+                readout: "mean".to_string(),
+                queue: (run == 0).then(|| "operation".to_string()),
+                ..SweepRow::default()
+            })
+            .collect();
+        let path = std::env::temp_dir().join(format!("sweep-rows-{}.parquet", std::process::id()));
+        write_sweep_parquet(&path, &serde_json::json!({"runs": 3}), &rows).unwrap();
+
+        let builder = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(
+            std::fs::File::open(&path).unwrap(),
+        )
+        .unwrap();
+        let schema = builder.schema().clone();
+        let metadata = builder
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .cloned();
+        let batches: Vec<_> = builder.build().unwrap().map(Result::unwrap).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        for name in [
+            "subqueues",
+            "enqueue_d",
+            "dequeue_d",
+            "prefill",
+            "operations",
+            "run",
+            "seed",
+            "readout",
+            "value",
+        ] {
+            assert!(schema.field_with_name(name).is_ok(), "missing {name}");
+        }
+        assert_eq!(
+            batches.iter().map(|batch| batch.num_rows()).sum::<usize>(),
+            3
+        );
+        let metadata = metadata.unwrap();
+        assert_eq!(metadata[0].key, "metadata");
+        assert_eq!(metadata[0].value.as_deref(), Some(r#"{"runs":3}"#));
+    }
+}
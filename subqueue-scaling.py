@@ -1,5 +1,6 @@
 import argparse
 import subprocess
+import gzip
 import json
 import re
 import matplotlib.pyplot as plt
@@ -8,6 +9,13 @@ import pandas as pd
 import numpy as np
 
 
+def open_output(path):
+    # Outputs can be gzipped, which is shown by the suffix
+    if str(path).endswith('.gz'):
+        return gzip.open(path, 'rt')
+    return open(path, 'r')
+
+
 def parse_arguments():
     parser = argparse.ArgumentParser(
         description="Run Rust tests and plot heatmap.")
@@ -55,10 +63,13 @@ def extract_file_path(output):
 
 
 def read_and_parse_data(filepath):
-    with open(filepath, 'r') as file:
+    with open_output(filepath) as file:
         data = json.load(file)
+        # Results are wrapped together with their metadata, but older files are not
+        if isinstance(data, dict):
+            data = data["results"]
         subqueue_errors = sorted(
-            [(int(p_ops_str[1:].split(',')[0]), avg) for [p_ops_str, avg] in data], key=lambda pair: pair[0])
+            [(int(p_ops_str[1:].split(',')[0]), avg) for [p_ops_str, avg, *_] in data], key=lambda pair: pair[0])
     return subqueue_errors
 
 
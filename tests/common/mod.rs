@@ -0,0 +1,150 @@
+//! Helpers shared by the integration tests, which run the binary on tiny fixed-seed tests
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use serde_json::Value;
+
+/// The fields which hold wall-clock measurements, and so differ between otherwise equal runs
+const TIMING_FIELDS: [&str; 1] = ["ops_per_second"];
+
+/// A fresh output directory, removed again when dropped
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("relaxation-analysis-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("Could not create the test directory");
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// The path of an output written with --no-timestamp
+    pub fn output(&self, output_name: &str) -> PathBuf {
+        self.0.join(format!("{output_name}.json"))
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The binary, run from the directory so that it writes any default outputs there
+pub fn command(dir: &TestDir) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_relaxation-analysis"));
+    command.current_dir(dir.path());
+    command
+}
+
+/// Runs the binary with the global arguments writing to the directory without timestamps,
+/// followed by the arguments
+pub fn run(dir: &TestDir, args: &[&str]) -> Output {
+    command(dir)
+        .arg("--output-dir")
+        .arg(dir.path())
+        .arg("--no-timestamp")
+        .args(args)
+        .output()
+        .expect("Could not run the binary")
+}
+
+/// As run, but asserts that it succeeds, and returns its stdout
+pub fn run_ok(dir: &TestDir, args: &[&str]) -> String {
+    let output = run(dir, args);
+    assert!(
+        output.status.success(),
+        "{args:?} failed with {}:\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).expect("The stdout is utf-8")
+}
+
+pub fn read_json(path: &Path) -> Value {
+    let file = fs::File::open(path)
+        .unwrap_or_else(|err| panic!("Could not open {}: {err}", path.display()));
+    serde_json::from_reader(file).expect("The output is json")
+}
+
+/// Replaces the wall-clock measurements in the json, so that it only holds deterministic values
+pub fn normalize(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                if TIMING_FIELDS.contains(&key.as_str()) {
+                    *field = Value::String("<timing>".to_string());
+                } else {
+                    normalize(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(normalize),
+        _ => {}
+    }
+}
+
+/// Finds the field anywhere in the json, such as in the extras of every sweep point
+pub fn find_all<'a>(value: &'a Value, field: &str, found: &mut Vec<&'a Value>) {
+    match value {
+        Value::Object(map) => {
+            for (key, inner) in map {
+                if key == field {
+                    found.push(inner);
+                }
+                find_all(inner, field, found);
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|item| find_all(item, field, found)),
+        _ => {}
+    }
+}
+
+/// Compares the text with tests/golden/{name}, or overwrites the golden file if UPDATE_GOLDENS
+/// is set, such as after an intended schema change
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name);
+    if std::env::var_os("UPDATE_GOLDENS").is_some() {
+        fs::write(&path, actual).expect("Could not write the golden file");
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_else(|err| {
+        panic!(
+            "Could not read {}: {err}. Run with UPDATE_GOLDENS=1 to create it",
+            path.display()
+        )
+    });
+    assert!(
+        expected == actual,
+        "The output differs from {}, run with UPDATE_GOLDENS=1 if the change is intended:\n{}",
+        path.display(),
+        first_difference(&expected, actual)
+    );
+}
+
+fn first_difference(expected: &str, actual: &str) -> String {
+    expected
+        .lines()
+        .zip(actual.lines())
+        .enumerate()
+        .find(|(_, (expected, actual))| expected != actual)
+        .map(|(line, (expected, actual))| {
+            format!(
+                "line {}:\n  expected: {expected}\n  actual:   {actual}",
+                line + 1
+            )
+        })
+        .unwrap_or_else(|| "the outputs differ in length".to_string())
+}
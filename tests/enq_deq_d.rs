@@ -0,0 +1,98 @@
+//! Sweeps over the d of the enqueues and dequeues, which run every pair of them on the same seeds
+
+mod common;
+
+use common::{read_json, run, run_ok, TestDir};
+
+#[test]
+fn sweeps_every_pair_of_d() {
+    let dir = TestDir::new("enq-deq-d");
+    run_ok(
+        &dir,
+        &[
+            "--seed",
+            "1",
+            "ops-and-prefill",
+            "-s",
+            "8",
+            "-o",
+            "2000",
+            "-i",
+            "100",
+            "-r",
+            "2",
+            "--enq-d",
+            "1",
+            "2",
+            "--deq-d",
+            "1",
+            "4",
+        ],
+    );
+    let output = read_json(&dir.output("OpsAndPrefill"));
+    let points = output["results"].as_array().unwrap();
+    let keys: Vec<&str> = points
+        .iter()
+        .map(|point| point[0].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        keys,
+        [
+            "(1, 1, 100, 2000)",
+            "(1, 4, 100, 2000)",
+            "(2, 1, 100, 2000)",
+            "(2, 4, 100, 2000)"
+        ]
+    );
+    for (point, (enq_d, deq_d)) in points.iter().zip([(1, 1), (1, 4), (2, 1), (2, 4)]) {
+        assert_eq!(point[2]["enqueue_d"], enq_d);
+        assert_eq!(point[2]["dequeue_d"], deq_d);
+        // The pairs of d are compared on common random numbers
+        assert_eq!(point[2]["run_seeds"], points[0][2]["run_seeds"]);
+    }
+}
+
+#[test]
+fn sweeps_one_side_with_d_for_the_other() {
+    let dir = TestDir::new("deq-d");
+    run_ok(
+        &dir,
+        &[
+            "--seed",
+            "1",
+            "subqueues-and-prefill",
+            "-s",
+            "4",
+            "-d",
+            "3",
+            "-i",
+            "10",
+            "-o",
+            "200",
+            "--deq-d",
+            "1",
+            "2",
+        ],
+    );
+    let output = read_json(&dir.output("SubqueuesAndPrefill"));
+    let keys: Vec<&str> = output["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|point| point[0].as_str().unwrap())
+        .collect();
+    assert_eq!(keys, ["(3, 1, 4, 10)", "(3, 2, 4, 10)"]);
+}
+
+#[test]
+fn only_sweeps_take_several_d() {
+    let dir = TestDir::new("single-d");
+    let output = run(
+        &dir,
+        &[
+            "single", "-s", "4", "-o", "100", "-i", "10", "--enq-d", "1", "2",
+        ],
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Only the sweeps"));
+}
@@ -0,0 +1,51 @@
+//! Reproduces a single run of a sweep from its recorded seeds with single --exact-seed
+
+mod common;
+
+use common::{read_json, run_ok, TestDir};
+
+#[test]
+fn exact_seed_reproduces_sweep_run() {
+    let dir = TestDir::new("exact-seed");
+    run_ok(
+        &dir,
+        &[
+            "--seed",
+            "5",
+            "ops-and-prefill",
+            "-s",
+            "4",
+            "-o",
+            "1000",
+            "-i",
+            "50",
+            "-r",
+            "3",
+        ],
+    );
+    let sweep = read_json(&dir.output("OpsAndPrefill"));
+    let extras = &sweep["results"][0][2];
+
+    for run in 0..3 {
+        let seeds = &extras["run_seeds"][run];
+        let recorded = extras["run_values"][run].as_f64().unwrap() as f32;
+        let stdout = run_ok(
+            &dir,
+            &[
+                "single",
+                "-s",
+                "4",
+                "-o",
+                "1000",
+                "-i",
+                "50",
+                "--exact-seed",
+                &seeds["queue"].to_string(),
+                "--exact-ops-seed",
+                &seeds["operations"].to_string(),
+            ],
+        );
+        let value: f32 = stdout.lines().next().unwrap().parse().unwrap();
+        assert_eq!(value, recorded, "run {run}");
+    }
+}
@@ -0,0 +1,86 @@
+//! Registers a strict FIFO queue as a wrapping binary would, and runs the sweeps against it
+//! through run_cli, which should then measure no rank errors at all
+
+mod common;
+
+use std::collections::VecDeque;
+
+use common::{read_json, TestDir};
+use relaxation_analysis::{register_queue, run_cli, RelaxedFifo};
+
+struct StrictFifo(VecDeque<usize>);
+
+impl RelaxedFifo<usize> for StrictFifo {
+    fn enqueue(&mut self, item: usize) {
+        self.0.push_back(item);
+    }
+
+    fn dequeue(&mut self) -> Option<usize> {
+        self.0.pop_front()
+    }
+}
+
+/// Runs the sweep on the registered queue, asserting that every readout of it is zero
+fn assert_exact_sweep(dir: &TestDir, args: &[&str]) {
+    let mut full_args = vec![
+        "relaxation-analysis",
+        "--output-dir",
+        dir.path().to_str().unwrap(),
+        "--no-timestamp",
+        "--seed",
+        "1",
+    ];
+    full_args.extend_from_slice(args);
+    let path = run_cli(full_args).expect("The sweep writes an output");
+    let output = read_json(&path);
+    let points = output["results"].as_array().unwrap();
+    assert_eq!(points.len(), 4, "{}", path.display());
+    for point in points {
+        assert_eq!(point[1].as_f64(), Some(0.0), "{point}");
+        let run_values = point[2]["run_values"].as_array().unwrap();
+        assert_eq!(run_values.len(), 2, "{point}");
+        assert!(run_values.iter().all(|value| value.as_f64() == Some(0.0)));
+    }
+}
+
+#[test]
+fn sweeps_run_registered_queue() {
+    register_queue("strict-fifo", |_, _| Box::new(StrictFifo(VecDeque::new())));
+    let dir = TestDir::new("external-queue");
+    assert_exact_sweep(
+        &dir,
+        &[
+            "ops-and-prefill",
+            "--queue",
+            "external:strict-fifo",
+            "-s",
+            "4",
+            "-o",
+            "100",
+            "200",
+            "-i",
+            "0",
+            "10",
+            "-r",
+            "2",
+        ],
+    );
+    assert_exact_sweep(
+        &dir,
+        &[
+            "subqueues-and-prefill",
+            "--queue",
+            "external:strict-fifo",
+            "-s",
+            "2",
+            "4",
+            "-i",
+            "0",
+            "10",
+            "-o",
+            "100",
+            "-r",
+            "2",
+        ],
+    );
+}
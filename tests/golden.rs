@@ -0,0 +1,126 @@
+//! Compares the outputs of tiny fixed-seed tests against the checked-in files in tests/golden, so
+//! that changes to field names, nesting or numeric formatting show up in review
+
+mod common;
+
+use common::{assert_golden, normalize, read_json, run_ok, TestDir};
+
+/// Runs the test and compares its normalized json output with the golden file of the same name
+fn assert_json_golden(output_name: &str, args: &[&str]) {
+    let dir = TestDir::new(output_name);
+    run_ok(&dir, args);
+    let mut output = read_json(&dir.output(output_name));
+    normalize(&mut output);
+    let mut text = serde_json::to_string_pretty(&output).unwrap();
+    text.push('\n');
+    assert_golden(&format!("{output_name}.json"), &text);
+}
+
+/// Replaces the timing in the stdout of a single test, which differs between runs
+fn mask_timing(stdout: &str) -> String {
+    stdout
+        .lines()
+        .map(
+            |line| match line.strip_prefix("Simulated operations per second: ") {
+                Some(_) => "Simulated operations per second: <timing>\n".to_string(),
+                None => format!("{line}\n"),
+            },
+        )
+        .collect()
+}
+
+#[test]
+fn single() {
+    let dir = TestDir::new("single");
+    let stdout = run_ok(
+        &dir,
+        &["--seed", "1", "single", "-s", "4", "-o", "200", "-i", "20"],
+    );
+    assert_golden("Single.txt", &mask_timing(&stdout));
+}
+
+#[test]
+fn choice_trace() {
+    let dir = TestDir::new("choice-trace");
+    let stdout = run_ok(
+        &dir,
+        &[
+            "--seed",
+            "1",
+            "single",
+            "-s",
+            "4",
+            "-o",
+            "10",
+            "-i",
+            "3",
+            "--trace-stdout",
+            "10",
+        ],
+    );
+    assert_golden("ChoiceTrace.txt", &mask_timing(&stdout));
+}
+
+#[test]
+fn ops_and_prefill() {
+    assert_json_golden(
+        "OpsAndPrefill",
+        &[
+            "--seed",
+            "1",
+            "ops-and-prefill",
+            "-s",
+            "4",
+            "-o",
+            "100",
+            "200",
+            "-i",
+            "0",
+            "10",
+            "-r",
+            "2",
+        ],
+    );
+}
+
+#[test]
+fn subqueues_and_prefill() {
+    assert_json_golden(
+        "SubqueuesAndPrefill",
+        &[
+            "--seed",
+            "1",
+            "subqueues-and-prefill",
+            "-s",
+            "2",
+            "4",
+            "-i",
+            "0",
+            "10",
+            "-o",
+            "100",
+            "-r",
+            "2",
+        ],
+    );
+}
+
+#[test]
+fn distributions() {
+    assert_json_golden(
+        "Distributions",
+        &[
+            "--seed",
+            "1",
+            "distributions",
+            "-s",
+            "4",
+            "-o",
+            "200",
+            "-i",
+            "20",
+            "-r",
+            "2",
+        ],
+    );
+}
@@ -0,0 +1,80 @@
+//! Loads the checked-in outputs of each historical schema version in tests/fixtures, which must
+//! keep upgrading to the current schema
+
+use std::path::PathBuf;
+
+use relaxation_analysis::{load_results, ResultFile, SCHEMA_VERSION};
+use serde_json::json;
+
+fn load_fixture(name: &str) -> Result<ResultFile, String> {
+    load_results(
+        &PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name),
+    )
+}
+
+fn results() -> serde_json::Value {
+    json!([
+        ["(4, 100)", 1.25, {"std": 0.5}],
+        ["(4, 200)", 1.5, {"std": 0.25}],
+        ["(8, 100)", 3.0, {"std": 1.0}]
+    ])
+}
+
+#[test]
+fn version_0_keeps_its_points() {
+    let output = load_fixture("results_v0.json").unwrap();
+    assert_eq!(output.schema_version, SCHEMA_VERSION);
+    assert!(output.metadata.is_null());
+    assert_eq!(
+        output.results,
+        json!([["(4, 100)", 1.25], ["(4, 200)", 1.5], ["(8, 100)", 3.0]])
+    );
+    assert_eq!(output.fits, None);
+    assert_eq!(output.warnings, None);
+}
+
+#[test]
+fn version_1_keeps_its_metadata_and_fits() {
+    for name in ["results_v1.json", "results_v1.json.gz"] {
+        let output = load_fixture(name).unwrap();
+        assert_eq!(output.schema_version, SCHEMA_VERSION);
+        assert_eq!(output.metadata["seed"], 1, "{name}");
+        assert_eq!(
+            output.metadata["config"]["subqueues-and-prefill"]["subqueues"],
+            json!([4, 8])
+        );
+        assert_eq!(output.results, results(), "{name}");
+        assert_eq!(output.fits.unwrap()["subqueues"]["exponent"], 1.0);
+        assert_eq!(output.warnings, None);
+    }
+}
+
+#[test]
+fn current_version_loads_unchanged() {
+    let output = load_fixture("results_v2.json").unwrap();
+    assert_eq!(output.schema_version, SCHEMA_VERSION);
+    assert_eq!(output.metadata["seed_scheme"], 3);
+    assert_eq!(output.results, results());
+    assert!(output.fits.is_some());
+    assert_eq!(output.warnings.unwrap()[0]["point"], "(8, 100)");
+
+    // And written back as it was read
+    let text = std::fs::read_to_string(
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/results_v2.json"),
+    )
+    .unwrap();
+    let written = load_fixture("results_v2.json").unwrap().into_json();
+    assert_eq!(
+        written,
+        serde_json::from_str::<serde_json::Value>(&text).unwrap()
+    );
+}
+
+#[test]
+fn unknown_versions_are_rejected() {
+    let err = load_fixture("results_v99.json").unwrap_err();
+    assert!(err.contains("unknown schema version 99"), "{err}");
+    assert!(load_fixture("missing.json").is_err());
+}
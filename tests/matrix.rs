@@ -0,0 +1,56 @@
+//! Compares queue kinds with the matrix, which runs every kind on the same seeds
+
+mod common;
+
+use common::{read_json, run_ok, TestDir};
+
+#[test]
+fn kinds_share_seeds() {
+    let dir = TestDir::new("matrix");
+    run_ok(
+        &dir,
+        &[
+            "--seed",
+            "1",
+            "matrix",
+            "-s",
+            "8",
+            "-o",
+            "2000",
+            "-i",
+            "100",
+            "-r",
+            "3",
+            "--kinds",
+            "operation",
+            "operation",
+            "round-robin",
+            "--k-fifo",
+            "4",
+            "4",
+        ],
+    );
+    let output = read_json(&dir.output("Matrix"));
+    let rows = output["results"].as_array().unwrap();
+    let names: Vec<&str> = rows
+        .iter()
+        .map(|row| row["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "operation",
+            "operation",
+            "round-robin",
+            "k-fifo-4",
+            "k-fifo-4"
+        ]
+    );
+    // Repeating a kind repeats its queue seeds, so it gets the same rank errors
+    assert_eq!(rows[0], rows[1]);
+    assert_eq!(rows[3], rows[4]);
+    assert!(rows[0]["mean_rank_error"].as_f64().unwrap() > 0.0);
+    // A sequential round-robin queue without empty returns is strict
+    assert_eq!(rows[2]["max_rank_error"], 0);
+    assert!(rows[3]["max_rank_error"].as_u64().unwrap() < 4);
+}
@@ -0,0 +1,40 @@
+//! Compares the estimated memory of a run with the resident memory it takes, in a process of its
+//! own so that no other test allocates meanwhile
+#![cfg(target_os = "linux")]
+
+use rand::{rngs::StdRng, SeedableRng};
+use relaxation_analysis::{
+    analyze_simple, estimate_run_memory, gen_balanced_ops, DChoiceQueueBuilder,
+};
+
+/// A field of /proc/self/status in bytes, such as the current VmRSS or the peak VmHWM
+fn status_bytes(field: &str) -> usize {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap();
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+        .unwrap()
+        .trim()
+        .trim_end_matches(" kB");
+    kb.parse::<usize>().unwrap() * 1024
+}
+
+/// The estimate assumes that every item is in the queue at once, so it is an upper bound. With
+/// a balanced workload from a prefill as large as the operations, about half of them are, and the
+/// estimate stays within four times the measured peak
+#[test]
+fn estimate_bounds_the_measured_memory() {
+    let (prefill, operations, subqueues) = (1_000_000, 1_000_000, 64);
+    let before = status_bytes("VmRSS");
+    let ops = gen_balanced_ops(operations, &mut StdRng::seed_from_u64(1));
+    let mut queue = DChoiceQueueBuilder::new(subqueues).seed(Some(1)).build();
+    let rank_errors = analyze_simple(&mut queue, prefill, &ops).unwrap();
+    let measured = status_bytes("VmHWM") - before;
+    assert_eq!(rank_errors.len(), operations / 2);
+
+    let estimate = estimate_run_memory(prefill, operations, subqueues);
+    assert!(
+        measured <= estimate && estimate <= 4 * measured,
+        "Estimated {estimate} bytes, but measured {measured}"
+    );
+}
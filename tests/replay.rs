@@ -0,0 +1,84 @@
+//! Replays a tiny sweep and checks that it reproduces the original
+
+mod common;
+
+use common::{command, normalize, read_json, run_ok, TestDir};
+
+#[test]
+fn replay_reproduces_sweep() {
+    let dir = TestDir::new("replay");
+    run_ok(
+        &dir,
+        &[
+            "--seed",
+            "7",
+            "ops-and-prefill",
+            "-s",
+            "4",
+            "-o",
+            "100",
+            "200",
+            "-i",
+            "0",
+            "10",
+            "-r",
+            "3",
+        ],
+    );
+    let original_path = dir.output("OpsAndPrefill");
+
+    // Without --output-dir, the replay is written next to the original
+    let replay = command(&dir)
+        .arg("--no-timestamp")
+        .arg("replay")
+        .arg(&original_path)
+        .output()
+        .unwrap();
+    assert!(
+        replay.status.success(),
+        "{}",
+        String::from_utf8_lossy(&replay.stdout)
+    );
+    assert!(String::from_utf8_lossy(&replay.stdout).contains("All 4 points reproduced"));
+    assert!(!dir.path().join("results").exists());
+
+    let mut original = read_json(&original_path);
+    let mut replayed = read_json(&dir.output("OpsAndPrefill-replay"));
+    normalize(&mut original);
+    normalize(&mut replayed);
+    assert_eq!(original["results"], replayed["results"]);
+}
+
+#[test]
+fn replay_reports_divergence() {
+    let dir = TestDir::new("replay-divergence");
+    run_ok(
+        &dir,
+        &[
+            "--seed",
+            "7",
+            "ops-and-prefill",
+            "-s",
+            "4",
+            "-o",
+            "100",
+            "-i",
+            "10",
+            "-r",
+            "2",
+        ],
+    );
+    let path = dir.output("OpsAndPrefill");
+    let mut output = read_json(&path);
+    output["results"][0][1] = serde_json::json!(1000.0);
+    std::fs::write(&path, serde_json::to_string(&output).unwrap()).unwrap();
+
+    let replay = command(&dir)
+        .arg("--no-timestamp")
+        .arg("replay")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(replay.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&replay.stdout).contains("results[\"(10, 100)\"].value"));
+}
@@ -0,0 +1,81 @@
+//! Runs the same sweep twice with the same seed, and checks that the outputs are identical
+
+mod common;
+
+use common::{command, normalize, read_json, TestDir};
+
+/// Runs the sweep with the rayon threads, and returns its output without the timings
+fn sweep_output(name: &str, threads: &str) -> String {
+    let dir = TestDir::new(name);
+    let output = command(&dir)
+        .env("RAYON_NUM_THREADS", threads)
+        .arg("--output-dir")
+        .arg(dir.path())
+        .arg("--no-timestamp")
+        .args([
+            "--seed",
+            "7",
+            "ops-and-prefill",
+            "-s",
+            "4",
+            "-i",
+            "0",
+            "20",
+            "--enqueues",
+            "600",
+            "400",
+            "500",
+            "--dequeues",
+            "400",
+            "600",
+            "500",
+            "-r",
+            "5",
+        ])
+        .output()
+        .expect("Could not run the binary");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let mut sweep = read_json(&dir.output("OpsAndPrefill"));
+    normalize(&mut sweep);
+    serde_json::to_string_pretty(&sweep).unwrap()
+}
+
+#[test]
+fn same_seed_gives_identical_output() {
+    // The runs of each point are split differently between the threads
+    let sequential = sweep_output("reproducible-sequential", "1");
+    let parallel = sweep_output("reproducible-parallel", "4");
+    assert!(
+        sequential == parallel,
+        "The outputs differ between executions"
+    );
+
+    // Points with the same total are ordered by their split, not by when they finished
+    let sweep: serde_json::Value = serde_json::from_str(&sequential).unwrap();
+    let splits: Vec<(String, u64)> = sweep["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|record| {
+            (
+                record[0].as_str().unwrap().to_string(),
+                record[2]["enqueues"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        splits,
+        [
+            ("(0, 1000)".to_string(), 400),
+            ("(0, 1000)".to_string(), 500),
+            ("(0, 1000)".to_string(), 600),
+            ("(20, 1000)".to_string(), 400),
+            ("(20, 1000)".to_string(), 500),
+            ("(20, 1000)".to_string(), 600),
+        ]
+    );
+}
@@ -0,0 +1,145 @@
+//! Runs every subcommand with --smoke, checking that it succeeds and that its output is marked
+
+mod common;
+
+use std::collections::BTreeSet;
+
+use common::{read_json, run, run_ok, TestDir};
+
+/// The subcommands listed by --help, apart from help itself
+fn subcommands(dir: &TestDir) -> BTreeSet<String> {
+    let help = run_ok(dir, &["--help"]);
+    help.lines()
+        .skip_while(|line| *line != "Commands:")
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|name| *name != "help")
+        .map(str::to_string)
+        .collect()
+}
+
+/// Checks the schema of a json output of the subcommand, and that it is marked as smoke
+fn assert_smoke_output(dir: &TestDir, output_name: &str, subcommand: &str) {
+    let output = read_json(&dir.output(output_name));
+    assert!(output["schema_version"].is_u64(), "{output_name}");
+    assert!(!output["results"].is_null(), "{output_name}");
+    assert_eq!(output["metadata"]["smoke"], true, "{output_name}");
+    assert!(
+        output["metadata"]["config"][subcommand].is_object(),
+        "{output_name} has no config of {subcommand}"
+    );
+}
+
+#[test]
+fn every_subcommand_runs_in_smoke_mode() {
+    let dir = TestDir::new("smoke");
+    let mut covered = BTreeSet::new();
+    let mut smoke = |subcommand: &str, args: &[&str], output_name: Option<&str>| {
+        let mut full_args = vec!["--smoke", "--seed", "1", subcommand];
+        full_args.extend_from_slice(args);
+        let stdout = run_ok(&dir, &full_args);
+        if let Some(output_name) = output_name {
+            assert_smoke_output(&dir, output_name, subcommand);
+        }
+        covered.insert(subcommand.to_string());
+        stdout
+    };
+
+    let stdout = smoke(
+        "single",
+        &[
+            "-s",
+            "4",
+            "-o",
+            "100000",
+            "-i",
+            "1000",
+            "--full-log",
+            "log.bin",
+        ],
+        None,
+    );
+    assert!(stdout.contains("\"operations\":200"), "{stdout}");
+    smoke(
+        "ops-and-prefill",
+        &[
+            "-s", "4", "-o", "1000", "2000", "3000", "-i", "0", "100", "-r", "5",
+        ],
+        Some("OpsAndPrefill"),
+    );
+    let sweep = read_json(&dir.output("OpsAndPrefill"));
+    assert_eq!(sweep["results"].as_array().unwrap().len(), 4);
+    smoke(
+        "subqueues-and-prefill",
+        &[
+            "-s", "2", "4", "8", "-i", "0", "100", "-o", "1000", "-r", "5",
+        ],
+        Some("SubqueuesAndPrefill"),
+    );
+    smoke(
+        "distributions",
+        &["-s", "4", "-o", "10000", "-i", "100", "-r", "3"],
+        Some("Distributions"),
+    );
+    smoke(
+        "matrix",
+        &["-s", "4", "-o", "10000", "-i", "100"],
+        Some("Matrix"),
+    );
+    smoke("concurrent", &["-s", "4", "-o", "10000", "-i", "100"], None);
+    smoke("exhaustive", &["-s", "2", "--ops-pattern", "EEDD"], None);
+    smoke("eval-heuristic", &[], Some("EvalHeuristic"));
+    smoke(
+        "describe-ops",
+        &["-o", "10000", "-i", "100"],
+        Some("DescribeOps"),
+    );
+    smoke("min-max-gaps", &["-o", "10000"], Some("MinMaxGaps"));
+
+    let log = dir.path().join("log.bin");
+    smoke("log-stats", &[log.to_str().unwrap()], None);
+    let sidecar = read_json(&dir.path().join("log.bin.json"));
+    assert_eq!(sidecar["metadata"]["smoke"], true);
+
+    let sweep_path = dir.output("OpsAndPrefill");
+    let sweep_path = sweep_path.to_str().unwrap();
+    smoke("pareto", &[sweep_path], Some("Pareto"));
+    smoke("replay", &[sweep_path], None);
+    assert!(dir.output("OpsAndPrefill-replay").exists());
+
+    let other = run(
+        &dir,
+        &[
+            "--smoke",
+            "--seed",
+            "2",
+            "ops-and-prefill",
+            "-s",
+            "4",
+            "-o",
+            "1000",
+            "2000",
+            "3000",
+            "-i",
+            "0",
+            "100",
+            "-r",
+            "5",
+            "--output-name",
+            "Other",
+        ],
+    );
+    assert!(other.status.success());
+    let other_path = dir.output("Other");
+    smoke("merge", &[sweep_path, other_path.to_str().unwrap()], None);
+    let merged = read_json(&dir.output("Merged"));
+    assert_eq!(merged["metadata"]["smoke"], true);
+    assert_eq!(merged["results"].as_array().unwrap().len(), 4);
+
+    assert_eq!(
+        covered,
+        subcommands(&dir),
+        "Not every subcommand is smoke tested"
+    );
+}
@@ -0,0 +1,54 @@
+//! Checks that single and the sweeps report their simulated operations per second
+
+mod common;
+
+use common::{find_all, read_json, run_ok, TestDir};
+
+#[test]
+fn single_prints_speed() {
+    let dir = TestDir::new("single-speed");
+    let stdout = run_ok(
+        &dir,
+        &["--seed", "3", "single", "-s", "4", "-o", "500", "-i", "50"],
+    );
+    let speed: f64 = stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("Simulated operations per second: "))
+        .expect("The speed is printed")
+        .parse()
+        .expect("The speed is a number");
+    assert!(speed > 0.0);
+}
+
+#[test]
+fn sweep_points_have_speed() {
+    let dir = TestDir::new("sweep-speed");
+    run_ok(
+        &dir,
+        &[
+            "--seed",
+            "3",
+            "ops-and-prefill",
+            "-s",
+            "4",
+            "-o",
+            "100",
+            "200",
+            "-i",
+            "0",
+            "10",
+            "-r",
+            "2",
+        ],
+    );
+    let output = read_json(&dir.output("OpsAndPrefill"));
+    let points = output["results"]
+        .as_array()
+        .expect("The results are a list");
+    let mut speeds = vec![];
+    find_all(&output["results"], "ops_per_second", &mut speeds);
+    assert_eq!(speeds.len(), points.len());
+    for speed in speeds {
+        assert!(speed.as_f64().expect("The speed is a number") > 0.0);
+    }
+}
@@ -0,0 +1,102 @@
+//! Runs the sweeps over several queue kinds, which give one record per kind and point
+
+mod common;
+
+use std::collections::BTreeSet;
+
+use common::{read_json, run_ok, TestDir};
+use serde_json::Value;
+
+/// The records of the sweep, checking that each has the per-run values of every run
+fn sweep_records(output_name: &str, args: &[&str], runs: usize) -> Vec<Value> {
+    let dir = TestDir::new(output_name);
+    run_ok(&dir, args);
+    let output = read_json(&dir.output(output_name));
+    let records = output["results"].as_array().unwrap().clone();
+    for record in &records {
+        assert_eq!(record[2]["run_values"].as_array().unwrap().len(), runs);
+    }
+    records
+}
+
+/// The points of the records of each kind, which must be the same for all kinds
+fn points_by_kind(records: &[Value], kinds: &[&str]) -> BTreeSet<String> {
+    let point_sets: Vec<BTreeSet<String>> = kinds
+        .iter()
+        .map(|kind| {
+            records
+                .iter()
+                .filter(|record| record[2]["queue"] == *kind)
+                .map(|record| record[2]["run_seeds"].to_string())
+                .collect()
+        })
+        .collect();
+    for points in &point_sets {
+        assert_eq!(points, &point_sets[0]);
+    }
+    point_sets[0].clone()
+}
+
+#[test]
+fn ops_and_prefill_runs_every_kind_at_every_point() {
+    let kinds = ["operation", "round-robin", "length"];
+    let records = sweep_records(
+        "KindsOpsAndPrefill",
+        &[
+            "--seed",
+            "1",
+            "ops-and-prefill",
+            "-s",
+            "4",
+            "-o",
+            "100",
+            "200",
+            "-i",
+            "0",
+            "10",
+            "-r",
+            "3",
+            "--kinds",
+            "operation",
+            "round-robin",
+            "length",
+            "--output-name",
+            "KindsOpsAndPrefill",
+        ],
+        3,
+    );
+    assert_eq!(records.len(), kinds.len() * 2 * 2);
+    // The kinds share the seeds of each point
+    assert_eq!(points_by_kind(&records, &kinds).len(), 2 * 2);
+}
+
+#[test]
+fn subqueues_and_prefill_runs_every_kind_at_every_point() {
+    let kinds = ["operation", "round-robin"];
+    let records = sweep_records(
+        "KindsSubqueuesAndPrefill",
+        &[
+            "--seed",
+            "1",
+            "subqueues-and-prefill",
+            "-s",
+            "2",
+            "4",
+            "8",
+            "-i",
+            "10",
+            "-o",
+            "100",
+            "-r",
+            "2",
+            "--kinds",
+            "operation",
+            "round-robin",
+            "--output-name",
+            "KindsSubqueuesAndPrefill",
+        ],
+        2,
+    );
+    assert_eq!(records.len(), kinds.len() * 3);
+    assert_eq!(points_by_kind(&records, &kinds).len(), 3);
+}